@@ -0,0 +1,734 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use super::diagnostic::{did_you_mean, Diagnostic};
+use super::include::Includer;
+use super::parser::{error, parse, AsmResult, Line, Operand, Statement};
+use crate::error::LC3Result;
+use crate::isa::Isa;
+use crate::symbols::SymbolTable;
+
+const DIRECTIVES: [&str; 8] = [
+    ".ORIG", ".END", ".FILL", ".BLKW", ".STRINGZ", ".INCLUDE", ".GLOBAL", ".EXTERN",
+];
+
+// The result of assembling a source file: a single block of words to be
+// loaded at origin, plus the labels defined along the way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assembly {
+    pub origin: u16,
+    pub words: Vec<u16>,
+    pub symbols: SymbolTable,
+    // Source line number (1-based) to the address of the first word that
+    // line assembled to, for lines that produce words. Lines of included
+    // files aren't in here.
+    pub line_addresses: BTreeMap<usize, u16>,
+    // Labels declared .GLOBAL, for other modules to use once linked
+    pub globals: Vec<String>,
+    // Uses of labels declared .EXTERN, left as zeroes for the linker to fill
+    // in. There can only be any when assembling a module.
+    pub references: Vec<ExternalReference>,
+}
+
+// A word that needs the address of a label from another module
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternalReference {
+    pub address: u16,
+    pub label: String,
+    // The width of the PC offset field to fill in, or None when the whole
+    // word is the label's address, as for .FILL
+    pub offset_bits: Option<u8>,
+}
+
+impl Assembly {
+    // The program in LC-3 object format, ready for VM::load_object
+    pub fn to_object(&self) -> Vec<u16> {
+        let mut object = Vec::with_capacity(self.words.len() + 1);
+        object.push(self.origin);
+        object.extend(&self.words);
+        object
+    }
+}
+
+// Two pass assembler. The first pass works out the address of every line
+// so the labels can be resolved, the second does the actual encoding.
+// Fails with the first error found, see assemble_with_diagnostics for all
+// of them. Any .INCLUDEs are looked for relative to the current directory.
+pub fn assemble(source: &str) -> LC3Result<Assembly> {
+    Assembler::new().assemble(source, None)
+}
+
+pub fn assemble_with_diagnostics(source: &str) -> (Option<Assembly>, Vec<Diagnostic>) {
+    Assembler::new().assemble_with_diagnostics(source, None)
+}
+
+// For assembling with more control over where .INCLUDEd files come from
+#[derive(Debug, Clone, Default)]
+pub struct Assembler {
+    include_paths: Vec<PathBuf>,
+    module: bool,
+    isa: Isa,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Somewhere else to look for .INCLUDEd files, after the directory of the
+    // file doing the including. Searched in the order they're added.
+    pub fn include_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.include_paths.push(path.into());
+        self
+    }
+
+    // Assemble a module to be linked with others, where .EXTERN labels can
+    // be used. Otherwise using one is an error, as there's nothing to say
+    // where it is.
+    pub fn module(mut self) -> Self {
+        self.module = true;
+        self
+    }
+
+    // Assemble for the LC-3b, where addresses count bytes and there are
+    // byte loads and stores, XOR and shifts in place of LD, LDI, LDR, ST,
+    // STI and STR. .STRINGZ packs two characters to a word, low byte first,
+    // as the LC-3b's byte loads expect.
+    pub fn isa(mut self, isa: Isa) -> Self {
+        self.isa = isa;
+        self
+    }
+
+    // path is where the source came from, which .INCLUDEs are relative to.
+    // Without one they're relative to the current directory.
+    pub fn assemble(&self, source: &str, path: Option<&Path>) -> LC3Result<Assembly> {
+        let (assembly, diagnostics) = self.assemble_with_diagnostics(source, path);
+        match assembly {
+            Some(assembly) => Ok(assembly),
+            None => Err(diagnostics
+                .into_iter()
+                .find(Diagnostic::is_error)
+                .expect("Assembly only fails with an error")
+                .into()),
+        }
+    }
+
+    // Assemble, carrying on past errors to report as many as possible. The
+    // assembly is only there when there were no errors, though there can
+    // still be warnings. Encoding is skipped if the first pass fails, as the
+    // addresses it worked out can't be trusted. Diagnostics are in source
+    // order, with those for included files after the rest.
+    pub fn assemble_with_diagnostics(
+        &self,
+        source: &str,
+        path: Option<&Path>,
+    ) -> (Option<Assembly>, Vec<Diagnostic>) {
+        let mut diagnostics = Vec::new();
+        let assembly = self.assemble_source(source, path, &mut diagnostics);
+        diagnostics.sort_by(|a, b| (&a.file, a.line, a.column).cmp(&(&b.file, b.line, b.column)));
+        (assembly, diagnostics)
+    }
+
+    fn assemble_source(
+        &self,
+        source: &str,
+        path: Option<&Path>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Option<Assembly> {
+        let lines = match parse(source, self.isa) {
+            Ok(lines) => lines,
+            Err(errors) => {
+                diagnostics.extend(errors);
+                return None;
+            }
+        };
+        let lines = Includer::new(&self.include_paths, self.isa, path).expand(lines, diagnostics);
+        if diagnostics.iter().any(Diagnostic::is_error) {
+            return None;
+        }
+
+        assemble_lines(&lines, self.module, self.isa, diagnostics)
+    }
+}
+
+fn assemble_lines(
+    lines: &[Line],
+    module: bool,
+    isa: Isa,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<Assembly> {
+    let (origin, placed) = place_lines(lines, isa, diagnostics)?;
+    let globals = declared(lines, ".GLOBAL", diagnostics);
+    let externs = declared(lines, ".EXTERN", diagnostics);
+
+    let mut symbols = SymbolTable::new();
+    for (line, address) in &placed {
+        if let Some(label) = &line.label {
+            if symbols.address_of(label).is_some() {
+                diagnostics.push(
+                    line.error(format!("Label '{}' is defined more than once", label))
+                        .at(Some(line.columns.label)),
+                );
+                continue;
+            }
+            symbols.insert(label, *address);
+        }
+    }
+    for (line, column, label) in &globals {
+        if symbols.address_of(label).is_none() {
+            diagnostics.push(
+                line.error(format!("'{}' is declared .GLOBAL but never defined", label))
+                    .at(Some(*column)),
+            );
+        }
+    }
+    for (line, column, label) in &externs {
+        if symbols.address_of(label).is_some() {
+            diagnostics.push(
+                line.error(format!("'{}' is declared .EXTERN but defined here", label))
+                    .at(Some(*column)),
+            );
+        }
+    }
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        return None;
+    }
+
+    let externs: Vec<&str> = externs.iter().map(|(_, _, label)| *label).collect();
+    let mut references = Vec::new();
+    let mut words = Vec::new();
+    for (line, address) in &placed {
+        if let Some(statement) = &line.statement {
+            let mut encoder = Encoder {
+                line,
+                address: *address,
+                symbols: &symbols,
+                externs: &externs,
+                module,
+                isa,
+                references: &mut references,
+            };
+            match encoder.encode(statement) {
+                Ok(encoded) => words.extend(encoded),
+                Err(diagnostic) => diagnostics.push(diagnostic),
+            }
+        }
+    }
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        return None;
+    }
+
+    let line_addresses = placed
+        .iter()
+        .filter(|(line, _)| line.statement.is_some() && line.file.is_none())
+        .map(|(line, address)| (line.number, *address))
+        .collect();
+
+    let mut global_labels: Vec<String> = Vec::new();
+    for (_, _, label) in globals {
+        if !global_labels.iter().any(|global| global == label) {
+            global_labels.push(label.to_string());
+        }
+    }
+
+    Some(Assembly {
+        origin,
+        words,
+        symbols,
+        line_addresses,
+        globals: global_labels,
+        references,
+    })
+}
+
+// The labels named by every .GLOBAL or .EXTERN, along with where they were
+// named
+fn declared<'a>(
+    lines: &'a [Line],
+    directive: &str,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<(&'a Line, usize, &'a str)> {
+    let mut labels = Vec::new();
+    for line in lines {
+        let operands = match &line.statement {
+            Some(Statement::Directive { name, operands }) if name == directive => operands,
+            _ => continue,
+        };
+        if operands.is_empty() {
+            diagnostics.push(
+                line.error(format!("{} expects one or more labels", directive))
+                    .at(Some(line.columns.statement)),
+            );
+        }
+        for (operand, column) in operands.iter().zip(&line.columns.operands) {
+            match operand {
+                Operand::Label(label) => labels.push((line, *column, label.as_str())),
+                _ => diagnostics.push(
+                    line.error(format!("{} expects labels", directive))
+                        .at(Some(*column)),
+                ),
+            }
+        }
+    }
+    labels
+}
+
+// Pair every line between .ORIG and .END with the address it will be
+// assembled at. None if there's no working out where anything goes.
+fn place_lines<'a>(
+    lines: &'a [Line],
+    isa: Isa,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<(u16, Vec<(&'a Line, u16)>)> {
+    let mut origin = None;
+    let mut address: u32 = 0;
+    let mut placed = Vec::new();
+    let mut ended = false;
+    let mut warned_after_end = false;
+
+    for line in lines {
+        let statement = match &line.statement {
+            Some(statement) => statement,
+            None => {
+                if line.label.is_some() && origin.is_some() && !ended {
+                    placed.push((line, address as u16));
+                }
+                continue;
+            }
+        };
+
+        if let Statement::Directive { name, operands } = statement {
+            match name.as_str() {
+                ".ORIG" => {
+                    if origin.is_some() {
+                        diagnostics.push(
+                            line.error("Only one .ORIG block is supported".to_string())
+                                .at(Some(line.columns.statement)),
+                        );
+                        return None;
+                    }
+                    let start = match single_number(line, operands)
+                        .and_then(|start| to_word(line, start))
+                        .and_then(|start| aligned(line, isa, start))
+                    {
+                        Ok(start) => start,
+                        Err(diagnostic) => {
+                            diagnostics.push(diagnostic);
+                            return None;
+                        }
+                    };
+                    origin = Some(start);
+                    address = start as u32;
+                    continue;
+                }
+                ".END" => {
+                    ended = true;
+                    continue;
+                }
+                // Only say how labels are shared, so can go anywhere
+                ".GLOBAL" | ".EXTERN" => continue,
+                _ => {}
+            }
+        }
+
+        if ended {
+            if !warned_after_end {
+                warned_after_end = true;
+                diagnostics.push(
+                    line.warning("Ignoring everything after .END".to_string())
+                        .at(Some(line.columns.statement)),
+                );
+            }
+            continue;
+        }
+
+        if origin.is_none() {
+            diagnostics.push(
+                line.error("Expected .ORIG before the first statement".to_string())
+                    .at(Some(line.columns.statement)),
+            );
+            return None;
+        }
+
+        placed.push((line, address as u16));
+        match statement_size(line, statement, isa) {
+            Ok(size) => address += size * u32::from(isa.word_size()),
+            // Whatever's after this is placed as if it took no space
+            Err(diagnostic) => diagnostics.push(diagnostic),
+        }
+
+        if address > 0x10000 {
+            diagnostics.push(line.error("Program runs past the end of memory".to_string()));
+            return None;
+        }
+    }
+
+    match origin {
+        Some(origin) if !diagnostics.iter().any(Diagnostic::is_error) => Some((origin, placed)),
+        Some(_) => None,
+        None => {
+            // At the end of the source, not whatever it included
+            let end = lines.iter().filter(|line| line.file.is_none()).count();
+            diagnostics.push(error(end, "Missing .ORIG directive".to_string()));
+            None
+        }
+    }
+}
+
+// In words
+fn statement_size(line: &Line, statement: &Statement, isa: Isa) -> AsmResult<u32> {
+    let size = match statement {
+        Statement::Instruction { .. } => 1,
+        Statement::Directive { name, operands } => match name.as_str() {
+            ".FILL" => 1,
+            ".BLKW" => {
+                let count = single_number(line, operands)?;
+                if !(0..=0xFFFF).contains(&count) {
+                    return Err(line.error(format!("Invalid .BLKW size {}", count)).at(line
+                        .columns
+                        .operands
+                        .first()
+                        .copied()));
+                }
+                count as u32
+            }
+            ".STRINGZ" => match operands.as_slice() {
+                [Operand::Str(text)] => match isa {
+                    Isa::Lc3 => text.chars().count() as u32 + 1,
+                    Isa::Lc3b => (text.chars().count() as u32 + 1).div_ceil(2),
+                },
+                _ => {
+                    return Err(line
+                        .error(".STRINGZ expects a string".to_string())
+                        .at(Some(line.columns.statement)))
+                }
+            },
+            _ => {
+                return Err(line
+                    .error(format!("Unknown directive '{}'", name))
+                    .at(Some(line.columns.statement))
+                    .with_note(did_you_mean(name, DIRECTIVES.iter().copied())))
+            }
+        },
+    };
+
+    Ok(size)
+}
+
+fn single_number(line: &Line, operands: &[Operand]) -> AsmResult<i32> {
+    match operands {
+        [Operand::Number(value)] => Ok(*value),
+        _ => Err(line
+            .error("Expected a single number".to_string())
+            .at(Some(line.columns.statement))),
+    }
+}
+
+fn aligned(line: &Line, isa: Isa, address: u16) -> AsmResult<u16> {
+    isa.check_aligned(address).map_err(|_| {
+        line.error(format!(
+            "x{:04X} is odd, but LC-3b words have to start at even addresses",
+            address
+        ))
+        .at(line.columns.operands.first().copied())
+    })?;
+    Ok(address)
+}
+
+fn to_word(line: &Line, value: i32) -> AsmResult<u16> {
+    if (-0x8000..=0xFFFF).contains(&value) {
+        Ok(value as u16)
+    } else {
+        Err(line
+            .error(format!("Value {} does not fit in 16 bits", value))
+            .at(line.columns.operands.first().copied()))
+    }
+}
+
+// What each instruction's operands should look like, for when they don't
+fn usage(mnemonic: &str) -> &'static str {
+    match mnemonic {
+        "ADD" | "AND" => "DR, SR1, SR2 or DR, SR1, imm5",
+        "NOT" => "DR, SR",
+        "JMP" | "JSRR" => "a base register",
+        "JSR" => "a label or PCoffset11",
+        "LD" | "LDI" | "LEA" => "DR and a label or PCoffset9",
+        "ST" | "STI" => "SR and a label or PCoffset9",
+        "LDR" | "LDB" | "LDW" => "DR, BaseR, offset6",
+        "STR" | "STB" | "STW" => "SR, BaseR, offset6",
+        "XOR" => "DR, SR1, SR2 or DR, SR1, imm5",
+        "LSHF" | "RSHFL" | "RSHFA" => "DR, SR, amount4",
+        "TRAP" => "a trap vector, like x25",
+        branch if branch.starts_with("BR") => "a label or PCoffset9",
+        _ => "no operands",
+    }
+}
+
+struct Encoder<'a> {
+    line: &'a Line,
+    address: u16,
+    symbols: &'a SymbolTable,
+    externs: &'a [&'a str],
+    module: bool,
+    isa: Isa,
+    references: &'a mut Vec<ExternalReference>,
+}
+
+impl<'a> Encoder<'a> {
+    fn encode(&mut self, statement: &Statement) -> AsmResult<Vec<u16>> {
+        match statement {
+            Statement::Instruction { mnemonic, operands } => {
+                Ok(vec![self.encode_instruction(mnemonic, operands)?])
+            }
+            Statement::Directive { name, operands } => self.encode_directive(name, operands),
+        }
+    }
+
+    fn encode_directive(&mut self, name: &str, operands: &[Operand]) -> AsmResult<Vec<u16>> {
+        let words = match (name, operands) {
+            (".FILL", [Operand::Number(value)]) => vec![to_word(self.line, *value)?],
+            (".FILL", [Operand::Label(label)]) => vec![self.resolve(label, None)?.unwrap_or(0)],
+            (".FILL", _) => return Err(self.error(".FILL expects a number or label")),
+            (".BLKW", _) => vec![0; single_number(self.line, operands)? as usize],
+            (".STRINGZ", [Operand::Str(text)]) => {
+                let mut words: Vec<u16> = text.chars().map(|ch| ch as u16).collect();
+                words.push(0);
+                match self.isa {
+                    Isa::Lc3 => words,
+                    Isa::Lc3b => words
+                        .chunks(2)
+                        .map(|pair| {
+                            (pair[0] & 0xFF) | (pair.get(1).map_or(0, |high| high & 0xFF) << 8)
+                        })
+                        .collect(),
+                }
+            }
+            _ => return Err(self.error(&format!("Malformed {} directive", name))),
+        };
+
+        Ok(words)
+    }
+
+    fn encode_instruction(&mut self, mnemonic: &str, operands: &[Operand]) -> AsmResult<u16> {
+        use Operand::*;
+
+        if self.isa == Isa::Lc3b && ["LD", "LDI", "LDR", "ST", "STI", "STR"].contains(&mnemonic) {
+            return Err(self
+                .error(&format!("{} isn't an LC-3b instruction", mnemonic))
+                .with_note(Some(
+                    "the LC-3b loads and stores with LDB, LDW, STB and STW".to_string(),
+                )));
+        }
+
+        let word = match (mnemonic, operands) {
+            ("ADD", [Register(dr), Register(sr1), Register(sr2)]) => {
+                0x1000 | reg(*dr, 9) | reg(*sr1, 6) | *sr2 as u16
+            }
+            ("ADD", [Register(dr), Register(sr1), Number(imm)]) => {
+                0x1000 | reg(*dr, 9) | reg(*sr1, 6) | 0x20 | self.signed(*imm, 5)?
+            }
+            ("AND", [Register(dr), Register(sr1), Register(sr2)]) => {
+                0x5000 | reg(*dr, 9) | reg(*sr1, 6) | *sr2 as u16
+            }
+            ("AND", [Register(dr), Register(sr1), Number(imm)]) => {
+                0x5000 | reg(*dr, 9) | reg(*sr1, 6) | 0x20 | self.signed(*imm, 5)?
+            }
+            ("NOT", [Register(dr), Register(sr)]) => 0x903F | reg(*dr, 9) | reg(*sr, 6),
+            (branch, [target]) if branch.starts_with("BR") => {
+                let flags = match &branch[2..] {
+                    // A bare BR is unconditional
+                    "" => 0x0E00,
+                    flags => {
+                        let mut bits = 0;
+                        if flags.contains('N') {
+                            bits |= 0x0800;
+                        }
+                        if flags.contains('Z') {
+                            bits |= 0x0400;
+                        }
+                        if flags.contains('P') {
+                            bits |= 0x0200;
+                        }
+                        bits
+                    }
+                };
+                flags | self.pc_offset(target, 9)?
+            }
+            ("JMP", [Register(base)]) => 0xC000 | reg(*base, 6),
+            ("RET", []) => 0xC1C0,
+            ("JSR", [target]) => 0x4800 | self.pc_offset(target, 11)?,
+            ("JSRR", [Register(base)]) => 0x4000 | reg(*base, 6),
+            ("LD", [Register(dr), target]) => 0x2000 | reg(*dr, 9) | self.pc_offset(target, 9)?,
+            ("LDI", [Register(dr), target]) => 0xA000 | reg(*dr, 9) | self.pc_offset(target, 9)?,
+            ("LEA", [Register(dr), target]) => 0xE000 | reg(*dr, 9) | self.pc_offset(target, 9)?,
+            ("ST", [Register(sr), target]) => 0x3000 | reg(*sr, 9) | self.pc_offset(target, 9)?,
+            ("STI", [Register(sr), target]) => 0xB000 | reg(*sr, 9) | self.pc_offset(target, 9)?,
+            ("LDR", [Register(dr), Register(base), Number(offset)]) => {
+                0x6000 | reg(*dr, 9) | reg(*base, 6) | self.signed(*offset, 6)?
+            }
+            ("STR", [Register(sr), Register(base), Number(offset)]) => {
+                0x7000 | reg(*sr, 9) | reg(*base, 6) | self.signed(*offset, 6)?
+            }
+            ("LDB", [Register(dr), Register(base), Number(offset)]) => {
+                0x2000 | reg(*dr, 9) | reg(*base, 6) | self.signed(*offset, 6)?
+            }
+            ("STB", [Register(sr), Register(base), Number(offset)]) => {
+                0x3000 | reg(*sr, 9) | reg(*base, 6) | self.signed(*offset, 6)?
+            }
+            ("LDW", [Register(dr), Register(base), Number(offset)]) => {
+                0x6000 | reg(*dr, 9) | reg(*base, 6) | self.signed(*offset, 6)?
+            }
+            ("STW", [Register(sr), Register(base), Number(offset)]) => {
+                0x7000 | reg(*sr, 9) | reg(*base, 6) | self.signed(*offset, 6)?
+            }
+            ("XOR", [Register(dr), Register(sr1), Register(sr2)]) => {
+                0x9000 | reg(*dr, 9) | reg(*sr1, 6) | *sr2 as u16
+            }
+            ("XOR", [Register(dr), Register(sr1), Number(imm)]) => {
+                0x9000 | reg(*dr, 9) | reg(*sr1, 6) | 0x20 | self.signed(*imm, 5)?
+            }
+            (
+                shift @ ("LSHF" | "RSHFL" | "RSHFA"),
+                [Register(dr), Register(sr), Number(amount)],
+            ) => {
+                if !(0..=15).contains(amount) {
+                    return Err(self
+                        .error(&format!(
+                            "Shift amount {} out of range, range is 0 to 15",
+                            amount
+                        ))
+                        .at(self.column_of(&Number(*amount))));
+                }
+                let direction = match shift {
+                    "LSHF" => 0x00,
+                    "RSHFL" => 0x10,
+                    _ => 0x30,
+                };
+                0xD000 | reg(*dr, 9) | reg(*sr, 6) | direction | *amount as u16
+            }
+            ("TRAP", [Number(vector)]) => {
+                if !(0..=0xFF).contains(vector) {
+                    return Err(self
+                        .error(&format!("Trap vector {} out of range", vector))
+                        .at(self.column_of(&Number(*vector))));
+                }
+                0xF000 | *vector as u16
+            }
+            ("RTI", []) => 0x8000,
+            ("GETC", []) => 0xF020,
+            ("OUT", []) => 0xF021,
+            ("PUTS", []) => 0xF022,
+            ("IN", []) => 0xF023,
+            ("PUTSP", []) => 0xF024,
+            ("HALT", []) => 0xF025,
+            _ => {
+                return Err(self
+                    .error(&format!("Invalid operands for {}", mnemonic))
+                    .with_note(Some(format!("{} takes {}", mnemonic, usage(mnemonic)))))
+            }
+        };
+
+        Ok(word)
+    }
+
+    // Labels are turned into an offset from the incremented PC, which for
+    // the LC-3b counts words rather than bytes. Plain numbers are taken to
+    // already be offsets.
+    fn pc_offset(&mut self, target: &Operand, bits: u8) -> AsmResult<u16> {
+        let step = i32::from(self.isa.word_size());
+        let offset = match target {
+            Operand::Label(label) => match self.resolve(label, Some(bits))? {
+                Some(address) => (address as i32 - (self.address as i32 + step)) / step,
+                None => 0,
+            },
+            Operand::Number(offset) => *offset,
+            _ => {
+                return Err(self
+                    .error("Expected a label or offset")
+                    .at(self.column_of(target)))
+            }
+        };
+
+        self.signed(offset, bits)
+            .map_err(|diagnostic| diagnostic.at(self.column_of(target)))
+    }
+
+    fn signed(&self, value: i32, bits: u8) -> AsmResult<u16> {
+        let min = -(1 << (bits - 1));
+        let max = (1 << (bits - 1)) - 1;
+        if value < min || value > max {
+            return Err(self
+                .error(&format!(
+                    "Value {} does not fit in {} bits, range is {} to {}",
+                    value, bits, min, max
+                ))
+                .at(self.column_of(&Operand::Number(value))));
+        }
+
+        Ok((value as u16) & ((1 << bits) - 1))
+    }
+
+    // The address of a label, or None for an .EXTERN label, where the use is
+    // recorded for the linker
+    fn resolve(&mut self, label: &str, offset_bits: Option<u8>) -> AsmResult<Option<u16>> {
+        if let Some(address) = self.symbols.address_of(label) {
+            return Ok(Some(address));
+        }
+        let column = self.column_of(&Operand::Label(label.to_string()));
+        if !self.externs.contains(&label) {
+            let labels = self
+                .symbols
+                .iter()
+                .map(|(_, name)| name)
+                .chain(self.externs.iter().copied());
+            return Err(self
+                .error(&format!("Undefined label '{}'", label))
+                .at(column)
+                .with_note(did_you_mean(label, labels)));
+        }
+        if self.isa == Isa::Lc3b {
+            return Err(self
+                .error(&format!("'{}' is in another module", label))
+                .at(column)
+                .with_note(Some("LC-3b modules can't be linked".to_string())));
+        }
+        if !self.module {
+            return Err(self
+                .error(&format!("'{}' is in another module", label))
+                .at(column)
+                .with_note(Some(
+                    "it has to be assembled as a module and linked".to_string(),
+                )));
+        }
+
+        self.references.push(ExternalReference {
+            address: self.address,
+            label: label.to_string(),
+            offset_bits,
+        });
+        Ok(None)
+    }
+
+    // Where an operand starts on the line, for pointing at it
+    fn column_of(&self, operand: &Operand) -> Option<usize> {
+        let operands = match &self.line.statement {
+            Some(Statement::Instruction { operands, .. })
+            | Some(Statement::Directive { operands, .. }) => operands,
+            None => return None,
+        };
+        let index = operands.iter().position(|candidate| candidate == operand)?;
+        self.line.columns.operands.get(index).copied()
+    }
+
+    // Errors point at the mnemonic or directive unless there's something
+    // more specific
+    fn error(&self, message: &str) -> Diagnostic {
+        self.line
+            .error(message.to_string())
+            .at(Some(self.line.columns.statement))
+    }
+}
+
+fn reg(index: u8, shift: u8) -> u16 {
+    (index as u16) << shift
+}
@@ -0,0 +1,176 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::error::LC3Error;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    // The source can't be assembled
+    Error,
+    // The source assembles, but probably not into what was meant
+    Warning,
+}
+
+// A problem found while assembling, pointing at the token it's about where
+// there is one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    // The included file the problem's in, or None when it's in the source
+    // being assembled
+    pub file: Option<PathBuf>,
+    // 1-based
+    pub line: usize,
+    // 1-based, in characters
+    pub column: Option<usize>,
+    pub message: String,
+    // More help, like a suggestion for a misspelled label
+    pub note: Option<String>,
+}
+
+impl Diagnostic {
+    pub(crate) fn error(line: usize, message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            file: None,
+            line,
+            column: None,
+            message,
+            note: None,
+        }
+    }
+
+    pub(crate) fn warning(line: usize, message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(line, message)
+        }
+    }
+
+    pub(crate) fn at(mut self, column: Option<usize>) -> Self {
+        self.column = column;
+        self
+    }
+
+    pub(crate) fn in_file(mut self, file: Option<&Path>) -> Self {
+        self.file = file.map(Path::to_path_buf);
+        self
+    }
+
+    pub(crate) fn with_note(mut self, note: Option<String>) -> Self {
+        self.note = note;
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    // Compiler style, with the line of source and a marker under the column:
+    //
+    // prog.asm:3:12: error: Undefined label 'LOPP'
+    //         BRp LOPP
+    //             ^
+    // note: did you mean 'LOOP'?
+    //
+    // path and source are those of the file the diagnostic is in, so for one
+    // in an included file, file and what's in it.
+    pub fn render(&self, path: &str, source: &str) -> String {
+        let mut text = match self.column {
+            Some(column) => format!("{}:{}:{}: {}", path, self.line, column, self.headline()),
+            None => format!("{}:{}: {}", path, self.line, self.headline()),
+        };
+        if let Some(line) = source.lines().nth(self.line.wrapping_sub(1)) {
+            text.push_str(&format!("\n{}", line));
+            if let Some(column) = self.column {
+                // Tabs are kept so the marker lines up however wide they are
+                let indent: String = line
+                    .chars()
+                    .take(column - 1)
+                    .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+                    .collect();
+                text.push_str(&format!("\n{}^", indent));
+            }
+        }
+        if let Some(note) = &self.note {
+            text.push_str(&format!("\nnote: {}", note));
+        }
+        text
+    }
+
+    fn headline(&self) -> String {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        format!("{}: {}", severity, self.message)
+    }
+}
+
+// e.g. "line 3, column 12: error: Undefined label 'LOPP' (did you mean
+// 'LOOP'?)", with the file first if it's in an included one
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{} ", file.display())?;
+        }
+        write!(f, "line {}", self.line)?;
+        if let Some(column) = self.column {
+            write!(f, ", column {}", column)?;
+        }
+        write!(f, ": {}", self.headline())?;
+        if let Some(note) = &self.note {
+            write!(f, " ({})", note)?;
+        }
+        Ok(())
+    }
+}
+
+// For callers that only want the first error, as an LC3Error
+impl From<Diagnostic> for LC3Error {
+    fn from(diagnostic: Diagnostic) -> Self {
+        let mut message = match diagnostic.note {
+            Some(note) => format!("{} ({})", diagnostic.message, note),
+            None => diagnostic.message,
+        };
+        if let Some(file) = diagnostic.file {
+            message = format!("{}: {}", file.display(), message);
+        }
+        LC3Error::Assembly {
+            line: diagnostic.line,
+            message,
+        }
+    }
+}
+
+// A note suggesting whichever candidate is closest to name, as long as it's
+// close enough to be a typo. Case is ignored.
+pub(crate) fn did_you_mean<'a, I>(name: &str, candidates: I) -> Option<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let name = name.to_uppercase();
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(&name, &candidate.to_uppercase()), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| format!("did you mean '{}'?", candidate))
+}
+
+// Levenshtein distance: the fewest characters inserted, removed or swapped
+// for others to turn one string into the other
+fn edit_distance(from: &str, to: &str) -> usize {
+    let to: Vec<char> = to.chars().collect();
+    let mut previous: Vec<usize> = (0..=to.len()).collect();
+    for (i, from_ch) in from.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, to_ch) in to.iter().enumerate() {
+            let substitution = previous[j] + usize::from(from_ch != *to_ch);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[to.len()]
+}
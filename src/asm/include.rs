@@ -0,0 +1,168 @@
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use super::diagnostic::Diagnostic;
+use super::parser::{parse, Line, Operand, Statement};
+use crate::isa::Isa;
+
+// Splices the lines of every .INCLUDEd file in place of the .INCLUDE, as if
+// they'd been written there. Files are looked for next to the file doing
+// the including first, then in each of the include paths in order.
+pub(crate) struct Includer<'a> {
+    include_paths: &'a [PathBuf],
+    isa: Isa,
+    // The files being included, outermost first, to catch files that end up
+    // including themselves. Canonical paths alongside the ones to show.
+    stack: Vec<(PathBuf, PathBuf)>,
+}
+
+impl<'a> Includer<'a> {
+    // path is the file the source came from, if it came from one
+    pub(crate) fn new(include_paths: &'a [PathBuf], isa: Isa, path: Option<&Path>) -> Self {
+        let stack = path
+            .map(|path| (canonical(path), path.to_path_buf()))
+            .into_iter()
+            .collect();
+        Self {
+            include_paths,
+            isa,
+            stack,
+        }
+    }
+
+    pub(crate) fn expand(
+        &mut self,
+        lines: Vec<Line>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Vec<Line> {
+        let directory = match self.stack.last() {
+            Some((_, path)) => path.parent().unwrap_or_else(|| Path::new("")).to_path_buf(),
+            None => PathBuf::new(),
+        };
+
+        let mut expanded = Vec::with_capacity(lines.len());
+        for line in lines {
+            let name = match &line.statement {
+                Some(Statement::Directive { name, operands }) if name == ".INCLUDE" => {
+                    match operands.as_slice() {
+                        [Operand::Str(file)] => file.clone(),
+                        _ => {
+                            diagnostics.push(
+                                line.error(".INCLUDE expects a file name in quotes".to_string())
+                                    .at(Some(line.columns.statement)),
+                            );
+                            continue;
+                        }
+                    }
+                }
+                _ => {
+                    expanded.push(line);
+                    continue;
+                }
+            };
+
+            // A label on the .INCLUDE names whatever comes first in the file
+            if line.label.is_some() {
+                expanded.push(Line {
+                    statement: None,
+                    ..line.clone()
+                });
+            }
+            expanded.extend(self.include(&line, &name, &directory, diagnostics));
+        }
+        expanded
+    }
+
+    fn include(
+        &mut self,
+        line: &Line,
+        name: &str,
+        directory: &Path,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Vec<Line> {
+        let column = line.columns.operands.first().copied();
+        let directories: Vec<&Path> = std::iter::once(directory)
+            .chain(self.include_paths.iter().map(PathBuf::as_path))
+            .collect();
+        let path = match directories
+            .iter()
+            .map(|directory| directory.join(name))
+            .find(|path| path.is_file())
+        {
+            Some(path) => path,
+            None => {
+                let searched: Vec<String> = directories
+                    .iter()
+                    .map(|directory| match directory.to_string_lossy() {
+                        name if name.is_empty() => ".".to_string(),
+                        name => name.into_owned(),
+                    })
+                    .collect();
+                diagnostics.push(
+                    line.error(format!("Couldn't find '{}' to include", name))
+                        .at(column)
+                        .with_note(Some(format!("looked in {}", searched.join(", ")))),
+                );
+                return Vec::new();
+            }
+        };
+
+        let canonical = canonical(&path);
+        if self
+            .stack
+            .iter()
+            .any(|(included, _)| *included == canonical)
+        {
+            let chain: Vec<String> = self
+                .stack
+                .iter()
+                .map(|(_, shown)| shown.display().to_string())
+                .chain(std::iter::once(path.display().to_string()))
+                .collect();
+            diagnostics.push(
+                line.error(format!("'{}' is already being included", name))
+                    .at(column)
+                    .with_note(Some(chain.join(" includes "))),
+            );
+            return Vec::new();
+        }
+
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) => {
+                diagnostics.push(
+                    line.error(format!("Couldn't read '{}': {}", path.display(), err))
+                        .at(column),
+                );
+                return Vec::new();
+            }
+        };
+
+        let file: Rc<Path> = Rc::from(path.as_path());
+        let lines = match parse(&text, self.isa) {
+            Ok(lines) => lines,
+            Err(errors) => {
+                diagnostics.extend(errors.into_iter().map(|error| error.in_file(Some(&file))));
+                return Vec::new();
+            }
+        };
+        let lines = lines
+            .into_iter()
+            .map(|line| Line {
+                file: Some(Rc::clone(&file)),
+                ..line
+            })
+            .collect();
+
+        self.stack.push((canonical, path));
+        let expanded = self.expand(lines, diagnostics);
+        self.stack.pop();
+        expanded
+    }
+}
+
+// The path with any links and .. resolved, so the same file always looks the
+// same, or the path as it is if that can't be done
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
@@ -0,0 +1,91 @@
+use super::assembler::assemble_with_diagnostics;
+
+// Assemble LC-3 assembly written straight into Rust code, into the words of
+// the program. Statements are separated by semicolons, as newlines can't be
+// seen by a macro, and the program goes at x3000 unless it has an .ORIG.
+// It has to be made of Rust tokens, so comments are Rust comments. Assembly
+// that doesn't assemble panics with the errors, as a test would want.
+#[macro_export]
+macro_rules! lc3_program {
+    ($($tokens:tt)*) => {
+        $crate::asm::assemble_inline(stringify!($($tokens)*))
+    };
+}
+
+// What lc3_program! expands to
+#[doc(hidden)]
+pub fn assemble_inline(tokens: &str) -> Vec<u16> {
+    let source = inline_source(tokens);
+    let (assembly, diagnostics) = assemble_with_diagnostics(&source);
+    match assembly {
+        Some(assembly) => assembly.words,
+        None => {
+            let errors: Vec<String> = diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.is_error())
+                .map(|diagnostic| diagnostic.render("lc3_program!", &source))
+                .collect();
+            panic!("lc3_program! failed to assemble\n{}", errors.join("\n"));
+        }
+    }
+}
+
+// The macro's tokens as a source file. How Rust spaces the tokens out
+// depends on the compiler, so spaces it may have put inside an operand such
+// as "# - 1" or a directive such as ". FILL" are taken back out.
+pub(crate) fn inline_source(tokens: &str) -> String {
+    let mut source = String::new();
+    let mut chars = tokens.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' => {
+                source.push(ch);
+                while let Some(ch) = chars.next() {
+                    source.push(ch);
+                    if ch == '\\' {
+                        if let Some(escaped) = chars.next() {
+                            source.push(escaped);
+                        }
+                    } else if ch == '"' {
+                        break;
+                    }
+                }
+            }
+            ';' => {
+                source.truncate(source.trim_end().len());
+                source.push('\n');
+            }
+            '.' | '#' | '-' => {
+                source.push(ch);
+                while chars.peek().is_some_and(|ch| ch.is_whitespace()) {
+                    chars.next();
+                }
+            }
+            // Labels need a space after them to be told apart from what
+            // follows
+            ':' => {
+                source.truncate(source.trim_end().len());
+                source.push_str(": ");
+            }
+            ch if ch.is_whitespace() => {
+                if !(source.is_empty() || source.ends_with(' ') || source.ends_with('\n')) {
+                    source.push(' ');
+                }
+            }
+            _ => source.push(ch),
+        }
+    }
+    source.truncate(source.trim_end().len());
+
+    let has_origin = source.lines().any(|line| {
+        line.split_whitespace()
+            .take(2)
+            .any(|token| token.eq_ignore_ascii_case(".ORIG"))
+    });
+    if has_origin {
+        source
+    } else {
+        format!(".ORIG x3000\n{}", source)
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use super::assembler::{Assembly, ExternalReference};
+use super::diagnostic::did_you_mean;
+use crate::error::{BoxErrors, LC3Error, LC3Result};
+use crate::json::Json;
+use crate::symbols::SymbolTable;
+
+const FORMAT_VERSION: u64 = 1;
+
+// A source file assembled with Assembler::module, ready to be linked with
+// others. Each module keeps its own .ORIG, so linking fills in the uses of
+// other modules' labels rather than moving anything.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    // Where the module came from, for error messages
+    pub name: String,
+    pub assembly: Assembly,
+}
+
+impl Module {
+    pub fn new<S: Into<String>>(name: S, assembly: Assembly) -> Self {
+        Self {
+            name: name.into(),
+            assembly,
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> LC3Result<()> {
+        std::fs::write(path, self.to_json()).map_io_error()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> LC3Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_io_error()?;
+        Self::from_json(path.to_string_lossy(), &text)
+    }
+
+    // Everything the linker needs, without the line numbers:
+    //
+    // {"version":1,"origin":12288,"words":[...],"symbols":[["MAIN",12288]],
+    //  "globals":["MAIN"],
+    //  "references":[{"address":12289,"label":"PRINT","offset_bits":11}]}
+    pub fn to_json(&self) -> String {
+        let assembly = &self.assembly;
+        let words: Vec<Json> = assembly.words.iter().map(|word| (*word).into()).collect();
+        let symbols: Vec<Json> = assembly
+            .symbols
+            .iter()
+            .map(|(address, label)| vec![label.into(), address.into()].into())
+            .collect();
+        let globals: Vec<Json> = assembly
+            .globals
+            .iter()
+            .map(|label| label.as_str().into())
+            .collect();
+        let references: Vec<Json> = assembly
+            .references
+            .iter()
+            .map(|reference| {
+                Json::object(vec![
+                    ("address", reference.address.into()),
+                    ("label", reference.label.as_str().into()),
+                    ("offset_bits", reference.offset_bits.map(u16::from).into()),
+                ])
+            })
+            .collect();
+        Json::object(vec![
+            ("version", FORMAT_VERSION.into()),
+            ("origin", assembly.origin.into()),
+            ("words", words.into()),
+            ("symbols", symbols.into()),
+            ("globals", globals.into()),
+            ("references", references.into()),
+        ])
+        .to_string()
+    }
+
+    pub fn from_json<S: Into<String>>(name: S, text: &str) -> LC3Result<Self> {
+        let invalid = |what: &str| LC3Error::BadModule(format!("Invalid {}", what));
+        let json = Json::parse(text).map_err(LC3Error::BadModule)?;
+        let field = |name: &str| {
+            json.get(name)
+                .ok_or_else(|| LC3Error::BadModule(format!("Missing field '{}'", name)))
+        };
+        let array = |name: &str| field(name)?.as_array().ok_or_else(|| invalid(name));
+
+        if field("version")?.as_u64() != Some(FORMAT_VERSION) {
+            return Err(LC3Error::BadModule(
+                "Unsupported module version".to_string(),
+            ));
+        }
+
+        let origin = word(field("origin")?).ok_or_else(|| invalid("origin"))?;
+        let words = array("words")?
+            .iter()
+            .map(word)
+            .collect::<Option<Vec<u16>>>()
+            .ok_or_else(|| invalid("words"))?;
+
+        let mut symbols = SymbolTable::new();
+        for symbol in array("symbols")? {
+            match symbol.as_array().unwrap_or_default() {
+                [label, address] => match (label.as_str(), word(address)) {
+                    (Some(label), Some(address)) => symbols.insert(label, address),
+                    _ => return Err(invalid("symbols")),
+                },
+                _ => return Err(invalid("symbols")),
+            }
+        }
+
+        let globals = array("globals")?
+            .iter()
+            .map(|label| label.as_str().map(str::to_string))
+            .collect::<Option<Vec<String>>>()
+            .ok_or_else(|| invalid("globals"))?;
+
+        let references = array("references")?
+            .iter()
+            .map(|reference| {
+                let offset_bits = match reference.get("offset_bits")? {
+                    Json::Null => None,
+                    bits => Some(bits.as_u64().filter(|bits| (1..=16).contains(bits))? as u8),
+                };
+                Some(ExternalReference {
+                    address: word(reference.get("address")?)?,
+                    label: reference.get("label")?.as_str()?.to_string(),
+                    offset_bits,
+                })
+            })
+            .collect::<Option<Vec<ExternalReference>>>()
+            .ok_or_else(|| invalid("references"))?;
+
+        Ok(Self::new(
+            name,
+            Assembly {
+                origin,
+                words,
+                symbols,
+                line_addresses: BTreeMap::new(),
+                globals,
+                references,
+            },
+        ))
+    }
+
+    // The first and last addresses the module takes up, if it takes up any
+    fn span(&self) -> Option<(u16, u16)> {
+        let origin = self.assembly.origin;
+        match self.assembly.words.len() {
+            0 => None,
+            len => Some((origin, (origin as usize + len - 1) as u16)),
+        }
+    }
+}
+
+fn word(json: &Json) -> Option<u16> {
+    json.as_u64()
+        .filter(|word| *word <= u16::MAX as u64)
+        .map(|word| word as u16)
+}
+
+// Combine modules into a single program, filling in every use of an
+// .EXTERN label with where the module declaring it .GLOBAL put it. Gaps
+// between the modules are zeroes. The program's symbols are every module's
+// labels, though where two modules have a label of the same name only the
+// global one, or else the first, is kept.
+pub fn link(modules: &[Module]) -> LC3Result<Assembly> {
+    let error = |message: String| Err(LC3Error::Link(message));
+
+    let mut spans: Vec<(u16, u16, &str)> = modules
+        .iter()
+        .filter_map(|module| {
+            module
+                .span()
+                .map(|(start, end)| (start, end, module.name.as_str()))
+        })
+        .collect();
+    spans.sort_unstable();
+    for pair in spans.windows(2) {
+        let ((start, end, name), (next_start, next_end, next_name)) = (pair[0], pair[1]);
+        if next_start <= end {
+            return error(format!(
+                "{} (x{:04X} to x{:04X}) overlaps {} (x{:04X} to x{:04X})",
+                name, start, end, next_name, next_start, next_end
+            ));
+        }
+    }
+
+    let mut globals: BTreeMap<&str, (u16, &str)> = BTreeMap::new();
+    for module in modules {
+        for label in &module.assembly.globals {
+            let address = match module.assembly.symbols.address_of(label) {
+                Some(address) => address,
+                None => {
+                    return error(format!(
+                        "{} declares '{}' .GLOBAL but doesn't define it",
+                        module.name, label
+                    ))
+                }
+            };
+            if let Some((_, other)) = globals.insert(label, (address, &module.name)) {
+                return error(format!(
+                    "'{}' is defined by both {} and {}",
+                    label, other, module.name
+                ));
+            }
+        }
+    }
+
+    let origin = match spans.first() {
+        Some((start, _, _)) => *start,
+        None => modules.first().map_or(0, |module| module.assembly.origin),
+    };
+    let end = spans.iter().map(|(_, end, _)| *end as usize + 1).max();
+    let mut words = vec![0; end.map_or(0, |end| end - origin as usize)];
+    for module in modules.iter().filter(|module| module.span().is_some()) {
+        let start = (module.assembly.origin - origin) as usize;
+        words[start..start + module.assembly.words.len()].copy_from_slice(&module.assembly.words);
+    }
+
+    for module in modules {
+        for reference in &module.assembly.references {
+            let target = match globals.get(reference.label.as_str()) {
+                Some((address, _)) => *address,
+                None => {
+                    let note = did_you_mean(&reference.label, globals.keys().copied())
+                        .map(|note| format!(" ({})", note))
+                        .unwrap_or_default();
+                    return error(format!(
+                        "{} uses '{}', which no module declares .GLOBAL{}",
+                        module.name, reference.label, note
+                    ));
+                }
+            };
+            let word = &mut words[(reference.address - origin) as usize];
+            match reference.offset_bits {
+                None => *word = target,
+                Some(bits) => {
+                    let offset = target as i32 - (reference.address as i32 + 1);
+                    let min = -(1 << (bits - 1));
+                    let max = (1 << (bits - 1)) - 1;
+                    if offset < min || offset > max {
+                        return error(format!(
+                            "{} at x{:04X} can't reach '{}' at x{:04X} with a {} bit offset",
+                            module.name, reference.address, reference.label, target, bits
+                        ));
+                    }
+                    *word |= (offset as u16) & ((1 << bits) - 1);
+                }
+            }
+        }
+    }
+
+    let mut symbols = SymbolTable::new();
+    for (label, (address, _)) in &globals {
+        symbols.insert(label, *address);
+    }
+    for module in modules {
+        for (address, label) in module.assembly.symbols.iter() {
+            if symbols.address_of(label).is_none() {
+                symbols.insert(label, address);
+            }
+        }
+    }
+
+    Ok(Assembly {
+        origin,
+        words,
+        symbols,
+        line_addresses: BTreeMap::new(),
+        globals: Vec::new(),
+        references: Vec::new(),
+    })
+}
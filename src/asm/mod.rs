@@ -0,0 +1,16 @@
+mod assembler;
+mod diagnostic;
+mod include;
+mod inline;
+mod linker;
+mod parser;
+
+pub use assembler::{assemble, assemble_with_diagnostics, Assembler, Assembly, ExternalReference};
+pub use diagnostic::{Diagnostic, Severity};
+#[doc(hidden)]
+pub use inline::assemble_inline;
+pub use linker::{link, Module};
+pub(crate) use parser::{is_usable_label, parse_number};
+
+#[cfg(test)]
+mod test;
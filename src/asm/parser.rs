@@ -0,0 +1,356 @@
+use std::path::Path;
+use std::rc::Rc;
+
+use super::diagnostic::{did_you_mean, Diagnostic};
+use crate::isa::Isa;
+
+pub(crate) type AsmResult<T> = Result<T, Diagnostic>;
+
+const MNEMONICS: [&str; 23] = [
+    "ADD", "AND", "NOT", "BR", "JMP", "JSR", "JSRR", "LD", "LDI", "LDR", "LEA", "ST", "STI", "STR",
+    "TRAP", "RET", "RTI", "GETC", "OUT", "PUTS", "IN", "PUTSP", "HALT",
+];
+
+// Only instructions when assembling for the LC-3b, so LC-3 programs can
+// still use them as labels
+const LC3B_MNEMONICS: [&str; 8] = ["LDB", "STB", "LDW", "STW", "XOR", "LSHF", "RSHFL", "RSHFA"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Operand {
+    Register(u8),
+    Number(i32),
+    Label(String),
+    Str(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Statement {
+    // Mnemonics are upper cased. Branches keep their condition suffix,
+    // e.g. BRNZ.
+    Instruction {
+        mnemonic: String,
+        operands: Vec<Operand>,
+    },
+    // Directive names are upper cased and keep their leading dot
+    Directive {
+        name: String,
+        operands: Vec<Operand>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Line {
+    // 1-based, for error reporting
+    pub(crate) number: usize,
+    pub(crate) label: Option<String>,
+    pub(crate) statement: Option<Statement>,
+    pub(crate) columns: Columns,
+    // The file the line came from when it's been included, None for lines
+    // of the source being assembled
+    pub(crate) file: Option<Rc<Path>>,
+}
+
+impl Line {
+    pub(crate) fn error(&self, message: String) -> Diagnostic {
+        error(self.number, message).in_file(self.file.as_deref())
+    }
+
+    pub(crate) fn warning(&self, message: String) -> Diagnostic {
+        Diagnostic::warning(self.number, message).in_file(self.file.as_deref())
+    }
+}
+
+// Where each part of a line starts, 1-based, so diagnostics can point at it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Columns {
+    pub(crate) label: usize,
+    // The mnemonic or directive
+    pub(crate) statement: usize,
+    pub(crate) operands: Vec<usize>,
+}
+
+// Every line that parses, or a diagnostic for each one that doesn't
+pub(crate) fn parse(source: &str, isa: Isa) -> Result<Vec<Line>, Vec<Diagnostic>> {
+    let mut lines = Vec::new();
+    let mut diagnostics = Vec::new();
+    for (index, text) in source.lines().enumerate() {
+        match parse_line(index + 1, text, isa) {
+            Ok(line) => lines.push(line),
+            Err(diagnostic) => diagnostics.push(diagnostic),
+        }
+    }
+    if diagnostics.is_empty() {
+        Ok(lines)
+    } else {
+        Err(diagnostics)
+    }
+}
+
+fn parse_line(number: usize, text: &str, isa: Isa) -> AsmResult<Line> {
+    let mut tokens = tokenize(number, text)?.into_iter().peekable();
+    let mut columns = Columns::default();
+
+    let mut label = None;
+    if let Some((column, first)) = tokens.peek() {
+        if !first.starts_with('.') && !first.starts_with('"') && !is_mnemonic(first, isa) {
+            let name = first.trim_end_matches(':').to_string();
+            if !is_label(&name) {
+                return Err(error(number, format!("Invalid label '{}'", first)).at(Some(*column)));
+            }
+            columns.label = *column;
+            label = Some(name);
+            tokens.next();
+        }
+    }
+
+    let statement = match tokens.next() {
+        Some((column, head)) => {
+            columns.statement = column;
+            let (operand_columns, tokens): (Vec<usize>, Vec<String>) = tokens.unzip();
+            let operands = tokens
+                .iter()
+                .zip(&operand_columns)
+                .map(|(token, column)| {
+                    parse_operand(number, token).map_err(|err| err.at(Some(*column)))
+                })
+                .collect::<AsmResult<Vec<Operand>>>()?;
+            columns.operands = operand_columns;
+
+            if head.starts_with('.') {
+                Some(Statement::Directive {
+                    name: head.to_uppercase(),
+                    operands,
+                })
+            } else if is_mnemonic(&head, isa) {
+                Some(Statement::Instruction {
+                    mnemonic: head.to_uppercase(),
+                    operands,
+                })
+            } else {
+                // A misspelled instruction at the start of a line is taken
+                // for a label, in which case it's the label to complain about
+                if let Some(label) = &label {
+                    if let Some(note) = did_you_mean(label, mnemonics(isa)) {
+                        return Err(error(number, format!("Unknown instruction '{}'", label))
+                            .at(Some(columns.label))
+                            .with_note(Some(note)));
+                    }
+                }
+                return Err(error(number, format!("Unknown instruction '{}'", head))
+                    .at(Some(column))
+                    .with_note(did_you_mean(&head, mnemonics(isa))));
+            }
+        }
+        None => None,
+    };
+
+    Ok(Line {
+        number,
+        label,
+        statement,
+        columns,
+        file: None,
+    })
+}
+
+// Split a line into whitespace or comma separated tokens, dropping any
+// comment, each with the column it starts at. String literals are kept
+// whole, quotes included, with their escapes still in place.
+fn tokenize(number: usize, text: &str) -> AsmResult<Vec<(usize, String)>> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut start = 0;
+    let mut chars = text.chars().enumerate();
+
+    while let Some((index, ch)) = chars.next() {
+        if current.is_empty() {
+            start = index + 1;
+        }
+        match ch {
+            ';' => break,
+            '"' => {
+                current.push(ch);
+                let mut closed = false;
+                while let Some((_, ch)) = chars.next() {
+                    current.push(ch);
+                    if ch == '\\' {
+                        if let Some((_, escaped)) = chars.next() {
+                            current.push(escaped);
+                        }
+                    } else if ch == '"' {
+                        closed = true;
+                        break;
+                    }
+                }
+                if !closed {
+                    return Err(
+                        error(number, "Unterminated string".to_string()).at(Some(index + 1))
+                    );
+                }
+            }
+            ch if ch.is_whitespace() || ch == ',' => {
+                if !current.is_empty() {
+                    tokens.push((start, std::mem::take(&mut current)));
+                }
+            }
+            _ => current.push(ch),
+        }
+    }
+
+    if !current.is_empty() {
+        tokens.push((start, current));
+    }
+
+    Ok(tokens)
+}
+
+fn parse_operand(number: usize, token: &str) -> AsmResult<Operand> {
+    if token.starts_with('"') {
+        return parse_string(number, token).map(Operand::Str);
+    }
+
+    if let Some(register) = parse_register(token) {
+        return Ok(Operand::Register(register));
+    }
+
+    if let Some(value) = parse_number(token) {
+        return Ok(Operand::Number(value));
+    }
+
+    if is_label(token) {
+        return Ok(Operand::Label(token.to_string()));
+    }
+
+    Err(error(number, format!("Invalid operand '{}'", token)))
+}
+
+fn parse_register(token: &str) -> Option<u8> {
+    let mut chars = token.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('R'), Some(digit), None) | (Some('r'), Some(digit), None) => {
+            digit.to_digit(8).map(|index| index as u8)
+        }
+        _ => None,
+    }
+}
+
+// Numbers can be written as #decimal, xHEX, 0xHEX, bBINARY or plain
+// decimal, each with an optional minus sign.
+pub(crate) fn parse_number(token: &str) -> Option<i32> {
+    let (radix, digits) = if let Some(rest) = token.strip_prefix('#') {
+        (10, rest)
+    } else if let Some(rest) = token
+        .strip_prefix("0x")
+        .or_else(|| token.strip_prefix("0X"))
+    {
+        (16, rest)
+    } else if let Some(rest) = token.strip_prefix('x').or_else(|| token.strip_prefix('X')) {
+        (16, rest)
+    } else if let Some(rest) = token.strip_prefix('b').or_else(|| token.strip_prefix('B')) {
+        (2, rest)
+    } else {
+        (10, token)
+    };
+
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, digits),
+    };
+
+    if digits.is_empty() || !digits.chars().all(|ch| ch.is_digit(radix)) {
+        return None;
+    }
+
+    let value = i32::from_str_radix(digits, radix).ok()?;
+    Some(if negative { -value } else { value })
+}
+
+fn parse_string(number: usize, token: &str) -> AsmResult<String> {
+    let inner = &token[1..token.len() - 1];
+    let mut parsed = String::new();
+    let mut chars = inner.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            parsed.push(ch);
+            continue;
+        }
+
+        let escaped = match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('e') => '\x1B',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            other => {
+                return Err(error(
+                    number,
+                    format!("Unknown escape sequence '\\{}'", other.unwrap_or(' ')),
+                ))
+            }
+        };
+        parsed.push(escaped);
+    }
+
+    Ok(parsed)
+}
+
+fn mnemonics(isa: Isa) -> impl Iterator<Item = &'static str> {
+    let lc3b: &[&str] = match isa {
+        Isa::Lc3 => &[],
+        Isa::Lc3b => &LC3B_MNEMONICS,
+    };
+    MNEMONICS.iter().chain(lc3b).copied()
+}
+
+pub(crate) fn is_mnemonic(token: &str, isa: Isa) -> bool {
+    let upper = token.to_uppercase();
+    if mnemonics(isa).any(|mnemonic| mnemonic == upper) {
+        return true;
+    }
+
+    // Branches can carry any combination of n, z and p, in that order
+    match upper.strip_prefix("BR") {
+        Some(flags) => {
+            !flags.is_empty()
+                && flags.len() <= 3
+                && ["N", "Z", "P"]
+                    .iter()
+                    .filter(|flag| flags.contains(*flag))
+                    .count()
+                    == flags.len()
+                && is_ordered_flags(flags)
+        }
+        None => false,
+    }
+}
+
+fn is_ordered_flags(flags: &str) -> bool {
+    let positions: Vec<usize> = flags.chars().filter_map(|flag| "NZP".find(flag)).collect();
+    positions.windows(2).all(|pair| pair[0] < pair[1])
+}
+
+// Whether name works as a label wherever it's used, rather than being read
+// as an instruction, register or number
+pub(crate) fn is_usable_label(name: &str) -> bool {
+    is_label(name)
+        && !is_mnemonic(name, Isa::Lc3)
+        && parse_register(name).is_none()
+        && parse_number(name).is_none()
+}
+
+fn is_label(token: &str) -> bool {
+    let mut chars = token.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+        }
+        _ => false,
+    }
+}
+
+pub(crate) fn error(line: usize, message: String) -> Diagnostic {
+    Diagnostic::error(line, message)
+}
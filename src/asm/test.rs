@@ -0,0 +1,376 @@
+use super::inline::inline_source;
+use super::{assemble, assemble_with_diagnostics, link, Assembler, Module, Severity};
+use crate::error::{LC3Error, LC3Result};
+use crate::io::TestIOHandle;
+use crate::isa::Isa;
+use crate::vm::VM;
+
+#[test]
+fn can_assemble_hello_world() -> LC3Result<()> {
+    let source = r#"
+        .ORIG x3000
+        LEA R0, MSG     ; Load the address of the message
+        PUTS
+        HALT
+MSG     .STRINGZ "Hi"
+        .END
+    "#;
+
+    let assembly = assemble(source)?;
+    assert_eq!(assembly.origin, 0x3000);
+    assert_eq!(
+        assembly.words,
+        vec![
+            0b1110_0000_0000_0010,
+            0xF022,
+            0xF025,
+            'H' as u16,
+            'i' as u16,
+            0
+        ]
+    );
+    assert_eq!(assembly.symbols.address_of("MSG"), Some(0x3003));
+    assert_eq!(assembly.to_object()[0], 0x3000);
+    assert_eq!(
+        assembly.line_addresses.iter().collect::<Vec<_>>(),
+        vec![(&3, &0x3000), (&4, &0x3001), (&5, &0x3002), (&6, &0x3003)]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn can_assemble_every_op() -> LC3Result<()> {
+    let source = r#"
+        .ORIG x3000
+TOP     ADD R1, R2, R3
+        ADD R1, R2, #-1
+        AND R1, R2, R3
+        AND R1, R2, x0F
+        NOT R4, R5
+        BRnp TOP
+        BR TOP
+        JMP R2
+        RET
+        JSR TOP
+        JSRR R3
+        LD R1, DATA
+        LDI R1, DATA
+        LDR R1, R2, #-32
+        LEA R1, DATA
+        ST R1, DATA
+        STI R1, DATA
+        STR R1, R2, #31
+        TRAP x25
+        RTI
+DATA    .FILL xBEEF
+        .FILL TOP
+        .BLKW 2
+        .END
+    "#;
+
+    let assembly = assemble(source)?;
+    assert_eq!(
+        assembly.words,
+        vec![
+            0x1283, 0x12BF, 0x5283, 0x52AF, 0x997F, 0x0BFA, 0x0FF9, 0xC080, 0xC1C0, 0x4FF6, 0x40C0,
+            0x2208, 0xA207, 0x62A0, 0xE205, 0x3204, 0xB203, 0x729F, 0xF025, 0x8000, 0xBEEF, 0x3000,
+            0x0000, 0x0000,
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn can_assemble_for_the_lc3b() -> LC3Result<()> {
+    let source = r#"
+        .ORIG x3000
+        LEA R1, MSG
+        LDB R2, R1, #-1
+        STW R2, R1, #3
+        XOR R3, R2, #-2
+        RSHFL R3, R3, #15
+        BRz DONE
+DONE    HALT
+MSG     .STRINGZ "abc"
+        .END
+    "#;
+
+    let assembly = Assembler::new().isa(Isa::Lc3b).assemble(source, None)?;
+    assert_eq!(
+        assembly.words,
+        vec![0xE206, 0x247F, 0x7443, 0x96BE, 0xD6DF, 0x0400, 0xF025, 0x6261, 0x0063]
+    );
+    // Addresses count bytes
+    assert_eq!(assembly.symbols.address_of("DONE"), Some(0x300C));
+    assert_eq!(assembly.symbols.address_of("MSG"), Some(0x300E));
+
+    let test_cases = vec![
+        (".ORIG x3001\nHALT\n.END", "x3001 is odd"),
+        (
+            ".ORIG x3000\nLDR R0, R1, #0\n.END",
+            "LDR isn't an LC-3b instruction",
+        ),
+        (
+            ".ORIG x3000\nLSHF R0, R1, #16\n.END",
+            "Shift amount 16 out of range",
+        ),
+    ];
+    for (source, message) in test_cases {
+        let (assembly, diagnostics) = Assembler::new()
+            .isa(Isa::Lc3b)
+            .assemble_with_diagnostics(source, None);
+        assert!(assembly.is_none());
+        assert!(diagnostics[0].message.starts_with(message), "{}", source);
+    }
+    // The LC-3b's mnemonics are only mnemonics for the LC-3b
+    assert!(assemble(".ORIG x3000\nLDB R0, R1, #0\n.END").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn reports_errors_with_line_numbers() {
+    let test_cases = vec![
+        (".ORIG x3000\nLD R0, NOWHERE\n.END", 2),
+        (".ORIG x3000\nADD R0, R0, #16\n.END", 2),
+        (".ORIG x3000\nA .FILL 1\nA .FILL 2\n.END", 3),
+        (".ORIG x3000\n\nFOO R1\n.END", 3),
+        ("ADD R0, R0, R0", 1),
+    ];
+
+    for (source, expected_line) in test_cases {
+        match assemble(source) {
+            Err(LC3Error::Assembly { line, .. }) => assert_eq!(line, expected_line),
+            other => panic!("Expected an assembly error, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn reports_diagnostics_with_columns_and_suggestions() {
+    let source = "        .ORIG x3000
+LOOP    ADD R1, R1, #20
+        ADD R1, R1
+        BRp LOPP
+        HALT
+        .END
+        HALT";
+
+    let (assembly, diagnostics) = assemble_with_diagnostics(source);
+    assert_eq!(assembly, None);
+    let found: Vec<_> = diagnostics
+        .iter()
+        .map(|d| (d.severity, d.line, d.column, d.note.as_deref()))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            (Severity::Error, 2, Some(21), None),
+            (
+                Severity::Error,
+                3,
+                Some(9),
+                Some("ADD takes DR, SR1, SR2 or DR, SR1, imm5")
+            ),
+            (Severity::Error, 4, Some(13), Some("did you mean 'LOOP'?")),
+            (Severity::Warning, 7, Some(9), None),
+        ]
+    );
+    assert_eq!(
+        diagnostics[2].render("loop.asm", source),
+        "loop.asm:4:13: error: Undefined label 'LOPP'\n        BRp LOPP\n            ^\n\
+         note: did you mean 'LOOP'?"
+    );
+
+    // Misspelled instructions and directives get suggestions too
+    let (_, diagnostics) = assemble_with_diagnostics(".ORIG x3000\nADDD R1, R1, #1\n.END");
+    assert_eq!(diagnostics[0].column, Some(1));
+    assert_eq!(diagnostics[0].note.as_deref(), Some("did you mean 'ADD'?"));
+    let (_, diagnostics) = assemble_with_diagnostics(".ORIG x3000\n.STRNGZ \"a\"\n.END");
+    assert_eq!(
+        diagnostics[0].note.as_deref(),
+        Some("did you mean '.STRINGZ'?")
+    );
+
+    // Warnings alone don't stop the program assembling
+    let (assembly, diagnostics) = assemble_with_diagnostics(".ORIG x3000\n.END\nHALT");
+    assert!(assembly.is_some());
+    assert!(!diagnostics[0].is_error());
+    assert!(assemble(".ORIG x3000\n.END\nHALT").is_ok());
+}
+
+#[test]
+fn can_include_files() -> LC3Result<()> {
+    let dir = std::env::temp_dir().join(format!("lc3rs_{}_include", std::process::id()));
+    let lib = dir.join("lib");
+    std::fs::create_dir_all(&lib).unwrap();
+    let write = |name: &str, text: &str| std::fs::write(dir.join(name), text).unwrap();
+    write(
+        "lib/io.asm",
+        "PRINTLN PUTS\n        LD R0, NEWLINE\n        OUT\n        RET\nNEWLINE .FILL x0A",
+    );
+    write("defs.asm", ".INCLUDE \"io.asm\"");
+    write("loop.asm", ".INCLUDE \"loop.asm\"");
+    let main = dir.join("main.asm");
+    let source = "        .ORIG x3000
+        LEA R0, MSG
+        JSR PRINTLN
+        HALT
+MSG     .STRINGZ \"Hi\"
+        .INCLUDE \"defs.asm\"
+        .END";
+
+    let assembler = Assembler::new().include_path(&lib);
+    let result = assembler.assemble(source, Some(&main));
+    // Includes that go round in circles are caught
+    let (_, circular) = assembler
+        .assemble_with_diagnostics(".ORIG x3000\n.INCLUDE \"loop.asm\"\n.END", Some(&main));
+    // Without the include path, io.asm can't be found
+    let missing = Assembler::new().assemble(source, Some(&main));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let assembly = result?;
+    assert_eq!(assembly.symbols.address_of("PRINTLN"), Some(0x3006));
+    assert_eq!(assembly.symbols.address_of("NEWLINE"), Some(0x300A));
+    assert_eq!(
+        &assembly.words[6..],
+        [0xF022, 0x2002, 0xF021, 0xC1C0, 0x000A]
+    );
+    // Only lines of the source itself have addresses
+    assert_eq!(assembly.line_addresses.keys().last(), Some(&5));
+
+    assert_eq!(circular.len(), 1);
+    assert_eq!(circular[0].file, Some(dir.join("loop.asm")));
+    assert_eq!(circular[0].message, "'loop.asm' is already being included");
+
+    match missing {
+        Err(LC3Error::Assembly { line: 1, message }) => {
+            assert!(message.contains("Couldn't find 'io.asm' to include"))
+        }
+        other => panic!("Expected an assembly error, got {:?}", other),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn can_link_modules() -> LC3Result<()> {
+    let main = r#"
+        .ORIG x3000
+        .EXTERN PRINTLN, COUNT
+        JSR PRINTLN
+        LD R1, COUNTP
+        HALT
+COUNTP  .FILL COUNT
+        .END
+    "#;
+    let io = r#"
+        .ORIG x3100
+        .GLOBAL PRINTLN, COUNT
+PRINTLN PUTS
+        RET
+COUNT   .FILL 7
+        .END
+    "#;
+    let module = |name: &str, source: &str| -> LC3Result<Module> {
+        let assembly = Assembler::new().module().assemble(source, None)?;
+        Ok(Module::new(name, assembly))
+    };
+    let main = module("main.asm", main)?;
+    let io = module("io.asm", io)?;
+    assert_eq!(main.assembly.references.len(), 2);
+    assert_eq!(io.assembly.globals, vec!["PRINTLN", "COUNT"]);
+
+    // Modules survive being written out for later, apart from line numbers
+    let mut saved = io.clone();
+    saved.assembly.line_addresses.clear();
+    assert_eq!(Module::from_json("io.asm", &io.to_json())?, saved);
+
+    let program = link(&[main.clone(), io.clone()])?;
+    assert_eq!(program.origin, 0x3000);
+    assert_eq!(program.words.len(), 0x103);
+    // JSR x3100, and .FILL x3102 for COUNT
+    assert_eq!(&program.words[..4], [0x48FF, 0x2201, 0xF025, 0x3102]);
+    assert_eq!(&program.words[0x100..], [0xF022, 0xC1C0, 0x0007]);
+    assert_eq!(program.symbols.address_of("COUNT"), Some(0x3102));
+    assert_eq!(program.symbols.address_of("COUNTP"), Some(0x3003));
+
+    // Whatever can't be put together is explained
+    let errors = vec![
+        (
+            vec![main.clone()],
+            "main.asm uses 'PRINTLN', which no module declares .GLOBAL",
+        ),
+        (
+            vec![main.clone(), io.clone(), io.clone()],
+            "io.asm (x3100 to x3102) overlaps io.asm (x3100 to x3102)",
+        ),
+    ];
+    for (modules, expected) in errors {
+        match link(&modules) {
+            Err(LC3Error::Link(message)) => assert_eq!(message, expected),
+            other => panic!("Expected a link error, got {:?}", other),
+        }
+    }
+    let far = module("far.asm", ".ORIG x5000\n.GLOBAL PRINTLN\nPRINTLN RET\n.END")?;
+    assert!(link(&[main, far]).is_err());
+
+    // External labels need assembling as a module
+    assert!(assemble(".ORIG x3000\n.EXTERN FAR\nBR FAR\n.END").is_err());
+    assert!(assemble(".ORIG x3000\n.GLOBAL NOWHERE\n.END").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn can_assemble_inline_programs() -> LC3Result<()> {
+    let program = crate::lc3_program! {
+        LEA R0, msg;
+        PUTS;
+        // Count down from 2
+        LD R1, count;
+        loop: ADD R1, R1, #-1;
+        BRp loop;
+        HALT;
+        msg: .STRINGZ "hi; \"there\"\n";
+        count: .FILL #2
+    };
+    let source = r#"
+        .ORIG x3000
+        LEA R0, MSG
+        PUTS
+        LD R1, COUNT
+LOOP    ADD R1, R1, #-1
+        BRp LOOP
+        HALT
+MSG     .STRINGZ "hi; \"there\"\n"
+COUNT   .FILL #2
+        .END
+    "#;
+    assert_eq!(program, assemble(source)?.words);
+
+    let mut vm = VM::new_with_io(TestIOHandle::new());
+    vm.load_program(&program)?;
+    vm.run()?;
+    assert_eq!(vm.into_io_handle().output(), "hi; \"there\"\n");
+
+    // Rust may space the tokens out differently, but it's the same program
+    assert_eq!(
+        inline_source("lbl : ADD R0 , R0 , # - 1 ; BR lbl ; . FILL x0F"),
+        ".ORIG x3000\nlbl: ADD R0 , R0 , #-1\nBR lbl\n.FILL x0F"
+    );
+    assert_eq!(
+        crate::lc3_program! { .ORIG x4000; ADD R0, R0, #1; .END },
+        vec![0x1021]
+    );
+
+    Ok(())
+}
+
+#[test]
+#[should_panic(expected = "Undefined label 'msg'")]
+fn can_reject_inline_programs() {
+    crate::lc3_program! { LEA R0, msg; HALT };
+}
@@ -0,0 +1,170 @@
+use crate::error::{LC3Error, LC3Result};
+use crate::memory::{Memory, MemoryFill, MemoryMap};
+
+// Words in each bank, and so in the window they're switched into
+pub(crate) const BANK_SIZE: usize = 0x4000;
+
+// Bank switched memory, for programs bigger than the address space. Writing
+// a bank number to the bank select register swaps that bank into the window,
+// the 16K words from window up, and reading it says which bank is there.
+// Bank 0 is in the window to start with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BankedMemory {
+    // Where the window starts, on a 16K boundary such as x4000
+    pub window: u16,
+    // How many banks there are. Selecting one past the last wraps around.
+    pub banks: usize,
+}
+
+impl BankedMemory {
+    pub(crate) fn check(&self, map: &MemoryMap) -> LC3Result<()> {
+        let invalid = |message: String| Err(LC3Error::BadBanks(message));
+        if !(self.window as usize).is_multiple_of(BANK_SIZE) {
+            return invalid(format!(
+                "The window has to start on a 16K boundary, not at x{:04X}",
+                self.window
+            ));
+        }
+        let window = self.window as usize..self.window as usize + BANK_SIZE;
+        let in_ram = map
+            .ram()
+            .iter()
+            .any(|ram| ram.start <= window.start && window.end <= ram.end);
+        if !in_ram {
+            return invalid(format!(
+                "The window at x{:04X} has to be in memory and clear of the device registers",
+                self.window
+            ));
+        }
+        if self.banks == 0 || self.banks > u16::MAX as usize + 1 {
+            return invalid(format!(
+                "There can be from 1 to 65536 banks, not {}",
+                self.banks
+            ));
+        }
+        Ok(())
+    }
+}
+
+// The banks themselves. The one in the window lives in memory, so
+// everything else sees it as ordinary memory, and its copy here is only
+// brought up to date when it's switched out.
+pub(crate) struct Banks {
+    pub(crate) config: BankedMemory,
+    stored: Vec<Box<[u16]>>,
+    pub(crate) selected: usize,
+    // Everything written by load_bank as (bank, words) pairs, kept so reset
+    // can put them back
+    loaded: Vec<(usize, Vec<u16>)>,
+}
+
+impl Banks {
+    // Bank 0 is whatever's in the window already
+    pub(crate) fn new(config: BankedMemory, fill: MemoryFill, memory: &Memory) -> Self {
+        let mut banks = Self {
+            config,
+            stored: Vec::new(),
+            selected: 0,
+            loaded: Vec::new(),
+        };
+        banks.fill(fill);
+        banks.save(memory);
+        banks
+    }
+
+    pub(crate) fn window(&self) -> std::ops::Range<usize> {
+        let start = self.config.window as usize;
+        start..start + BANK_SIZE
+    }
+
+    fn fill(&mut self, fill: MemoryFill) {
+        let mut words = vec![0; self.config.banks * BANK_SIZE];
+        fill.fill(&mut words);
+        self.stored = words
+            .chunks(BANK_SIZE)
+            .map(|bank| bank.to_vec().into_boxed_slice())
+            .collect();
+    }
+
+    // Swap bank into the window, wrapping it round if it's past the last
+    // one, and say which bank that ended up being
+    pub(crate) fn select(&mut self, bank: usize, memory: &mut Memory) -> usize {
+        let bank = bank % self.config.banks;
+        if bank != self.selected {
+            let window = memory.range_mut(self.window());
+            self.stored[self.selected].copy_from_slice(window);
+            window.copy_from_slice(&self.stored[bank]);
+            self.selected = bank;
+        }
+        bank
+    }
+
+    // Bring the selected bank's copy up to date with the window
+    pub(crate) fn save(&mut self, memory: &Memory) {
+        let window = self.window();
+        self.stored[self.selected].copy_from_slice(&memory[window]);
+    }
+
+    pub(crate) fn read(&self, bank: usize, memory: &Memory) -> LC3Result<Vec<u16>> {
+        self.check_bank(bank)?;
+        if bank == self.selected {
+            Ok(memory[self.window()].to_vec())
+        } else {
+            Ok(self.stored[bank].to_vec())
+        }
+    }
+
+    // Write words to the start of bank, keeping them for reset
+    pub(crate) fn load(
+        &mut self,
+        bank: usize,
+        words: &[u16],
+        memory: &mut Memory,
+    ) -> LC3Result<()> {
+        self.check_bank(bank)?;
+        if words.len() > BANK_SIZE {
+            return Err(LC3Error::ProgramSize {
+                len: words.len(),
+                max_len: BANK_SIZE,
+            });
+        }
+        self.write(bank, words, memory);
+        self.loaded.retain(|(loaded, _)| *loaded != bank);
+        self.loaded.push((bank, words.to_vec()));
+        Ok(())
+    }
+
+    // Back to bank 0 in the window with every bank filled and what was
+    // loaded into them put back. The window itself has already been filled
+    // by the memory reset.
+    pub(crate) fn reset(&mut self, fill: MemoryFill, memory: &mut Memory) {
+        self.fill(fill);
+        self.selected = 0;
+        self.save(memory);
+        for (bank, words) in std::mem::take(&mut self.loaded) {
+            self.write(bank, &words, memory);
+            self.loaded.push((bank, words));
+        }
+    }
+
+    fn write(&mut self, bank: usize, words: &[u16], memory: &mut Memory) {
+        if bank == self.selected {
+            let start = self.window().start;
+            memory
+                .range_mut(start..start + words.len())
+                .copy_from_slice(words);
+        } else {
+            self.stored[bank][..words.len()].copy_from_slice(words);
+        }
+    }
+
+    fn check_bank(&self, bank: usize) -> LC3Result<()> {
+        if bank >= self.config.banks {
+            return Err(LC3Error::NoSuchBank {
+                bank,
+                banks: self.config.banks,
+            });
+        }
+        Ok(())
+    }
+}
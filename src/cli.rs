@@ -1,29 +1,838 @@
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
 use structopt::StructOpt;
 
-use crate::error::{BoxErrors, PublicResult};
+use crate::asm::Assembler;
+use crate::config::Config;
+use crate::error::{BoxErrors, LC3Error, LC3Result, PublicResult};
+use crate::io::{CharEncoding, EchoMode, NewlinePolicy, PackedByteOrder};
+use crate::isa::SpecProfile;
+use crate::memory::MemoryFill;
+use crate::plugin::builtin::ExitCodeSource;
+use crate::symbols::SymbolTable;
+use crate::vm::{KB_STATUS_POS, PC_START};
+
+// Stands for stdin or stdout in place of a path
+pub const STDIO_PATH: &str = "-";
+
+// What asm --module writes, for link to read
+pub const MODULE_EXTENSION: &str = "lobj";
 
+// Only ever parsed once, so the size of the run options doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(StructOpt)]
 #[structopt(rename_all = "kebab-case")]
-pub struct Options {
-    pub path: String,
+pub enum Options {
+    #[structopt(about = "Run an LC-3 program")]
+    Run(RunOptions),
+    // Takes the same options as run, with source files in place of the
+    // programs
+    #[structopt(about = "Re-assemble and re-run LC-3 source files whenever they change")]
+    Watch(RunOptions),
+    #[structopt(about = "Time an LC-3 program with IO stubbed out and report its speed")]
+    Bench(BenchOptions),
+    #[structopt(about = "Assemble an LC-3 source file into an object file")]
+    Asm(AsmOptions),
+    #[structopt(about = "Link LC-3 modules into a single object file")]
+    Link(LinkOptions),
+    #[structopt(about = "Disassemble an LC-3 program")]
+    Disasm(DisasmOptions),
+    #[structopt(about = "Step through an LC-3 program in the interactive debugger")]
+    Debug(DebugOptions),
+    #[structopt(about = "Look around a core dump written by run --core")]
+    Inspect(InspectOptions),
+    #[structopt(about = "Serve the Debug Adapter Protocol over stdin and stdout for editors")]
+    Dap,
+}
+
+#[derive(StructOpt, Clone)]
+#[structopt(rename_all = "kebab-case")]
+pub struct RunOptions {
+    // The programs to run, each loaded at its own origin and run from the
+    // first one's. - reads an object file from stdin.
+    #[structopt(required = true)]
+    pub paths: Vec<String>,
+    // Load a file somewhere other than its origin, as PATH=ADDRESS with the
+    // address in hex such as x4000. Can be given for each file.
+    #[structopt(long, number_of_values = 1)]
+    pub at: Vec<LoadAt>,
     #[structopt(long, short)]
     pub debug_log_path: Option<String>,
+    #[structopt(flatten)]
+    pub load: LoadOptions,
+    // Print a hot spot report to stderr once the program halts
+    #[structopt(long)]
+    pub profile: bool,
+    #[structopt(long, overrides_with = "profile")]
+    pub no_profile: bool,
+    // Write a coverage report here once the program halts. Paths ending in
+    // .json get the JSON report, anything else an annotated listing.
+    #[structopt(long)]
+    pub coverage: Option<String>,
+    // Write read, write and fetch counts per address here once the program
+    // halts, as JSON for paths ending in .json and CSV otherwise
+    #[structopt(long)]
+    pub heatmap: Option<String>,
+    // Report misuse of R6 as a stack pointer to stderr
+    #[structopt(long)]
+    pub check_stack: bool,
+    #[structopt(long, overrides_with = "check-stack")]
+    pub no_check_stack: bool,
+    // Load the bundled LC-3 operating system and run traps through it
+    #[structopt(long)]
+    pub os: bool,
+    #[structopt(long, overrides_with = "os")]
+    pub no_os: bool,
+    // Load this object file as the operating system instead of the bundled
+    // one. It needs its own trap and interrupt vector tables.
+    #[structopt(long)]
+    pub os_image: Option<String>,
+    // Which simulator's rules to follow where revisions of the LC-3
+    // disagree: patt-2nd-ed, the default, where LEA sets the condition
+    // codes, or patt-3rd-ed or lc3tools, where it doesn't and TRAP saves
+    // the PSR on the supervisor stack
+    #[structopt(long)]
+    pub spec: Option<SpecProfile>,
+    // Fill memory before loading anything, so reads of memory the program
+    // never wrote stand out: with a word in hex such as xDEAD, random for
+    // random words, random:SEED for different ones, or zero, the default
+    #[structopt(long)]
+    pub fill: Option<MemoryFill>,
+    // Let the program use files in this directory through the file traps,
+    // x26 to x29
+    #[structopt(long)]
+    pub file_root: Option<String>,
+    // Seed for the RNG device register at xFE08, to get the same numbers
+    // every run
+    #[structopt(long)]
+    pub seed: Option<u64>,
+    // Have the clock registers at xFE0E and xFE10 count instructions
+    // rather than real milliseconds
+    #[structopt(long)]
+    pub deterministic_clock: bool,
+    #[structopt(long, overrides_with = "deterministic-clock")]
+    pub no_deterministic_clock: bool,
+    // Wait for a client to connect to this address, such as 0.0.0.0:2323,
+    // and use the connection as the console instead of the terminal
+    #[structopt(long)]
+    pub listen: Option<String>,
+    // Whether GETC and IN echo what they read: standard, the default,
+    // where only IN does, always or never
+    #[structopt(long)]
+    pub echo: Option<EchoMode>,
+    // Line ending translation: raw, the default, or crlf to write '\n' as
+    // "\r\n" and lf to read '\r' and "\r\n" as '\n', separated by a
+    // comma for both
+    #[structopt(long)]
+    pub newlines: Option<NewlinePolicy>,
+    // What happens to characters outside ASCII: latin1, the default, to pass
+    // bytes from x80 up through as Latin-1, replace to swap them for a
+    // placeholder, or strict to stop with an error
+    #[structopt(long)]
+    pub encoding: Option<CharEncoding>,
+    // Which byte of each word PUTSP writes first, low-first as the spec
+    // says, the default, or high-first
+    #[structopt(long)]
+    pub packed_byte_order: Option<PackedByteOrder>,
+    // Write control characters such as the bell, backspace and tab to the
+    // terminal as they are, rather than carrying them out
+    #[structopt(long)]
+    pub raw_controls: bool,
+    #[structopt(long, overrides_with = "raw-controls")]
+    pub no_raw_controls: bool,
+    // Stop the program if it executes this many instructions without
+    // halting
+    #[structopt(long)]
+    pub max_instructions: Option<u64>,
+    // Stop the program if it runs for longer than this many seconds
+    // without halting
+    #[structopt(long)]
+    pub timeout: Option<f64>,
+    // Write a line per executed instruction to stderr, or to the file given
+    // as --trace=FILE, as JSON lines for paths ending in .jsonl
+    #[structopt(long, require_equals = true)]
+    pub trace: Option<Option<String>>,
+    // Type the contents of this file as the program's keyboard input, or
+    // whatever's piped in for -, rather than reading the terminal. Reading
+    // past the end is an error.
+    #[structopt(long, conflicts_with = "listen")]
+    pub stdin_file: Option<String>,
+    // Save everything the program prints to this file as well as showing
+    // it, even if the program stops with an error
+    #[structopt(long)]
+    pub transcript: Option<String>,
+    // Don't show what the program prints, for use with --transcript
+    #[structopt(long, short)]
+    pub quiet: bool,
+    #[structopt(long, overrides_with = "quiet")]
+    pub no_quiet: bool,
+    // Label traces and the profile from this symbol table, rather than the
+    // .sym files next to the programs
+    #[structopt(long)]
+    pub symbols: Option<String>,
+    // Exit with this register, such as R0, or the word at this address once
+    // the program halts, so scripts can tell whether it passed. Values
+    // above 255 exit with 255.
+    #[structopt(long)]
+    pub exit_code_from: Option<ExitCodeSource>,
+    // Write the registers and memory here if the program fails, for lc3rs
+    // inspect to look at
+    #[structopt(long)]
+    pub core: Option<String>,
+    // Only keep this many words of memory either side of the failed
+    // instruction in the core dump
+    #[structopt(long)]
+    pub core_window: Option<u16>,
+    // Default options from this file rather than lc3rs.toml
+    #[structopt(long)]
+    pub config: Option<String>,
+}
+
+impl RunOptions {
+    // Fills in whatever wasn't given on the command line from the [run]
+    // table
+    pub fn apply_config(&mut self, config: &Config) -> LC3Result<()> {
+        for value in config.values("run") {
+            match value.key {
+                "little-endian" => set_flag(
+                    &mut self.load.little_endian,
+                    self.load.no_little_endian,
+                    value.bool()?,
+                ),
+                "endian" => set(&mut self.load.endian, value.parsed()?),
+                "format" => set(&mut self.load.format, value.parsed()?),
+                "debug-log-path" => set(&mut self.debug_log_path, value.string()?),
+                "profile" => set_flag(&mut self.profile, self.no_profile, value.bool()?),
+                "coverage" => set(&mut self.coverage, value.string()?),
+                "heatmap" => set(&mut self.heatmap, value.string()?),
+                "check-stack" => {
+                    set_flag(&mut self.check_stack, self.no_check_stack, value.bool()?)
+                }
+                "os" => set_flag(&mut self.os, self.no_os, value.bool()?),
+                "os-image" => set(&mut self.os_image, value.string()?),
+                "spec" => set(&mut self.spec, value.parsed()?),
+                "fill" => match value.integer() {
+                    Ok(word) => set(&mut self.fill, MemoryFill::Word(word)),
+                    Err(_) => set(&mut self.fill, value.parsed()?),
+                },
+                "file-root" => set(&mut self.file_root, value.string()?),
+                "seed" => set(&mut self.seed, value.integer()?),
+                "deterministic-clock" => set_flag(
+                    &mut self.deterministic_clock,
+                    self.no_deterministic_clock,
+                    value.bool()?,
+                ),
+                "listen" => set(&mut self.listen, value.string()?),
+                "echo" => set(&mut self.echo, value.parsed()?),
+                "newlines" => set(&mut self.newlines, value.parsed()?),
+                "encoding" => set(&mut self.encoding, value.parsed()?),
+                "packed-byte-order" => set(&mut self.packed_byte_order, value.parsed()?),
+                "raw-controls" => {
+                    set_flag(&mut self.raw_controls, self.no_raw_controls, value.bool()?)
+                }
+                "max-instructions" => set(&mut self.max_instructions, value.integer()?),
+                "timeout" => set(&mut self.timeout, value.float()?),
+                // true for stderr, or the path to write to
+                "trace" => match value.bool() {
+                    Ok(true) => set(&mut self.trace, None),
+                    Ok(false) => {}
+                    Err(_) => set(&mut self.trace, Some(value.string()?)),
+                },
+                "stdin-file" => set(&mut self.stdin_file, value.string()?),
+                "transcript" => set(&mut self.transcript, value.string()?),
+                "quiet" => set_flag(&mut self.quiet, self.no_quiet, value.bool()?),
+                "symbols" => set(&mut self.symbols, value.string()?),
+                "exit-code-from" => set(&mut self.exit_code_from, value.parsed()?),
+                "core" => set(&mut self.core, value.string()?),
+                "core-window" => set(&mut self.core_window, value.integer()?),
+                _ => return Err(value.unknown("run")),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub struct AsmOptions {
+    pub path: String,
+    // Defaults to the source path with a .obj extension. The symbol table
+    // is written next to it with a .sym extension, unless the output is -
+    // for stdout.
     #[structopt(short, long)]
-    pub little_endian: bool, 
+    pub output: Option<String>,
+    // Where to look for .INCLUDEd files that aren't next to the file
+    // including them, in order
+    #[structopt(short = "I", long = "include-path", number_of_values = 1)]
+    pub include_paths: Vec<String>,
+    // Write a module for link, which can use .EXTERN labels, rather than an
+    // object file. Defaults to the source path with a .lobj extension.
+    #[structopt(long)]
+    pub module: bool,
+}
+
+#[derive(StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub struct LinkOptions {
+    // Sources, which are assembled as modules, or modules from asm --module
+    #[structopt(required = true)]
+    pub paths: Vec<String>,
+    // Defaults to the first path with a .obj extension. The symbol table is
+    // written next to it, as for asm.
+    #[structopt(short, long)]
+    pub output: Option<String>,
+    #[structopt(short = "I", long = "include-path", number_of_values = 1)]
+    pub include_paths: Vec<String>,
+}
+
+#[derive(StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub struct BenchOptions {
+    pub path: String,
+    #[structopt(flatten)]
+    pub load: LoadOptions,
+    // Load the bundled LC-3 operating system and run traps through it
+    #[structopt(long)]
+    pub os: bool,
+    // How many times to run the program. The fastest run is reported, as
+    // the others were slowed down by something else.
+    #[structopt(long, default_value = "5")]
+    pub runs: u32,
+    // Give up on a run after this many instructions
+    #[structopt(long)]
+    pub max_instructions: Option<u64>,
+}
+
+#[derive(StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub struct DisasmOptions {
+    pub path: String,
+    #[structopt(flatten)]
+    pub load: LoadOptions,
+    // Write assembler source, with labels for branch and load targets,
+    // rather than a listing of addresses and words. Assembling it gives back
+    // the same program.
+    #[structopt(long)]
+    pub source: bool,
+    // Labels to use for --source, from this symbol table rather than the
+    // .sym file next to the program
+    #[structopt(long)]
+    pub symbols: Option<String>,
+}
+
+#[derive(StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub struct DebugOptions {
+    pub path: String,
+    #[structopt(flatten)]
+    pub load: LoadOptions,
+    // Full screen interface instead of the command prompt
+    #[structopt(short, long)]
+    pub tui: bool,
+    #[structopt(long, overrides_with = "tui")]
+    pub no_tui: bool,
+    // Labels to show and accept for addresses, from this symbol table
+    // rather than the .sym file next to the program
+    #[structopt(long)]
+    pub symbols: Option<String>,
+    // Start with a breakpoint here, written as for the break command, such
+    // as LOOP or "LOOP if R1 == 10". Can be given more than once.
+    #[structopt(long = "break", number_of_values = 1)]
+    pub breakpoints: Vec<String>,
+    // Default options from this file rather than lc3rs.toml
+    #[structopt(long)]
+    pub config: Option<String>,
+}
+
+#[derive(StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub struct InspectOptions {
+    pub path: String,
+    // Labels for the addresses in the dump
+    #[structopt(long)]
+    pub symbols: Option<String>,
+}
+
+impl DebugOptions {
+    // Fills in whatever wasn't given on the command line from the [debug]
+    // table. Breakpoints from both are set.
+    pub fn apply_config(&mut self, config: &Config) -> LC3Result<()> {
+        for value in config.values("debug") {
+            match value.key {
+                "little-endian" => set_flag(
+                    &mut self.load.little_endian,
+                    self.load.no_little_endian,
+                    value.bool()?,
+                ),
+                "endian" => set(&mut self.load.endian, value.parsed()?),
+                "format" => set(&mut self.load.format, value.parsed()?),
+                "tui" => set_flag(&mut self.tui, self.no_tui, value.bool()?),
+                "symbols" => set(&mut self.symbols, value.string()?),
+                "break" => self.breakpoints.extend(value.strings()?),
+                _ => return Err(value.unknown("debug")),
+            }
+        }
+        Ok(())
+    }
+}
+
+// Options from the command line take priority over configuration files
+fn set<T>(option: &mut Option<T>, value: T) {
+    if option.is_none() {
+        *option = Some(value);
+    }
+}
+
+// The same for flags, which the command line turns off with their --no-
+// form
+fn set_flag(flag: &mut bool, unset: bool, value: bool) {
+    if !*flag && !unset {
+        *flag = value;
+    }
+}
+
+// How program files are read, for the commands that read them
+#[derive(StructOpt, Clone, Default)]
+#[structopt(rename_all = "kebab-case")]
+pub struct LoadOptions {
+    // Same as --endian little
+    #[structopt(short, long, conflicts_with = "endian")]
+    pub little_endian: bool,
+    #[structopt(long, overrides_with = "little-endian")]
+    pub no_little_endian: bool,
+    // The byte order of words in object and raw files, big or little.
+    // Defaults to big, as the LC-3 tools write them.
+    #[structopt(long)]
+    pub endian: Option<Endian>,
+    // obj, raw, hex, bin or asm. Defaults to obj for .obj files, hex for
+    // .hex files, asm for .asm files and raw for anything else.
+    #[structopt(long)]
+    pub format: Option<ProgramFormat>,
+}
+
+impl LoadOptions {
+    pub fn endian(&self) -> Endian {
+        match self.endian {
+            Some(endian) => endian,
+            None if self.little_endian => Endian::Little,
+            None => Endian::Big,
+        }
+    }
+
+    pub fn read_program(&self, path: &str) -> LC3Result<LoadedProgram> {
+        read_program(path, self.format, self.endian())
+    }
+}
+
+// Where --at says to load one of the files
+#[derive(Clone)]
+pub struct LoadAt {
+    pub path: String,
+    pub origin: u16,
+}
+
+impl FromStr for LoadAt {
+    type Err = LC3Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            LC3Error::Other(format!(
+                "Expected PATH=ADDRESS such as prog.obj=x4000, not {}",
+                text
+            ))
+        };
+        let (path, address) = text.rsplit_once('=').ok_or_else(invalid)?;
+        let origin = parse_address(address).ok_or_else(invalid)?;
+        Ok(Self {
+            path: path.to_string(),
+            origin,
+        })
+    }
+}
+
+// Hex, with or without an x or 0x in front
+fn parse_address(text: &str) -> Option<u16> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix('x'))
+        .or_else(|| text.strip_prefix('X'))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+// The order of the two bytes in each word of a binary program file
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Endian {
+    // High byte first, as the LC-3 tools write them
+    #[default]
+    Big,
+    Little,
 }
 
-pub fn read_program(path: &String, little_endian: bool) -> PublicResult<Vec<u16>> {
-    let bytes = std::fs::read(path).box_error()?;
+impl FromStr for Endian {
+    type Err = LC3Error;
 
-    let mut commands: Vec<u16> = bytes
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "big" => Ok(Self::Big),
+            "little" => Ok(Self::Little),
+            _ => Err(LC3Error::Other(format!(
+                "Unknown byte order {}, expected big or little",
+                text
+            ))),
+        }
+    }
+}
+
+// How a program file is laid out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProgramFormat {
+    // Starts with the origin word, as produced by lc3as and friends
+    Object,
+    // Just the words, to be loaded at the default start address
+    Raw,
+    // Text with a word in hex on each line, starting with the origin, as
+    // lc3convert writes them
+    Hex,
+    // The same, with each word written out as 16 binary digits
+    Bin,
+    // Source code, assembled on the way in
+    Assembly,
+}
+
+impl ProgramFormat {
+    // Files with a .obj extension are object files, as is whatever's piped
+    // in for -, .hex files are hex, .asm files are source and anything else
+    // is raw. .bin files are raw binary as often as they're binary digits,
+    // so they're left raw.
+    pub fn detect(path: &str) -> Self {
+        if path == STDIO_PATH {
+            return Self::Object;
+        }
+        let extension = std::path::Path::new(path)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("obj") => Self::Object,
+            Some("hex") => Self::Hex,
+            Some("asm") => Self::Assembly,
+            _ => Self::Raw,
+        }
+    }
+
+    // Whether the file holds words as bytes rather than text
+    pub fn is_binary(self) -> bool {
+        matches!(self, Self::Object | Self::Raw)
+    }
+}
+
+impl FromStr for ProgramFormat {
+    type Err = LC3Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "obj" => Ok(Self::Object),
+            "raw" => Ok(Self::Raw),
+            "hex" => Ok(Self::Hex),
+            "bin" => Ok(Self::Bin),
+            "asm" => Ok(Self::Assembly),
+            _ => Err(LC3Error::Other(format!(
+                "Unknown program format {}, expected obj, raw, hex, bin or asm",
+                text
+            ))),
+        }
+    }
+}
+
+// A program read from a file, ready to load
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadedProgram {
+    pub origin: u16,
+    pub words: Vec<u16>,
+    pub format: ProgramFormat,
+    // The labels defined in source files, which don't have a .sym file
+    pub symbols: Option<SymbolTable>,
+}
+
+impl LoadedProgram {
+    // Reads a program from the contents of a file, detecting the format from
+    // the path unless it's given. The path is only used for that and for
+    // error messages.
+    pub fn parse(
+        path: &str,
+        bytes: &[u8],
+        format: Option<ProgramFormat>,
+        endian: Endian,
+    ) -> LC3Result<Self> {
+        let format = format.unwrap_or_else(|| ProgramFormat::detect(path));
+        if format == ProgramFormat::Assembly {
+            // .INCLUDEs are found next to the source
+            let source = Some(Path::new(path)).filter(|_| path != STDIO_PATH);
+            let assembly = Assembler::new().assemble(&String::from_utf8_lossy(bytes), source)?;
+            check_fits(assembly.origin, assembly.words.len())?;
+            return Ok(Self {
+                origin: assembly.origin,
+                words: assembly.words,
+                format,
+                symbols: Some(assembly.symbols),
+            });
+        }
+
+        let mut words = if format.is_binary() {
+            binary_words(path, bytes, endian)?
+        } else {
+            text_words(path, bytes, format)?
+        };
+
+        let origin = match format {
+            ProgramFormat::Raw => PC_START,
+            _ => {
+                if words.is_empty() {
+                    return Err(LC3Error::EmptyObject);
+                }
+                words.remove(0)
+            }
+        };
+        check_fits(origin, words.len())?;
+
+        Ok(Self {
+            origin,
+            words,
+            format,
+            symbols: None,
+        })
+    }
+
+    // Moves the program somewhere else, as long as it still fits
+    pub fn relocate(&mut self, origin: u16) -> LC3Result<()> {
+        check_fits(origin, self.words.len())?;
+        self.origin = origin;
+        Ok(())
+    }
+
+    // The program in LC-3 object format, for VM::load_object
+    pub fn to_object(&self) -> Vec<u16> {
+        let mut object = Vec::with_capacity(self.words.len() + 1);
+        object.push(self.origin);
+        object.extend_from_slice(&self.words);
+        object
+    }
+}
+
+// Everything from the keyboard status register up is device registers, so
+// programs have to finish before it
+fn check_fits(origin: u16, len: usize) -> LC3Result<()> {
+    let max_len = KB_STATUS_POS as usize;
+    if len > max_len {
+        return Err(LC3Error::ProgramSize { len, max_len });
+    }
+    if origin as usize + len > max_len {
+        return Err(LC3Error::BadOrigin { origin, len });
+    }
+    Ok(())
+}
+
+// Read a program from disk, in the format given or else the one detected
+// from its path. A path of - reads an object file from stdin, so programs
+// can be piped in.
+pub fn read_program(
+    path: &str,
+    format: Option<ProgramFormat>,
+    endian: Endian,
+) -> LC3Result<LoadedProgram> {
+    let read_error = |source: std::io::Error| match source.kind() {
+        ErrorKind::NotFound => LC3Error::ProgramMissing {
+            path: path.to_string(),
+        },
+        _ => LC3Error::ProgramRead {
+            path: path.to_string(),
+            source,
+        },
+    };
+    let bytes = if path == STDIO_PATH {
+        let mut bytes = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut bytes)
+            .map_err(read_error)?;
+        bytes
+    } else {
+        std::fs::read(path).map_err(read_error)?
+    };
+    LoadedProgram::parse(path, &bytes, format, endian)
+}
+
+fn binary_words(path: &str, bytes: &[u8], endian: Endian) -> LC3Result<Vec<u16>> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(LC3Error::OddByteCount {
+            path: path.to_string(),
+            len: bytes.len(),
+        });
+    }
+    Ok(bytes
         .chunks_exact(2)
-        .map(|a| (a[0] as u16, a[1] as u16))
-        .map(|a| a.1 + (a.0 << 8))
-        .collect();
+        .map(|pair| match endian {
+            Endian::Big => u16::from_be_bytes([pair[0], pair[1]]),
+            Endian::Little => u16::from_le_bytes([pair[0], pair[1]]),
+        })
+        .collect())
+}
+
+// A word on each line, skipping blank lines
+fn text_words(path: &str, bytes: &[u8], format: ProgramFormat) -> LC3Result<Vec<u16>> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut words = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let word = match format {
+            ProgramFormat::Hex => parse_address(line),
+            ProgramFormat::Bin if line.len() <= 16 => u16::from_str_radix(line, 2).ok(),
+            _ => None,
+        };
+        words.push(word.ok_or_else(|| LC3Error::BadProgramLine {
+            path: path.to_string(),
+            line: index + 1,
+            format: match format {
+                ProgramFormat::Hex => "hex",
+                _ => "binary",
+            },
+            text: line.to_string(),
+        })?);
+    }
+    Ok(words)
+}
+
+// Write a program in LC-3 object format as big-endian words, to stdout for
+// a path of -
+pub fn write_program(path: &str, object: &[u16]) -> PublicResult<()> {
+    let bytes: Vec<u8> = object.iter().flat_map(|word| word.to_be_bytes()).collect();
+    if path == STDIO_PATH {
+        let mut stdout = std::io::stdout();
+        return stdout
+            .write_all(&bytes)
+            .and_then(|_| stdout.flush())
+            .box_error();
+    }
+    std::fs::write(path, bytes).box_error()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read_bytes(name: &str, bytes: &[u8]) -> LC3Result<LoadedProgram> {
+        let path = std::env::temp_dir().join(format!("lc3rs_{}_{}", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        let program = read_program(&path.to_string_lossy(), None, Endian::Big);
+        std::fs::remove_file(&path).unwrap();
+        program
+    }
+
+    #[test]
+    fn can_read_programs() -> LC3Result<()> {
+        let program = read_bytes("prog.obj", &[0x40, 0x00, 0xF0, 0x25])?;
+        assert_eq!(program.format, ProgramFormat::Object);
+        assert_eq!(program.to_object(), vec![0x4000, 0xF025]);
 
-    if little_endian {
-        commands  = commands.iter().map(|a| a.swap_bytes()).collect()
+        let mut program = read_bytes("prog.bin", &[0xF0, 0x25])?;
+        assert_eq!(program.format, ProgramFormat::Raw);
+        assert_eq!(program.to_object(), vec![PC_START, 0xF025]);
+        program.relocate(0x5000)?;
+        assert_eq!(program.origin, 0x5000);
+
+        let program = read_bytes("prog.hex", b"3000\nxF025\n\n0x0041\n")?;
+        assert_eq!(program.format, ProgramFormat::Hex);
+        assert_eq!(program.to_object(), vec![0x3000, 0xF025, 0x0041]);
+
+        let program = read_bytes("prog.asm", b".ORIG x3000\nLOOP BR LOOP\n.END\n")?;
+        assert_eq!(program.format, ProgramFormat::Assembly);
+        assert_eq!(program.to_object(), vec![0x3000, 0x0FFF]);
+        assert_eq!(program.symbols.unwrap().address_of("LOOP"), Some(0x3000));
+
+        // The format and byte order can be given rather than detected
+        let bin = b"0011000000000000\n1111000000100101\n";
+        let program = LoadedProgram::parse("prog.txt", bin, Some(ProgramFormat::Bin), Endian::Big)?;
+        assert_eq!(program.to_object(), vec![0x3000, 0xF025]);
+        let program =
+            LoadedProgram::parse("prog.obj", &[0x00, 0x30, 0x25, 0xF0], None, Endian::Little)?;
+        assert_eq!(program.to_object(), vec![0x3000, 0xF025]);
+        let program = LoadedProgram::parse(
+            "prog",
+            &[0x40, 0x00],
+            Some(ProgramFormat::Object),
+            Endian::Big,
+        )?;
+        assert_eq!(program.origin, 0x4000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_explain_bad_programs() {
+        let missing = read_program("no/such/lc3rs/program.obj", None, Endian::Big);
+        assert!(matches!(missing, Err(LC3Error::ProgramMissing { .. })));
+
+        let odd = read_bytes("odd.obj", &[0x30, 0x00, 0xF0]);
+        assert!(matches!(odd, Err(LC3Error::OddByteCount { len: 3, .. })));
+
+        let empty = read_bytes("empty.obj", &[]);
+        assert!(matches!(empty, Err(LC3Error::EmptyObject)));
+
+        let garbled = read_bytes("garbled.hex", b"3000\n\nF0G5\n");
+        assert_eq!(
+            garbled.unwrap_err().to_string(),
+            format!(
+                "Line 3 of program file {} isn't a valid hex word: F0G5",
+                std::env::temp_dir()
+                    .join(format!("lc3rs_{}_garbled.hex", std::process::id()))
+                    .display()
+            )
+        );
+
+        let too_far = read_bytes("far.obj", &[0xFD, 0xFF, 0x12, 0x34, 0x56, 0x78]);
+        assert!(matches!(
+            too_far,
+            Err(LC3Error::BadOrigin {
+                origin: 0xFDFF,
+                len: 2
+            })
+        ));
+
+        let mut program = read_bytes("relocated.obj", &[0x30, 0x00, 0x12, 0x34]).unwrap();
+        assert!(program.relocate(0xFE00).is_err());
+        assert_eq!(program.origin, 0x3000);
     }
 
-    Ok(commands)
+    #[test]
+    fn can_override_config_flags() -> LC3Result<()> {
+        let config = Config::parse("[run]\nos = true\nquiet = true\nprofile = false\n")?;
+        let parse = |args: &[&str]| -> LC3Result<RunOptions> {
+            let mut options = RunOptions::from_iter(args);
+            options.apply_config(&config)?;
+            Ok(options)
+        };
+
+        let options = parse(&["run", "prog.obj"])?;
+        assert!(options.os && options.quiet && !options.profile);
+
+        let options = parse(&["run", "--no-os", "--profile", "prog.obj"])?;
+        assert!(!options.os && options.quiet && options.profile);
+
+        // Whichever of a flag and its --no- form comes last wins
+        let options = parse(&[
+            "run",
+            "--no-quiet",
+            "--quiet",
+            "--os",
+            "--no-os",
+            "prog.obj",
+        ])?;
+        assert!(!options.os && options.quiet);
+
+        Ok(())
+    }
 }
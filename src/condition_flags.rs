@@ -1,3 +1,35 @@
+use std::fmt;
+
 pub(crate) const FL_POS: u16 = 1;
 pub(crate) const FL_ZRO: u16 = 1 << 1;
 pub(crate) const FL_NEG: u16 = 1 << 2;
+
+// Decoded view of the condition register, for inspecting a VM from outside
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConditionFlags {
+    pub negative: bool,
+    pub zero: bool,
+    pub positive: bool,
+}
+
+impl ConditionFlags {
+    pub(crate) fn from_bits(bits: u16) -> Self {
+        Self {
+            negative: bits & FL_NEG != 0,
+            zero: bits & FL_ZRO != 0,
+            positive: bits & FL_POS != 0,
+        }
+    }
+}
+
+// The set flags as letters, e.g. "Z" or "NP"
+impl fmt::Display for ConditionFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (set, flag) in &[(self.negative, 'N'), (self.zero, 'Z'), (self.positive, 'P')] {
+            if *set {
+                write!(f, "{}", flag)?;
+            }
+        }
+        Ok(())
+    }
+}
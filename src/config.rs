@@ -0,0 +1,209 @@
+use std::convert::TryFrom;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::{LC3Error, LC3Result};
+use crate::toml::{Toml, TomlTable};
+
+// Looked for in the current directory when no --config is given
+pub const DEFAULT_CONFIG_PATH: &str = "lc3rs.toml";
+
+// The tables a configuration file can have, one per command
+const TABLES: [&str; 2] = ["run", "debug"];
+
+// Default options for the command line, so a class can hand out one file
+// rather than a long command line. Each command reads its own table, with
+// keys named after its long options:
+//
+// [run]
+// os = true
+// echo = "always"
+// max-instructions = 1_000_000
+// fill = 0xDEAD
+//
+// [debug]
+// break = ["LOOP", "DONE if R0 == 0"]
+//
+// Options given on the command line win over the file.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    tables: Vec<(String, TomlTable)>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> LC3Result<Self> {
+        let tables = Toml::parse(text).map_err(LC3Error::BadConfig)?;
+        for (name, table) in &tables {
+            if name.is_empty() {
+                if let Some((key, _)) = table.first() {
+                    return Err(LC3Error::BadConfig(format!(
+                        "{} has to go in a table such as [run]",
+                        key
+                    )));
+                }
+            } else if !TABLES.contains(&name.as_str()) {
+                return Err(LC3Error::BadConfig(format!(
+                    "Unknown table [{}], expected [run] or [debug]",
+                    name
+                )));
+            }
+        }
+        Ok(Self { tables })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> LC3Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|err| {
+            LC3Error::BadConfig(format!("Couldn't read {}: {}", path.display(), err))
+        })?;
+        Self::parse(&text)
+    }
+
+    // The file at path if there is one, otherwise lc3rs.toml if it exists
+    pub fn find(path: Option<&str>) -> LC3Result<Option<Self>> {
+        match path {
+            Some(path) => Self::from_file(path).map(Some),
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(DEFAULT_CONFIG_PATH).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    // The keys set for a command, in the order they appear
+    pub fn values(&self, command: &str) -> Vec<ConfigValue<'_>> {
+        self.tables
+            .iter()
+            .filter(|(name, _)| name == command)
+            .flat_map(|(_, table)| table.iter())
+            .map(|(key, value)| ConfigValue { key, value })
+            .collect()
+    }
+}
+
+// A key from the configuration file, with conversions that explain what
+// was expected when the value's the wrong type
+pub struct ConfigValue<'a> {
+    pub key: &'a str,
+    value: &'a Toml,
+}
+
+impl ConfigValue<'_> {
+    pub fn bool(&self) -> LC3Result<bool> {
+        match self.value {
+            Toml::Bool(value) => Ok(*value),
+            _ => Err(self.expected("true or false")),
+        }
+    }
+
+    pub fn string(&self) -> LC3Result<String> {
+        match self.value {
+            Toml::String(text) => Ok(text.clone()),
+            _ => Err(self.expected("a string")),
+        }
+    }
+
+    pub fn strings(&self) -> LC3Result<Vec<String>> {
+        match self.value {
+            Toml::Array(values) => values
+                .iter()
+                .map(|value| match value {
+                    Toml::String(text) => Ok(text.clone()),
+                    _ => Err(self.expected("an array of strings")),
+                })
+                .collect(),
+            _ => Err(self.expected("an array of strings")),
+        }
+    }
+
+    // Integers that don't fit in T are out of range rather than wrapped
+    pub fn integer<T: TryFrom<i64>>(&self) -> LC3Result<T> {
+        match self.value {
+            Toml::Integer(value) => T::try_from(*value).map_err(|_| {
+                LC3Error::BadConfig(format!("{} is out of range for {}", value, self.key))
+            }),
+            _ => Err(self.expected("an integer")),
+        }
+    }
+
+    pub fn float(&self) -> LC3Result<f64> {
+        match self.value {
+            Toml::Float(value) => Ok(*value),
+            Toml::Integer(value) => Ok(*value as f64),
+            _ => Err(self.expected("a number")),
+        }
+    }
+
+    // A string read the same way as on the command line
+    pub fn parsed<T: FromStr<Err = LC3Error>>(&self) -> LC3Result<T> {
+        self.string()?
+            .parse()
+            .map_err(|err| LC3Error::BadConfig(format!("{}: {}", self.key, err)))
+    }
+
+    pub fn unknown(&self, command: &str) -> LC3Error {
+        LC3Error::BadConfig(format!("Unknown key {} in [{}]", self.key, command))
+    }
+
+    fn expected(&self, expected: &str) -> LC3Error {
+        LC3Error::BadConfig(format!(
+            "{} should be {}, not {}",
+            self.key,
+            expected,
+            self.value.kind()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Config;
+    use crate::error::{LC3Error, LC3Result};
+    use crate::io::EchoMode;
+
+    #[test]
+    fn can_read_config() -> LC3Result<()> {
+        let config = Config::parse(
+            r#"
+[run]
+os = true
+echo = "always"
+fill = 0xDEAD
+timeout = 2
+
+[debug]
+break = ["LOOP"]
+"#,
+        )?;
+
+        let run = config.values("run");
+        let keys: Vec<&str> = run.iter().map(|value| value.key).collect();
+        assert_eq!(keys, vec!["os", "echo", "fill", "timeout"]);
+        assert!(run[0].bool()?);
+        assert_eq!(run[1].parsed::<EchoMode>()?, EchoMode::Always);
+        assert_eq!(run[2].integer::<u16>()?, 0xDEAD);
+        assert_eq!(run[3].float()?, 2.0);
+        assert_eq!(config.values("debug")[0].strings()?, vec!["LOOP"]);
+
+        // Values of the wrong type say what they should have been
+        let err = run[1].bool().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid configuration: echo should be true or false, not a string"
+        );
+        assert!(run[2].integer::<u8>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_reject_bad_config() {
+        assert!(matches!(
+            Config::parse("os = true"),
+            Err(LC3Error::BadConfig(_))
+        ));
+        assert!(Config::parse("[rnu]\nos = true").is_err());
+        assert!(Config::parse("[run]\nos = ").is_err());
+        assert!(Config::from_file("no/such/lc3rs.toml").is_err());
+    }
+}
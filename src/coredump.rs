@@ -0,0 +1,183 @@
+use std::path::Path;
+
+use crate::disasm::disassemble;
+use crate::error::{BoxErrors, LC3Error, LC3Result};
+use crate::json::Json;
+use crate::snapshot::Snapshot;
+use crate::symbols::SymbolTable;
+
+const FORMAT_VERSION: u64 = 1;
+
+// The machine as it was when a run failed, taken with VmState::core_dump,
+// so what went wrong can be looked into afterwards with lc3rs inspect.
+// Memory can be cut down to a window around the failed instruction, in
+// which case everything outside the window reads as zero.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoreDump {
+    pub error: String,
+    // Where the failed instruction was fetched from, and the instruction
+    pub address: u16,
+    pub instruction: u16,
+    // The first and last addresses kept, or None when all of memory was
+    pub window: Option<(u16, u16)>,
+    pub snapshot: Snapshot,
+}
+
+impl CoreDump {
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> LC3Result<()> {
+        std::fs::write(path, self.to_json()).map_io_error()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> LC3Result<Self> {
+        let text = std::fs::read_to_string(path).map_io_error()?;
+        Self::from_json(&text)
+    }
+
+    // The snapshot's JSON, along with the error and where it happened:
+    //
+    // {"version":1,"error":"...","address":12289,"instruction":53248,
+    //  "window":[12225,12353],"snapshot":{...}}
+    pub fn to_json(&self) -> String {
+        let window = match self.window {
+            Some((start, end)) => vec![start.into(), end.into()].into(),
+            None => Json::Null,
+        };
+        Json::object(vec![
+            ("version", FORMAT_VERSION.into()),
+            ("error", self.error.as_str().into()),
+            ("address", self.address.into()),
+            ("instruction", self.instruction.into()),
+            ("window", window),
+            ("snapshot", self.snapshot.to_json_value()),
+        ])
+        .to_string()
+    }
+
+    pub fn from_json(text: &str) -> LC3Result<Self> {
+        let json = Json::parse(text).map_err(LC3Error::BadCoreDump)?;
+        let field = |name: &str| {
+            json.get(name)
+                .ok_or_else(|| LC3Error::BadCoreDump(format!("Missing field '{}'", name)))
+        };
+        let word = |name: &str| {
+            field(name)?
+                .as_u64()
+                .filter(|word| *word <= u16::MAX as u64)
+                .map(|word| word as u16)
+                .ok_or_else(|| LC3Error::BadCoreDump(format!("Invalid {}", name)))
+        };
+
+        if field("version")?.as_u64() != Some(FORMAT_VERSION) {
+            return Err(LC3Error::BadCoreDump(
+                "Unsupported core dump version".to_string(),
+            ));
+        }
+
+        let window = match field("window")? {
+            Json::Null => None,
+            window => {
+                let bounds: Vec<u16> = window
+                    .as_array()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(Json::as_u64)
+                    .filter(|bound| *bound <= u16::MAX as u64)
+                    .map(|bound| bound as u16)
+                    .collect();
+                match bounds[..] {
+                    [start, end] => Some((start, end)),
+                    _ => return Err(LC3Error::BadCoreDump("Invalid window".to_string())),
+                }
+            }
+        };
+
+        Ok(Self {
+            error: field("error")?
+                .as_str()
+                .ok_or_else(|| LC3Error::BadCoreDump("Invalid error".to_string()))?
+                .to_string(),
+            address: word("address")?,
+            instruction: word("instruction")?,
+            window,
+            snapshot: Snapshot::from_json_value(field("snapshot")?)?,
+        })
+    }
+
+    // What went wrong and where, to start an inspect session with
+    pub fn summary(&self, symbols: Option<&SymbolTable>) -> String {
+        let location = match symbols {
+            Some(symbols) => symbols.describe(self.address),
+            None => format!("x{:04X}", self.address),
+        };
+        let mut summary = format!(
+            "{}\nFailed at {}: x{:04X}  {}",
+            self.error,
+            location,
+            self.instruction,
+            disassemble(self.address, self.instruction)
+        );
+        if let Some((start, end)) = self.window {
+            summary.push_str(&format!(
+                "\nOnly memory from x{:04X} to x{:04X} was kept",
+                start, end
+            ));
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CoreDump;
+    use crate::error::{LC3Error, LC3Result};
+    use crate::io::TestIOHandle;
+    use crate::register::Register::RR1;
+    use crate::vm::VM;
+
+    #[test]
+    fn can_dump_core() -> LC3Result<()> {
+        let program: Vec<u16> = vec![
+            // ADD R1, R1, #5
+            0x1265,
+            // Reserved op code
+            0xD000,
+        ];
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_program(&program)?;
+        let err = vm.run().unwrap_err();
+
+        let core = vm.core_dump(&err, Some(1));
+        assert_eq!(core.address, 0x3001);
+        assert_eq!(core.instruction, 0xD000);
+        assert_eq!(core.window, Some((0x3000, 0x3002)));
+        assert_eq!(core.snapshot.register(RR1), 5);
+        assert_eq!(
+            core.summary(None),
+            format!(
+                "{}\nFailed at x3001: xD000  .FILL xD000\n\
+                 Only memory from x3000 to x3002 was kept",
+                err
+            )
+        );
+
+        // Everything survives the trip through a file
+        let path = std::env::temp_dir().join(format!("lc3rs_{}_core.lc3", std::process::id()));
+        core.save(&path)?;
+        let loaded = CoreDump::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded?, core);
+
+        // The whole of memory is kept without a window
+        vm.set_memory_at(0x5000, 0x1234);
+        let core = vm.core_dump(&err, None);
+        assert_eq!(core.window, None);
+        assert_eq!(core.snapshot.memory_at(0x5000), 0x1234);
+
+        assert!(matches!(
+            CoreDump::from_json("{\"version\":1}"),
+            Err(LC3Error::BadCoreDump(_))
+        ));
+
+        Ok(())
+    }
+}
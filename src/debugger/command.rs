@@ -0,0 +1,215 @@
+use super::condition::Condition;
+use super::engine::DEFAULT_RECORD_INTERVAL;
+use super::watch::{Access, WatchTarget};
+use crate::asm::parse_number;
+use crate::register::Register;
+use crate::symbols::SymbolTable;
+
+// How many instructions list shows when it isn't given an end
+pub(crate) const LIST_LENGTH: u16 = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugCommand {
+    Step(u32),
+    StepBack(u32),
+    // None turns recording off
+    Record(Option<u64>),
+    Continue,
+    Break(u16, Option<Condition>),
+    Delete(u16),
+    Breakpoints,
+    Watch(WatchTarget, Access),
+    Unwatch(WatchTarget),
+    Watchpoints,
+    Registers,
+    Backtrace,
+    // The first and last addresses, which are the same for a single word
+    Examine(u16, u16),
+    // The address and the words to write from there on
+    Deposit(u16, Vec<u16>),
+    SetRegister(Register, u16),
+    // Disassemble from the first address, or the PC, to the last, or for
+    // LIST_LENGTH instructions
+    List(Option<u16>, Option<u16>),
+    Help,
+    Quit,
+}
+
+pub(crate) const HELP: &str = "\
+(s)tep [count]            Execute one (or count) instructions
+back (sb) [count]         Step backwards over one (or count) instructions,
+                          needs recording to be on
+record [interval|off]     Record history so execution can be stepped back,
+                          snapshotting every interval instructions
+(c)ontinue                Run until a breakpoint or HALT
+(b)reak <addr> [if cond]  Set a breakpoint, optionally only stopping when
+                          a condition like R1 == 10 && mem[x4000] != 0
+                          holds
+(del)ete <addr>           Remove a breakpoint
+breakpoints (bs)          List breakpoints
+(w)atch <target> [r|w|rw] Stop when a register (R0-R7, PC) or address is
+                          read and/or written, defaults to writes
+unwatch <target>          Remove a watchpoint
+watchpoints (ws)          List watchpoints
+(r)egisters               Print the registers, PC and condition codes
+backtrace (bt)            Print the subroutine calls leading to the PC
+e(x)amine <addr> [end]    Print the word at an address, or dump the words
+                          from addr to end
+(d)eposit <addr> <value>  Write a word into memory, or into a register
+                          (R0-R7, PC) in place of the address. More
+                          values go into the words after addr.
+(l)ist [addr] [end]       Disassemble from addr, or the PC, to end, or for
+                          the next ten instructions
+(h)elp                    Print this message
+(q)uit                    Leave the debugger
+
+Addresses and values can be written as x3000, #12 or, if a symbol
+table is loaded, as a label.";
+
+impl DebugCommand {
+    // Parse a line typed at the debugger prompt. Most commands can be
+    // shortened, see HELP for the full names.
+    pub fn parse(line: &str, symbols: Option<&SymbolTable>) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let name = words.next().ok_or_else(|| "Empty command".to_string())?;
+        let args: Vec<&str> = words.collect();
+        let value = |index: usize| -> Result<u16, String> {
+            let text = args
+                .get(index)
+                .ok_or_else(|| format!("{} expects more arguments", name))?;
+            parse_value(text, symbols)
+        };
+        let target = |index: usize| -> Result<WatchTarget, String> {
+            let text = args
+                .get(index)
+                .ok_or_else(|| format!("{} expects more arguments", name))?;
+            match parse_register(text) {
+                Some(register) => Ok(WatchTarget::Register(register)),
+                None => parse_value(text, symbols).map(WatchTarget::Memory),
+            }
+        };
+
+        use DebugCommand::*;
+        let command = match name.to_lowercase().as_str() {
+            "s" | "step" => match args.first() {
+                Some(count) => Step(
+                    count
+                        .parse()
+                        .map_err(|_| format!("Invalid step count '{}'", count))?,
+                ),
+                None => Step(1),
+            },
+            "sb" | "back" => match args.first() {
+                Some(count) => StepBack(
+                    count
+                        .parse()
+                        .map_err(|_| format!("Invalid step count '{}'", count))?,
+                ),
+                None => StepBack(1),
+            },
+            "record" => match args.first() {
+                Some(off) if off.eq_ignore_ascii_case("off") => Record(None),
+                Some(interval) => Record(Some(
+                    interval
+                        .parse()
+                        .ok()
+                        .filter(|interval| *interval > 0)
+                        .ok_or_else(|| format!("Invalid snapshot interval '{}'", interval))?,
+                )),
+                None => Record(Some(DEFAULT_RECORD_INTERVAL)),
+            },
+            "c" | "continue" => Continue,
+            "b" | "break" => {
+                let condition = match args.get(1) {
+                    Some(keyword) if keyword.eq_ignore_ascii_case("if") => {
+                        let source = args[2..].join(" ");
+                        Some(Condition::parse(&source, symbols)?)
+                    }
+                    Some(other) => return Err(format!("Expected 'if', found '{}'", other)),
+                    None => None,
+                };
+                Break(value(0)?, condition)
+            }
+            "del" | "delete" => Delete(value(0)?),
+            "bs" | "breakpoints" => Breakpoints,
+            "w" | "watch" => {
+                let target = target(0)?;
+                let access = match args.get(1).map(|kind| kind.to_lowercase()) {
+                    None => Access::Write,
+                    Some(kind) => match kind.as_str() {
+                        "r" => Access::Read,
+                        "w" => Access::Write,
+                        "rw" => Access::ReadWrite,
+                        _ => return Err(format!("Unknown access kind '{}'", kind)),
+                    },
+                };
+                Watch(target, access)
+            }
+            "unwatch" => Unwatch(target(0)?),
+            "ws" | "watchpoints" => Watchpoints,
+            "r" | "registers" => Registers,
+            "bt" | "backtrace" => Backtrace,
+            "x" | "e" | "examine" => {
+                let start = value(0)?;
+                let end = if args.len() > 1 { value(1)? } else { start };
+                if end < start {
+                    return Err("The end of the range comes before the start".to_string());
+                }
+                Examine(start, end)
+            }
+            "d" | "dep" | "deposit" => match args.first().and_then(|text| parse_register(text)) {
+                Some(register) => SetRegister(register, value(1)?),
+                None => Deposit(
+                    value(0)?,
+                    (1..args.len().max(2))
+                        .map(value)
+                        .collect::<Result<Vec<u16>, String>>()?,
+                ),
+            },
+            "l" | "list" => match args.len() {
+                0 => List(None, None),
+                1 => List(Some(value(0)?), None),
+                _ => {
+                    let (start, end) = (value(0)?, value(1)?);
+                    if end < start {
+                        return Err("The end of the range comes before the start".to_string());
+                    }
+                    List(Some(start), Some(end))
+                }
+            },
+            "h" | "help" | "?" => Help,
+            "q" | "quit" | "exit" => Quit,
+            _ => return Err(format!("Unknown command '{}', try help", name)),
+        };
+
+        Ok(command)
+    }
+}
+
+// A number in any of the forms the assembler accepts, or a label
+pub(crate) fn parse_value(text: &str, symbols: Option<&SymbolTable>) -> Result<u16, String> {
+    if let Some(value) = parse_number(text) {
+        if (-0x8000..=0xFFFF).contains(&value) {
+            return Ok(value as u16);
+        }
+        return Err(format!("{} does not fit in 16 bits", text));
+    }
+
+    symbols
+        .and_then(|symbols| symbols.address_of(text))
+        .ok_or_else(|| format!("Unknown address or label '{}'", text))
+}
+
+pub(crate) fn parse_register(text: &str) -> Option<Register> {
+    let upper = text.to_uppercase();
+    if upper == "PC" {
+        return Some(Register::RPC);
+    }
+
+    let index = upper.strip_prefix('R')?.parse::<u8>().ok()?;
+    if index < 8 {
+        Register::from_index(index)
+    } else {
+        None
+    }
+}
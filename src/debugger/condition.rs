@@ -0,0 +1,382 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use super::command::parse_register;
+use crate::asm::parse_number;
+use crate::io::IOHandle;
+use crate::register::Register;
+use crate::symbols::SymbolTable;
+use crate::vm::VmState;
+
+// A boolean expression over the machine state, used to make breakpoints
+// conditional. For example:
+//
+// R1 == 10 && mem[x4000] != 0
+// (N || Z) && PC >= LOOP
+//
+// Registers are R0-R7 and PC, N, Z and P are the condition codes and
+// mem[...] reads a word of memory. Numbers can be written in any of the
+// assembler's notations and labels can be used if a symbol table is
+// available. Arithmetic wraps at 16 bits and comparisons treat values as
+// signed, the same as the LC-3 itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    source: String,
+    root: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Value(u16),
+    Register(Register),
+    Flag(char),
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Op(&'static str),
+    Open(char),
+    Close(char),
+}
+
+impl Condition {
+    pub fn parse(source: &str, symbols: Option<&SymbolTable>) -> Result<Self, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            symbols,
+        };
+        let root = parser.or()?;
+
+        if let Some(token) = parser.peek() {
+            return Err(format!("Unexpected {:?} in condition", token));
+        }
+
+        Ok(Self {
+            source: source.trim().to_string(),
+            root,
+        })
+    }
+
+    pub fn evaluate<IOType: IOHandle>(&self, vm: &VmState<IOType>) -> bool {
+        self.evaluate_at(vm, vm.pc())
+    }
+
+    // Evaluate as if the PC held pc. Plugins see an instruction after the
+    // PC has moved past it, but conditions should see its address.
+    pub(crate) fn evaluate_at<IOType: IOHandle>(&self, vm: &VmState<IOType>, pc: u16) -> bool {
+        evaluate(&self.root, vm, pc) != 0
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+// pc stands in for the PC register, see Condition::evaluate_at
+fn evaluate<IOType: IOHandle>(expr: &Expr, vm: &VmState<IOType>, pc: u16) -> u16 {
+    match expr {
+        Expr::Value(value) => *value,
+        Expr::Register(Register::RPC) => pc,
+        Expr::Register(register) => vm.register(*register),
+        Expr::Flag(flag) => {
+            let flags = vm.condition_flags();
+            let set = match flag {
+                'N' => flags.negative,
+                'Z' => flags.zero,
+                _ => flags.positive,
+            };
+            set as u16
+        }
+        Expr::Memory(address) => vm.memory_at(evaluate(address, vm, pc)),
+        Expr::Not(inner) => (evaluate(inner, vm, pc) == 0) as u16,
+        Expr::Negate(inner) => evaluate(inner, vm, pc).wrapping_neg(),
+        Expr::Binary(left, op, right) => {
+            let left = evaluate(left, vm, pc);
+            // Short circuit the logical operators
+            match op {
+                BinaryOp::And if left == 0 => return 0,
+                BinaryOp::Or if left != 0 => return 1,
+                _ => {}
+            }
+            let right = evaluate(right, vm, pc);
+            let (signed_left, signed_right) = (left as i16, right as i16);
+
+            match op {
+                BinaryOp::Add => left.wrapping_add(right),
+                BinaryOp::Sub => left.wrapping_sub(right),
+                BinaryOp::Eq => (left == right) as u16,
+                BinaryOp::Ne => (left != right) as u16,
+                BinaryOp::Lt => (signed_left < signed_right) as u16,
+                BinaryOp::Le => (signed_left <= signed_right) as u16,
+                BinaryOp::Gt => (signed_left > signed_right) as u16,
+                BinaryOp::Ge => (signed_left >= signed_right) as u16,
+                BinaryOp::And | BinaryOp::Or => (right != 0) as u16,
+            }
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars: Peekable<Chars> = source.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        let token = match ch {
+            ch if ch.is_whitespace() => continue,
+            '(' | '[' => Token::Open(ch),
+            ')' | ']' => Token::Close(ch),
+            '+' => Token::Op("+"),
+            '-' => Token::Op("-"),
+            '=' | '!' | '<' | '>' => {
+                if chars.peek() == Some(&'=') {
+                    chars.next();
+                    match ch {
+                        '=' => Token::Op("=="),
+                        '!' => Token::Op("!="),
+                        '<' => Token::Op("<="),
+                        _ => Token::Op(">="),
+                    }
+                } else {
+                    match ch {
+                        '!' => Token::Op("!"),
+                        '<' => Token::Op("<"),
+                        '>' => Token::Op(">"),
+                        _ => return Err("Use == to compare values".to_string()),
+                    }
+                }
+            }
+            '&' | '|' => {
+                if chars.next() != Some(ch) {
+                    return Err(format!("Expected {}{}", ch, ch));
+                }
+                if ch == '&' {
+                    Token::Op("&&")
+                } else {
+                    Token::Op("||")
+                }
+            }
+            ch if ch.is_ascii_alphanumeric() || ch == '#' || ch == '_' => {
+                let mut word = ch.to_string();
+                while let Some(next) = chars.peek() {
+                    if next.is_ascii_alphanumeric() || *next == '_' {
+                        word.push(*next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                Token::Word(word)
+            }
+            _ => return Err(format!("Unexpected character '{}' in condition", ch)),
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    symbols: Option<&'a SymbolTable>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn accept(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                let op = *op;
+                self.position += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect_close(&mut self, close: char) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Close(ch)) if ch == close => Ok(()),
+            _ => Err(format!("Expected '{}' in condition", close)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.accept(&["||"]).is_some() {
+            expr = Expr::Binary(Box::new(expr), BinaryOp::Or, Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.comparison()?;
+        while self.accept(&["&&"]).is_some() {
+            expr = Expr::Binary(Box::new(expr), BinaryOp::And, Box::new(self.comparison()?));
+        }
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let left = self.sum()?;
+        let op = match self.accept(&["==", "!=", "<", "<=", ">", ">="]) {
+            Some("==") => BinaryOp::Eq,
+            Some("!=") => BinaryOp::Ne,
+            Some("<") => BinaryOp::Lt,
+            Some("<=") => BinaryOp::Le,
+            Some(">") => BinaryOp::Gt,
+            Some(">=") => BinaryOp::Ge,
+            _ => return Ok(left),
+        };
+        Ok(Expr::Binary(Box::new(left), op, Box::new(self.sum()?)))
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while let Some(op) = self.accept(&["+", "-"]) {
+            let op = if op == "+" {
+                BinaryOp::Add
+            } else {
+                BinaryOp::Sub
+            };
+            expr = Expr::Binary(Box::new(expr), op, Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.accept(&["!", "-"]) {
+            Some("!") => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(_) => Ok(Expr::Negate(Box::new(self.unary()?))),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Open('(')) => {
+                let expr = self.or()?;
+                self.expect_close(')')?;
+                Ok(expr)
+            }
+            Some(Token::Word(word)) => {
+                if word.eq_ignore_ascii_case("mem") {
+                    match self.next() {
+                        Some(Token::Open('[')) => {}
+                        _ => return Err("Expected '[' after mem".to_string()),
+                    }
+                    let address = self.or()?;
+                    self.expect_close(']')?;
+                    return Ok(Expr::Memory(Box::new(address)));
+                }
+
+                if let Some(register) = parse_register(&word) {
+                    return Ok(Expr::Register(register));
+                }
+
+                let upper = word.to_uppercase();
+                if upper == "N" || upper == "Z" || upper == "P" {
+                    return Ok(Expr::Flag(upper.chars().next().unwrap_or('P')));
+                }
+
+                if let Some(value) = parse_number(&word) {
+                    return Ok(Expr::Value(value as u16));
+                }
+
+                self.symbols
+                    .and_then(|symbols| symbols.address_of(&word))
+                    .map(Expr::Value)
+                    .ok_or_else(|| format!("Unknown name '{}' in condition", word))
+            }
+            Some(token) => Err(format!("Unexpected {:?} in condition", token)),
+            None => Err("Condition ended unexpectedly".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Condition;
+    use crate::io::TestIOHandle;
+    use crate::register::Register::{RR1, RR2};
+    use crate::symbols::SymbolTable;
+    use crate::vm::VM;
+
+    #[test]
+    fn can_evaluate_conditions() {
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.set_register(RR1, 10);
+        vm.set_register(RR2, 0xFFFF);
+        vm.set_memory_at(0x4000, 7);
+        // Negative flag
+        vm.set_register(crate::register::Register::RCond, 1 << 2);
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("DATA", 0x4000);
+
+        let test_cases = vec![
+            ("R1 == 10", true),
+            ("R1 == #10 && mem[x4000] != 0", true),
+            ("R1 == 10 && mem[x4001] != 0", false),
+            ("mem[DATA] == 7", true),
+            ("mem[DATA + 1] == 0", true),
+            ("R2 < 0", true),
+            ("R2 == -1", true),
+            ("R2 + 1 == 0", true),
+            ("N && !Z", true),
+            ("(Z || P) || R1 >= 11", false),
+            ("PC == x3000", true),
+        ];
+
+        for (source, expected) in test_cases {
+            let condition = Condition::parse(source, Some(&symbols)).unwrap();
+            assert_eq!(condition.evaluate(&vm), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_conditions() {
+        let test_cases = vec![
+            "R1 =",
+            "R1 = 10",
+            "mem[x4000",
+            "R1 &",
+            "NOWHERE == 1",
+            "1 2",
+        ];
+
+        for source in test_cases {
+            assert!(Condition::parse(source, None).is_err(), "{}", source);
+        }
+    }
+}
@@ -0,0 +1,654 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use super::command::{parse_register, parse_value};
+use super::condition::Condition;
+use super::engine::{DebugStop, Debugger, DEFAULT_RECORD_INTERVAL};
+use crate::asm::assemble;
+use crate::cli::{read_program, Endian};
+use crate::error::{BoxErrors, LC3Error, LC3Result};
+use crate::io::IOHandle;
+use crate::json::Json;
+use crate::register::Register;
+use crate::symbols::SymbolTable;
+use crate::vm::VM;
+
+// The LC-3 only ever has the one thread and the one scope
+const THREAD_ID: u64 = 1;
+const REGISTERS_REFERENCE: u64 = 1;
+
+// stdin and stdout carry the protocol, so program output is sent to the
+// editor as output events instead and keyboard input isn't available
+#[derive(Clone, Default)]
+pub struct DapIOHandle {
+    output: Rc<RefCell<String>>,
+}
+
+impl IOHandle for DapIOHandle {
+    fn getchar(&self) -> LC3Result<char> {
+        Err(LC3Error::Other(
+            "Keyboard input isn't available when debugging over DAP".to_string(),
+        ))
+    }
+
+    fn putchar(&self, ch: char) -> LC3Result<()> {
+        self.output.borrow_mut().push(ch);
+        Ok(())
+    }
+
+    fn is_key_down(&self) -> LC3Result<bool> {
+        Ok(false)
+    }
+}
+
+struct Session {
+    debugger: Debugger<DapIOHandle>,
+    io: DapIOHandle,
+    // Only known when the program was launched from assembly source
+    source_path: Option<String>,
+    line_addresses: BTreeMap<usize, u16>,
+    // Each setBreakpoints or setInstructionBreakpoints request replaces the
+    // breakpoints set by the last one of the same kind
+    source_breakpoints: BTreeSet<u16>,
+    instruction_breakpoints: BTreeSet<u16>,
+    stop_on_entry: bool,
+}
+
+// Debug Adapter Protocol server, so editors like VS Code can drive the
+// debugger. Requests are handled one at a time and execution runs to
+// completion before the next request is read, so pause is a no-op.
+pub struct DapServer<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+    seq: u64,
+    session: Option<Session>,
+}
+
+impl<R: BufRead, W: Write> DapServer<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self {
+            input,
+            output,
+            seq: 0,
+            session: None,
+        }
+    }
+
+    // Serve requests until the client disconnects or closes the input
+    pub fn run(&mut self) -> LC3Result<()> {
+        while let Some(request) = self.read_message()? {
+            if !self.handle_request(&request)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_message(&mut self) -> LC3Result<Option<Json>> {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            if self.input.read_line(&mut header).map_io_error()? == 0 {
+                return Ok(None);
+            }
+
+            let header = header.trim();
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                let value = value.trim();
+                length = Some(value.parse::<usize>().map_err(|_| {
+                    LC3Error::Other(format!("Invalid DAP content length '{}'", value))
+                })?);
+            } else if header.is_empty() {
+                if let Some(length) = length {
+                    let mut body = vec![0; length];
+                    self.input.read_exact(&mut body).map_io_error()?;
+                    let text = String::from_utf8(body).map_io_error()?;
+                    return Json::parse(&text).map(Some).map_err(LC3Error::Other);
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, mut fields: Vec<(&str, Json)>) -> LC3Result<()> {
+        self.seq += 1;
+        fields.insert(0, ("seq", self.seq.into()));
+        let body = Json::object(fields).to_string();
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .map_io_error()?;
+        self.output.flush().map_io_error()
+    }
+
+    fn respond(&mut self, request: &Json, result: Result<Json, String>) -> LC3Result<()> {
+        let mut fields = vec![
+            ("type", "response".into()),
+            (
+                "request_seq",
+                request
+                    .get("seq")
+                    .and_then(Json::as_u64)
+                    .unwrap_or(0)
+                    .into(),
+            ),
+            ("command", command_of(request).into()),
+        ];
+        match result {
+            Ok(body) => {
+                fields.push(("success", true.into()));
+                fields.push(("body", body));
+            }
+            Err(message) => {
+                fields.push(("success", false.into()));
+                fields.push(("message", message.into()));
+            }
+        }
+        self.send(fields)
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> LC3Result<()> {
+        self.send(vec![
+            ("type", "event".into()),
+            ("event", event.into()),
+            ("body", body),
+        ])
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) -> LC3Result<()> {
+        let mut fields = vec![
+            ("reason", reason.into()),
+            ("threadId", THREAD_ID.into()),
+            ("allThreadsStopped", true.into()),
+        ];
+        if let Some(text) = text {
+            fields.push(("text", text.into()));
+        }
+        self.event("stopped", Json::object(fields))
+    }
+
+    // Returns false once the client has disconnected
+    fn handle_request(&mut self, request: &Json) -> LC3Result<bool> {
+        let empty = Json::object(vec![]);
+        let arguments = request.get("arguments").unwrap_or(&empty);
+
+        match command_of(request) {
+            "initialize" => {
+                let capabilities = Json::object(vec![
+                    ("supportsConfigurationDoneRequest", true.into()),
+                    ("supportsConditionalBreakpoints", true.into()),
+                    ("supportsInstructionBreakpoints", true.into()),
+                    ("supportsEvaluateForHovers", true.into()),
+                    ("supportsTerminateRequest", true.into()),
+                    ("supportsStepBack", true.into()),
+                ]);
+                self.respond(request, Ok(capabilities))?;
+                self.event("initialized", empty)?;
+            }
+            "launch" => {
+                let result = self.launch(arguments).map(|_| empty);
+                self.respond(request, result)?;
+            }
+            "setBreakpoints" => {
+                let result = self.set_breakpoints(arguments);
+                self.respond(request, result)?;
+            }
+            "setInstructionBreakpoints" => {
+                let result = self.set_instruction_breakpoints(arguments);
+                self.respond(request, result)?;
+            }
+            "configurationDone" => {
+                self.respond(request, Ok(empty))?;
+                match &self.session {
+                    Some(session) if session.stop_on_entry => self.stopped("entry", None)?,
+                    Some(_) => self.resume(false)?,
+                    None => {}
+                }
+            }
+            "threads" => {
+                let thread = Json::object(vec![("id", THREAD_ID.into()), ("name", "LC-3".into())]);
+                let body = Json::object(vec![("threads", vec![thread].into())]);
+                self.respond(request, Ok(body))?;
+            }
+            "stackTrace" => {
+                let result = self.stack_trace();
+                self.respond(request, result)?;
+            }
+            "scopes" => {
+                let scope = Json::object(vec![
+                    ("name", "Registers".into()),
+                    ("variablesReference", REGISTERS_REFERENCE.into()),
+                    ("expensive", false.into()),
+                ]);
+                let body = Json::object(vec![("scopes", vec![scope].into())]);
+                self.respond(request, Ok(body))?;
+            }
+            "variables" => {
+                let result = self.variables(arguments);
+                self.respond(request, result)?;
+            }
+            "continue" => {
+                let body = Json::object(vec![("allThreadsContinued", true.into())]);
+                self.respond(request, Ok(body))?;
+                self.resume(false)?;
+            }
+            // There's no call stack to step in or out of, so these are all
+            // a single instruction
+            "next" | "stepIn" | "stepOut" => {
+                self.respond(request, Ok(empty))?;
+                self.resume(true)?;
+            }
+            "stepBack" => {
+                let result = self.step_back().map(|_| empty);
+                let stepped = result.is_ok();
+                self.respond(request, result)?;
+                if stepped {
+                    self.stopped("step", None)?;
+                }
+            }
+            "pause" => self.respond(request, Ok(empty))?,
+            "evaluate" => {
+                let result = self.evaluate(arguments);
+                self.respond(request, result)?;
+                self.send_output()?;
+            }
+            "disconnect" | "terminate" => {
+                self.respond(request, Ok(empty))?;
+                return Ok(false);
+            }
+            command => {
+                let message = format!("Unsupported request '{}'", command);
+                self.respond(request, Err(message))?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn session(&mut self) -> Result<&mut Session, String> {
+        self.session
+            .as_mut()
+            .ok_or_else(|| "No program has been launched".to_string())
+    }
+
+    // Programs can be launched from assembly source, in which case source
+    // line breakpoints are available, or from a program file. A .sym file
+    // next to a program file is picked up automatically.
+    fn launch(&mut self, arguments: &Json) -> Result<(), String> {
+        let program = arguments
+            .get("program")
+            .and_then(Json::as_str)
+            .ok_or_else(|| "launch needs a program path".to_string())?;
+        let flag = |name: &str| arguments.get(name).and_then(Json::as_bool).unwrap_or(false);
+
+        let io = DapIOHandle::default();
+        let mut vm = VM::new_with_io(io.clone());
+        let is_source = Path::new(program)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("asm"));
+
+        let (symbols, line_addresses, source_path) = if is_source {
+            let source = std::fs::read_to_string(program).map_err(|err| err.to_string())?;
+            let assembly = assemble(&source).map_err(|err| err.to_string())?;
+            vm.load_object(&assembly.to_object())
+                .map_err(|err| err.to_string())?;
+            (
+                Some(assembly.symbols),
+                assembly.line_addresses,
+                Some(program.to_string()),
+            )
+        } else {
+            let endian = if flag("littleEndian") {
+                Endian::Little
+            } else {
+                Endian::Big
+            };
+            let loaded = read_program(program, None, endian).map_err(|err| err.to_string())?;
+            vm.load_object(&loaded.to_object())
+                .map_err(|err| err.to_string())?;
+            let symbol_path = Path::new(program).with_extension("sym");
+            let symbols = if symbol_path.exists() {
+                Some(SymbolTable::from_file(symbol_path).map_err(|err| err.to_string())?)
+            } else {
+                None
+            };
+            (symbols, BTreeMap::new(), None)
+        };
+
+        let mut debugger = Debugger::new(vm);
+        if let Some(symbols) = symbols {
+            debugger = debugger.with_symbols(symbols);
+        }
+        debugger.start_recording(DEFAULT_RECORD_INTERVAL);
+
+        self.session = Some(Session {
+            debugger,
+            io,
+            source_path,
+            line_addresses,
+            source_breakpoints: BTreeSet::new(),
+            instruction_breakpoints: BTreeSet::new(),
+            stop_on_entry: flag("stopOnEntry"),
+        });
+        Ok(())
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let session = self.session()?;
+        for address in std::mem::take(&mut session.source_breakpoints) {
+            session.debugger.remove_breakpoint(address);
+        }
+
+        let mut results = Vec::new();
+        for breakpoint in breakpoints_of(arguments) {
+            let line = breakpoint.get("line").and_then(Json::as_u64).unwrap_or(0) as usize;
+            // Breakpoints on blank lines and comments move down to the next
+            // line that assembled to something
+            let placed = session
+                .line_addresses
+                .range(line..)
+                .next()
+                .map(|(line, address)| (*line, *address));
+
+            let result = match placed {
+                Some((line, address)) => match session.add_breakpoint(address, breakpoint) {
+                    Ok(()) => {
+                        session.source_breakpoints.insert(address);
+                        verified_breakpoint(Some(line), address)
+                    }
+                    Err(message) => unverified_breakpoint(Some(line), message),
+                },
+                None => unverified_breakpoint(
+                    Some(line),
+                    "No instruction at or after this line".to_string(),
+                ),
+            };
+            results.push(result);
+        }
+
+        Ok(Json::object(vec![("breakpoints", results.into())]))
+    }
+
+    fn set_instruction_breakpoints(&mut self, arguments: &Json) -> Result<Json, String> {
+        let session = self.session()?;
+        for address in std::mem::take(&mut session.instruction_breakpoints) {
+            session.debugger.remove_breakpoint(address);
+        }
+
+        let mut results = Vec::new();
+        for breakpoint in breakpoints_of(arguments) {
+            let reference = breakpoint
+                .get("instructionReference")
+                .and_then(Json::as_str)
+                .unwrap_or("");
+            let offset = match breakpoint.get("offset") {
+                Some(Json::Number(offset)) => *offset as i64 as u16,
+                _ => 0,
+            };
+
+            let result = match parse_reference(reference, session.debugger.symbols()) {
+                Ok(address) => {
+                    let address = address.wrapping_add(offset);
+                    match session.add_breakpoint(address, breakpoint) {
+                        Ok(()) => {
+                            session.instruction_breakpoints.insert(address);
+                            verified_breakpoint(None, address)
+                        }
+                        Err(message) => unverified_breakpoint(None, message),
+                    }
+                }
+                Err(message) => unverified_breakpoint(None, message),
+            };
+            results.push(result);
+        }
+
+        Ok(Json::object(vec![("breakpoints", results.into())]))
+    }
+
+    fn step_back(&mut self) -> Result<(), String> {
+        let session = self.session()?;
+        match session.debugger.step_back() {
+            Ok(true) => Ok(()),
+            Ok(false) => Err("Already at the start of the recording".to_string()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    fn stack_trace(&mut self) -> Result<Json, String> {
+        let session = self.session()?;
+        let debugger = &session.debugger;
+        let calls = debugger.call_stack();
+
+        // Innermost first. Frames inside a subroutine are named after it,
+        // the outermost one after wherever it's stopped.
+        let mut locations = vec![debugger.vm().pc()];
+        let mut names = Vec::new();
+        for frame in calls.frames().iter().rev() {
+            locations.push(frame.call_site);
+            names.push(debugger.name(frame.entry));
+        }
+        names.push(debugger.name(*locations.last().unwrap()));
+
+        let frames: Vec<Json> = locations
+            .iter()
+            .zip(names)
+            .enumerate()
+            .map(|(id, (address, name))| {
+                let line = session
+                    .line_addresses
+                    .iter()
+                    .find(|(_, line_address)| *line_address == address)
+                    .map(|(line, _)| *line);
+
+                let mut frame = vec![
+                    ("id", id.into()),
+                    ("name", name.into()),
+                    ("line", line.unwrap_or(0).into()),
+                    ("column", (line.is_some() as u64).into()),
+                    (
+                        "instructionPointerReference",
+                        format!("0x{:04X}", address).into(),
+                    ),
+                ];
+                if let (Some(_), Some(path)) = (line, &session.source_path) {
+                    frame.push(("source", Json::object(vec![("path", path.as_str().into())])));
+                }
+                Json::object(frame)
+            })
+            .collect();
+
+        let total = frames.len();
+        Ok(Json::object(vec![
+            ("stackFrames", frames.into()),
+            ("totalFrames", total.into()),
+        ]))
+    }
+
+    fn variables(&mut self, arguments: &Json) -> Result<Json, String> {
+        let reference = arguments
+            .get("variablesReference")
+            .and_then(Json::as_u64)
+            .unwrap_or(0);
+        let session = self.session()?;
+        let vm = session.debugger.vm();
+
+        let mut variables = Vec::new();
+        if reference == REGISTERS_REFERENCE {
+            let registers = (0..8)
+                .filter_map(Register::from_index)
+                .chain(Some(Register::RPC));
+            for register in registers {
+                variables.push(variable(
+                    &register.to_string(),
+                    describe_value(vm.register(register)),
+                ));
+            }
+
+            let flags = vm.condition_flags();
+            let cc: String = [
+                (flags.negative, 'N'),
+                (flags.zero, 'Z'),
+                (flags.positive, 'P'),
+            ]
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, flag)| *flag)
+            .collect();
+            variables.push(variable("CC", cc));
+        }
+
+        Ok(Json::object(vec![("variables", variables.into())]))
+    }
+
+    // The debug console accepts any of the debugger prompt's commands.
+    // Watch and hover expressions can be registers, addresses or labels.
+    fn evaluate(&mut self, arguments: &Json) -> Result<Json, String> {
+        let expression = arguments
+            .get("expression")
+            .and_then(Json::as_str)
+            .unwrap_or("")
+            .trim();
+        let context = arguments.get("context").and_then(Json::as_str);
+        let session = self.session()?;
+
+        let result = if context == Some("repl") {
+            session.debugger.execute(expression).0
+        } else {
+            let vm = session.debugger.vm();
+            let value = match parse_register(expression) {
+                Some(register) => vm.register(register),
+                None => vm.memory_at(parse_value(expression, session.debugger.symbols())?),
+            };
+            describe_value(value)
+        };
+
+        Ok(Json::object(vec![
+            ("result", result.into()),
+            ("variablesReference", 0u64.into()),
+        ]))
+    }
+
+    fn resume(&mut self, single_step: bool) -> LC3Result<()> {
+        let session = match self.session.as_mut() {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+
+        let stop = if single_step {
+            session.debugger.step().map(|outcome| {
+                let hits = session.debugger.last_watch_hits();
+                if outcome.halted {
+                    Some(DebugStop::Halted)
+                } else if !hits.is_empty() {
+                    Some(DebugStop::Watchpoint(hits))
+                } else {
+                    None
+                }
+            })
+        } else {
+            session.debugger.continue_execution().map(Some)
+        };
+        self.send_output()?;
+
+        match stop {
+            Ok(None) => self.stopped("step", None),
+            Ok(Some(DebugStop::Breakpoint(_))) => self.stopped("breakpoint", None),
+            Ok(Some(DebugStop::Watchpoint(hits))) => {
+                let text: Vec<String> = hits.iter().map(|hit| hit.to_string()).collect();
+                self.stopped("data breakpoint", Some(text.join("\n")))
+            }
+            Ok(Some(DebugStop::Halted)) => {
+                self.event("exited", Json::object(vec![("exitCode", 0u64.into())]))?;
+                self.event("terminated", Json::object(vec![]))
+            }
+            Err(err) => self.stopped("exception", Some(err.to_string())),
+        }
+    }
+
+    fn send_output(&mut self) -> LC3Result<()> {
+        let output = match &self.session {
+            Some(session) => session.io.output.replace(String::new()),
+            None => return Ok(()),
+        };
+        if output.is_empty() {
+            return Ok(());
+        }
+        self.event(
+            "output",
+            Json::object(vec![
+                ("category", "stdout".into()),
+                ("output", output.into()),
+            ]),
+        )
+    }
+}
+
+impl Session {
+    fn add_breakpoint(&mut self, address: u16, breakpoint: &Json) -> Result<(), String> {
+        match breakpoint.get("condition").and_then(Json::as_str) {
+            Some(condition) if !condition.trim().is_empty() => {
+                let condition = Condition::parse(condition, self.debugger.symbols())?;
+                self.debugger.add_conditional_breakpoint(address, condition);
+            }
+            _ => self.debugger.add_breakpoint(address),
+        }
+        Ok(())
+    }
+}
+
+fn command_of(request: &Json) -> &str {
+    request.get("command").and_then(Json::as_str).unwrap_or("")
+}
+
+fn breakpoints_of(arguments: &Json) -> &[Json] {
+    arguments
+        .get("breakpoints")
+        .and_then(Json::as_array)
+        .unwrap_or(&[])
+}
+
+// Editors send addresses in C style hex, but accept anything the debugger
+// prompt does too
+fn parse_reference(reference: &str, symbols: Option<&SymbolTable>) -> Result<u16, String> {
+    match reference.strip_prefix("0x") {
+        Some(digits) => u16::from_str_radix(digits, 16)
+            .map_err(|_| format!("Invalid instruction reference '{}'", reference)),
+        None => parse_value(reference, symbols),
+    }
+}
+
+fn verified_breakpoint(line: Option<usize>, address: u16) -> Json {
+    let mut fields = vec![
+        ("verified", true.into()),
+        ("instructionReference", format!("0x{:04X}", address).into()),
+    ];
+    if let Some(line) = line {
+        fields.push(("line", line.into()));
+    }
+    Json::object(fields)
+}
+
+fn unverified_breakpoint(line: Option<usize>, message: String) -> Json {
+    let mut fields = vec![("verified", false.into()), ("message", message.into())];
+    if let Some(line) = line {
+        fields.push(("line", line.into()));
+    }
+    Json::object(fields)
+}
+
+fn variable(name: &str, value: String) -> Json {
+    Json::object(vec![
+        ("name", name.into()),
+        ("value", value.into()),
+        ("variablesReference", 0u64.into()),
+    ])
+}
+
+fn describe_value(value: u16) -> String {
+    format!("x{:04X} (#{})", value, value as i16)
+}
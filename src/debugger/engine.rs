@@ -0,0 +1,526 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+use super::command::{DebugCommand, HELP, LIST_LENGTH};
+use super::condition::Condition;
+use super::history::{History, RecordPlugin};
+use super::watch::{Access, WatchHit, WatchPlugin, WatchState, WatchTarget};
+use crate::disasm::disassemble;
+use crate::error::{BoxErrors, LC3Result};
+use crate::io::IOHandle;
+use crate::plugin::builtin::{CallStack, CallStackPlugin};
+use crate::register::Register;
+use crate::symbols::SymbolTable;
+use crate::vm::{StepOutcome, VM};
+
+// Snapshot interval used when recording is started from a command. Each
+// snapshot is around 128KB.
+pub(crate) const DEFAULT_RECORD_INTERVAL: u64 = 1000;
+
+// Why continue_execution handed control back to the debugger
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugStop {
+    Breakpoint(u16),
+    // Every watched access made by the last instruction
+    Watchpoint(Vec<WatchHit>),
+    Halted,
+}
+
+pub struct Debugger<IOType: IOHandle> {
+    vm: VM<IOType>,
+    // Breakpoints with a condition only stop execution when it holds
+    breakpoints: BTreeMap<u16, Option<Condition>>,
+    watch: Rc<RefCell<WatchState>>,
+    calls: CallStackPlugin,
+    // Only present while recording
+    history: Rc<RefCell<Option<History>>>,
+    symbols: Option<SymbolTable>,
+}
+
+impl<IOType: IOHandle> Debugger<IOType> {
+    // The VM should already have its program loaded
+    pub fn new(mut vm: VM<IOType>) -> Self {
+        let watch = Rc::new(RefCell::new(WatchState::default()));
+        vm.add_plugin(Box::new(WatchPlugin {
+            state: watch.clone(),
+        }));
+        let calls = CallStackPlugin::new();
+        vm.add_plugin(Box::new(calls.clone()));
+        let history = Rc::new(RefCell::new(None));
+        vm.add_plugin(Box::new(RecordPlugin {
+            history: history.clone(),
+        }));
+
+        Self {
+            vm,
+            breakpoints: BTreeMap::new(),
+            watch,
+            calls,
+            history,
+            symbols: None,
+        }
+    }
+
+    pub fn with_symbols(mut self, symbols: SymbolTable) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    pub fn vm(&self) -> &VM<IOType> {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut VM<IOType> {
+        &mut self.vm
+    }
+
+    pub fn into_vm(self) -> VM<IOType> {
+        self.vm
+    }
+
+    pub fn symbols(&self) -> Option<&SymbolTable> {
+        self.symbols.as_ref()
+    }
+
+    // Subroutine calls made by the program that haven't returned yet
+    pub fn call_stack(&self) -> CallStack {
+        self.calls.call_stack()
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address, None);
+    }
+
+    pub fn add_conditional_breakpoint(&mut self, address: u16, condition: Condition) {
+        self.breakpoints.insert(address, Some(condition));
+    }
+
+    // Returns false if there was no breakpoint at the address
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address).is_some()
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.keys().copied()
+    }
+
+    pub fn has_breakpoint(&self, address: u16) -> bool {
+        self.breakpoints.contains_key(&address)
+    }
+
+    fn should_break(&self, address: u16) -> bool {
+        match self.breakpoints.get(&address) {
+            Some(Some(condition)) => condition.evaluate(&self.vm),
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    // Watch for reads and/or writes of a memory address or register.
+    // Watching a target that's already watched replaces the access kind.
+    pub fn add_watchpoint(&mut self, target: WatchTarget, access: Access) {
+        let mut watch = self.watch.borrow_mut();
+        watch.watchpoints.retain(|(watched, _)| *watched != target);
+        watch.watchpoints.push((target, access));
+    }
+
+    // Returns false if the target wasn't being watched
+    pub fn remove_watchpoint(&mut self, target: WatchTarget) -> bool {
+        let mut watch = self.watch.borrow_mut();
+        let before = watch.watchpoints.len();
+        watch.watchpoints.retain(|(watched, _)| *watched != target);
+        watch.watchpoints.len() != before
+    }
+
+    pub fn watchpoints(&self) -> Vec<(WatchTarget, Access)> {
+        self.watch.borrow().watchpoints.clone()
+    }
+
+    // Watched accesses made by the most recent step
+    pub fn last_watch_hits(&self) -> Vec<WatchHit> {
+        self.watch.borrow().hits.clone()
+    }
+
+    pub fn step(&mut self) -> LC3Result<StepOutcome> {
+        self.watch.borrow_mut().hits.clear();
+        let outcome = self.vm.step()?;
+        for hit in &mut self.watch.borrow_mut().hits {
+            hit.pc = outcome.address;
+        }
+        if let Some(history) = self.history.borrow_mut().as_mut() {
+            let (vm, calls) = (&self.vm, &self.calls);
+            history.record_step(|| (vm.snapshot(), calls.call_stack()));
+        }
+        Ok(outcome)
+    }
+
+    // Start keeping enough history to step backwards, with a snapshot every
+    // interval instructions. Smaller intervals make stepping back quicker
+    // but use more memory. Changes made through vm_mut while recording
+    // aren't part of the history and can be lost by stepping back.
+    pub fn start_recording(&mut self, interval: u64) {
+        *self.history.borrow_mut() = Some(History::new(
+            interval,
+            self.vm.snapshot(),
+            self.calls.call_stack(),
+        ));
+    }
+
+    pub fn stop_recording(&mut self) {
+        *self.history.borrow_mut() = None;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.history.borrow().is_some()
+    }
+
+    // Undo the most recent instruction by restoring the nearest snapshot
+    // before it and re-executing forwards, feeding the program the same
+    // input it read the first time. Returns false if there's nothing
+    // recorded to step back over.
+    pub fn step_back(&mut self) -> LC3Result<bool> {
+        let (start, target, (snapshot, calls), inputs) = {
+            let mut history = self.history.borrow_mut();
+            let history = match history.as_mut() {
+                Some(history) if history.steps > 0 => history,
+                _ => return Ok(false),
+            };
+            let target = history.steps - 1;
+            let (start, checkpoint, inputs) = history.rewind(target);
+            history.replaying = true;
+            (start, target, checkpoint, inputs)
+        };
+
+        self.vm.restore(&snapshot);
+        self.calls.restore(calls);
+        self.vm.start_replay(inputs);
+        let mut result = Ok(true);
+        for _ in start..target {
+            if let Err(err) = self.vm.step() {
+                result = Err(err);
+                break;
+            }
+        }
+        self.vm.stop_replay();
+
+        self.watch.borrow_mut().hits.clear();
+        if let Some(history) = self.history.borrow_mut().as_mut() {
+            history.replaying = false;
+        }
+        result
+    }
+
+    // Run until the program halts, the PC lands on a breakpoint or a
+    // watchpoint is hit. The instruction under the PC is always executed
+    // first, so continuing from a breakpoint doesn't immediately stop on it
+    // again.
+    pub fn continue_execution(&mut self) -> LC3Result<DebugStop> {
+        loop {
+            if let Some(stop) = self.continue_for(u64::MAX)? {
+                return Ok(stop);
+            }
+        }
+    }
+
+    // Like continue_execution, but gives up after max_instructions and
+    // returns None so front ends can stay responsive while a long running
+    // program executes
+    pub fn continue_for(&mut self, max_instructions: u64) -> LC3Result<Option<DebugStop>> {
+        for _ in 0..max_instructions {
+            let outcome = self.step()?;
+            let hits = self.last_watch_hits();
+            if !hits.is_empty() {
+                return Ok(Some(DebugStop::Watchpoint(hits)));
+            }
+            if outcome.halted {
+                return Ok(Some(DebugStop::Halted));
+            }
+            if self.should_break(outcome.pc) {
+                return Ok(Some(DebugStop::Breakpoint(outcome.pc)));
+            }
+        }
+        Ok(None)
+    }
+
+    // Carry out a single line of debugger input, returning the text to show
+    // the user and whether they asked to quit. Mistakes in the command and
+    // errors from the VM are reported in the text rather than as an Err so
+    // an interactive session can carry on.
+    pub fn execute(&mut self, line: &str) -> (String, bool) {
+        let command = match DebugCommand::parse(line, self.symbols.as_ref()) {
+            Ok(command) => command,
+            Err(message) => return (message, false),
+        };
+
+        if command == DebugCommand::Quit {
+            return (String::new(), true);
+        }
+
+        let output = self
+            .run_command(&command)
+            .unwrap_or_else(|err| format!("Error: {}", err));
+        (output, false)
+    }
+
+    fn run_command(&mut self, command: &DebugCommand) -> LC3Result<String> {
+        let output = match command {
+            DebugCommand::Step(count) => {
+                let mut last = None;
+                for _ in 0..*count {
+                    let outcome = self.step()?;
+                    let stop = outcome.halted || !self.watch.borrow().hits.is_empty();
+                    last = Some(outcome);
+                    if stop {
+                        break;
+                    }
+                }
+
+                let mut lines: Vec<String> = self
+                    .last_watch_hits()
+                    .iter()
+                    .map(|hit| hit.to_string())
+                    .collect();
+                match last {
+                    Some(outcome) if outcome.halted => lines.push("Program halted".to_string()),
+                    _ => lines.push(self.describe_pc()),
+                }
+                lines.join("\n")
+            }
+            DebugCommand::StepBack(count) => {
+                let mut stepped = 0;
+                while stepped < *count && self.step_back()? {
+                    stepped += 1;
+                }
+                if stepped == 0 {
+                    "Nothing to step back over, use record to start recording".to_string()
+                } else {
+                    self.describe_pc()
+                }
+            }
+            DebugCommand::Record(Some(interval)) => {
+                self.start_recording(*interval);
+                format!("Recording, with a snapshot every {} instructions", interval)
+            }
+            DebugCommand::Record(None) => {
+                self.stop_recording();
+                "Recording stopped".to_string()
+            }
+            DebugCommand::Continue => match self.continue_execution()? {
+                DebugStop::Halted => "Program halted".to_string(),
+                DebugStop::Watchpoint(hits) => {
+                    let mut lines: Vec<String> = hits.iter().map(|hit| hit.to_string()).collect();
+                    lines.push(self.describe_pc());
+                    lines.join("\n")
+                }
+                DebugStop::Breakpoint(address) => {
+                    format!(
+                        "Breakpoint at {}\n{}",
+                        self.name(address),
+                        self.describe_pc()
+                    )
+                }
+            },
+            DebugCommand::Break(address, condition) => {
+                self.breakpoints.insert(*address, condition.clone());
+                match condition {
+                    Some(condition) => format!(
+                        "Breakpoint set at {} if {}",
+                        self.name(*address),
+                        condition.source()
+                    ),
+                    None => format!("Breakpoint set at {}", self.name(*address)),
+                }
+            }
+            DebugCommand::Delete(address) => {
+                if self.remove_breakpoint(*address) {
+                    format!("Breakpoint at {} removed", self.name(*address))
+                } else {
+                    format!("No breakpoint at {}", self.name(*address))
+                }
+            }
+            DebugCommand::Breakpoints => {
+                if self.breakpoints.is_empty() {
+                    "No breakpoints set".to_string()
+                } else {
+                    self.breakpoints
+                        .iter()
+                        .map(|(address, condition)| match condition {
+                            Some(condition) => {
+                                format!("{} if {}", self.name(*address), condition.source())
+                            }
+                            None => self.name(*address),
+                        })
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                }
+            }
+            DebugCommand::Watch(target, access) => {
+                self.add_watchpoint(*target, *access);
+                format!("Watching {} for {}", target, describe_access(*access))
+            }
+            DebugCommand::Unwatch(target) => {
+                if self.remove_watchpoint(*target) {
+                    format!("Stopped watching {}", target)
+                } else {
+                    format!("{} isn't being watched", target)
+                }
+            }
+            DebugCommand::Watchpoints => {
+                let watchpoints = self.watchpoints();
+                if watchpoints.is_empty() {
+                    "No watchpoints set".to_string()
+                } else {
+                    watchpoints
+                        .iter()
+                        .map(|(target, access)| {
+                            format!("{} ({})", target, describe_access(*access))
+                        })
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                }
+            }
+            DebugCommand::Registers => self.describe_registers(),
+            DebugCommand::Backtrace => self
+                .call_stack()
+                .backtrace(self.vm.pc(), self.symbols.as_ref()),
+            DebugCommand::Examine(start, end) if start == end => self.describe_address(*start),
+            DebugCommand::Examine(start, end) => self.dump_memory(*start, *end),
+            DebugCommand::Deposit(address, words) => {
+                self.vm.patch(*address, words)?;
+                (0..words.len())
+                    .map(|offset| self.describe_address(address + offset as u16))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            }
+            DebugCommand::SetRegister(register, value) => {
+                self.vm.set_register(*register, *value);
+                format!("{} x{:04X}", register, value)
+            }
+            DebugCommand::List(start, end) => {
+                let start = start.unwrap_or_else(|| self.vm.pc());
+                let end = end.unwrap_or_else(|| start.saturating_add(LIST_LENGTH - 1));
+                (start..=end)
+                    .map(|address| self.describe_address(address))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            }
+            DebugCommand::Help => HELP.to_string(),
+            DebugCommand::Quit => String::new(),
+        };
+
+        Ok(output)
+    }
+
+    // Read commands from input until it runs out or the user quits
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> LC3Result<()> {
+        writeln!(output, "{}", self.describe_pc()).map_io_error()?;
+        write!(output, "(lc3rs) ").map_io_error()?;
+        output.flush().map_io_error()?;
+
+        for line in input.lines() {
+            let line = line.map_io_error()?;
+            if !line.trim().is_empty() {
+                let (text, quit) = self.execute(&line);
+                if quit {
+                    break;
+                }
+                writeln!(output, "{}", text).map_io_error()?;
+            }
+            write!(output, "(lc3rs) ").map_io_error()?;
+            output.flush().map_io_error()?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn name(&self, address: u16) -> String {
+        match &self.symbols {
+            Some(symbols) => symbols.describe(address),
+            None => format!("x{:04X}", address),
+        }
+    }
+
+    fn describe_pc(&self) -> String {
+        format!("PC {}", self.describe_address(self.vm.pc()))
+    }
+
+    fn describe_address(&self, address: u16) -> String {
+        let word = self.vm.memory_at(address);
+        format!(
+            "{}: x{:04X}  {}",
+            self.name(address),
+            word,
+            disassemble(address, word)
+        )
+    }
+
+    // Eight words to a line, with the characters they'd print as
+    fn dump_memory(&self, start: u16, end: u16) -> String {
+        self.vm.memory()[start as usize..=end as usize]
+            .chunks(8)
+            .enumerate()
+            .map(|(index, words)| {
+                let hex: Vec<String> = words.iter().map(|word| format!("x{:04X}", word)).collect();
+                let text: String = words
+                    .iter()
+                    .map(|word| match *word {
+                        0x20..=0x7E => char::from(*word as u8),
+                        _ => '.',
+                    })
+                    .collect();
+                let address = start as usize + index * 8;
+                format!("x{:04X}  {:<47}  {}", address, hex.join(" "), text)
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn describe_registers(&self) -> String {
+        let registers = [
+            Register::RR0,
+            Register::RR1,
+            Register::RR2,
+            Register::RR3,
+            Register::RR4,
+            Register::RR5,
+            Register::RR6,
+            Register::RR7,
+        ];
+
+        let values: Vec<String> = registers
+            .iter()
+            .enumerate()
+            .map(|(index, register)| format!("R{} x{:04X}", index, self.vm.register(*register)))
+            .collect();
+
+        let flags = self.vm.condition_flags();
+        let mut cc = String::new();
+        if flags.negative {
+            cc.push('N');
+        }
+        if flags.zero {
+            cc.push('Z');
+        }
+        if flags.positive {
+            cc.push('P');
+        }
+
+        format!(
+            "{}\n{}\nPC x{:04X}  CC {}",
+            values[..4].join("  "),
+            values[4..].join("  "),
+            self.vm.pc(),
+            cc
+        )
+    }
+}
+
+fn describe_access(access: Access) -> &'static str {
+    match access {
+        Access::Read => "reads",
+        Access::Write => "writes",
+        Access::ReadWrite => "reads and writes",
+    }
+}
@@ -0,0 +1,89 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::error::LC3Result;
+use crate::io::{IOHandle, RecordedInput};
+use crate::plugin::builtin::CallStack;
+use crate::plugin::{Event, Plugin};
+use crate::snapshot::Snapshot;
+use crate::vm::VmState;
+
+// The machine and the debugger's view of its call stack at one point in
+// time
+pub(crate) type Checkpoint = (Snapshot, CallStack);
+
+// What's needed to get back to any instruction executed since recording
+// started: a checkpoint every interval instructions, plus every input the
+// program read so re-executing from a checkpoint sees the same keys.
+pub(crate) struct History {
+    interval: u64,
+    // Instructions executed since recording started
+    pub(crate) steps: u64,
+    checkpoints: Vec<(u64, Checkpoint)>,
+    inputs: Vec<(u64, RecordedInput)>,
+    // Set while re-executing so replayed input isn't recorded twice
+    pub(crate) replaying: bool,
+}
+
+impl History {
+    pub(crate) fn new(interval: u64, start: Snapshot, calls: CallStack) -> Self {
+        Self {
+            interval: interval.max(1),
+            steps: 0,
+            checkpoints: vec![(0, (start, calls))],
+            inputs: Vec::new(),
+            replaying: false,
+        }
+    }
+
+    // Called after every recorded instruction with the state it left behind
+    pub(crate) fn record_step<F: FnOnce() -> Checkpoint>(&mut self, checkpoint: F) {
+        self.steps += 1;
+        if self.steps.is_multiple_of(self.interval) {
+            self.checkpoints.push((self.steps, checkpoint()));
+        }
+    }
+
+    // Forget everything after target and return the nearest checkpoint at
+    // or before it, along with the input read between the two
+    pub(crate) fn rewind(&mut self, target: u64) -> (u64, Checkpoint, VecDeque<RecordedInput>) {
+        self.checkpoints.retain(|(step, _)| *step <= target);
+        self.inputs.retain(|(step, _)| *step < target);
+        self.steps = target;
+
+        // The checkpoint at step 0 is never removed, so there's always one
+        let (start, checkpoint) = self.checkpoints.last().cloned().unwrap();
+        let inputs = self
+            .inputs
+            .iter()
+            .filter(|(step, _)| *step >= start)
+            .map(|(_, input)| *input)
+            .collect();
+        (start, checkpoint, inputs)
+    }
+}
+
+// Logs the input read by each instruction into the shared history
+pub(crate) struct RecordPlugin {
+    pub(crate) history: Rc<RefCell<Option<History>>>,
+}
+
+impl<IOType: IOHandle> Plugin<IOType> for RecordPlugin {
+    fn handle_event(&mut self, _vm: &mut VmState<IOType>, event: &Event) -> LC3Result<()> {
+        let mut history = self.history.borrow_mut();
+        let history = match history.as_mut() {
+            Some(history) if !history.replaying => history,
+            _ => return Ok(()),
+        };
+
+        let input = match event {
+            Event::CharGet { ch } => RecordedInput::Char(*ch),
+            Event::KeyDownGet { value } => RecordedInput::KeyDown(*value),
+            _ => return Ok(()),
+        };
+        // The step counter is only bumped once the instruction completes
+        history.inputs.push((history.steps, input));
+        Ok(())
+    }
+}
@@ -0,0 +1,19 @@
+mod command;
+mod condition;
+mod dap;
+mod engine;
+mod history;
+#[cfg(not(target_arch = "wasm32"))]
+mod tui;
+mod watch;
+
+pub use command::DebugCommand;
+pub use condition::Condition;
+pub use dap::{DapIOHandle, DapServer};
+pub use engine::{DebugStop, Debugger};
+#[cfg(not(target_arch = "wasm32"))]
+pub use tui::{Tui, TuiIOHandle};
+pub use watch::{Access, WatchHit, WatchTarget};
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod test;
@@ -0,0 +1,514 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use super::{Access, Condition, DapServer, DebugStop, Debugger, Tui, WatchHit, WatchTarget};
+use crate::asm::assemble;
+use crate::error::{LC3Error, LC3Result};
+use crate::interrupt::{PSR_USER, VECTOR_TABLE};
+use crate::io::TestIOHandle;
+use crate::json::Json;
+use crate::register::Register::{RR0, RR1, RR2, RR6};
+use crate::vm::VM;
+
+fn debugger_for(source: &str) -> LC3Result<Debugger<TestIOHandle>> {
+    let assembly = assemble(source)?;
+    let mut vm = VM::new_with_io(TestIOHandle::new());
+    vm.load_object(&assembly.to_object())?;
+    Ok(Debugger::new(vm).with_symbols(assembly.symbols))
+}
+
+const COUNTER: &str = r#"
+        .ORIG x3000
+        AND R1, R1, #0
+LOOP    ADD R1, R1, #1
+        ADD R2, R1, #-3
+        BRn LOOP
+        HALT
+        .END
+"#;
+
+#[test]
+fn can_stop_at_breakpoints() -> LC3Result<()> {
+    let mut debugger = debugger_for(COUNTER)?;
+    debugger.add_breakpoint(0x3002);
+
+    for expected in 1..=3 {
+        assert_eq!(
+            debugger.continue_execution()?,
+            DebugStop::Breakpoint(0x3002)
+        );
+        assert_eq!(debugger.vm().register(RR1), expected);
+    }
+
+    assert!(debugger.remove_breakpoint(0x3002));
+    assert_eq!(debugger.continue_execution()?, DebugStop::Halted);
+
+    Ok(())
+}
+
+#[test]
+fn cant_continue_past_reserved_op_code() -> LC3Result<()> {
+    // With no operating system there's nothing to handle the exception, so
+    // the run fails rather than pushing onto a stack at x0000
+    let mut debugger = debugger_for(".ORIG x3000\n.FILL xD000\n.END")?;
+    assert!(matches!(
+        debugger.continue_execution(),
+        Err(LC3Error::IllegalOpcode { word: 0xD000 })
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn can_stop_at_conditional_breakpoints() -> LC3Result<()> {
+    let mut debugger = debugger_for(COUNTER)?;
+    let condition = Condition::parse("R1 == 2", None).unwrap();
+    debugger.add_conditional_breakpoint(0x3002, condition);
+
+    assert_eq!(
+        debugger.continue_execution()?,
+        DebugStop::Breakpoint(0x3002)
+    );
+    assert_eq!(debugger.vm().register(RR1), 2);
+    assert_eq!(debugger.continue_execution()?, DebugStop::Halted);
+
+    let mut debugger = debugger_for(COUNTER)?;
+    let (output, _) = debugger.execute("break x3002 if R1 >= #3 && Z");
+    assert_eq!(output, "Breakpoint set at x3002 if R1 >= #3 && Z");
+    let (output, _) = debugger.execute("breakpoints");
+    assert_eq!(output, "x3002 if R1 >= #3 && Z");
+    debugger.execute("continue");
+    assert_eq!(debugger.vm().register(RR1), 3);
+
+    Ok(())
+}
+
+#[test]
+fn can_execute_commands() -> LC3Result<()> {
+    let mut debugger = debugger_for(COUNTER)?;
+
+    let (output, _) = debugger.execute("break LOOP");
+    assert_eq!(output, "Breakpoint set at LOOP");
+
+    let (output, _) = debugger.execute("continue");
+    assert_eq!(output, "Breakpoint at LOOP\nPC LOOP: x1261  ADD R1, R1, #1");
+
+    let (output, _) = debugger.execute("step 2");
+    assert_eq!(output, "PC x3003: x09FD  BRn x3001");
+
+    let (output, _) = debugger.execute("deposit x4000 x1234");
+    assert_eq!(output, "x4000: x1234  ADD R1, R0, #-12");
+    let (output, _) = debugger.execute("deposit x4001 xF025 #0");
+    assert_eq!(output, "x4001: xF025  HALT\nx4002: x0000  .FILL x0000");
+    let (output, _) = debugger.execute("deposit xFFFF #1 #2");
+    assert!(output.contains("past the end of memory"));
+
+    let (output, _) = debugger.execute("registers");
+    assert!(output.starts_with("R0 x0000  R1 x0001"));
+    assert!(output.ends_with("PC x3003  CC N"));
+
+    let (output, quit) = debugger.execute("frobnicate");
+    assert_eq!(output, "Unknown command 'frobnicate', try help");
+    assert!(!quit);
+
+    let (_, quit) = debugger.execute("quit");
+    assert!(quit);
+
+    Ok(())
+}
+
+#[test]
+fn can_run_monitor_commands() -> LC3Result<()> {
+    let mut debugger = debugger_for(COUNTER)?;
+
+    let (output, _) = debugger.execute("d x3006 x0041");
+    assert_eq!(output, "x3006: x0041  .FILL x0041");
+    let (output, _) = debugger.execute("x x3000 x3008");
+    assert_eq!(
+        output,
+        "x3000  x5260 x1261 x147D x09FD xF025 x0000 x0041 x0000  ......A.\n\
+         x3008  x0000                                            ."
+    );
+
+    let (output, _) = debugger.execute("d R2 xFFFF");
+    assert_eq!(output, "R2 xFFFF");
+    assert_eq!(debugger.vm().register(RR2), 0xFFFF);
+    let (output, _) = debugger.execute("deposit PC LOOP");
+    assert_eq!(output, "PC x3001");
+
+    let (output, _) = debugger.execute("list");
+    assert_eq!(output.lines().count(), 10);
+    assert!(output.starts_with("LOOP: x1261  ADD R1, R1, #1\nx3002: x147D  ADD R2, R1, #-3"));
+    let (output, _) = debugger.execute("l x3003 x3004");
+    assert_eq!(output, "x3003: x09FD  BRn x3001\nx3004: xF025  HALT");
+
+    let (output, _) = debugger.execute("x x3004 x3000");
+    assert_eq!(output, "The end of the range comes before the start");
+
+    Ok(())
+}
+
+#[test]
+fn can_run_repl() -> LC3Result<()> {
+    let mut debugger = debugger_for(COUNTER)?;
+    let input = "step\n\nexamine x3000\nquit\nstep\n".as_bytes();
+    let mut output = Vec::new();
+
+    debugger.repl(input, &mut output)?;
+
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(
+        output,
+        "PC x3000: x5260  AND R1, R1, #0\n\
+         (lc3rs) PC LOOP: x1261  ADD R1, R1, #1\n\
+         (lc3rs) (lc3rs) x3000: x5260  AND R1, R1, #0\n\
+         (lc3rs) "
+    );
+
+    Ok(())
+}
+
+#[test]
+fn can_stop_at_watchpoints() -> LC3Result<()> {
+    let source = r#"
+        .ORIG x3000
+        LD R1, VALUE
+        ADD R1, R1, #1
+        ST R1, VALUE
+        HALT
+VALUE   .FILL #41
+        .END
+    "#;
+
+    let mut debugger = debugger_for(source)?;
+    debugger.add_watchpoint(WatchTarget::Memory(0x3004), Access::Write);
+    debugger.add_watchpoint(WatchTarget::Register(RR2), Access::ReadWrite);
+
+    assert_eq!(
+        debugger.continue_execution()?,
+        DebugStop::Watchpoint(vec![WatchHit {
+            target: WatchTarget::Memory(0x3004),
+            access: Access::Write,
+            old_value: 41,
+            new_value: 42,
+            pc: 0x3002,
+        }])
+    );
+    assert_eq!(debugger.continue_execution()?, DebugStop::Halted);
+
+    let mut debugger = debugger_for(source)?;
+    let (output, _) = debugger.execute("watch R1");
+    assert_eq!(output, "Watching R1 for writes");
+    let (output, _) = debugger.execute("continue");
+    assert_eq!(
+        output,
+        "Watchpoint: R1 changed from x0000 to x0029 at x3000\nPC x3001: x1261  ADD R1, R1, #1"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn can_step_back() -> LC3Result<()> {
+    let source = r#"
+        .ORIG x3000
+        GETC
+        ST R0, SAVED
+        GETC
+        ADD R1, R1, R0
+        ST R1, SAVED
+        HALT
+SAVED   .FILL #0
+        .END
+    "#;
+    let assembly = assemble(source)?;
+    let mut io_handle = TestIOHandle::new();
+    for key in "abc".chars() {
+        io_handle.add_key_press(key);
+    }
+    let mut vm = VM::new_with_io(io_handle);
+    vm.load_object(&assembly.to_object())?;
+    let mut debugger = Debugger::new(vm);
+
+    let (output, _) = debugger.execute("back");
+    assert_eq!(
+        output,
+        "Nothing to step back over, use record to start recording"
+    );
+
+    debugger.start_recording(2);
+    for _ in 0..5 {
+        debugger.step()?;
+    }
+    assert_eq!(debugger.vm().memory_at(0x3006), 'b' as u16);
+
+    assert!(debugger.step_back()?);
+    assert_eq!(debugger.vm().pc(), 0x3004);
+    assert_eq!(debugger.vm().memory_at(0x3006), 'a' as u16);
+    assert_eq!(debugger.vm().register(RR1), 'b' as u16);
+
+    let (output, _) = debugger.execute("back 2");
+    assert_eq!(output, "PC x3002: xF020  GETC");
+    assert_eq!(debugger.vm().register(RR0), 'a' as u16);
+    assert_eq!(debugger.vm().register(RR1), 0);
+
+    // Going forward again reads fresh input
+    debugger.step()?;
+    assert_eq!(debugger.vm().register(RR0), 'c' as u16);
+
+    for _ in 0..3 {
+        assert!(debugger.step_back()?);
+    }
+    assert_eq!(debugger.vm().pc(), 0x3000);
+    assert!(!debugger.step_back()?);
+
+    Ok(())
+}
+
+#[test]
+fn can_step_back_over_interrupts() -> LC3Result<()> {
+    let mut debugger = debugger_for(
+        r#"
+        .ORIG x3000
+LOOP    ADD R1, R1, #1
+        BR LOOP
+        .END
+    "#,
+    )?;
+    let handler = assemble(
+        r#"
+        .ORIG x1000
+        ADD R2, R2, #1
+        RTI
+        .END
+    "#,
+    )?;
+    let vm = debugger.vm_mut();
+    vm.load_program_at(handler.origin, &handler.words)?;
+    vm.load_program_at(VECTOR_TABLE + 0x81, &[0x1000])?;
+    // A timer interrupt every 4 instructions, taken from user mode
+    vm.set_memory_at(0xFE0C, 4);
+    vm.set_memory_at(0xFE0A, 0x4001);
+    vm.set_psr(PSR_USER);
+    vm.set_register(RR6, 0x4000);
+
+    // Stepping back has to land on the same state as stepping forwards did,
+    // inside the handler and out of it
+    debugger.start_recording(3);
+    let state = |debugger: &Debugger<TestIOHandle>| {
+        let vm = debugger.vm();
+        (vm.pc(), vm.psr(), vm.register(RR6), vm.register(RR2))
+    };
+    let mut states = vec![state(&debugger)];
+    for _ in 0..12 {
+        debugger.step()?;
+        states.push(state(&debugger));
+    }
+    assert!(states.iter().any(|(_, psr, _, _)| psr & PSR_USER == 0));
+    assert!(debugger.vm().register(RR2) > 0);
+
+    states.pop();
+    while let Some(expected) = states.pop() {
+        assert!(debugger.step_back()?);
+        assert_eq!(state(&debugger), expected);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn can_show_backtrace() -> LC3Result<()> {
+    let mut debugger = debugger_for(
+        r#"
+        .ORIG x3000
+        JSR OUTER
+        HALT
+OUTER   ST R7, SAVE
+        JSR INNER
+        LD R7, SAVE
+        RET
+INNER   ADD R0, R0, #1
+        RET
+SAVE    .BLKW 1
+        .END
+    "#,
+    )?;
+    debugger.start_recording(2);
+    debugger.add_breakpoint(0x3007);
+    debugger.continue_execution()?;
+
+    let (output, _) = debugger.execute("bt");
+    assert_eq!(output, "#0  x3007  in INNER\n#1  x3003  in OUTER\n#2  x3000");
+    assert_eq!(debugger.call_stack().max_depth(), 2);
+
+    debugger.step_back()?;
+    let (output, _) = debugger.execute("bt");
+    assert_eq!(output, "#0  x3006  in INNER\n#1  x3003  in OUTER\n#2  x3000");
+
+    // Stepping back over the JSR leaves INNER again
+    debugger.step_back()?;
+    let (output, _) = debugger.execute("backtrace");
+    assert_eq!(output, "#0  x3003  in OUTER\n#1  x3000");
+
+    Ok(())
+}
+
+#[test]
+fn can_drive_tui() -> LC3Result<()> {
+    let source = r#"
+        .ORIG x3000
+        LEA R0, MSG
+        PUTS
+LOOP    ADD R1, R1, #1
+        HALT
+MSG     .STRINGZ "hi\nthere"
+        .END
+    "#;
+    let assembly = assemble(source)?;
+    let mut tui = Tui::new(&assembly.to_object(), Some(assembly.symbols))?;
+    let press = |tui: &mut Tui, keys: &[KeyCode]| -> LC3Result<bool> {
+        let mut running = true;
+        for code in keys {
+            running = tui.handle_key(KeyEvent {
+                code: *code,
+                modifiers: KeyModifiers::NONE,
+            })?;
+        }
+        Ok(running)
+    };
+    let typed = |text: &str| -> Vec<KeyCode> {
+        let mut keys: Vec<KeyCode> = text.chars().map(KeyCode::Char).collect();
+        keys.push(KeyCode::Enter);
+        keys
+    };
+
+    let steps = [KeyCode::Char('s'), KeyCode::Char('s'), KeyCode::Char('b')];
+    assert!(press(&mut tui, &steps)?);
+    assert!(press(&mut tui, &typed("mMSG"))?);
+
+    let (lines, cursor_row) = tui.render(100, 24);
+    assert_eq!(lines.len(), 24);
+    assert!(lines.iter().all(|line| line.len() == 100));
+    assert!(lines[0].contains("Breakpoint set at LOOP"));
+    assert!(lines[2].starts_with("R0 x3004  R1 x0000  R2 x0000  R3 x0000"));
+    assert!(lines[2].contains("x3004  0068 0069 000A 0074"));
+    assert!(lines[4].starts_with("PC x3002  CC P"));
+
+    let cursor_row = cursor_row.unwrap();
+    assert!(lines[cursor_row].starts_with(">* x3002 LOOP     x1261  ADD R1, R1, #1"));
+    assert!(lines[cursor_row - 1].starts_with("   x3001          xF022  PUTS"));
+    assert!(lines
+        .iter()
+        .any(|line| line.ends_with("hi") || line.contains(" hi ")));
+    assert!(lines.iter().any(|line| line.contains(" there ")));
+
+    assert!(press(&mut tui, &typed(":step"))?);
+    assert_eq!(tui.debugger().vm().pc(), 0x3003);
+    assert!(!press(&mut tui, &[KeyCode::Char('q')])?);
+
+    Ok(())
+}
+
+#[test]
+fn can_serve_dap() -> LC3Result<()> {
+    let path = std::env::temp_dir().join(format!("lc3rs_dap_{}.asm", std::process::id()));
+    std::fs::write(
+        &path,
+        ".ORIG x3000\nLEA R0, MSG\nPUTS\n\n; Count up\nLOOP ADD R1, R1, #1\nHALT\nMSG .STRINGZ \"hi\"\n.END\n",
+    )
+    .unwrap();
+
+    let requests = vec![
+        r#"{"seq":1,"type":"request","command":"initialize","arguments":{}}"#.to_string(),
+        format!(
+            r#"{{"seq":2,"type":"request","command":"launch","arguments":{{"program":{},"stopOnEntry":true}}}}"#,
+            Json::from(path.to_string_lossy().into_owned())
+        ),
+        r#"{"seq":3,"type":"request","command":"setBreakpoints","arguments":{"breakpoints":[{"line":4},{"line":20}]}}"#.to_string(),
+        r#"{"seq":4,"type":"request","command":"configurationDone"}"#.to_string(),
+        r#"{"seq":5,"type":"request","command":"continue","arguments":{"threadId":1}}"#.to_string(),
+        r#"{"seq":6,"type":"request","command":"stackTrace","arguments":{"threadId":1}}"#.to_string(),
+        r#"{"seq":7,"type":"request","command":"variables","arguments":{"variablesReference":1}}"#.to_string(),
+        r#"{"seq":8,"type":"request","command":"evaluate","arguments":{"expression":"MSG","context":"hover"}}"#.to_string(),
+        r#"{"seq":9,"type":"request","command":"continue","arguments":{"threadId":1}}"#.to_string(),
+        r#"{"seq":10,"type":"request","command":"disconnect"}"#.to_string(),
+    ];
+    let input: String = requests
+        .iter()
+        .map(|body| format!("Content-Length: {}\r\n\r\n{}", body.len(), body))
+        .collect();
+    let mut output = Vec::new();
+
+    DapServer::new(input.as_bytes(), &mut output).run()?;
+    std::fs::remove_file(&path).unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let messages: Vec<Json> = output
+        .split("Content-Length: ")
+        .skip(1)
+        .map(|message| Json::parse(message.split("\r\n\r\n").nth(1).unwrap()).unwrap())
+        .collect();
+    let summary: Vec<String> = messages
+        .iter()
+        .map(|message| {
+            let name = message
+                .get("command")
+                .or_else(|| message.get("event"))
+                .and_then(Json::as_str)
+                .unwrap();
+            match message.get("success").and_then(Json::as_bool) {
+                Some(false) => format!("{} failed", name),
+                _ => name.to_string(),
+            }
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            "initialize",
+            "initialized",
+            "launch",
+            "setBreakpoints",
+            "configurationDone",
+            "stopped",
+            "continue",
+            "output",
+            "stopped",
+            "stackTrace",
+            "variables",
+            "evaluate",
+            "continue",
+            "exited",
+            "terminated",
+            "disconnect",
+        ]
+    );
+
+    let body = |index: usize| messages[index].get("body").unwrap();
+    assert_eq!(
+        body(3).to_string(),
+        r#"{"breakpoints":[{"verified":true,"instructionReference":"0x3002","line":6},{"verified":false,"message":"No instruction at or after this line","line":20}]}"#
+    );
+    assert_eq!(body(5).get("reason").and_then(Json::as_str), Some("entry"));
+    assert_eq!(body(7).get("output").and_then(Json::as_str), Some("hi"));
+    assert_eq!(
+        body(8).get("reason").and_then(Json::as_str),
+        Some("breakpoint")
+    );
+
+    let frame = &body(9).get("stackFrames").and_then(Json::as_array).unwrap()[0];
+    assert_eq!(frame.get("name").and_then(Json::as_str), Some("LOOP"));
+    assert_eq!(frame.get("line").and_then(Json::as_u64), Some(6));
+
+    let variables = body(10).get("variables").and_then(Json::as_array).unwrap();
+    assert_eq!(variables.len(), 10);
+    assert_eq!(
+        variables[0].get("value").and_then(Json::as_str),
+        Some("x3004 (#12292)")
+    );
+    assert_eq!(
+        body(11).get("result").and_then(Json::as_str),
+        Some("x0068 (#104)")
+    );
+
+    Ok(())
+}
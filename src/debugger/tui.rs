@@ -0,0 +1,485 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{stdout, Write};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crossterm::cursor::MoveTo;
+use crossterm::event::{poll, read, Event, KeyCode, KeyEvent};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{size, Clear, ClearType};
+use crossterm::{execute, queue};
+
+use super::command::parse_value;
+use super::engine::{DebugStop, Debugger, DEFAULT_RECORD_INTERVAL};
+use crate::disasm::disassemble;
+use crate::error::{BoxErrors, LC3Result};
+use crate::io::{key_char, strip_escapes, IOHandle, TerminalGuard};
+use crate::register::Register;
+use crate::symbols::SymbolTable;
+use crate::vm::VM;
+use crate::wrapping_add;
+
+// Instructions to run between checks for Esc while continuing
+const CONTINUE_CHUNK: u64 = 10_000;
+const LEFT_WIDTH: usize = 48;
+const MEMORY_ROWS: u16 = 8;
+const WORDS_PER_ROW: u16 = 8;
+
+const KEY_HELP: &str =
+    "s step  p step back  c continue  b breakpoint  Up/Down move  m memory  PgUp/PgDn scroll  : command  q quit";
+
+// Program output is collected for the console pane rather than printed, and
+// keys typed while the program is running are queued up for it to read
+#[derive(Clone, Default)]
+pub struct TuiIOHandle {
+    console: Rc<RefCell<String>>,
+    pending: Rc<RefCell<VecDeque<char>>>,
+}
+
+impl IOHandle for TuiIOHandle {
+    fn getchar(&self) -> LC3Result<char> {
+        if let Some(ch) = self.pending.borrow_mut().pop_front() {
+            return Ok(ch);
+        }
+
+        loop {
+            if let Event::Key(key) = read().map_io_error()? {
+                if let Some(ch) = key_char(key) {
+                    return Ok(ch);
+                }
+            }
+        }
+    }
+
+    fn putchar(&self, ch: char) -> LC3Result<()> {
+        self.console.borrow_mut().push(ch);
+        Ok(())
+    }
+
+    fn is_key_down(&self) -> LC3Result<bool> {
+        if !self.pending.borrow().is_empty() {
+            return Ok(true);
+        }
+        poll(Duration::from_millis(0)).map_io_error()
+    }
+
+    // Keys that don't stand for a character are passed over rather than
+    // reported, so they can't leave the program waiting in getchar
+    fn try_getchar(&self, timeout: Duration) -> LC3Result<Option<char>> {
+        if let Some(ch) = self.pending.borrow_mut().pop_front() {
+            return Ok(Some(ch));
+        }
+
+        while poll(timeout).map_io_error()? {
+            if let Event::Key(key) = read().map_io_error()? {
+                if let Some(ch) = key_char(key) {
+                    return Ok(Some(ch));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+enum PromptKind {
+    Command,
+    Memory,
+}
+
+// Full screen debugger showing the registers, disassembly around the PC, a
+// page of memory and the program's console output
+pub struct Tui {
+    debugger: Debugger<TuiIOHandle>,
+    io: TuiIOHandle,
+    // Address shown in the top left of the memory pane
+    memory_base: u16,
+    // Instruction breakpoints are toggled on. Moves with the arrow keys and
+    // jumps back to the PC whenever the program runs.
+    cursor: u16,
+    message: String,
+    prompt: Option<(PromptKind, String)>,
+}
+
+impl Tui {
+    pub fn new(object: &[u16], symbols: Option<SymbolTable>) -> LC3Result<Self> {
+        let io = TuiIOHandle::default();
+        let mut vm = VM::new_with_io(io.clone());
+        vm.load_object(object)?;
+
+        let mut debugger = Debugger::new(vm);
+        if let Some(symbols) = symbols {
+            debugger = debugger.with_symbols(symbols);
+        }
+        debugger.start_recording(DEFAULT_RECORD_INTERVAL);
+        let pc = debugger.vm().pc();
+
+        Ok(Self {
+            debugger,
+            io,
+            memory_base: pc,
+            cursor: pc,
+            message: String::new(),
+            prompt: None,
+        })
+    }
+
+    pub fn debugger(&self) -> &Debugger<TuiIOHandle> {
+        &self.debugger
+    }
+
+    pub fn debugger_mut(&mut self) -> &mut Debugger<TuiIOHandle> {
+        &mut self.debugger
+    }
+
+    // Take over the terminal until the user quits
+    pub fn run(&mut self) -> LC3Result<()> {
+        let guard = TerminalGuard::full_screen()?;
+
+        let result = self.event_loop();
+
+        // Put the terminal back even if the session failed, but report the
+        // session's error first
+        let restored = guard.restore();
+        result.and(restored)
+    }
+
+    fn event_loop(&mut self) -> LC3Result<()> {
+        execute!(stdout(), Clear(ClearType::All)).map_io_error()?;
+        loop {
+            self.draw()?;
+            match read().map_io_error()? {
+                Event::Key(key) if !self.handle_key(key)? => return Ok(()),
+                Event::Resize(_, _) => execute!(stdout(), Clear(ClearType::All)).map_io_error()?,
+                _ => {}
+            }
+        }
+    }
+
+    // Returns false once the user asks to quit
+    pub(crate) fn handle_key(&mut self, key: KeyEvent) -> LC3Result<bool> {
+        if self.prompt.is_some() {
+            return Ok(self.handle_prompt_key(key));
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+            KeyCode::Char('s') | KeyCode::F(10) => {
+                self.message = match self.debugger.step() {
+                    Ok(outcome) if outcome.halted => "Program halted".to_string(),
+                    Ok(_) => self
+                        .debugger
+                        .last_watch_hits()
+                        .iter()
+                        .map(|hit| hit.to_string())
+                        .collect::<Vec<String>>()
+                        .join("; "),
+                    Err(err) => format!("Error: {}", err),
+                };
+                self.cursor = self.debugger.vm().pc();
+            }
+            KeyCode::Char('p') => {
+                self.message = match self.debugger.step_back() {
+                    Ok(true) => String::new(),
+                    Ok(false) => "Already at the start of the recording".to_string(),
+                    Err(err) => format!("Error: {}", err),
+                };
+                self.cursor = self.debugger.vm().pc();
+            }
+            KeyCode::Char('c') | KeyCode::F(5) => self.continue_execution()?,
+            KeyCode::Char('b') | KeyCode::F(9) => {
+                let name = self.debugger.name(self.cursor);
+                self.message = if self.debugger.remove_breakpoint(self.cursor) {
+                    format!("Breakpoint at {} removed", name)
+                } else {
+                    self.debugger.add_breakpoint(self.cursor);
+                    format!("Breakpoint set at {}", name)
+                };
+            }
+            KeyCode::Up => self.cursor = self.cursor.wrapping_sub(1),
+            KeyCode::Down => self.cursor = wrapping_add!(self.cursor, 1),
+            KeyCode::PageUp => {
+                self.memory_base = self.memory_base.wrapping_sub(MEMORY_ROWS * WORDS_PER_ROW)
+            }
+            KeyCode::PageDown => {
+                self.memory_base = wrapping_add!(self.memory_base, MEMORY_ROWS * WORDS_PER_ROW)
+            }
+            KeyCode::Char('m') => self.prompt = Some((PromptKind::Memory, String::new())),
+            KeyCode::Char(':') => self.prompt = Some((PromptKind::Command, String::new())),
+            _ => {}
+        }
+
+        Ok(true)
+    }
+
+    fn handle_prompt_key(&mut self, key: KeyEvent) -> bool {
+        let (kind, text) = match self.prompt.take() {
+            Some(prompt) => prompt,
+            None => return true,
+        };
+
+        match key.code {
+            KeyCode::Enter => {}
+            KeyCode::Esc => return true,
+            KeyCode::Backspace => {
+                let mut text = text;
+                text.pop();
+                self.prompt = Some((kind, text));
+                return true;
+            }
+            KeyCode::Char(ch) => {
+                let mut text = text;
+                text.push(ch);
+                self.prompt = Some((kind, text));
+                return true;
+            }
+            _ => {
+                self.prompt = Some((kind, text));
+                return true;
+            }
+        }
+
+        match kind {
+            PromptKind::Memory => match parse_value(text.trim(), self.debugger.symbols()) {
+                Ok(address) => self.memory_base = address,
+                Err(message) => self.message = message,
+            },
+            PromptKind::Command => {
+                if text.trim().is_empty() {
+                    return true;
+                }
+                let (output, quit) = self.debugger.execute(&text);
+                if quit {
+                    return false;
+                }
+                self.message = output.replace('\n', "; ");
+                self.cursor = self.debugger.vm().pc();
+            }
+        }
+
+        true
+    }
+
+    // Continue in chunks so the console keeps updating and Esc can pause a
+    // program that never reaches a breakpoint
+    fn continue_execution(&mut self) -> LC3Result<()> {
+        self.message = "Running, press Esc to pause".to_string();
+        loop {
+            self.draw()?;
+            match self.debugger.continue_for(CONTINUE_CHUNK) {
+                Ok(Some(stop)) => {
+                    self.message = self.describe_stop(&stop);
+                    break;
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    self.message = format!("Error: {}", err);
+                    break;
+                }
+            }
+            if self.interrupted()? {
+                self.message = "Paused".to_string();
+                break;
+            }
+        }
+
+        self.cursor = self.debugger.vm().pc();
+        Ok(())
+    }
+
+    // Anything other than Esc typed while running is meant for the program
+    fn interrupted(&mut self) -> LC3Result<bool> {
+        while poll(Duration::from_millis(0)).map_io_error()? {
+            if let Event::Key(key) = read().map_io_error()? {
+                if key.code == KeyCode::Esc {
+                    return Ok(true);
+                }
+                if let Some(ch) = key_char(key) {
+                    self.io.pending.borrow_mut().push_back(ch);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    fn describe_stop(&self, stop: &DebugStop) -> String {
+        match stop {
+            DebugStop::Halted => "Program halted".to_string(),
+            DebugStop::Breakpoint(address) => {
+                format!("Breakpoint at {}", self.debugger.name(*address))
+            }
+            DebugStop::Watchpoint(hits) => hits
+                .iter()
+                .map(|hit| hit.to_string())
+                .collect::<Vec<String>>()
+                .join("; "),
+        }
+    }
+
+    fn draw(&self) -> LC3Result<()> {
+        let (width, height) = size().map_io_error()?;
+        let (lines, cursor_row) = self.render(width as usize, height as usize);
+
+        let mut out = stdout();
+        for (row, line) in lines.iter().enumerate() {
+            queue!(out, MoveTo(0, row as u16)).map_io_error()?;
+            if Some(row) == cursor_row {
+                let highlighted: String = line.chars().take(LEFT_WIDTH).collect();
+                let rest: String = line.chars().skip(LEFT_WIDTH).collect();
+                queue!(
+                    out,
+                    SetAttribute(Attribute::Reverse),
+                    Print(highlighted),
+                    SetAttribute(Attribute::Reset),
+                    Print(rest)
+                )
+                .map_io_error()?;
+            } else {
+                queue!(out, Print(line)).map_io_error()?;
+            }
+        }
+        out.flush().map_io_error()
+    }
+
+    // Lay out the whole screen as plain text, one string per terminal row,
+    // along with the row holding the disassembly cursor
+    pub(crate) fn render(&self, width: usize, height: usize) -> (Vec<String>, Option<usize>) {
+        let body_height = height.saturating_sub(2);
+
+        let mut left = self.registers();
+        left.push(String::new());
+        left.push("Disassembly".to_string());
+        let disassembly_start = left.len();
+        let (disassembly, cursor_index) =
+            self.disassembly(body_height.saturating_sub(disassembly_start));
+        left.extend(disassembly);
+
+        let mut right = vec!["Memory".to_string()];
+        right.extend(self.memory());
+        right.push(String::new());
+        right.push("Console".to_string());
+        let console_rows = body_height.saturating_sub(right.len());
+        right.extend(self.console(console_rows));
+
+        let title = if self.message.is_empty() {
+            " lc3rs debugger".to_string()
+        } else {
+            format!(" lc3rs debugger | {}", self.message)
+        };
+
+        let mut lines = vec![fit(&title, width)];
+        for row in 0..body_height {
+            let left = left.get(row).map(String::as_str).unwrap_or("");
+            let right = right.get(row).map(String::as_str).unwrap_or("");
+            lines.push(fit(&(fit(left, LEFT_WIDTH) + right), width));
+        }
+
+        let footer = match &self.prompt {
+            Some((PromptKind::Memory, text)) => format!("Show memory at: {}_", text),
+            Some((PromptKind::Command, text)) => format!(":{}_", text),
+            None => KEY_HELP.to_string(),
+        };
+        lines.push(fit(&footer, width));
+        lines.truncate(height);
+
+        let cursor_row = cursor_index
+            .map(|index| 1 + disassembly_start + index)
+            .filter(|row| *row < lines.len());
+        (lines, cursor_row)
+    }
+
+    fn registers(&self) -> Vec<String> {
+        let vm = self.debugger.vm();
+        let row = |first: u8| -> String {
+            (first..first + 4)
+                .filter_map(Register::from_index)
+                .map(|register| format!("{} x{:04X}", register, vm.register(register)))
+                .collect::<Vec<String>>()
+                .join("  ")
+        };
+
+        let flags = vm.condition_flags();
+        let cc: String = [
+            (flags.negative, 'N'),
+            (flags.zero, 'Z'),
+            (flags.positive, 'P'),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, flag)| *flag)
+        .collect();
+
+        vec![
+            "Registers".to_string(),
+            row(0),
+            row(4),
+            format!("PC x{:04X}  CC {}", vm.pc(), cc),
+        ]
+    }
+
+    // Rows of instructions centered on the cursor. The PC is marked with >
+    // and breakpoints with *.
+    fn disassembly(&self, rows: usize) -> (Vec<String>, Option<usize>) {
+        let vm = self.debugger.vm();
+        let first = self.cursor.wrapping_sub((rows / 2) as u16);
+
+        let lines = (0..rows)
+            .map(|index| {
+                let address = wrapping_add!(first, index as u16);
+                let word = vm.memory_at(address);
+                let label = self
+                    .debugger
+                    .symbols()
+                    .and_then(|symbols| symbols.label_at(address))
+                    .unwrap_or("");
+                format!(
+                    "{}{} x{:04X} {:<8} x{:04X}  {}",
+                    if address == vm.pc() { '>' } else { ' ' },
+                    if self.debugger.has_breakpoint(address) {
+                        '*'
+                    } else {
+                        ' '
+                    },
+                    address,
+                    label,
+                    word,
+                    disassemble(address, word)
+                )
+            })
+            .collect();
+
+        let cursor_index = if rows > 0 { Some(rows / 2) } else { None };
+        (lines, cursor_index)
+    }
+
+    fn memory(&self) -> Vec<String> {
+        let vm = self.debugger.vm();
+        (0..MEMORY_ROWS)
+            .map(|row| {
+                let start = wrapping_add!(self.memory_base, row * WORDS_PER_ROW);
+                let words: Vec<String> = (0..WORDS_PER_ROW)
+                    .map(|offset| format!("{:04X}", vm.memory_at(wrapping_add!(start, offset))))
+                    .collect();
+                format!("x{:04X}  {}", start, words.join(" "))
+            })
+            .collect()
+    }
+
+    // The last few lines the program printed. Escape sequences meant for
+    // a terminal would scramble the screen, so they're left out.
+    fn console(&self, rows: usize) -> Vec<String> {
+        let console = strip_escapes(&self.io.console.borrow());
+        let lines: Vec<&str> = console.split('\n').collect();
+        let skip = lines.len().saturating_sub(rows);
+        lines[skip..]
+            .iter()
+            .map(|line| line.trim_end_matches('\r').to_string())
+            .collect()
+    }
+}
+
+// Pad or cut text to exactly width characters
+fn fit(text: &str, width: usize) -> String {
+    let fitted: String = text.chars().take(width).collect();
+    format!("{:<width$}", fitted, width = width)
+}
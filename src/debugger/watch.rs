@@ -0,0 +1,145 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::error::LC3Result;
+use crate::io::IOHandle;
+use crate::plugin::{Event, Plugin};
+use crate::register::Register;
+use crate::vm::VmState;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchTarget {
+    Memory(u16),
+    Register(Register),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn includes(&self, other: Access) -> bool {
+        *self == Access::ReadWrite || *self == other
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchHit {
+    pub target: WatchTarget,
+    // Either Read or Write, never ReadWrite
+    pub access: Access,
+    // For reads both values are the value that was read
+    pub old_value: u16,
+    pub new_value: u16,
+    // Address of the instruction that made the access
+    pub pc: u16,
+}
+
+impl fmt::Display for WatchTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WatchTarget::Memory(address) => write!(f, "x{:04X}", address),
+            WatchTarget::Register(register) => write!(f, "{}", register),
+        }
+    }
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.access {
+            Access::Read => write!(
+                f,
+                "Watchpoint: read x{:04X} from {} at x{:04X}",
+                self.new_value, self.target, self.pc
+            ),
+            _ => write!(
+                f,
+                "Watchpoint: {} changed from x{:04X} to x{:04X} at x{:04X}",
+                self.target, self.old_value, self.new_value, self.pc
+            ),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct WatchState {
+    pub(crate) watchpoints: Vec<(WatchTarget, Access)>,
+    // Hits seen since the debugger last collected them. The plugin doesn't
+    // know which instruction it's in the middle of, so pc is filled in by
+    // the debugger once the step completes.
+    pub(crate) hits: Vec<WatchHit>,
+}
+
+impl WatchState {
+    fn record(&mut self, target: WatchTarget, access: Access, old_value: u16, new_value: u16) {
+        let watched = self
+            .watchpoints
+            .iter()
+            .any(|(watched, kind)| *watched == target && kind.includes(access));
+
+        if watched {
+            self.hits.push(WatchHit {
+                target,
+                access,
+                old_value,
+                new_value,
+                pc: 0,
+            });
+        }
+    }
+}
+
+// Feeds memory and register accesses into the shared watch state
+pub(crate) struct WatchPlugin {
+    pub(crate) state: Rc<RefCell<WatchState>>,
+}
+
+impl<IOType: IOHandle> Plugin<IOType> for WatchPlugin {
+    fn handle_event(&mut self, vm: &mut VmState<IOType>, event: &Event) -> LC3Result<()> {
+        let mut state = self.state.borrow_mut();
+        if state.watchpoints.is_empty() {
+            return Ok(());
+        }
+
+        match event {
+            Event::MemGet { location, value } => {
+                state.record(WatchTarget::Memory(*location), Access::Read, *value, *value)
+            }
+            // Set events are sent before the write happens, so the VM still
+            // holds the old value
+            Event::MemSet { location, value } => state.record(
+                WatchTarget::Memory(*location),
+                Access::Write,
+                vm.memory_at(*location),
+                *value,
+            ),
+            Event::RegGet { index, value } => {
+                if let Some(register) = Register::from_index(*index) {
+                    state.record(
+                        WatchTarget::Register(register),
+                        Access::Read,
+                        *value,
+                        *value,
+                    )
+                }
+            }
+            Event::RegSet { index, value } => {
+                if let Some(register) = Register::from_index(*index) {
+                    state.record(
+                        WatchTarget::Register(register),
+                        Access::Write,
+                        vm.register(register),
+                        *value,
+                    )
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,478 @@
+use crate::asm::is_usable_label;
+use crate::command::Command;
+use crate::error::LC3Result;
+use crate::instruction::Instruction;
+use crate::isa::Isa;
+use crate::op::Op;
+use crate::symbols::SymbolTable;
+use crate::utils::sign_extend;
+use crate::wrapping_add;
+
+// Labels are written in a column this wide, as the assembler's examples are
+const LABEL_WIDTH: usize = 8;
+
+// Turn a single instruction back into assembly. The address the instruction
+// lives at is needed to turn PC relative offsets into absolute addresses.
+// Words that don't decode to a valid instruction are shown as .FILL.
+pub fn disassemble(address: u16, word: u16) -> String {
+    let absolute = |target: u16, _| format!("x{:04X}", target);
+    disassemble_command(address, &Command::new(word), &absolute).unwrap_or_else(|_| fill(word))
+}
+
+// One line per word: address, raw value and the decoded instruction
+pub fn disassemble_program(origin: u16, words: &[u16]) -> Vec<String> {
+    words
+        .iter()
+        .enumerate()
+        .map(|(index, word)| {
+            let address = wrapping_add!(origin, index as u16);
+            format!(
+                "x{:04X}  x{:04X}  {}",
+                address,
+                word,
+                disassemble(address, *word)
+            )
+        })
+        .collect()
+}
+
+// A program as assembler source, with a label on everything that's the
+// target of a branch, JSR or PC relative load or store. Labels come from
+// symbols where it has one for the address, otherwise they're made up as L1,
+// L2 and so on in address order. Targets outside the program are left as
+// offsets.
+//
+// Assembling the source gives back exactly the same words. Anything that
+// wouldn't, like an instruction with bits set that it ignores, is written as
+// .FILL, and runs of zeroes as .BLKW.
+pub fn disassemble_source(
+    origin: u16,
+    words: &[u16],
+    symbols: Option<&SymbolTable>,
+) -> Vec<String> {
+    let labels = infer_labels(origin, words, symbols);
+    let target_label = |target: u16, offset: i16| match labels.label_at(target) {
+        Some(label) => label.to_string(),
+        None => format!("#{}", offset),
+    };
+
+    let mut lines = vec![source_line(None, &format!(".ORIG x{:04X}", origin))];
+    let mut index = 0;
+    while index < words.len() {
+        let address = wrapping_add!(origin, index as u16);
+        let label = labels.label_at(address);
+
+        // Only the first of the zeroes can have a label, as nothing else
+        // in the run can be pointed at
+        let zeroes = words[index..]
+            .iter()
+            .enumerate()
+            .take_while(|(offset, word)| {
+                **word == 0
+                    && (*offset == 0
+                        || labels
+                            .label_at(wrapping_add!(address, *offset as u16))
+                            .is_none())
+            })
+            .count();
+        if zeroes > 1 {
+            lines.push(source_line(label, &format!(".BLKW {}", zeroes)));
+            index += zeroes;
+            continue;
+        }
+
+        let word = words[index];
+        let command = Command::new(word);
+        let text = if is_canonical(&command) {
+            disassemble_command(address, &command, &target_label).unwrap_or_else(|_| fill(word))
+        } else {
+            fill(word)
+        };
+        lines.push(source_line(label, &text));
+        index += 1;
+    }
+    lines.push(source_line(None, ".END"));
+    lines
+}
+
+// The labels disassemble_source uses: those of symbols that are inside the
+// program, plus made up ones for any targets still without one
+pub fn infer_labels(origin: u16, words: &[u16], symbols: Option<&SymbolTable>) -> SymbolTable {
+    let contains = |address: u16| (address.wrapping_sub(origin) as usize) < words.len();
+    let mut labels = SymbolTable::new();
+    for (address, label) in symbols.iter().flat_map(|symbols| symbols.iter()) {
+        if contains(address) && is_usable_label(label) && labels.label_at(address).is_none() {
+            labels.insert(label, address);
+        }
+    }
+
+    let mut targets: Vec<u16> = words
+        .iter()
+        .enumerate()
+        .filter_map(|(index, word)| pc_target(wrapping_add!(origin, index as u16), *word))
+        .filter(|target| contains(*target))
+        .collect();
+    targets.sort_unstable();
+    targets.dedup();
+
+    let mut names = (1..).map(|number| format!("L{}", number));
+    for target in targets {
+        if labels.label_at(target).is_some() {
+            continue;
+        }
+        // Skipping any names the symbols already use, wherever they are
+        let taken = |name: &str| {
+            labels.address_of(name).is_some()
+                || symbols.is_some_and(|symbols| symbols.address_of(name).is_some())
+        };
+        let name = names
+            .by_ref()
+            .find(|name| !taken(name))
+            .expect("There's always another name");
+        labels.insert(&name, target);
+    }
+    labels
+}
+
+// The address a PC relative instruction refers to, for words that are one
+fn pc_target(address: u16, word: u16) -> Option<u16> {
+    let command = Command::new(word);
+    let bits = match Op::from_int(command.op_code().ok()?).ok()? {
+        Op::Br if command.bit_slice(4, 6).ok()? != 0 => 9,
+        Op::Ld | Op::St | Op::Ldi | Op::Sti | Op::Lea => 9,
+        Op::Jsr if command.bit_slice(4, 4).ok()? == 1 => 11,
+        _ => return None,
+    };
+    let offset = sign_extend(command.bit_slice(16 - bits, 15).ok()?, bits);
+    Some(wrapping_add!(wrapping_add!(address, 1), offset))
+}
+
+// Whether the word is exactly what assembling its disassembly gives back.
+// Data can look like an instruction with some of the bits it ignores set.
+fn is_canonical(command: &Command) -> bool {
+    let word = command.get_bytes();
+    Instruction::decode(word).encode() == word
+}
+
+fn source_line(label: Option<&str>, text: &str) -> String {
+    match label {
+        Some(label) if label.len() < LABEL_WIDTH => {
+            format!("{:width$}{}", label, text, width = LABEL_WIDTH)
+        }
+        Some(label) => format!("{} {}", label, text),
+        None => format!("{:width$}{}", "", text, width = LABEL_WIDTH),
+    }
+}
+
+// target_name turns the target of a PC relative instruction, and the offset
+// to it, into its operand
+pub(crate) fn disassemble_command(
+    address: u16,
+    command: &Command,
+    target_name: &dyn Fn(u16, i16) -> String,
+) -> LC3Result<String> {
+    let word = command.get_bytes();
+    let reg = |left: u8| -> LC3Result<u16> { command.bit_slice(left, left + 2) };
+    let target = |bits: u8| -> LC3Result<String> {
+        let offset = sign_extend(command.bit_slice(16 - bits, 15)?, bits);
+        let target = wrapping_add!(wrapping_add!(address, 1), offset);
+        Ok(target_name(target, offset as i16))
+    };
+    let immediate = |bits: u8| -> LC3Result<i16> {
+        Ok(sign_extend(command.bit_slice(16 - bits, 15)?, bits) as i16)
+    };
+
+    let text = match Op::from_int(command.op_code()?)? {
+        Op::Add | Op::And => {
+            let name = if command.op_code()? == 1 {
+                "ADD"
+            } else {
+                "AND"
+            };
+            if command.bit_slice(10, 10)? == 1 {
+                format!("{} R{}, R{}, #{}", name, reg(4)?, reg(7)?, immediate(5)?)
+            } else {
+                format!("{} R{}, R{}, R{}", name, reg(4)?, reg(7)?, reg(13)?)
+            }
+        }
+        Op::Br => {
+            let flags = command.bit_slice(4, 6)?;
+            if flags == 0 {
+                // Never branches, so it's most likely data
+                fill(word)
+            } else {
+                let mut name = "BR".to_string();
+                if flags & 0b100 != 0 {
+                    name.push('n');
+                }
+                if flags & 0b010 != 0 {
+                    name.push('z');
+                }
+                if flags & 0b001 != 0 {
+                    name.push('p');
+                }
+                format!("{} {}", name, target(9)?)
+            }
+        }
+        Op::Ld => format!("LD R{}, {}", reg(4)?, target(9)?),
+        Op::St => format!("ST R{}, {}", reg(4)?, target(9)?),
+        Op::Ldi => format!("LDI R{}, {}", reg(4)?, target(9)?),
+        Op::Sti => format!("STI R{}, {}", reg(4)?, target(9)?),
+        Op::Lea => format!("LEA R{}, {}", reg(4)?, target(9)?),
+        Op::Jsr => {
+            if command.bit_slice(4, 4)? == 1 {
+                format!("JSR {}", target(11)?)
+            } else {
+                format!("JSRR R{}", reg(7)?)
+            }
+        }
+        Op::Ldr => format!("LDR R{}, R{}, #{}", reg(4)?, reg(7)?, immediate(6)?),
+        Op::Str => format!("STR R{}, R{}, #{}", reg(4)?, reg(7)?, immediate(6)?),
+        Op::Not => format!("NOT R{}, R{}", reg(4)?, reg(7)?),
+        Op::Jmp => match reg(7)? {
+            7 => "RET".to_string(),
+            base => format!("JMP R{}", base),
+        },
+        Op::Rti => "RTI".to_string(),
+        Op::Trap => match command.bit_slice(8, 15)? {
+            0x20 => "GETC".to_string(),
+            0x21 => "OUT".to_string(),
+            0x22 => "PUTS".to_string(),
+            0x23 => "IN".to_string(),
+            0x24 => "PUTSP".to_string(),
+            0x25 => "HALT".to_string(),
+            vector => format!("TRAP x{:02X}", vector),
+        },
+        Op::Res | Op::Ldb | Op::Stb | Op::Ldw | Op::Stw | Op::Xor | Op::Shf => fill(word),
+    };
+
+    Ok(text)
+}
+
+// disassemble for the LC-3b, where address is a byte address and PC
+// relative offsets count words
+pub fn disassemble_lc3b(address: u16, word: u16) -> String {
+    disassemble_lc3b_command(address, &Command::new(word)).unwrap_or_else(|_| fill(word))
+}
+
+fn disassemble_lc3b_command(address: u16, command: &Command) -> LC3Result<String> {
+    let word = command.get_bytes();
+    let reg = |left: u8| -> LC3Result<u16> { command.bit_slice(left, left + 2) };
+    let target = |bits: u8| -> LC3Result<String> {
+        let offset = sign_extend(command.bit_slice(16 - bits, 15)?, bits) << 1;
+        Ok(format!(
+            "x{:04X}",
+            wrapping_add!(wrapping_add!(address, 2), offset)
+        ))
+    };
+    let immediate = |bits: u8| -> LC3Result<i16> {
+        Ok(sign_extend(command.bit_slice(16 - bits, 15)?, bits) as i16)
+    };
+    let base_offset = |name: &str| -> LC3Result<String> {
+        Ok(format!(
+            "{} R{}, R{}, #{}",
+            name,
+            reg(4)?,
+            reg(7)?,
+            immediate(6)?
+        ))
+    };
+
+    let text = match Op::from_int_for(Isa::Lc3b, command.op_code()?)? {
+        // The same as the LC-3's apart from the addresses
+        Op::Add | Op::And | Op::Jmp | Op::Rti | Op::Trap => {
+            disassemble_command(address, command, &|_, _| String::new())?
+        }
+        Op::Br => match command.bit_slice(4, 6)? {
+            0 => fill(word),
+            flags => {
+                let mut name = "BR".to_string();
+                for (bit, flag) in [(0b100, 'n'), (0b010, 'z'), (0b001, 'p')] {
+                    if flags & bit != 0 {
+                        name.push(flag);
+                    }
+                }
+                format!("{} {}", name, target(9)?)
+            }
+        },
+        Op::Jsr => {
+            if command.bit_slice(4, 4)? == 1 {
+                format!("JSR {}", target(11)?)
+            } else {
+                format!("JSRR R{}", reg(7)?)
+            }
+        }
+        Op::Lea => format!("LEA R{}, {}", reg(4)?, target(9)?),
+        Op::Ldb => base_offset("LDB")?,
+        Op::Stb => base_offset("STB")?,
+        Op::Ldw => base_offset("LDW")?,
+        Op::Stw => base_offset("STW")?,
+        Op::Xor => {
+            if command.bit_slice(10, 10)? == 0 {
+                format!("XOR R{}, R{}, R{}", reg(4)?, reg(7)?, reg(13)?)
+            } else if immediate(5)? == -1 {
+                format!("NOT R{}, R{}", reg(4)?, reg(7)?)
+            } else {
+                format!("XOR R{}, R{}, #{}", reg(4)?, reg(7)?, immediate(5)?)
+            }
+        }
+        Op::Shf => {
+            let name = match command.bit_slice(10, 11)? {
+                0b01 => "RSHFL",
+                0b11 => "RSHFA",
+                _ => "LSHF",
+            };
+            let amount = command.bit_slice(12, 15)?;
+            format!("{} R{}, R{}, #{}", name, reg(4)?, reg(7)?, amount)
+        }
+        _ => fill(word),
+    };
+
+    Ok(text)
+}
+
+fn fill(word: u16) -> String {
+    format!(".FILL x{:04X}", word)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{disassemble, disassemble_lc3b, disassemble_source};
+    use crate::asm::assemble;
+    use crate::error::LC3Result;
+    use crate::symbols::SymbolTable;
+
+    #[test]
+    fn can_disassemble() {
+        // Tuple format: (address, word, expected text)
+        let test_cases = vec![
+            (0x3000, 0x1283, "ADD R1, R2, R3"),
+            (0x3000, 0x12BF, "ADD R1, R2, #-1"),
+            (0x3000, 0x52AF, "AND R1, R2, #15"),
+            (0x3005, 0x0BFA, "BRnp x3000"),
+            (0x3000, 0x0E00, "BRnzp x3001"),
+            (0x3000, 0xC1C0, "RET"),
+            (0x3000, 0xC080, "JMP R2"),
+            (0x3009, 0x4FF6, "JSR x3000"),
+            (0x3000, 0x40C0, "JSRR R3"),
+            (0x3000, 0xE202, "LEA R1, x3003"),
+            (0x3000, 0x62A0, "LDR R1, R2, #-32"),
+            (0x3000, 0x997F, "NOT R4, R5"),
+            (0x3000, 0xF025, "HALT"),
+            (0x3000, 0xF0FF, "TRAP xFF"),
+            (0x3000, 0x8000, "RTI"),
+            (0x3000, 0xD123, ".FILL xD123"),
+            (0x3000, 0x0041, ".FILL x0041"),
+        ];
+
+        for (address, word, expected) in test_cases {
+            assert_eq!(disassemble(address, word), expected);
+        }
+    }
+
+    #[test]
+    fn can_disassemble_lc3b() {
+        // Tuple format: (address, word, expected text)
+        let test_cases = vec![
+            (0x3000, 0x1283, "ADD R1, R2, R3"),
+            (0x300A, 0x0BFA, "BRnp x3000"),
+            (0x3000, 0x4803, "JSR x3008"),
+            (0x3000, 0xE202, "LEA R1, x3006"),
+            (0x3000, 0x22BF, "LDB R1, R2, #-1"),
+            (0x3000, 0x3283, "STB R1, R2, #3"),
+            (0x3000, 0x62A0, "LDW R1, R2, #-32"),
+            (0x3000, 0x7281, "STW R1, R2, #1"),
+            (0x3000, 0x9283, "XOR R1, R2, R3"),
+            (0x3000, 0x92A5, "XOR R1, R2, #5"),
+            (0x3000, 0x997F, "NOT R4, R5"),
+            (0x3000, 0xD283, "LSHF R1, R2, #3"),
+            (0x3000, 0xD291, "RSHFL R1, R2, #1"),
+            (0x3000, 0xD2BF, "RSHFA R1, R2, #15"),
+            (0x3000, 0xF025, "HALT"),
+            (0x3000, 0xA000, ".FILL xA000"),
+        ];
+
+        for (address, word, expected) in test_cases {
+            assert_eq!(disassemble_lc3b(address, word), expected);
+        }
+    }
+
+    #[test]
+    fn can_reconstruct_labels() -> LC3Result<()> {
+        let source = r#"
+            .ORIG x3000
+            LEA R0, MSG
+            AND R1, R1, #0
+    LOOP    ADD R1, R1, #1
+            BRn LOOP
+            JSR #250
+            HALT
+    MSG     .FILL x48
+            .END
+        "#;
+        let assembly = assemble(source)?;
+
+        let lines = disassemble_source(assembly.origin, &assembly.words, None);
+        assert_eq!(
+            lines,
+            vec![
+                "        .ORIG x3000",
+                "        LEA R0, L2",
+                "        AND R1, R1, #0",
+                "L1      ADD R1, R1, #1",
+                "        BRn L1",
+                // Outside the program, so it stays an offset
+                "        JSR #250",
+                "        HALT",
+                "L2      .FILL x0048",
+                "        .END",
+            ]
+        );
+        assert_eq!(assemble(&lines.join("\n"))?.words, assembly.words);
+
+        // Labels from a symbol table win, and made up ones don't clash
+        let mut symbols = SymbolTable::new();
+        symbols.insert("COUNTING_LOOP", 0x3002);
+        symbols.insert("L1", 0x4000);
+        let lines = disassemble_source(assembly.origin, &assembly.words, Some(&symbols));
+        assert_eq!(lines[1], "        LEA R0, L2");
+        assert_eq!(lines[3], "COUNTING_LOOP ADD R1, R1, #1");
+        assert_eq!(lines[4], "        BRn COUNTING_LOOP");
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_round_trip_source() -> LC3Result<()> {
+        // Every possible word, so every instruction, every piece of data that
+        // looks like one, and every target
+        let words: Vec<u16> = (0..=0xFFFF).collect();
+        let source = disassemble_source(0x0000, &words, None).join("\n");
+        let assembly = assemble(&source)?;
+        assert_eq!(assembly.origin, 0x0000);
+        assert_eq!(assembly.words, words);
+
+        let words = [
+            // ADD R1, R2, R3 with the unused bits set
+            0x129B, 0x0000, 0x0000, 0x0000, 0x0FFC,
+            // RET, with a label that can't be used as one
+            0xC1C0,
+        ];
+        let mut symbols = SymbolTable::new();
+        symbols.insert("R1", 0x3005);
+        let lines = disassemble_source(0x3000, &words, Some(&symbols));
+        assert_eq!(
+            lines[1..6],
+            [
+                "        .FILL x129B",
+                "L1      .BLKW 3",
+                "        BRnzp L1",
+                "        RET",
+                "        .END",
+            ]
+        );
+        assert_eq!(assemble(&lines.join("\n"))?.words, words);
+
+        Ok(())
+    }
+}
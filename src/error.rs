@@ -3,6 +3,8 @@ use std::result::Result as StdResult;
 
 use thiserror::Error;
 
+use crate::plugin::PluginId;
+
 pub type LC3Result<T> = StdResult<T, LC3Error>;
 
 pub(crate) type BoxedError = Box<dyn StdError + 'static>;
@@ -25,8 +27,85 @@ pub enum LC3Error {
     BadOpCode { code: u8 },
     #[error("Bad trap code {code} encountered during command parsing")]
     BadTrapCode { code: u8 },
+    #[error("Trap vector {code:#04x} is reserved, host traps must use x26 to xFF")]
+    ReservedTrap { code: u8 },
     #[error("Program length {len} exceeds maximum allowed size {max_len}")]
     ProgramSize { len: usize, max_len: usize },
+    #[error("Program loaded at {origin:#06x} overlaps program already loaded at {existing:#06x}")]
+    ProgramOverlap { origin: u16, existing: u16 },
+    #[error("Object file is empty, expected at least an origin word")]
+    EmptyObject,
+    #[error("Program file {path} doesn't exist")]
+    ProgramMissing { path: String },
+    #[error("Couldn't read program file {path}: {source}")]
+    ProgramRead {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Program file {path} is {len} bytes long, which isn't a whole number of 16 bit words")]
+    OddByteCount { path: String, len: usize },
+    #[error("Line {line} of program file {path} isn't a valid {format} word: {text}")]
+    BadProgramLine {
+        path: String,
+        line: usize,
+        format: &'static str,
+        text: String,
+    },
+    #[error("A program of {len} words loaded at {origin:#06x} would run into the device registers at xFE00")]
+    BadOrigin { origin: u16, len: usize },
+    #[error("{len} words from {address:#06x} would run past the end of memory")]
+    MemoryRange { address: u16, len: usize },
+    #[error("Address {address:#06x} is outside of memory")]
+    Unmapped { address: u16 },
+    #[error("Address {address:#06x} is odd, but words have to be at even addresses")]
+    Unaligned { address: u16 },
+    #[error("Invalid memory map: {0}")]
+    BadMemoryMap(String),
+    #[error("Invalid banked memory: {0}")]
+    BadBanks(String),
+    #[error("There's no bank {bank}, there are {banks} banks")]
+    NoSuchBank { bank: usize, banks: usize },
+    #[error("Malformed symbol table entry on line {line}: {text}")]
+    BadSymbolTable { line: usize, text: String },
+    #[error("Assembly failed on line {line}: {message}")]
+    Assembly { line: usize, message: String },
+    #[error("Linking failed: {0}")]
+    Link(String),
+    #[error("Invalid module: {0}")]
+    BadModule(String),
+    #[error("Invalid snapshot: {0}")]
+    BadSnapshot(String),
+    #[error("Invalid core dump: {0}")]
+    BadCoreDump(String),
+    #[error("Invalid configuration: {0}")]
+    BadConfig(String),
+    #[error("Malformed IO recording entry on line {line}: {text}")]
+    BadRecording { line: usize, text: String },
+    #[error("Replay diverged from the recording: {0}")]
+    ReplayDiverged(String),
+    #[error("No plugin with id {0} is attached to the VM")]
+    UnknownPlugin(PluginId),
+    #[error("Plugin {0} isn't of the requested type")]
+    WrongPluginType(PluginId),
+    #[error("Plugins sent more than {0} messages in a row, they're most likely stuck replying to each other")]
+    MessageLoop(usize),
+    #[error("Program tried to access privileged memory at {address:#06x} in user mode")]
+    AccessViolation { address: u16 },
+    #[error("Program tried to execute RTI in user mode")]
+    PrivilegeViolation,
+    #[error("Program tried to execute {word:#06x}, which uses the reserved op code")]
+    IllegalOpcode { word: u16 },
+    #[error("The VM the input was sent to no longer exists")]
+    InputClosed,
+    #[error("The other end of the console's channel was closed")]
+    ConsoleClosed,
+    #[error("The program asked for a key while none were waiting, and the IO handle can't wait for one")]
+    InputUnavailable,
+    #[error("The program was interrupted with Ctrl+C")]
+    Interrupted,
+    #[error("Character code {code:#x} isn't ASCII")]
+    NonAscii { code: u32 },
     #[error("Encountered the following error: {0}")]
     Other(String),
 }
@@ -0,0 +1,245 @@
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+use crate::error::LC3Result;
+use crate::io::IOHandle;
+use crate::plugin::PluginSet;
+use crate::register::Register::{RR0, RR1};
+use crate::state::VmState;
+use crate::vm::VM;
+
+// Opens the file named by the string R0 points to, in the mode given by R1,
+// and leaves a handle for it in R0
+pub const FOPEN: u8 = 0x26;
+// Reads a byte from the file with handle R0 into R0
+pub const FREAD: u8 = 0x27;
+// Writes the low byte of R1 to the file with handle R0
+pub const FWRITE: u8 = 0x28;
+// Closes the file with handle R0
+pub const FCLOSE: u8 = 0x29;
+
+pub const MODE_READ: u16 = 0;
+pub const MODE_WRITE: u16 = 1;
+pub const MODE_APPEND: u16 = 2;
+
+// Left in R0 when a trap fails, or when FREAD reaches the end of the file.
+// Like C's EOF, it can't be mistaken for a byte or a handle.
+pub const FILE_ERROR: u16 = 0xFFFF;
+
+// The longest file name FOPEN will read before giving up on finding the end
+const MAX_NAME_LEN: usize = 256;
+
+// The files the program has open. A handle is an index into files, and
+// closed handles are reused.
+struct FileTable {
+    root: PathBuf,
+    files: Vec<Option<File>>,
+}
+
+impl FileTable {
+    // File names are relative to the root, and can't climb out of it
+    fn resolve(&self, name: &str) -> Option<PathBuf> {
+        let name = Path::new(name);
+        let inside = name
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if name.as_os_str().is_empty() || !inside {
+            return None;
+        }
+        Some(self.root.join(name))
+    }
+
+    fn open(&mut self, name: &str, mode: u16) -> Option<u16> {
+        let path = self.resolve(name)?;
+        let mut options = OpenOptions::new();
+        match mode {
+            MODE_READ => options.read(true),
+            MODE_WRITE => options.write(true).create(true).truncate(true),
+            MODE_APPEND => options.append(true).create(true),
+            _ => return None,
+        };
+        let file = options.open(path).ok()?;
+
+        let handle = match self.files.iter().position(Option::is_none) {
+            Some(free) => free,
+            None => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+        };
+        if handle >= FILE_ERROR as usize {
+            return None;
+        }
+        self.files[handle] = Some(file);
+        Some(handle as u16)
+    }
+
+    fn file(&mut self, handle: u16) -> Option<&mut File> {
+        self.files.get_mut(handle as usize)?.as_mut()
+    }
+
+    fn read(&mut self, handle: u16) -> Option<u16> {
+        let mut byte = [0u8];
+        match self.file(handle)?.read(&mut byte) {
+            Ok(1) => Some(u16::from(byte[0])),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, handle: u16, byte: u8) -> Option<()> {
+        self.file(handle)?.write_all(&[byte]).ok()
+    }
+
+    fn close(&mut self, handle: u16) -> Option<()> {
+        self.files.get_mut(handle as usize)?.take().map(|_| ())
+    }
+}
+
+// Registers the file traps, with every file name taken relative to root
+pub(crate) fn register<IO, P>(vm: &mut VM<IO, P>, root: PathBuf) -> LC3Result<()>
+where
+    IO: IOHandle,
+    P: PluginSet<IO>,
+{
+    let table = Rc::new(RefCell::new(FileTable {
+        root,
+        files: Vec::new(),
+    }));
+
+    let files = table.clone();
+    vm.register_trap(FOPEN, move |state| {
+        let name = read_name(state);
+        let mode = state.read_register(RR1);
+        let handle = name.and_then(|name| files.borrow_mut().open(&name, mode));
+        state.write_register(RR0, handle.unwrap_or(FILE_ERROR));
+        Ok(())
+    })?;
+
+    let files = table.clone();
+    vm.register_trap(FREAD, move |state| {
+        let handle = state.read_register(RR0);
+        let byte = files.borrow_mut().read(handle);
+        state.write_register(RR0, byte.unwrap_or(FILE_ERROR));
+        Ok(())
+    })?;
+
+    let files = table.clone();
+    vm.register_trap(FWRITE, move |state| {
+        let handle = state.read_register(RR0);
+        let byte = state.read_register(RR1) as u8;
+        let written = files.borrow_mut().write(handle, byte);
+        state.write_register(RR0, status(written));
+        Ok(())
+    })?;
+
+    vm.register_trap(FCLOSE, move |state| {
+        let handle = state.read_register(RR0);
+        let closed = table.borrow_mut().close(handle);
+        state.write_register(RR0, status(closed));
+        Ok(())
+    })
+}
+
+// The string R0 points to, one character per word like PUTS takes
+fn read_name<IO: IOHandle>(state: &mut VmState<IO>) -> Option<String> {
+    let mut address = state.read_register(RR0);
+    let mut name = String::new();
+    for _ in 0..MAX_NAME_LEN {
+        let word = state.read_memory(address);
+        if word == 0 {
+            return Some(name);
+        }
+        name.push(char::from(word as u8));
+        address = address.wrapping_add(1);
+    }
+    None
+}
+
+fn status(result: Option<()>) -> u16 {
+    match result {
+        Some(()) => 0,
+        None => FILE_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::io::TestIOHandle;
+    use crate::register::Register::{RR2, RR3};
+
+    fn sandbox(name: &str) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("lc3rs_files_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    #[test]
+    fn can_write_and_read_back_files() -> LC3Result<()> {
+        let root = sandbox("round_trip");
+        let program = assemble(
+            r#"
+            .ORIG x3000
+            LEA R0, NAME
+            AND R1, R1, #0
+            ADD R1, R1, #1
+            TRAP x26
+            ST R0, HANDLE
+            LD R1, CHAR
+            TRAP x28
+            LD R0, HANDLE
+            TRAP x29
+            LEA R0, NAME
+            AND R1, R1, #0
+            TRAP x26
+            ST R0, HANDLE
+            TRAP x27
+            ADD R2, R0, #0
+            LD R0, HANDLE
+            TRAP x27
+            ADD R3, R0, #0
+            LD R0, HANDLE
+            TRAP x29
+            HALT
+NAME        .STRINGZ "saved.txt"
+HANDLE      .BLKW 1
+CHAR        .FILL x0041
+            .END
+        "#,
+        )?;
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.enable_file_traps(&root)?;
+        vm.load_object(&program.to_object())?;
+        vm.run()?;
+
+        assert_eq!(std::fs::read_to_string(root.join("saved.txt")).unwrap(), "A");
+        assert_eq!(vm.register(RR2), 'A' as u16);
+        // The second read hit the end of the file
+        assert_eq!(vm.register(RR3), FILE_ERROR);
+        // Closing the file succeeded
+        assert_eq!(vm.register(RR0), 0);
+
+        std::fs::remove_dir_all(root).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn cant_open_files_outside_root() {
+        let table = FileTable {
+            root: sandbox("outside"),
+            files: Vec::new(),
+        };
+        assert!(table.resolve("notes/today.txt").is_some());
+        assert!(table.resolve("../escaped.txt").is_none());
+        assert!(table.resolve("notes/../../escaped.txt").is_none());
+        assert!(table.resolve("/etc/passwd").is_none());
+        assert!(table.resolve("").is_none());
+        std::fs::remove_dir_all(&table.root).unwrap();
+    }
+}
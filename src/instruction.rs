@@ -0,0 +1,641 @@
+use std::fmt;
+
+use crate::command::Command;
+use crate::condition_flags::ConditionFlags;
+use crate::disasm::{disassemble, disassemble_command, disassemble_lc3b};
+use crate::error::LC3Result;
+use crate::isa::Isa;
+use crate::op::Op;
+use crate::utils::sign_extend;
+use crate::wrapping_add;
+
+// A decoded instruction, as handed to plugins in the PreInstruction and
+// PostInstruction events so they don't each have to pick the bits apart.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedInstruction {
+    // Where the instruction was fetched from
+    pub address: u16,
+    pub word: u16,
+    pub op: Op,
+    pub operands: Operands,
+    // Which instruction set it was decoded for, which says how to show it
+    pub isa: Isa,
+}
+
+// The operands of each instruction format. Register operands are register
+// indexes and PC relative offsets are already turned into addresses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operands {
+    // ADD and AND with a register as the second source
+    Register {
+        dr: u8,
+        sr1: u8,
+        sr2: u8,
+    },
+    // ADD and AND with an immediate as the second source
+    Immediate {
+        dr: u8,
+        sr1: u8,
+        imm: i16,
+    },
+    // BR, with the flags it tests and where it goes if it's taken
+    Branch {
+        flags: ConditionFlags,
+        target: u16,
+    },
+    // LD, ST, LDI, STI and LEA
+    PcRelative {
+        reg: u8,
+        address: u16,
+    },
+    // LDR and STR, and the LC-3b's LDB, STB, LDW and STW, with the offset
+    // in bytes for those
+    BaseOffset {
+        reg: u8,
+        base: u8,
+        offset: i16,
+    },
+    // JSR
+    Subroutine {
+        target: u16,
+    },
+    // JMP, RET and JSRR
+    BaseRegister {
+        base: u8,
+    },
+    // NOT
+    Not {
+        dr: u8,
+        sr: u8,
+    },
+    // The LC-3b's LSHF, RSHFL and RSHFA
+    Shift {
+        dr: u8,
+        sr: u8,
+        right: bool,
+        arithmetic: bool,
+        amount: u8,
+    },
+    // TRAP
+    Trap {
+        vector: u8,
+    },
+    // RTI and the reserved op code
+    Empty,
+}
+
+// What executing an instruction changed, for PostInstruction
+#[derive(Debug, Clone, PartialEq)]
+pub struct Effects {
+    // Register index and value for each register write, in order. Includes
+    // the PC when the instruction jumps and the condition register.
+    pub registers_written: Vec<(u8, u16)>,
+    // Address and value for each memory write, in order
+    pub memory_written: Vec<(u16, u16)>,
+    // Where execution carries on from
+    pub pc: u16,
+    pub flags: ConditionFlags,
+    pub halted: bool,
+}
+
+impl DecodedInstruction {
+    pub fn decode(address: u16, word: u16) -> LC3Result<Self> {
+        Self::decode_for(Isa::Lc3, address, word)
+    }
+
+    pub fn decode_for(isa: Isa, address: u16, word: u16) -> LC3Result<Self> {
+        let command = Command::new(word);
+        let reg = |left: u8| -> LC3Result<u8> { Ok(command.bit_slice(left, left + 2)? as u8) };
+        let signed = |bits: u8| -> LC3Result<i16> {
+            Ok(sign_extend(command.bit_slice(16 - bits, 15)?, bits) as i16)
+        };
+        // LC-3b offsets count words, and its addresses bytes
+        let step = isa.word_size();
+        let target = |bits: u8| -> LC3Result<u16> {
+            Ok(wrapping_add!(
+                wrapping_add!(address, step),
+                (signed(bits)? as u16).wrapping_mul(step)
+            ))
+        };
+
+        let op = Op::from_int_for(isa, command.op_code()?)?;
+        let operands = match op {
+            Op::Add | Op::And | Op::Xor => {
+                if command.bit_slice(10, 10)? == 1 {
+                    Operands::Immediate {
+                        dr: reg(4)?,
+                        sr1: reg(7)?,
+                        imm: signed(5)?,
+                    }
+                } else {
+                    Operands::Register {
+                        dr: reg(4)?,
+                        sr1: reg(7)?,
+                        sr2: reg(13)?,
+                    }
+                }
+            }
+            Op::Br => Operands::Branch {
+                flags: ConditionFlags::from_bits(command.bit_slice(4, 6)?),
+                target: target(9)?,
+            },
+            Op::Ld | Op::St | Op::Ldi | Op::Sti | Op::Lea => Operands::PcRelative {
+                reg: reg(4)?,
+                address: target(9)?,
+            },
+            Op::Ldr | Op::Str | Op::Ldb | Op::Stb => Operands::BaseOffset {
+                reg: reg(4)?,
+                base: reg(7)?,
+                offset: signed(6)?,
+            },
+            Op::Ldw | Op::Stw => Operands::BaseOffset {
+                reg: reg(4)?,
+                base: reg(7)?,
+                offset: signed(6)? * 2,
+            },
+            Op::Shf => Operands::Shift {
+                dr: reg(4)?,
+                sr: reg(7)?,
+                right: command.bit_slice(11, 11)? == 1,
+                arithmetic: command.bit_slice(10, 10)? == 1,
+                amount: command.bit_slice(12, 15)? as u8,
+            },
+            Op::Jsr if command.bit_slice(4, 4)? == 1 => Operands::Subroutine {
+                target: target(11)?,
+            },
+            Op::Jsr | Op::Jmp => Operands::BaseRegister { base: reg(7)? },
+            Op::Not => Operands::Not {
+                dr: reg(4)?,
+                sr: reg(7)?,
+            },
+            Op::Trap => Operands::Trap {
+                vector: command.bit_slice(8, 15)? as u8,
+            },
+            Op::Rti | Op::Res => Operands::Empty,
+        };
+
+        Ok(Self {
+            address,
+            word,
+            op,
+            operands,
+            isa,
+        })
+    }
+}
+
+// The instruction as assembly, e.g. "ADD R0, R0, #1"
+impl fmt::Display for DecodedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.isa {
+            Isa::Lc3 => write!(f, "{}", disassemble(self.address, self.word)),
+            Isa::Lc3b => write!(f, "{}", disassemble_lc3b(self.address, self.word)),
+        }
+    }
+}
+
+// An instruction as its fields, for building programs without working out
+// the bits by hand. Unlike DecodedInstruction, PC relative operands are
+// left as offsets, so an instruction encodes to the same word wherever it
+// ends up. RET is Jmp { base: 7 }.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    Add {
+        dr: u8,
+        sr1: u8,
+        src2: Source,
+    },
+    And {
+        dr: u8,
+        sr1: u8,
+        src2: Source,
+    },
+    Br {
+        n: bool,
+        z: bool,
+        p: bool,
+        offset: i16,
+    },
+    Jmp {
+        base: u8,
+    },
+    Jsr {
+        offset: i16,
+    },
+    Jsrr {
+        base: u8,
+    },
+    Ld {
+        dr: u8,
+        offset: i16,
+    },
+    Ldi {
+        dr: u8,
+        offset: i16,
+    },
+    Ldr {
+        dr: u8,
+        base: u8,
+        offset: i16,
+    },
+    Lea {
+        dr: u8,
+        offset: i16,
+    },
+    Not {
+        dr: u8,
+        sr: u8,
+    },
+    Rti,
+    St {
+        sr: u8,
+        offset: i16,
+    },
+    Sti {
+        sr: u8,
+        offset: i16,
+    },
+    Str {
+        sr: u8,
+        base: u8,
+        offset: i16,
+    },
+    Trap {
+        vector: u8,
+    },
+    // The unused op code, 1101
+    Reserved,
+}
+
+// The second source of ADD and AND
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Register(u8),
+    // -16 to 15
+    Immediate(i16),
+}
+
+impl Instruction {
+    // Every word decodes to something. Bits the instruction doesn't use are
+    // dropped, so encoding it again only gives back the same word if they
+    // were what the assembler would have put there.
+    pub fn decode(word: u16) -> Self {
+        let reg = |shift: u16| ((word >> shift) & 0x7) as u8;
+        let offset = |bits: u8| sign_extend(word & ((1 << bits) - 1), bits) as i16;
+
+        match Op::from_int((word >> 12) as u8) {
+            Ok(Op::Add) | Ok(Op::And) => {
+                let src2 = if word & 0x0020 != 0 {
+                    Source::Immediate(offset(5))
+                } else {
+                    Source::Register(reg(0))
+                };
+                if word >> 12 == 1 {
+                    Self::Add {
+                        dr: reg(9),
+                        sr1: reg(6),
+                        src2,
+                    }
+                } else {
+                    Self::And {
+                        dr: reg(9),
+                        sr1: reg(6),
+                        src2,
+                    }
+                }
+            }
+            Ok(Op::Br) => Self::Br {
+                n: word & 0x0800 != 0,
+                z: word & 0x0400 != 0,
+                p: word & 0x0200 != 0,
+                offset: offset(9),
+            },
+            Ok(Op::Jmp) => Self::Jmp { base: reg(6) },
+            Ok(Op::Jsr) if word & 0x0800 != 0 => Self::Jsr { offset: offset(11) },
+            Ok(Op::Jsr) => Self::Jsrr { base: reg(6) },
+            Ok(Op::Ld) => Self::Ld {
+                dr: reg(9),
+                offset: offset(9),
+            },
+            Ok(Op::Ldi) => Self::Ldi {
+                dr: reg(9),
+                offset: offset(9),
+            },
+            Ok(Op::Ldr) => Self::Ldr {
+                dr: reg(9),
+                base: reg(6),
+                offset: offset(6),
+            },
+            Ok(Op::Lea) => Self::Lea {
+                dr: reg(9),
+                offset: offset(9),
+            },
+            Ok(Op::Not) => Self::Not {
+                dr: reg(9),
+                sr: reg(6),
+            },
+            Ok(Op::Rti) => Self::Rti,
+            Ok(Op::St) => Self::St {
+                sr: reg(9),
+                offset: offset(9),
+            },
+            Ok(Op::Sti) => Self::Sti {
+                sr: reg(9),
+                offset: offset(9),
+            },
+            Ok(Op::Str) => Self::Str {
+                sr: reg(9),
+                base: reg(6),
+                offset: offset(6),
+            },
+            Ok(Op::Trap) => Self::Trap { vector: word as u8 },
+            // Including the LC-3b's op codes, which this never decodes to
+            _ => Self::Reserved,
+        }
+    }
+
+    // Registers and offsets too big for their fields are cut down to the bits
+    // that fit, as they would be by the machine
+    pub fn encode(&self) -> u16 {
+        let reg = |reg: u8, shift: u16| (reg as u16 & 0x7) << shift;
+        let offset = |offset: i16, bits: u8| offset as u16 & ((1 << bits) - 1);
+        let src2 = |src2: &Source| match src2 {
+            Source::Register(sr2) => reg(*sr2, 0),
+            Source::Immediate(imm) => 0x0020 | offset(*imm, 5),
+        };
+
+        match self {
+            Self::Add { dr, sr1, src2: s } => 0x1000 | reg(*dr, 9) | reg(*sr1, 6) | src2(s),
+            Self::And { dr, sr1, src2: s } => 0x5000 | reg(*dr, 9) | reg(*sr1, 6) | src2(s),
+            Self::Br { n, z, p, offset: o } => {
+                (*n as u16) << 11 | (*z as u16) << 10 | (*p as u16) << 9 | offset(*o, 9)
+            }
+            Self::Jmp { base } => 0xC000 | reg(*base, 6),
+            Self::Jsr { offset: o } => 0x4800 | offset(*o, 11),
+            Self::Jsrr { base } => 0x4000 | reg(*base, 6),
+            Self::Ld { dr, offset: o } => 0x2000 | reg(*dr, 9) | offset(*o, 9),
+            Self::Ldi { dr, offset: o } => 0xA000 | reg(*dr, 9) | offset(*o, 9),
+            Self::Ldr {
+                dr,
+                base,
+                offset: o,
+            } => 0x6000 | reg(*dr, 9) | reg(*base, 6) | offset(*o, 6),
+            Self::Lea { dr, offset: o } => 0xE000 | reg(*dr, 9) | offset(*o, 9),
+            Self::Not { dr, sr } => 0x9000 | reg(*dr, 9) | reg(*sr, 6) | 0x003F,
+            Self::Rti => 0x8000,
+            Self::St { sr, offset: o } => 0x3000 | reg(*sr, 9) | offset(*o, 9),
+            Self::Sti { sr, offset: o } => 0xB000 | reg(*sr, 9) | offset(*o, 9),
+            Self::Str {
+                sr,
+                base,
+                offset: o,
+            } => 0x7000 | reg(*sr, 9) | reg(*base, 6) | offset(*o, 6),
+            Self::Trap { vector } => 0xF000 | *vector as u16,
+            Self::Reserved => 0xD000,
+        }
+    }
+
+    pub fn op(&self) -> Op {
+        Op::from_int((self.encode() >> 12) as u8).unwrap_or(Op::Res)
+    }
+}
+
+// The instruction as assembly with offsets rather than labels, e.g.
+// "BRnz #-3"
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let offset = |_, offset: i16| format!("#{}", offset);
+        match disassemble_command(0, &Command::new(self.encode()), &offset) {
+            Ok(text) => write!(f, "{}", text),
+            Err(_) => write!(f, ".FILL x{:04X}", self.encode()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DecodedInstruction, Instruction, Operands, Source};
+    use crate::condition_flags::ConditionFlags;
+    use crate::error::LC3Result;
+    use crate::io::TestIOHandle;
+    use crate::isa::Isa;
+    use crate::op::Op;
+    use crate::register::Register::RR0;
+    use crate::vm::VM;
+
+    #[test]
+    fn can_decode_instructions() -> LC3Result<()> {
+        // Tuple format: (address, word, op, operands)
+        let test_cases = vec![
+            (
+                0x3000,
+                0x1283,
+                Op::Add,
+                Operands::Register {
+                    dr: 1,
+                    sr1: 2,
+                    sr2: 3,
+                },
+            ),
+            (
+                0x3000,
+                0x52BF,
+                Op::And,
+                Operands::Immediate {
+                    dr: 1,
+                    sr1: 2,
+                    imm: -1,
+                },
+            ),
+            (
+                0x3005,
+                0x0BFA,
+                Op::Br,
+                Operands::Branch {
+                    flags: ConditionFlags {
+                        negative: true,
+                        zero: false,
+                        positive: true,
+                    },
+                    target: 0x3000,
+                },
+            ),
+            (
+                0x3000,
+                0xE202,
+                Op::Lea,
+                Operands::PcRelative {
+                    reg: 1,
+                    address: 0x3003,
+                },
+            ),
+            (
+                0x3000,
+                0x62A0,
+                Op::Ldr,
+                Operands::BaseOffset {
+                    reg: 1,
+                    base: 2,
+                    offset: -32,
+                },
+            ),
+            (
+                0x3009,
+                0x4FF6,
+                Op::Jsr,
+                Operands::Subroutine { target: 0x3000 },
+            ),
+            (0x3000, 0x40C0, Op::Jsr, Operands::BaseRegister { base: 3 }),
+            (0x3000, 0xC1C0, Op::Jmp, Operands::BaseRegister { base: 7 }),
+            (0x3000, 0x997F, Op::Not, Operands::Not { dr: 4, sr: 5 }),
+            (0x3000, 0xF025, Op::Trap, Operands::Trap { vector: 0x25 }),
+            (0x3000, 0x8000, Op::Rti, Operands::Empty),
+        ];
+
+        for (address, word, op, operands) in test_cases {
+            let instruction = DecodedInstruction::decode(address, word)?;
+            assert_eq!(
+                instruction,
+                DecodedInstruction {
+                    address,
+                    word,
+                    op,
+                    operands,
+                    isa: Isa::Lc3,
+                }
+            );
+        }
+
+        assert_eq!(
+            DecodedInstruction::decode(0x3000, 0x1283)?.to_string(),
+            "ADD R1, R2, R3"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_encode_instructions() {
+        // Tuple format: (instruction, word, assembly)
+        let test_cases = vec![
+            (
+                Instruction::Add {
+                    dr: 1,
+                    sr1: 2,
+                    src2: Source::Register(3),
+                },
+                0x1283,
+                "ADD R1, R2, R3",
+            ),
+            (
+                Instruction::And {
+                    dr: 1,
+                    sr1: 2,
+                    src2: Source::Immediate(-1),
+                },
+                0x52BF,
+                "AND R1, R2, #-1",
+            ),
+            (
+                Instruction::Br {
+                    n: true,
+                    z: false,
+                    p: true,
+                    offset: -6,
+                },
+                0x0BFA,
+                "BRnp #-6",
+            ),
+            (Instruction::Jmp { base: 7 }, 0xC1C0, "RET"),
+            (Instruction::Jsr { offset: -10 }, 0x4FF6, "JSR #-10"),
+            (Instruction::Jsrr { base: 3 }, 0x40C0, "JSRR R3"),
+            (Instruction::Ld { dr: 0, offset: 4 }, 0x2004, "LD R0, #4"),
+            (Instruction::Ldi { dr: 1, offset: 4 }, 0xA204, "LDI R1, #4"),
+            (
+                Instruction::Ldr {
+                    dr: 1,
+                    base: 2,
+                    offset: -32,
+                },
+                0x62A0,
+                "LDR R1, R2, #-32",
+            ),
+            (Instruction::Lea { dr: 1, offset: 2 }, 0xE202, "LEA R1, #2"),
+            (Instruction::Not { dr: 4, sr: 5 }, 0x997F, "NOT R4, R5"),
+            (Instruction::Rti, 0x8000, "RTI"),
+            (Instruction::St { sr: 0, offset: 1 }, 0x3001, "ST R0, #1"),
+            (Instruction::Sti { sr: 0, offset: 3 }, 0xB003, "STI R0, #3"),
+            (
+                Instruction::Str {
+                    sr: 3,
+                    base: 6,
+                    offset: 0,
+                },
+                0x7780,
+                "STR R3, R6, #0",
+            ),
+            (Instruction::Trap { vector: 0x25 }, 0xF025, "HALT"),
+            (Instruction::Reserved, 0xD000, ".FILL xD000"),
+        ];
+
+        for (instruction, word, assembly) in test_cases {
+            assert_eq!(instruction.encode(), word);
+            assert_eq!(Instruction::decode(word), instruction);
+            assert_eq!(instruction.to_string(), assembly);
+        }
+        assert_eq!(Instruction::Rti.op(), Op::Rti);
+
+        // Every word decodes to something that encodes to a word which
+        // decodes the same way
+        for word in 0..=u16::MAX {
+            let instruction = Instruction::decode(word);
+            assert_eq!(Instruction::decode(instruction.encode()), instruction);
+        }
+
+        // Ignored bits are dropped, and fields too big are cut down to size
+        assert_eq!(Instruction::decode(0x8FFF), Instruction::Rti);
+        assert_eq!(Instruction::decode(0x9000).encode(), 0x903F);
+        assert_eq!(Instruction::Ld { dr: 8, offset: 256 }.encode(), 0x2100);
+    }
+
+    #[test]
+    fn can_run_encoded_programs() -> LC3Result<()> {
+        let program: Vec<u16> = [
+            Instruction::And {
+                dr: 0,
+                sr1: 0,
+                src2: Source::Immediate(0),
+            },
+            Instruction::Ld { dr: 1, offset: 4 },
+            // LOOP
+            Instruction::Add {
+                dr: 0,
+                sr1: 0,
+                src2: Source::Immediate(3),
+            },
+            Instruction::Add {
+                dr: 1,
+                sr1: 1,
+                src2: Source::Immediate(-1),
+            },
+            Instruction::Br {
+                n: false,
+                z: false,
+                p: true,
+                offset: -3,
+            },
+            Instruction::Trap { vector: 0x25 },
+        ]
+        .iter()
+        .map(Instruction::encode)
+        .chain(std::iter::once(5))
+        .collect();
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_program(&program)?;
+        vm.run()?;
+        assert_eq!(vm.register(RR0), 15);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,465 @@
+use crate::error::LC3Result;
+use crate::io::IOHandle;
+use crate::isa::Isa;
+use crate::memory::MemoryMap;
+use crate::plugin::PluginSet;
+use crate::register::Register::{RCond, RPC, RR6};
+use crate::vm::{KB_STATUS_POS, TIMER_CONTROL_POS, TIMER_INTERVAL_POS, VM};
+use crate::wrapping_add;
+
+// Where the addresses of the interrupt and exception handlers are kept,
+// indexed by vector
+pub(crate) const VECTOR_TABLE: u16 = 0x0100;
+
+// The LC-3b's, where each entry takes two bytes
+const LC3B_VECTOR_TABLE: u16 = 0x0200;
+
+// Bit 15 of the PSR, set while running in user mode
+pub(crate) const PSR_USER: u16 = 1 << 15;
+
+// Where R6 points when an interrupt first switches to the supervisor stack,
+// unless the host says otherwise. It grows down from just under user space.
+pub(crate) const SUPERVISOR_STACK_START: u16 = 0x3000;
+
+// Exception vectors
+pub(crate) const PRIVILEGE_VECTOR: u8 = 0x00;
+pub(crate) const ILLEGAL_OPCODE_VECTOR: u8 = 0x01;
+pub(crate) const ACCESS_VIOLATION_VECTOR: u8 = 0x02;
+
+// User mode programs are kept out of the operating system's memory, below
+// x3000, and out of the device registers, from xFE00 up unless the memory
+// map moves them
+pub(crate) fn is_privileged(map: &MemoryMap, address: u16) -> bool {
+    address < 0x3000 || map.is_device(address)
+}
+
+// Bit 14 of KBSR, which programs set to be interrupted when a key arrives
+// rather than polling for it
+pub(crate) const KB_INTERRUPT_ENABLE: u16 = 1 << 14;
+const KB_VECTOR: u8 = 0x80;
+const KB_PRIORITY: u8 = 4;
+
+// The timer's control register. Bit 0 starts it counting, bit 14 has it
+// interrupt when the interval is up and bit 15 is set every time it is, for
+// programs that poll instead. It's up to the program to clear bit 15.
+pub(crate) const TIMER_ENABLE: u16 = 1;
+pub(crate) const TIMER_INTERRUPT_ENABLE: u16 = 1 << 14;
+pub(crate) const TIMER_READY: u16 = 1 << 15;
+const TIMER_VECTOR: u8 = 0x81;
+const TIMER_PRIORITY: u8 = 5;
+
+// Checked before each instruction is fetched. A device only gets to
+// interrupt a program running at a lower priority than its own.
+pub(crate) fn poll<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>) -> LC3Result<()> {
+    // The handler takes the key from KBDR. Until it does, KBSR stays ready
+    // and the keyboard keeps asking to interrupt.
+    let kbsr = vm.memory_at(vm.device(KB_STATUS_POS));
+    if kbsr & KB_INTERRUPT_ENABLE != 0 && vm.priority < KB_PRIORITY && vm.poll_keyboard()? {
+        enter(vm, KB_VECTOR, KB_PRIORITY)?;
+    }
+    Ok(())
+}
+
+// Called after each instruction executes while the clock is running. The
+// timer counts instructions rather than time, so runs are repeatable. If
+// the program is already running at the timer's priority or above when the
+// interval is up, the tick only sets the ready bit.
+pub(crate) fn tick<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>) -> LC3Result<()> {
+    let control = vm.memory_at(vm.device(TIMER_CONTROL_POS));
+    let interval = vm.memory_at(vm.device(TIMER_INTERVAL_POS));
+    if control & TIMER_ENABLE == 0 || interval == 0 {
+        return Ok(());
+    }
+
+    vm.timer_count += 1;
+    if vm.timer_count < interval {
+        return Ok(());
+    }
+    vm.timer_count = 0;
+    vm.mem_write(vm.device(TIMER_CONTROL_POS), control | TIMER_READY)?;
+    if control & TIMER_INTERRUPT_ENABLE != 0 && vm.priority < TIMER_PRIORITY {
+        enter(vm, TIMER_VECTOR, TIMER_PRIORITY)?;
+    }
+    Ok(())
+}
+
+// Like an interrupt, but an exception is caused by the program itself so
+// the priority stays as it is
+pub(crate) fn exception<IO: IOHandle, P: PluginSet<IO>>(
+    vm: &mut VM<IO, P>,
+    vector: u8,
+) -> LC3Result<()> {
+    let priority = vm.priority;
+    enter(vm, vector, priority)
+}
+
+// Checked before each instruction is fetched, since user mode programs
+// can't execute privileged memory either
+pub(crate) fn check_fetch<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>) -> LC3Result<()> {
+    let pc = vm.pc();
+    if vm.check_access(pc).is_err() {
+        exception(vm, ACCESS_VIOLATION_VECTOR)?;
+    }
+    Ok(())
+}
+
+// Switch to supervisor mode and the supervisor stack, push the old PSR and
+// then the PC onto it, and carry on from the handler for vector at the
+// given priority
+pub(crate) fn enter<IO: IOHandle, P: PluginSet<IO>>(
+    vm: &mut VM<IO, P>,
+    vector: u8,
+    priority: u8,
+) -> LC3Result<()> {
+    save_context(vm)?;
+
+    vm.priority = priority;
+    let entry = match vm.isa {
+        Isa::Lc3 => wrapping_add!(VECTOR_TABLE, u16::from(vector)),
+        Isa::Lc3b => wrapping_add!(LC3B_VECTOR_TABLE, u16::from(vector) << 1),
+    };
+    let handler = vm.mem_read(entry)?;
+    vm.reg_write(RPC, handler)
+}
+
+// TRAP as the third edition has it: the same as an exception, but carrying
+// on from the service routine in the trap vector table
+pub(crate) fn enter_trap<IO: IOHandle, P: PluginSet<IO>>(
+    vm: &mut VM<IO, P>,
+    code: u8,
+) -> LC3Result<()> {
+    save_context(vm)?;
+    let routine = vm.mem_read(u16::from(code) * vm.isa.word_size())?;
+    vm.reg_write(RPC, routine)
+}
+
+fn save_context<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>) -> LC3Result<()> {
+    let psr = vm.psr();
+    let pc = vm.reg_read(RPC)?;
+    if vm.user_mode {
+        vm.saved_usp = vm.reg_read(RR6)?;
+        let ssp = vm.saved_ssp;
+        vm.reg_write(RR6, ssp)?;
+        vm.user_mode = false;
+    }
+    push(vm, psr)?;
+    push(vm, pc)
+}
+
+// Undo enter: pop the PC and PSR, and go back to the user stack if that's
+// the mode being returned to
+pub(crate) fn exit<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>) -> LC3Result<()> {
+    let pc = pop(vm)?;
+    let psr = pop(vm)?;
+    vm.reg_write(RPC, pc)?;
+    vm.set_psr(psr);
+    vm.reg_write(RCond, psr & 0x7)?;
+    if vm.user_mode {
+        vm.saved_ssp = vm.reg_read(RR6)?;
+        let usp = vm.saved_usp;
+        vm.reg_write(RR6, usp)?;
+    }
+    Ok(())
+}
+
+fn push<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>, val: u16) -> LC3Result<()> {
+    let sp = vm.reg_read(RR6)?.wrapping_sub(vm.isa.word_size());
+    vm.reg_write(RR6, sp)?;
+    vm.mem_write(sp, val)
+}
+
+fn pop<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>) -> LC3Result<u16> {
+    let sp = vm.reg_read(RR6)?;
+    let val = vm.mem_read(sp)?;
+    vm.reg_write(RR6, wrapping_add!(sp, vm.isa.word_size()))?;
+    Ok(val)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PSR_USER, VECTOR_TABLE};
+    use crate::asm::assemble;
+    use crate::error::{LC3Error, LC3Result};
+    use crate::io::TestIOHandle;
+    use crate::register::Register::{RPC, RR0, RR1, RR2, RR6};
+    use crate::vm::VM;
+
+    #[test]
+    fn can_interrupt_on_key_press() -> LC3Result<()> {
+        let program: Vec<u16> = vec![
+            // LD R6, STACK
+            0x2C04,
+            // LD R0, IE
+            0x2004,
+            // STI R0, KBSR
+            0xB004,
+            // LOOP: BRnzp LOOP
+            0x0FFF,
+            // HALT, never reached
+            0xF025,
+            // STACK
+            0x4000,
+            // IE
+            0x4000,
+            // KBSR
+            0xFE00,
+        ];
+        let handler: Vec<u16> = vec![
+            // LDI R0, KBDR
+            0xA002,
+            // OUT
+            0xF021,
+            // HALT
+            0xF025,
+            // KBDR
+            0xFE02,
+        ];
+
+        let mut io_handle = TestIOHandle::new();
+        io_handle.add_key_press('k');
+        // The key arrives on the third check, while the program is spinning
+        // in the loop
+        io_handle.add_keydown_response(false);
+        io_handle.add_keydown_response(false);
+        io_handle.add_keydown_response(true);
+
+        let mut vm = VM::new_with_io(io_handle);
+        vm.load_program(&program)?;
+        vm.load_program_at(0x3100, &handler)?;
+        vm.load_program_at(VECTOR_TABLE + 0x80, &[0x3100])?;
+        vm.run()?;
+
+        // The PC pushed last is the loop the program was interrupted in,
+        // with the PSR underneath it
+        assert_eq!(vm.register(RR6), 0x3FFE);
+        assert_eq!(vm.memory_at(0x3FFE), 0x3003);
+        assert_eq!(vm.memory_at(0x3FFF) & 0x0007, 0x0001);
+        assert_eq!(vm.register(RPC), 0x3103);
+        assert_eq!(vm.into_io_handle().get_test_outputs(), vec!['k']);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_return_to_user_mode() -> LC3Result<()> {
+        let program: Vec<u16> = vec![
+            // LOOP: ADD R1, R1, #1
+            0x1261,
+            // BRnzp LOOP
+            0x0FFE,
+        ];
+        let handler: Vec<u16> = vec![
+            // LDI R0, KBDR
+            0xA002,
+            // OUT
+            0xF021,
+            // RTI
+            0x8000,
+            // KBDR
+            0xFE02,
+        ];
+
+        let mut io_handle = TestIOHandle::new();
+        io_handle.add_key_press('k');
+        // No key, then a key, then no more while the program carries on
+        for key_down in &[false, true, false, false, false] {
+            io_handle.add_keydown_response(*key_down);
+        }
+
+        let mut vm = VM::new_with_io(io_handle);
+        vm.load_program(&program)?;
+        vm.load_program_at(0x3100, &handler)?;
+        vm.load_program_at(VECTOR_TABLE + 0x80, &[0x3100])?;
+        vm.set_memory_at(0xFE00, super::KB_INTERRUPT_ENABLE);
+        vm.set_psr(PSR_USER);
+        vm.set_register(RR6, 0x4000);
+        vm.step()?;
+        vm.step()?;
+
+        // The handler runs on the supervisor stack
+        assert_eq!(vm.psr() & PSR_USER, 0);
+        assert_eq!(vm.register(RR6), 0x2FFE);
+        assert_eq!(vm.memory_at(0x2FFE), 0x3001);
+        assert_eq!(vm.memory_at(0x2FFF), PSR_USER | 0x0001);
+
+        for _ in 0..5 {
+            vm.step()?;
+        }
+        assert_eq!(vm.psr(), PSR_USER | 0x0001);
+        assert_eq!(vm.register(RR6), 0x4000);
+        assert_eq!(vm.register(RR1), 2);
+        assert_eq!(vm.register(RPC), 0x3000);
+        assert_eq!(vm.into_io_handle().get_test_outputs(), vec!['k']);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_raise_privilege_exceptions() -> LC3Result<()> {
+        let test_cases = vec![
+            // LDI R0, KBSR_ADDRESS, reading a device register
+            (vec![0xA000, 0xFE00], super::ACCESS_VIOLATION_VECTOR, 0x3001),
+            // RTI
+            (vec![0x8000], super::PRIVILEGE_VECTOR, 0x3001),
+            // JMP R1, with R1 still zero, so the next fetch is from x0000
+            (vec![0xC040], super::ACCESS_VIOLATION_VECTOR, 0x0000),
+        ];
+
+        for (program, vector, saved_pc) in test_cases {
+            let mut vm = VM::new_with_io(TestIOHandle::new());
+            vm.load_program(&program)?;
+            // HALT
+            vm.load_program_at(0x1000, &[0xF025])?;
+            vm.load_program_at(VECTOR_TABLE + u16::from(vector), &[0x1000])?;
+            vm.set_psr(PSR_USER | 0x0002);
+            vm.set_register(RR6, 0x4000);
+            vm.run()?;
+
+            // The exception handler ran in supervisor mode, at the same
+            // priority, with the user PC and PSR saved on the supervisor
+            // stack
+            assert_eq!(vm.register(RPC), 0x1001);
+            assert_eq!(vm.psr() & PSR_USER, 0);
+            assert_eq!(vm.register(RR6), 0x2FFE);
+            assert_eq!(vm.memory_at(0x2FFE), saved_pc);
+            assert_eq!(vm.memory_at(0x2FFF), PSR_USER | 0x0002);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_raise_illegal_opcode_exceptions() -> LC3Result<()> {
+        // The reserved op code
+        let program: Vec<u16> = vec![0xD000];
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.set_illegal_opcode_exceptions(true);
+        vm.load_program(&program)?;
+        // HALT
+        vm.load_program_at(0x1000, &[0xF025])?;
+        vm.load_program_at(VECTOR_TABLE + 0x01, &[0x1000])?;
+        // Already in supervisor mode, so the state goes on whatever stack
+        // R6 points to
+        vm.set_register(RR6, 0x3000);
+        vm.run()?;
+        assert_eq!(vm.register(RPC), 0x1001);
+        assert_eq!(vm.memory_at(0x2FFE), 0x3001);
+
+        // Without an operating system the run fails instead
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_program(&program)?;
+        assert!(matches!(
+            vm.run(),
+            Err(LC3Error::IllegalOpcode { word: 0xD000 })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_return_from_nested_keyboard_interrupts() -> LC3Result<()> {
+        let program = assemble(
+            r#"
+            .ORIG x3000
+LOOP        ADD R1, R1, #1
+            BRnzp LOOP
+            .END
+        "#,
+        )?;
+        // Saves and restores the R0 it uses, like a real handler has to
+        let handler = assemble(
+            r#"
+            .ORIG x1000
+            ADD R6, R6, #-1
+            STR R0, R6, #0
+            LDI R0, KBDR
+            OUT
+            LDR R0, R6, #0
+            ADD R6, R6, #1
+            RTI
+KBDR        .FILL xFE02
+            .END
+        "#,
+        )?;
+
+        let mut io_handle = TestIOHandle::new();
+        io_handle.add_key_press('a');
+        io_handle.add_key_press('b');
+        // The first key arrives once the program has started, and the
+        // second as soon as the handler has returned from the first
+        for key_down in &[false, true, true, false] {
+            io_handle.add_keydown_response(*key_down);
+        }
+
+        let mut vm = VM::new_with_io(io_handle);
+        vm.load_object(&program.to_object())?;
+        vm.load_program_at(handler.origin, &handler.words)?;
+        vm.load_program_at(VECTOR_TABLE + 0x80, &[0x1000])?;
+        vm.set_memory_at(0xFE00, super::KB_INTERRUPT_ENABLE);
+        vm.set_psr(PSR_USER);
+        vm.set_register(RR0, 7);
+        vm.set_register(RR6, 0x4000);
+        for _ in 0..16 {
+            vm.step()?;
+        }
+
+        // Back in the user program at its own priority, privilege,
+        // condition codes and stack, with its registers untouched
+        assert_eq!(vm.psr(), PSR_USER | 0x0001);
+        assert_eq!(vm.register(RR6), 0x4000);
+        assert_eq!(vm.register(RR0), 7);
+        assert_eq!(vm.register(RR1), 1);
+        assert_eq!(vm.register(RPC), 0x3000);
+        assert_eq!(vm.into_io_handle().get_test_outputs(), vec!['a', 'b']);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_interrupt_on_timer_ticks() -> LC3Result<()> {
+        let program = assemble(
+            r#"
+            .ORIG x3000
+            LD R0, INTERVAL
+            STI R0, TMIR
+            LD R0, CONTROL
+            STI R0, TMCR
+LOOP        ADD R1, R1, #1
+            ADD R3, R2, #-2
+            BRn LOOP
+            HALT
+TMCR        .FILL xFE0A
+TMIR        .FILL xFE0C
+INTERVAL    .FILL #10
+CONTROL     .FILL x4001
+            .END
+        "#,
+        )?;
+        // Counts the ticks in R2
+        let handler = assemble(
+            r#"
+            .ORIG x1000
+            ADD R2, R2, #1
+            RTI
+            .END
+        "#,
+        )?;
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_object(&program.to_object())?;
+        vm.load_program_at(handler.origin, &handler.words)?;
+        vm.load_program_at(VECTOR_TABLE + 0x81, &[0x1000])?;
+        vm.set_register(RR6, 0x3000);
+        vm.run_with_limit(100)?;
+
+        assert!(!vm.is_running());
+        assert_eq!(vm.register(RR2), 2);
+        // The STI that started the timer and the handler's instructions
+        // count towards the intervals too
+        assert_eq!(vm.register(RR1), 7);
+        assert_eq!(vm.memory_at(0xFE0A), 0xC001);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+// Programs can style the terminal and move the cursor around by writing
+// ANSI escape sequences, which are passed through to the terminal as they
+// are. Anything showing the output somewhere else can take them out again.
+
+pub(crate) const ESCAPE: char = '\u{1b}';
+
+// Puts back the colours and attributes and shows the cursor, in case the
+// program changed them and didn't clean up after itself
+pub(crate) const RESET: &str = "\u{1b}[0m\u{1b}[?25h";
+
+// The text with escape sequences taken out: control sequences such as
+// colours and cursor moves, operating system commands such as window
+// titles, and the short two character ones
+pub(crate) fn strip_escapes(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch != ESCAPE {
+            stripped.push(ch);
+            continue;
+        }
+        match chars.next() {
+            // Parameters and intermediates up to a final byte from @ to ~
+            Some('[') => {
+                for ch in chars.by_ref() {
+                    if ('@'..='~').contains(&ch) {
+                        break;
+                    }
+                }
+            }
+            // Up to a bell, or an escape and backslash
+            Some(']') => {
+                while let Some(ch) = chars.next() {
+                    if ch == '\u{7}' {
+                        break;
+                    }
+                    if ch == ESCAPE && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn can_strip_escape_sequences() {
+        assert_eq!(strip_escapes("plain"), "plain");
+        assert_eq!(strip_escapes("\u{1b}[1;31mred\u{1b}[0m"), "red");
+        assert_eq!(strip_escapes("\u{1b}[2J\u{1b}[10;5Hmoved"), "moved");
+        assert_eq!(strip_escapes("\u{1b}]0;title\u{7}after"), "after");
+        assert_eq!(strip_escapes("\u{1b}]0;title\u{1b}\\after"), "after");
+        assert_eq!(strip_escapes("\u{1b}7saved\u{1b}8"), "saved");
+        // A sequence cut off at the end doesn't take anything else with it
+        assert_eq!(strip_escapes("text\u{1b}[1"), "text");
+    }
+}
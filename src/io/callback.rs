@@ -0,0 +1,123 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use super::io_handle::IOHandle;
+use crate::error::{LC3Error, LC3Result};
+
+// Hands each character the program writes to a callback, and takes keys
+// that have been fed to it. Nothing here needs a terminal or a thread, so
+// it's what an in-browser terminal is built on: with wasm-bindgen, the
+// callback calls into JS to draw the character or send it down a
+// WebSocket, and JS key or message events feed the keys back in.
+//
+// A browser can't block waiting for a key, so getchar fails with
+// InputUnavailable when none are waiting. Load the OS with vm.load_os(), so
+// GETC and IN poll KBSR rather than waiting, and run the VM a batch of
+// steps at a time from the event loop.
+pub struct CallbackIOHandle {
+    output: Box<dyn Fn(char)>,
+    keys: KeyFeeder,
+}
+
+impl CallbackIOHandle {
+    pub fn new<F: Fn(char) + 'static>(output: F) -> Self {
+        Self {
+            output: Box::new(output),
+            keys: KeyFeeder::default(),
+        }
+    }
+
+    pub fn key_feeder(&self) -> KeyFeeder {
+        self.keys.clone()
+    }
+}
+
+impl IOHandle for CallbackIOHandle {
+    fn getchar(&self) -> LC3Result<char> {
+        self.keys
+            .keys
+            .borrow_mut()
+            .pop_front()
+            .ok_or(LC3Error::InputUnavailable)
+    }
+
+    fn putchar(&self, ch: char) -> LC3Result<()> {
+        (self.output)(ch);
+        Ok(())
+    }
+
+    fn is_key_down(&self) -> LC3Result<bool> {
+        Ok(!self.keys.keys.borrow().is_empty())
+    }
+}
+
+// Queues keys for a CallbackIOHandle, from the same thread
+#[derive(Clone, Default)]
+pub struct KeyFeeder {
+    keys: Rc<RefCell<VecDeque<char>>>,
+}
+
+impl KeyFeeder {
+    pub fn push(&self, ch: char) {
+        self.keys.borrow_mut().push_back(ch);
+    }
+
+    pub fn push_str(&self, text: &str) {
+        self.keys.borrow_mut().extend(text.chars());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::vm::VM;
+
+    #[test]
+    fn can_run_console_through_callbacks() -> LC3Result<()> {
+        let program = assemble(
+            r#"
+            .ORIG x3000
+            GETC
+            OUT
+            GETC
+            OUT
+            HALT
+            .END
+        "#,
+        )?;
+
+        let output = Rc::new(RefCell::new(String::new()));
+        let written = output.clone();
+        let io_handle = CallbackIOHandle::new(move |ch| written.borrow_mut().push(ch));
+        let keys = io_handle.key_feeder();
+
+        let mut vm = VM::new_with_io(io_handle);
+        vm.load_os()?;
+        vm.load_object(&program.to_object())?;
+        keys.push('o');
+
+        // GETC keeps polling until the second key turns up
+        for _ in 0..1000 {
+            vm.step()?;
+        }
+        assert_eq!(*output.borrow(), "o");
+
+        keys.push('k');
+        while !vm.step()?.halted {}
+        assert_eq!(*output.borrow(), "ok\n--- Halting the LC-3 ---\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn cant_wait_for_keys() {
+        let io_handle = CallbackIOHandle::new(|_| ());
+        assert!(!io_handle.is_key_down().unwrap());
+        assert!(matches!(
+            io_handle.getchar(),
+            Err(LC3Error::InputUnavailable)
+        ));
+    }
+}
@@ -0,0 +1,109 @@
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use super::io_handle::IOHandle;
+use super::peekable::PeekableReceiver;
+use crate::error::{LC3Error, LC3Result};
+
+// Takes the program's input from one channel and sends its output down
+// another, so a host application can drive the console from another thread
+// without a terminal being involved at all. Once every key sender has gone
+// there can be no more input, so is_key_down says no and getchar fails
+// with ConsoleClosed, as does putchar once the output receiver has gone.
+pub struct ChannelIOHandle {
+    keys: PeekableReceiver<char>,
+    output: Sender<char>,
+}
+
+impl ChannelIOHandle {
+    pub fn new(keys: Receiver<char>, output: Sender<char>) -> Self {
+        Self {
+            keys: PeekableReceiver::new(keys),
+            output,
+        }
+    }
+
+    // A handle along with the host's ends of its channels: the sender for
+    // keys and the receiver for output
+    pub fn channels() -> (Self, Sender<char>, Receiver<char>) {
+        let (key_sender, keys) = channel();
+        let (output, output_receiver) = channel();
+        (Self::new(keys, output), key_sender, output_receiver)
+    }
+}
+
+impl IOHandle for ChannelIOHandle {
+    fn getchar(&self) -> LC3Result<char> {
+        self.keys.recv().map_err(|_| LC3Error::ConsoleClosed)
+    }
+
+    fn putchar(&self, ch: char) -> LC3Result<()> {
+        self.output.send(ch).map_err(|_| LC3Error::ConsoleClosed)
+    }
+
+    fn is_key_down(&self) -> LC3Result<bool> {
+        Ok(self.keys.has_next().unwrap_or(false))
+    }
+
+    fn try_getchar(&self, timeout: Duration) -> LC3Result<Option<char>> {
+        match self.keys.recv_timeout(timeout) {
+            Ok(ch) => Ok(Some(ch)),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::*;
+    use crate::asm::assemble;
+    use crate::vm::VM;
+
+    #[test]
+    fn can_drive_console_over_channels() -> LC3Result<()> {
+        let program = assemble(
+            r#"
+            .ORIG x3000
+    LOOP    GETC
+            ADD R1, R0, #-10
+            BRz DONE
+            OUT
+            BR LOOP
+    DONE    HALT
+            .END
+        "#,
+        )?;
+
+        let (io_handle, keys, output) = ChannelIOHandle::channels();
+        let host = thread::spawn(move || {
+            for ch in "echo\n".chars() {
+                keys.send(ch).unwrap();
+            }
+            output.iter().collect::<String>()
+        });
+
+        let mut vm = VM::new_with_io(io_handle);
+        vm.load_object(&program.to_object())?;
+        vm.run()?;
+        // Hangs up the output channel, so the host stops waiting for more
+        drop(vm);
+
+        assert_eq!(host.join().unwrap(), "echo");
+
+        Ok(())
+    }
+
+    #[test]
+    fn cant_read_once_keys_are_closed() {
+        let (io_handle, keys, _output) = ChannelIOHandle::channels();
+        keys.send('k').unwrap();
+        drop(keys);
+
+        assert!(io_handle.is_key_down().unwrap());
+        assert_eq!(io_handle.getchar().unwrap(), 'k');
+        assert!(!io_handle.is_key_down().unwrap());
+        assert!(matches!(io_handle.getchar(), Err(LC3Error::ConsoleClosed)));
+    }
+}
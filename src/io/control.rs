@@ -0,0 +1,107 @@
+use super::ansi::ESCAPE;
+
+const BELL: char = '\u{7}';
+const BACKSPACE: char = '\u{8}';
+const DELETE: char = '\u{7f}';
+const TAB_WIDTH: u16 = 8;
+
+// What the terminal should do for a character the program wrote
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ControlAction {
+    Print(char),
+    Bell,
+    // Move the cursor back a column without erasing anything, as programs
+    // that want to erase write "\b \b"
+    Backspace,
+    // Move to the next tab stop, as this many spaces so it shows the same
+    // everywhere
+    Tab(u16),
+    // Control characters with nothing sensible to show
+    Ignore,
+}
+
+// Turns control characters into terminal actions rather than writing them
+// literally, keeping track of the column the cursor's in to do it
+#[derive(Debug, Default)]
+pub(crate) struct ControlTranslator {
+    column: u16,
+    // Inside an escape sequence, which moves the cursor or styles text
+    // rather than printing anything
+    in_escape: bool,
+}
+
+impl ControlTranslator {
+    pub(crate) fn action(&mut self, ch: char) -> ControlAction {
+        if self.in_escape {
+            // Sequences end with a letter or the like, apart from the '['
+            // starting a control sequence
+            self.in_escape = ch == '[' || !('@'..='~').contains(&ch);
+            return ControlAction::Print(ch);
+        }
+
+        match ch {
+            ESCAPE => {
+                self.in_escape = true;
+                ControlAction::Print(ch)
+            }
+            '\n' | '\r' => {
+                self.column = 0;
+                ControlAction::Print(ch)
+            }
+            BELL => ControlAction::Bell,
+            BACKSPACE => {
+                if self.column == 0 {
+                    return ControlAction::Ignore;
+                }
+                self.column -= 1;
+                ControlAction::Backspace
+            }
+            '\t' => {
+                let spaces = TAB_WIDTH - self.column % TAB_WIDTH;
+                self.column = self.column.saturating_add(spaces);
+                ControlAction::Tab(spaces)
+            }
+            _ if ch.is_control() || ch == DELETE => ControlAction::Ignore,
+            _ => {
+                self.column = self.column.saturating_add(1);
+                ControlAction::Print(ch)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ControlAction::*;
+
+    fn actions(text: &str) -> Vec<ControlAction> {
+        let mut translator = ControlTranslator::default();
+        text.chars().map(|ch| translator.action(ch)).collect()
+    }
+
+    #[test]
+    fn can_translate_control_characters() {
+        assert_eq!(
+            actions("ab\u{8}\u{7}\u{0}"),
+            vec![Print('a'), Print('b'), Backspace, Bell, Ignore]
+        );
+        assert_eq!(
+            actions("abc\t\td\n\t"),
+            vec![
+                Print('a'),
+                Print('b'),
+                Print('c'),
+                Tab(5),
+                Tab(8),
+                Print('d'),
+                Print('\n'),
+                Tab(8)
+            ]
+        );
+        // Nothing to back over at the start of the line
+        assert_eq!(actions("\u{8}"), vec![Ignore]);
+        // Escape sequences don't take up any columns
+        assert_eq!(actions("\u{1b}[1;31mx\t")[7..], [Print('x'), Tab(7)]);
+    }
+}
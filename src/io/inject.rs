@@ -0,0 +1,52 @@
+use std::sync::mpsc::{channel, Sender};
+
+use super::peekable::PeekableReceiver;
+use crate::error::{LC3Error, LC3Result};
+
+// Pushes keys into a VM's keyboard queue from any thread, while it runs.
+// They're read ahead of anything from the IO handle, the same as if they'd
+// been typed.
+#[derive(Debug, Clone)]
+pub struct InputSender {
+    sender: Sender<char>,
+}
+
+impl InputSender {
+    pub fn send(&self, ch: char) -> LC3Result<()> {
+        self.sender.send(ch).map_err(|_| LC3Error::InputClosed)
+    }
+
+    pub fn send_str(&self, text: &str) -> LC3Result<()> {
+        text.chars().try_for_each(|ch| self.send(ch))
+    }
+}
+
+// The VM's end of the InputSenders
+pub(crate) struct InjectedInput {
+    sender: Sender<char>,
+    keys: PeekableReceiver<char>,
+}
+
+impl InjectedInput {
+    pub(crate) fn new() -> Self {
+        let (sender, keys) = channel();
+        Self {
+            sender,
+            keys: PeekableReceiver::new(keys),
+        }
+    }
+
+    pub(crate) fn sender(&self) -> InputSender {
+        InputSender {
+            sender: self.sender.clone(),
+        }
+    }
+
+    pub(crate) fn is_key_down(&mut self) -> bool {
+        self.keys.has_next().unwrap_or(false)
+    }
+
+    pub(crate) fn try_getchar(&mut self) -> Option<char> {
+        self.keys.try_recv().ok()
+    }
+}
@@ -1,14 +1,14 @@
 use std::io::{stdout, Write};
 
 use crossterm::event::{read, Event, KeyCode, KeyEvent, KeyModifiers};
-use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
 
+use super::terminal::TerminalGuard;
 use crate::error::{BoxErrors, LC3Result};
 
 pub fn getchar() -> LC3Result<char> {
     // Largely owe this code to this Stackoverflow answer:
     // https://stackoverflow.com/questions/60130532/detect-keydown-in-rust
-    try_enable_raw_mode()?;
+    let guard = TerminalGuard::raw_mode()?;
 
     let read_char = loop {
         match read().map_io_error()? {
@@ -21,7 +21,7 @@ pub fn getchar() -> LC3Result<char> {
             }
     };
 
-    try_disable_raw_mode()?;
+    guard.restore()?;
 
     Ok(read_char)
 }
@@ -32,10 +32,3 @@ pub(crate) fn putchar(ch: char) -> LC3Result<()> {
     stdout.flush().map_io_error()
 }
 
-fn try_enable_raw_mode() -> LC3Result<()> {
-    enable_raw_mode().map_io_error()
-}
-
-fn try_disable_raw_mode() -> LC3Result<()> {
-    disable_raw_mode().map_io_error()
-}
@@ -1,13 +1,8 @@
-#[cfg(test)]
 use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::time::Duration;
 
-use device_query::{DeviceQuery, DeviceState};
-
-use super::io::{getchar, putchar};
-use crate::error::LC3Result;
-
-#[cfg(test)]
-use crate::error::{LC3Error};
+use crate::error::{LC3Error, LC3Result};
 
 // TODO: Maybe the dependency should be flipped here, so this trait should
 // be part of the VM module. It's the VM's needs that actually determine
@@ -16,72 +11,89 @@ pub trait IOHandle {
     fn getchar(&self) -> LC3Result<char>;
     fn putchar(&self, ch: char) -> LC3Result<()>;
     fn is_key_down(&self) -> LC3Result<bool>;
+
+    // A key if one arrives within timeout, or None. The VM uses this for
+    // the keyboard registers, so that a program polling KBSR keeps running
+    // while nobody's typing. The default only checks is_key_down, so it
+    // never waits, and relies on getchar returning straight away once it
+    // says there's a key.
+    fn try_getchar(&self, _timeout: Duration) -> LC3Result<Option<char>> {
+        if self.is_key_down()? {
+            self.getchar().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Whether the user has asked for the program to be stopped, such as by
+    // pressing Ctrl+C at a terminal in raw mode, since this was last asked.
+    // The VM checks before every instruction, so even a program that's
+    // stopped reading the keyboard fails with Interrupted.
+    fn take_interrupt(&self) -> bool {
+        false
+    }
 }
 
-pub struct RealIOHandle {
-    device_state: DeviceState,
+// Answers the program with key presses and key down responses queued up
+// ahead of time, handed out in the order they were added, and keeps
+// everything it writes, for testing programs. Asking for more input than
+// was queued is an error, so a test can't silently wait forever.
+#[derive(Debug, Default)]
+pub struct TestIOHandle {
+    key_presses: RefCell<VecDeque<char>>,
+    outputs: RefCell<Vec<char>>,
+    keydown_values: RefCell<VecDeque<bool>>,
 }
 
-impl RealIOHandle {
+impl TestIOHandle {
     pub fn new() -> Self {
-        Self {
-            device_state: DeviceState::new(),
-        }
+        Self::default()
     }
-}
 
-impl IOHandle for RealIOHandle {
-    fn getchar(&self) -> LC3Result<char> {
-        getchar()
+    pub fn with_key_presses(mut self, keys: &str) -> Self {
+        keys.chars().for_each(|ch| self.add_key_press(ch));
+        self
     }
 
-    fn putchar(&self, ch: char) -> LC3Result<()> {
-        putchar(ch)
+    pub fn with_keydown_responses(mut self, responses: &[bool]) -> Self {
+        responses
+            .iter()
+            .for_each(|down| self.add_keydown_response(*down));
+        self
     }
 
-    fn is_key_down(&self) -> LC3Result<bool> {
-        Ok(self.device_state.get_keys().is_empty())
+    pub fn add_key_press(&mut self, ch: char) {
+        self.key_presses.borrow_mut().push_back(ch);
     }
-}
 
-#[cfg(test)]
-pub(crate) struct TestIOHandle {
-    key_presses: RefCell<Vec<char>>,
-    outputs: RefCell<Vec<char>>,
-    keydown_values: RefCell<Vec<bool>>,
-}
-
-#[cfg(test)]
-impl TestIOHandle {
-    pub(crate) fn new() -> Self {
-        Self {
-            key_presses: RefCell::new(Vec::new()),
-            outputs: RefCell::new(Vec::new()),
-            keydown_values: RefCell::new(Vec::new()),
-        }
+    pub fn add_keydown_response(&mut self, val: bool) {
+        self.keydown_values.borrow_mut().push_back(val)
     }
 
-    pub(crate) fn add_key_press(&mut self, ch: char) {
-        self.key_presses.borrow_mut().push(ch);
+    pub fn get_test_outputs(&self) -> Vec<char> {
+        self.outputs.borrow().clone()
     }
 
-    pub(crate) fn add_keydown_response(&mut self, val: bool) {
-        self.keydown_values.borrow_mut().push(val)
+    pub fn output(&self) -> String {
+        self.outputs.borrow().iter().collect()
     }
 
-    pub(crate) fn get_test_outputs(&self) -> Vec<char> {
-        self.outputs.borrow().clone()
+    // Key presses and key down responses that were never asked for
+    pub fn remaining(&self) -> (usize, usize) {
+        (
+            self.key_presses.borrow().len(),
+            self.keydown_values.borrow().len(),
+        )
     }
 }
 
-#[cfg(test)]
 impl IOHandle for TestIOHandle {
     fn getchar(&self) -> LC3Result<char> {
         self.key_presses
             .borrow_mut()
-            .pop()
+            .pop_front()
             .ok_or(LC3Error::Other(
-                "Attempted to call getchar on empty key presses vector".to_string(),
+                "Attempted to call getchar with no key presses left".to_string(),
             ))
     }
 
@@ -93,9 +105,9 @@ impl IOHandle for TestIOHandle {
     fn is_key_down(&self) -> LC3Result<bool> {
         self.keydown_values
             .borrow_mut()
-            .pop()
+            .pop_front()
             .ok_or(LC3Error::Other(
-                "Attempted to call getchar on empty key down vector".to_string(),
+                "Attempted to call is_key_down with no key down responses left".to_string(),
             ))
     }
 }
@@ -0,0 +1,171 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crossterm::event::{poll, read, Event, KeyCode, KeyEvent, KeyModifiers};
+
+use super::peekable::PeekableReceiver;
+use super::terminal::TerminalGuard;
+use crate::error::{BoxErrors, LC3Error, LC3Result};
+
+// How long the reader waits for a key before checking whether it's been
+// asked to stop
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// Keys typed at the terminal, read on a background thread as they arrive
+// and queued until the program takes them. Only keys that stand for a
+// character are queued, so the VM can't be left waiting on a press of
+// Shift that getchar would never return. Arrow keys and the like are
+// queued as the escape sequences a terminal would send for them.
+//
+// The terminal is in raw mode for as long as the queue exists, so keys
+// arrive one at a time rather than a line at a time. That also stops Ctrl+C
+// raising a signal, so the reader sets a flag instead, for the VM to find
+// before its next instruction or while it waits for a key.
+pub(crate) struct KeyQueue {
+    // The error type has to be sent between threads, which LC3Error can't
+    keys: PeekableReceiver<io::Result<char>>,
+    interrupted: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl KeyQueue {
+    pub(crate) fn start() -> Self {
+        let (sender, keys) = channel();
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (reader_interrupted, reader_stop) = (interrupted.clone(), stop.clone());
+        let reader = thread::spawn(move || read_keys(sender, reader_interrupted, reader_stop));
+        Self {
+            keys: PeekableReceiver::new(keys),
+            interrupted,
+            stop,
+            reader: Some(reader),
+        }
+    }
+
+    // Whether Ctrl+C has been pressed since this was last asked
+    pub(crate) fn take_interrupt(&self) -> bool {
+        self.interrupted.swap(false, Ordering::Relaxed)
+    }
+
+    // An error waiting on the channel counts as a key, so that getchar
+    // goes on to report it
+    pub(crate) fn is_key_down(&self) -> LC3Result<bool> {
+        self.keys.has_next().map_err(|_| reader_stopped())
+    }
+
+    // Blocks until there's a key if none is waiting, checking for Ctrl+C
+    // every so often
+    pub(crate) fn getchar(&self) -> LC3Result<char> {
+        loop {
+            if let Some(ch) = self.try_getchar(POLL_INTERVAL)? {
+                return Ok(ch);
+            }
+        }
+    }
+
+    pub(crate) fn try_getchar(&self, timeout: Duration) -> LC3Result<Option<char>> {
+        if self.take_interrupt() {
+            return Err(LC3Error::Interrupted);
+        }
+        match self.keys.recv_timeout(timeout) {
+            Ok(key) => key.map(Some).map_io_error(),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(reader_stopped()),
+        }
+    }
+}
+
+impl Drop for KeyQueue {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
+
+fn read_keys(
+    sender: Sender<io::Result<char>>,
+    interrupted: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+) {
+    let _guard = match TerminalGuard::raw_mode() {
+        Ok(guard) => guard,
+        Err(err) => {
+            let _ = sender.send(Err(io::Error::other(err.to_string())));
+            return;
+        }
+    };
+
+    while !stop.load(Ordering::Relaxed) {
+        let key = match poll(POLL_INTERVAL) {
+            Ok(false) => continue,
+            Ok(true) => read(),
+            Err(err) => Err(err),
+        };
+
+        let keys = match key {
+            Ok(Event::Key(key)) if is_interrupt(key) => {
+                interrupted.store(true, Ordering::Relaxed);
+                Vec::new()
+            }
+            Ok(Event::Key(key)) => key_chars(key).map(Ok).collect(),
+            Ok(_) => Vec::new(),
+            Err(err) => vec![Err(to_io_error(err))],
+        };
+
+        for key in keys {
+            let failed = key.is_err();
+            // Nobody's listening any more once the queue has been dropped
+            if sender.send(key).is_err() || failed {
+                return;
+            }
+        }
+    }
+}
+
+// The character a key press stands for, if any
+pub(crate) fn key_char(key: KeyEvent) -> Option<char> {
+    match key.code {
+        KeyCode::Char(ch) if !key.modifiers.contains(KeyModifiers::CONTROL) => Some(ch),
+        KeyCode::Enter => Some('\n'),
+        KeyCode::Tab => Some('\t'),
+        KeyCode::Backspace => Some('\u{8}'),
+        _ => None,
+    }
+}
+
+// What a terminal would send for the key: its character, or an escape
+// sequence for the arrows and other keys programs like games want
+fn key_chars(key: KeyEvent) -> impl Iterator<Item = char> {
+    let sequence = match key.code {
+        KeyCode::Up => "\u{1b}[A",
+        KeyCode::Down => "\u{1b}[B",
+        KeyCode::Right => "\u{1b}[C",
+        KeyCode::Left => "\u{1b}[D",
+        KeyCode::Home => "\u{1b}[H",
+        KeyCode::End => "\u{1b}[F",
+        KeyCode::Delete => "\u{1b}[3~",
+        KeyCode::Esc => "\u{1b}",
+        _ => "",
+    };
+    key_char(key).into_iter().chain(sequence.chars())
+}
+
+fn is_interrupt(key: KeyEvent) -> bool {
+    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL)
+}
+
+fn to_io_error(err: crossterm::ErrorKind) -> io::Error {
+    io::Error::other(err.to_string())
+}
+
+fn reader_stopped() -> LC3Error {
+    LC3Error::Other("The keyboard reader stopped unexpectedly".to_string())
+}
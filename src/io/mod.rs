@@ -1,8 +1,50 @@
+#[cfg(not(target_arch = "wasm32"))]
+mod ansi;
+mod callback;
+mod channel;
+#[cfg(not(target_arch = "wasm32"))]
+mod control;
+mod inject;
+// The terminal is only there on native builds
+#[cfg(not(target_arch = "wasm32"))]
 mod io;
 mod io_handle;
+#[cfg(not(target_arch = "wasm32"))]
+mod keyboard;
+mod newline;
+mod null;
+mod peekable;
+mod policy;
+#[cfg(not(target_arch = "wasm32"))]
+mod real;
+mod record;
+mod scripted;
+mod stream;
+mod tcp;
+mod tee;
+#[cfg(not(target_arch = "wasm32"))]
+mod terminal;
 
-pub use io_handle::IOHandle;
-pub(crate) use io_handle::{RealIOHandle};
-#[cfg(test)]
-pub(crate) use io_handle::{TestIOHandle};
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use ansi::strip_escapes;
+pub use callback::{CallbackIOHandle, KeyFeeder};
+pub use channel::ChannelIOHandle;
+pub(crate) use inject::InjectedInput;
+pub use inject::InputSender;
+pub use io_handle::{IOHandle, TestIOHandle};
+#[cfg(not(target_arch = "wasm32"))]
 pub use io::getchar;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use keyboard::key_char;
+pub use newline::NewlineIOHandle;
+pub use null::NullIOHandle;
+pub use policy::{CharEncoding, EchoMode, NewlinePolicy, PackedByteOrder};
+#[cfg(not(target_arch = "wasm32"))]
+pub use real::RealIOHandle;
+pub use record::{IORecording, RecordedIO, RecordedInput, Recorder, RecordingIOHandle, ReplayIOHandle};
+pub use scripted::ScriptedIOHandle;
+pub use stream::StreamIOHandle;
+pub use tcp::TcpIOHandle;
+pub use tee::{TeeIOHandle, Transcript};
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use terminal::TerminalGuard;
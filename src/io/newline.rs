@@ -0,0 +1,161 @@
+use std::cell::Cell;
+use std::time::Duration;
+
+use super::io_handle::IOHandle;
+use super::policy::NewlinePolicy;
+use crate::error::LC3Result;
+
+// Translates line endings between the program and another IO handle, so
+// output renders properly and input reads the same whichever platform or
+// terminal it comes from.
+pub struct NewlineIOHandle<IOType: IOHandle> {
+    inner: IOType,
+    policy: NewlinePolicy,
+    // Set after reading '\r', so a '\n' straight after it is dropped
+    skip_lf: Cell<bool>,
+    // A key read by is_key_down while checking for that '\n', but not yet
+    // by getchar
+    peeked: Cell<Option<char>>,
+}
+
+impl<IOType: IOHandle> NewlineIOHandle<IOType> {
+    pub fn new(inner: IOType, policy: NewlinePolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            skip_lf: Cell::new(false),
+            peeked: Cell::new(None),
+        }
+    }
+
+    pub fn into_inner(self) -> IOType {
+        self.inner
+    }
+
+    // None if the key was the '\n' of a "\r\n"
+    fn translate(&self, ch: char) -> Option<char> {
+        if !self.policy.lf_input {
+            return Some(ch);
+        }
+        let skip_lf = self.skip_lf.replace(false);
+        match ch {
+            '\n' if skip_lf => None,
+            '\r' => {
+                self.skip_lf.set(true);
+                Some('\n')
+            }
+            _ => Some(ch),
+        }
+    }
+}
+
+impl<IOType: IOHandle> IOHandle for NewlineIOHandle<IOType> {
+    fn getchar(&self) -> LC3Result<char> {
+        if let Some(ch) = self.peeked.take() {
+            return Ok(ch);
+        }
+        loop {
+            if let Some(ch) = self.translate(self.inner.getchar()?) {
+                return Ok(ch);
+            }
+        }
+    }
+
+    fn putchar(&self, ch: char) -> LC3Result<()> {
+        if ch == '\n' && self.policy.crlf_output {
+            self.inner.putchar('\r')?;
+        }
+        self.inner.putchar(ch)
+    }
+
+    fn is_key_down(&self) -> LC3Result<bool> {
+        if self.peeked.get().is_some() {
+            return Ok(true);
+        }
+        // The key waiting might be a '\n' that's going to be dropped, so
+        // it has to be read to find out
+        if !self.skip_lf.get() {
+            return self.inner.is_key_down();
+        }
+        while self.inner.is_key_down()? {
+            if let Some(ch) = self.translate(self.inner.getchar()?) {
+                self.peeked.set(Some(ch));
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn try_getchar(&self, timeout: Duration) -> LC3Result<Option<char>> {
+        if let Some(ch) = self.peeked.take() {
+            return Ok(Some(ch));
+        }
+        while let Some(ch) = self.inner.try_getchar(timeout)? {
+            if let Some(ch) = self.translate(ch) {
+                return Ok(Some(ch));
+            }
+        }
+        Ok(None)
+    }
+
+    fn take_interrupt(&self) -> bool {
+        self.inner.take_interrupt()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::TestIOHandle;
+
+    const BOTH: NewlinePolicy = NewlinePolicy {
+        crlf_output: true,
+        lf_input: true,
+    };
+
+    fn read_all(io_handle: &NewlineIOHandle<TestIOHandle>) -> String {
+        std::iter::from_fn(|| io_handle.getchar().ok()).collect()
+    }
+
+    #[test]
+    fn can_translate_newlines() -> LC3Result<()> {
+        let inner = TestIOHandle::new().with_key_presses("a\r\nb\rc\nd\r\r\n");
+        let io_handle = NewlineIOHandle::new(inner, BOTH);
+        assert_eq!(read_all(&io_handle), "a\nb\nc\nd\n\n");
+
+        io_handle.putchar('x')?;
+        io_handle.putchar('\n')?;
+        assert_eq!(io_handle.into_inner().output(), "x\r\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_leave_newlines_alone() -> LC3Result<()> {
+        let inner = TestIOHandle::new().with_key_presses("a\r\n");
+        let io_handle = NewlineIOHandle::new(inner, NewlinePolicy::RAW);
+        assert_eq!(read_all(&io_handle), "a\r\n");
+
+        io_handle.putchar('\n')?;
+        assert_eq!(io_handle.into_inner().output(), "\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn doesnt_report_dropped_lf_as_key() -> LC3Result<()> {
+        let inner = TestIOHandle::new()
+            .with_key_presses("\r\nz")
+            .with_keydown_responses(&[true, true, false, true, true]);
+        let io_handle = NewlineIOHandle::new(inner, BOTH);
+
+        assert!(io_handle.is_key_down()?);
+        assert_eq!(io_handle.getchar()?, '\n');
+        // The '\n' is read and dropped, and nothing follows it yet
+        assert!(!io_handle.is_key_down()?);
+        assert!(io_handle.is_key_down()?);
+        assert_eq!(io_handle.getchar()?, 'z');
+
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use super::io_handle::IOHandle;
+use crate::error::{LC3Error, LC3Result};
+
+// Throws away everything the program writes and never touches a terminal,
+// for compute-only programs run on servers or in CI. By default there's
+// never a key and asking for one fails with InputUnavailable, or it can
+// answer every read with the same character.
+#[derive(Debug, Clone, Default)]
+pub struct NullIOHandle {
+    input: Option<char>,
+}
+
+impl NullIOHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Every key read is ch, and there's always one waiting
+    pub fn with_input(ch: char) -> Self {
+        Self { input: Some(ch) }
+    }
+}
+
+impl IOHandle for NullIOHandle {
+    fn getchar(&self) -> LC3Result<char> {
+        self.input.ok_or(LC3Error::InputUnavailable)
+    }
+
+    fn putchar(&self, _ch: char) -> LC3Result<()> {
+        Ok(())
+    }
+
+    fn is_key_down(&self) -> LC3Result<bool> {
+        Ok(self.input.is_some())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::register::Register::RR1;
+    use crate::vm::VM;
+
+    fn run(io_handle: NullIOHandle) -> LC3Result<VM<NullIOHandle>> {
+        let program = assemble(
+            r#"
+            .ORIG x3000
+            LEA R0, GREETING
+            PUTS
+            GETC
+            ADD R1, R0, #0
+            HALT
+GREETING    .STRINGZ "Nobody's listening"
+            .END
+        "#,
+        )?;
+
+        let mut vm = VM::new_with_io(io_handle);
+        vm.load_object(&program.to_object())?;
+        vm.run()?;
+        Ok(vm)
+    }
+
+    #[test]
+    fn can_run_headless_with_sentinel_input() -> LC3Result<()> {
+        let vm = run(NullIOHandle::with_input('y'))?;
+        assert_eq!(vm.register(RR1), 'y' as u16);
+        Ok(())
+    }
+
+    #[test]
+    fn cant_read_input_without_sentinel() {
+        assert!(matches!(
+            run(NullIOHandle::new()),
+            Err(LC3Error::InputUnavailable)
+        ));
+    }
+}
@@ -0,0 +1,82 @@
+use std::cell::RefCell;
+use std::sync::mpsc::{Receiver, RecvError, RecvTimeoutError, TryRecvError};
+use std::time::Duration;
+
+// The receiving end of a channel that can tell whether anything's waiting
+// without taking it, as is_key_down has to before getchar reads the key
+pub(crate) struct PeekableReceiver<T> {
+    receiver: Receiver<T>,
+    // An item taken off the channel by has_next, but not yet received
+    peeked: RefCell<Option<T>>,
+}
+
+impl<T> PeekableReceiver<T> {
+    pub(crate) fn new(receiver: Receiver<T>) -> Self {
+        Self {
+            receiver,
+            peeked: RefCell::new(None),
+        }
+    }
+
+    // Fails once the senders have gone and there's nothing left to receive
+    pub(crate) fn has_next(&self) -> Result<bool, RecvError> {
+        let mut peeked = self.peeked.borrow_mut();
+        if peeked.is_none() {
+            *peeked = match self.receiver.try_recv() {
+                Ok(item) => Some(item),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+            };
+        }
+        Ok(peeked.is_some())
+    }
+
+    pub(crate) fn recv(&self) -> Result<T, RecvError> {
+        match self.peeked.borrow_mut().take() {
+            Some(item) => Ok(item),
+            None => self.receiver.recv(),
+        }
+    }
+
+    pub(crate) fn try_recv(&self) -> Result<T, TryRecvError> {
+        match self.peeked.borrow_mut().take() {
+            Some(item) => Ok(item),
+            None => self.receiver.try_recv(),
+        }
+    }
+
+    pub(crate) fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match self.peeked.borrow_mut().take() {
+            Some(item) => Ok(item),
+            None => self.receiver.recv_timeout(timeout),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc::channel;
+
+    use super::*;
+
+    #[test]
+    fn can_peek_without_taking() {
+        let (sender, receiver) = channel();
+        let receiver = PeekableReceiver::new(receiver);
+        assert_eq!(receiver.has_next(), Ok(false));
+
+        sender.send('a').unwrap();
+        sender.send('b').unwrap();
+        assert_eq!(receiver.has_next(), Ok(true));
+        assert_eq!(receiver.has_next(), Ok(true));
+        assert_eq!(receiver.recv(), Ok('a'));
+        assert_eq!(receiver.try_recv(), Ok('b'));
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+
+        sender.send('c').unwrap();
+        drop(sender);
+        assert_eq!(receiver.has_next(), Ok(true));
+        assert_eq!(receiver.recv_timeout(Duration::from_millis(0)), Ok('c'));
+        assert_eq!(receiver.has_next(), Err(RecvError));
+    }
+}
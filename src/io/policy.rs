@@ -0,0 +1,166 @@
+use std::str::FromStr;
+
+use crate::error::{LC3Error, LC3Result};
+
+// Whether the natively handled GETC and IN traps write the character they
+// read back out. The OS's own routines, loaded with load_os, always follow
+// the spec.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EchoMode {
+    // As the spec says: IN echoes and GETC doesn't
+    #[default]
+    Standard,
+    // Both echo, so everything typed shows up in a transcript
+    Always,
+    // Neither echoes
+    Never,
+}
+
+impl FromStr for EchoMode {
+    type Err = LC3Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "standard" => Ok(Self::Standard),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => Err(LC3Error::Other(format!(
+                "Unknown echo mode {}, expected standard, always or never",
+                text
+            ))),
+        }
+    }
+}
+
+// How NewlineIOHandle translates line endings. The LC-3 itself only knows
+// '\n', but a terminal in raw mode wants "\r\n" to get back to the start of
+// the line, and input typed or saved on Windows ends lines with "\r\n".
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NewlinePolicy {
+    // Write each '\n' as "\r\n"
+    pub crlf_output: bool,
+    // Read '\r' and "\r\n" as '\n'
+    pub lf_input: bool,
+}
+
+impl NewlinePolicy {
+    // Everything passes through untouched
+    pub const RAW: Self = Self {
+        crlf_output: false,
+        lf_input: false,
+    };
+}
+
+// "raw", or any of "crlf" and "lf" separated by commas
+impl FromStr for NewlinePolicy {
+    type Err = LC3Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut policy = Self::RAW;
+        for part in text.split(',') {
+            match part.trim() {
+                "raw" => {}
+                "crlf" => policy.crlf_output = true,
+                "lf" => policy.lf_input = true,
+                _ => {
+                    return Err(LC3Error::Other(format!(
+                        "Unknown newline policy {}, expected raw, crlf, lf or crlf,lf",
+                        text
+                    )))
+                }
+            }
+        }
+        Ok(policy)
+    }
+}
+
+// What happens to characters outside ASCII, which the LC-3 doesn't define.
+// Output is the low byte of the word written, and input is a single word.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CharEncoding {
+    // Bytes from x80 to xFF are the Latin-1 characters, both ways. Keys
+    // beyond Latin-1 are read as '?'.
+    #[default]
+    Latin1,
+    // Bytes from x80 up are written as the replacement character, and keys
+    // outside ASCII are read as '?'
+    Replace,
+    // Anything outside ASCII is a NonAscii error
+    Strict,
+}
+
+impl CharEncoding {
+    // The character a word written to the console stands for
+    pub(crate) fn decode(self, word: u16) -> LC3Result<char> {
+        let byte = word as u8;
+        match self {
+            _ if byte.is_ascii() => Ok(char::from(byte)),
+            Self::Latin1 => Ok(char::from(byte)),
+            Self::Replace => Ok(char::REPLACEMENT_CHARACTER),
+            Self::Strict => Err(LC3Error::NonAscii {
+                code: u32::from(byte),
+            }),
+        }
+    }
+
+    // The word a key is read into the machine as
+    pub(crate) fn encode(self, ch: char) -> LC3Result<u16> {
+        match self {
+            _ if ch.is_ascii() => Ok(ch as u16),
+            Self::Latin1 if (ch as u32) <= 0xFF => Ok(ch as u16),
+            Self::Latin1 | Self::Replace => Ok(u16::from(b'?')),
+            Self::Strict => Err(LC3Error::NonAscii { code: ch as u32 }),
+        }
+    }
+}
+
+impl FromStr for CharEncoding {
+    type Err = LC3Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "latin1" => Ok(Self::Latin1),
+            "replace" => Ok(Self::Replace),
+            "strict" => Ok(Self::Strict),
+            _ => Err(LC3Error::Other(format!(
+                "Unknown encoding {}, expected latin1, replace or strict",
+                text
+            ))),
+        }
+    }
+}
+
+// Which byte of each word PUTSP writes first. The spec packs strings low
+// byte first, but some assemblers pack them the other way round.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PackedByteOrder {
+    #[default]
+    LowFirst,
+    HighFirst,
+}
+
+impl PackedByteOrder {
+    // The word's two characters, in the order they're written
+    pub(crate) fn split(self, word: u16) -> (u16, u16) {
+        let (low, high) = (word & 0xFF, word >> 8);
+        match self {
+            Self::LowFirst => (low, high),
+            Self::HighFirst => (high, low),
+        }
+    }
+}
+
+impl FromStr for PackedByteOrder {
+    type Err = LC3Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "low-first" => Ok(Self::LowFirst),
+            "high-first" => Ok(Self::HighFirst),
+            _ => Err(LC3Error::Other(format!(
+                "Unknown byte order {}, expected low-first or high-first",
+                text
+            ))),
+        }
+    }
+}
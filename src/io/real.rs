@@ -0,0 +1,128 @@
+use std::cell::{Cell, RefCell};
+use std::io::stdout;
+use std::time::Duration;
+
+use crossterm::cursor::MoveLeft;
+use crossterm::execute;
+use crossterm::tty::IsTty;
+
+use super::ansi::{ESCAPE, RESET};
+use super::control::{ControlAction, ControlTranslator};
+use super::io::putchar;
+use super::io_handle::IOHandle;
+use super::keyboard::KeyQueue;
+use crate::error::{BoxErrors, LC3Result};
+
+// Reads the keyboard and writes to stdout. Keys are queued as they're typed
+// by a background reader, which is only started once the program first
+// looks at the keyboard so programs that never do leave the terminal alone.
+//
+// When writing to a terminal, the bell, backspace and tab characters are
+// carried out rather than written as they are, and other control
+// characters are dropped. Output that's been redirected is left untouched.
+pub struct RealIOHandle {
+    keyboard: RefCell<Option<KeyQueue>>,
+    // Whether the last character written was '\r', in which case a '\n'
+    // after it already starts the line
+    after_return: Cell<bool>,
+    // Whether the program has written an escape sequence, in which case it
+    // may have left the terminal styled
+    styled: Cell<bool>,
+    // None while control characters are written as they are
+    controls: RefCell<Option<ControlTranslator>>,
+}
+
+impl RealIOHandle {
+    pub fn new() -> Self {
+        Self {
+            keyboard: RefCell::new(None),
+            after_return: Cell::new(false),
+            styled: Cell::new(false),
+            controls: RefCell::new(stdout().is_tty().then(ControlTranslator::default)),
+        }
+    }
+
+    // Whether control characters are carried out or written as they are,
+    // say for capturing exactly what the program wrote
+    pub fn set_translate_controls(&mut self, enabled: bool) {
+        *self.controls.get_mut() = enabled.then(ControlTranslator::default);
+    }
+
+    fn print(&self, ch: char) -> LC3Result<()> {
+        // The terminal's in raw mode while the keyboard is being read, so
+        // it no longer goes back to the start of the line by itself
+        let after_return = self.after_return.replace(ch == '\r');
+        if ch == '\n' && !after_return && self.keyboard.borrow().is_some() {
+            putchar('\r')?;
+        }
+        putchar(ch)
+    }
+
+    fn with_keyboard<T>(&self, f: impl FnOnce(&KeyQueue) -> T) -> T {
+        let mut keyboard = self.keyboard.borrow_mut();
+        f(keyboard.get_or_insert_with(KeyQueue::start))
+    }
+}
+
+impl Default for RealIOHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IOHandle for RealIOHandle {
+    fn getchar(&self) -> LC3Result<char> {
+        self.with_keyboard(|keyboard| keyboard.getchar())
+    }
+
+    fn putchar(&self, ch: char) -> LC3Result<()> {
+        if ch == ESCAPE && !self.styled.replace(true) {
+            enable_escapes();
+        }
+        let action = match self.controls.borrow_mut().as_mut() {
+            Some(controls) => controls.action(ch),
+            None => ControlAction::Print(ch),
+        };
+        match action {
+            ControlAction::Print(ch) => self.print(ch),
+            ControlAction::Bell => putchar('\u{7}'),
+            ControlAction::Backspace => execute!(stdout(), MoveLeft(1)).map_io_error(),
+            ControlAction::Tab(spaces) => (0..spaces).try_for_each(|_| putchar(' ')),
+            ControlAction::Ignore => Ok(()),
+        }
+    }
+
+    fn is_key_down(&self) -> LC3Result<bool> {
+        self.with_keyboard(|keyboard| keyboard.is_key_down())
+    }
+
+    fn try_getchar(&self, timeout: Duration) -> LC3Result<Option<char>> {
+        self.with_keyboard(|keyboard| keyboard.try_getchar(timeout))
+    }
+
+    // Until the keyboard's been read the terminal isn't in raw mode, and
+    // Ctrl+C raises a signal as usual
+    fn take_interrupt(&self) -> bool {
+        let keyboard = self.keyboard.borrow();
+        keyboard.as_ref().is_some_and(KeyQueue::take_interrupt)
+    }
+}
+
+impl Drop for RealIOHandle {
+    fn drop(&mut self) {
+        if self.styled.get() {
+            for ch in RESET.chars() {
+                let _ = putchar(ch);
+            }
+        }
+    }
+}
+
+// Terminals on Windows only follow escape sequences once asked to
+#[cfg(windows)]
+fn enable_escapes() {
+    crossterm::ansi_support::supports_ansi();
+}
+
+#[cfg(not(windows))]
+fn enable_escapes() {}
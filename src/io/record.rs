@@ -0,0 +1,335 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::io_handle::IOHandle;
+use crate::error::{BoxErrors, LC3Error, LC3Result};
+use crate::plugin::{Event, Plugin};
+use crate::vm::VmState;
+
+// A single answer the IO handle gave the VM
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordedInput {
+    Char(char),
+    KeyDown(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordedIO {
+    // Zero based index of the instruction that asked for the input
+    pub instruction: u64,
+    pub input: RecordedInput,
+}
+
+// Every input a run read, in order. Saved as one line per input:
+//
+// 12 keydown 1
+// 13 char 97
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IORecording {
+    pub inputs: Vec<RecordedIO>,
+}
+
+impl IORecording {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> LC3Result<()> {
+        std::fs::write(path, self.to_string()).map_io_error()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> LC3Result<Self> {
+        let text = std::fs::read_to_string(path).map_io_error()?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> LC3Result<Self> {
+        let mut inputs = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let bad_line = || LC3Error::BadRecording {
+                line: index + 1,
+                text: line.to_string(),
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (instruction, kind, value) = match fields.as_slice() {
+                [instruction, kind, value] => (instruction, *kind, value),
+                _ => return Err(bad_line()),
+            };
+
+            let instruction = instruction.parse().map_err(|_| bad_line())?;
+            let value: u32 = value.parse().map_err(|_| bad_line())?;
+            let input = match (kind, value) {
+                ("char", value) => {
+                    RecordedInput::Char(std::char::from_u32(value).ok_or_else(bad_line)?)
+                }
+                ("keydown", 0) => RecordedInput::KeyDown(false),
+                ("keydown", 1) => RecordedInput::KeyDown(true),
+                _ => return Err(bad_line()),
+            };
+            inputs.push(RecordedIO { instruction, input });
+        }
+
+        Ok(Self { inputs })
+    }
+}
+
+impl fmt::Display for IORecording {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for recorded in &self.inputs {
+            match recorded.input {
+                RecordedInput::Char(ch) => {
+                    writeln!(f, "{} char {}", recorded.instruction, ch as u32)?
+                }
+                RecordedInput::KeyDown(down) => {
+                    writeln!(f, "{} keydown {}", recorded.instruction, down as u8)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Passes everything through to another IO handle, logging the input it
+// returns. The handle can't see which instruction is running, so the VM
+// also needs the plugin from recorder() to keep count.
+pub struct RecordingIOHandle<IOType: IOHandle> {
+    inner: IOType,
+    recorder: Recorder,
+}
+
+impl<IOType: IOHandle> RecordingIOHandle<IOType> {
+    pub fn new(inner: IOType) -> Self {
+        Self {
+            inner,
+            recorder: Recorder::default(),
+        }
+    }
+
+    pub fn recorder(&self) -> Recorder {
+        self.recorder.clone()
+    }
+
+    fn record(&self, input: RecordedInput) {
+        // The count is bumped as each instruction starts, so it's one ahead
+        let instruction = self.recorder.instructions.get().saturating_sub(1);
+        self.recorder
+            .recording
+            .borrow_mut()
+            .inputs
+            .push(RecordedIO { instruction, input });
+    }
+}
+
+impl<IOType: IOHandle> IOHandle for RecordingIOHandle<IOType> {
+    fn getchar(&self) -> LC3Result<char> {
+        let ch = self.inner.getchar()?;
+        self.record(RecordedInput::Char(ch));
+        Ok(ch)
+    }
+
+    fn putchar(&self, ch: char) -> LC3Result<()> {
+        self.inner.putchar(ch)
+    }
+
+    fn is_key_down(&self) -> LC3Result<bool> {
+        let down = self.inner.is_key_down()?;
+        self.record(RecordedInput::KeyDown(down));
+        Ok(down)
+    }
+
+    // Recorded as a check for a key and then the key itself, which is how
+    // it's replayed
+    fn try_getchar(&self, timeout: Duration) -> LC3Result<Option<char>> {
+        let key = self.inner.try_getchar(timeout)?;
+        self.record(RecordedInput::KeyDown(key.is_some()));
+        if let Some(ch) = key {
+            self.record(RecordedInput::Char(ch));
+        }
+        Ok(key)
+    }
+
+    fn take_interrupt(&self) -> bool {
+        self.inner.take_interrupt()
+    }
+}
+
+// Shares the recording with a RecordingIOHandle, and counts instructions
+// for it when added to the VM as a plugin
+#[derive(Clone, Default)]
+pub struct Recorder {
+    recording: Rc<RefCell<IORecording>>,
+    instructions: Rc<Cell<u64>>,
+}
+
+impl Recorder {
+    pub fn recording(&self) -> IORecording {
+        self.recording.borrow().clone()
+    }
+}
+
+impl<IOType: IOHandle> Plugin<IOType> for Recorder {
+    fn handle_event(&mut self, _: &mut VmState<IOType>, event: &Event) -> LC3Result<()> {
+        if let Event::Command { .. } = event {
+            self.instructions.set(self.instructions.get() + 1);
+        }
+        Ok(())
+    }
+}
+
+// Answers input requests from a recording rather than a keyboard, so an
+// interactive run can be reproduced exactly. Output goes to the inner
+// handle. Asking for a different kind of input than was recorded next, or
+// for more input than was recorded, is a ReplayDiverged error.
+pub struct ReplayIOHandle<IOType: IOHandle> {
+    inner: IOType,
+    inputs: RefCell<VecDeque<RecordedIO>>,
+}
+
+impl<IOType: IOHandle> ReplayIOHandle<IOType> {
+    pub fn new(inner: IOType, recording: IORecording) -> Self {
+        Self {
+            inner,
+            inputs: RefCell::new(recording.inputs.into()),
+        }
+    }
+
+    // Inputs left over once the run is done usually mean it went down a
+    // different path to the recorded one
+    pub fn remaining(&self) -> usize {
+        self.inputs.borrow().len()
+    }
+
+    fn next_input(&self) -> LC3Result<RecordedInput> {
+        self.inputs
+            .borrow_mut()
+            .pop_front()
+            .map(|recorded| recorded.input)
+            .ok_or_else(|| {
+                LC3Error::ReplayDiverged(
+                    "the program read more input than was recorded".to_string(),
+                )
+            })
+    }
+}
+
+impl<IOType: IOHandle> IOHandle for ReplayIOHandle<IOType> {
+    fn getchar(&self) -> LC3Result<char> {
+        match self.next_input()? {
+            RecordedInput::Char(ch) => Ok(ch),
+            other => Err(diverged("a character", other)),
+        }
+    }
+
+    fn putchar(&self, ch: char) -> LC3Result<()> {
+        self.inner.putchar(ch)
+    }
+
+    fn is_key_down(&self) -> LC3Result<bool> {
+        match self.next_input()? {
+            RecordedInput::KeyDown(down) => Ok(down),
+            other => Err(diverged("the keyboard status", other)),
+        }
+    }
+}
+
+fn diverged(wanted: &str, recorded: RecordedInput) -> LC3Error {
+    LC3Error::ReplayDiverged(format!(
+        "the program asked for {} but the next recorded input was {:?}",
+        wanted, recorded
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{IORecording, RecordedIO, RecordedInput, RecordingIOHandle, ReplayIOHandle};
+    use crate::error::{LC3Error, LC3Result};
+    use crate::io::TestIOHandle;
+    use crate::vm::VM;
+
+    // Wait for a key using KBSR and take it from KBDR, then read a second
+    // one with GETC
+    const PROGRAM: [u16; 7] = [
+        // LDI R0, KBSR
+        0b1010_0000_0000_0100,
+        // BRzp back to the LDI
+        0b0000_0111_1111_1110,
+        // LDI R0, KBDR
+        0b1010_0000_0000_0011,
+        // GETC
+        0xF020,
+        // HALT
+        0xF025,
+        // KBSR address
+        0xFE00,
+        // KBDR address
+        0xFE02,
+    ];
+
+    #[test]
+    fn can_record_and_replay_input() -> LC3Result<()> {
+        let mut io_handle = TestIOHandle::new();
+        io_handle.add_keydown_response(false);
+        io_handle.add_keydown_response(true);
+        io_handle.add_key_press('a');
+        io_handle.add_key_press('b');
+
+        let io = RecordingIOHandle::new(io_handle);
+        let recorder = io.recorder();
+        let mut vm = VM::new_with_io(io);
+        vm.add_plugin(Box::new(recorder.clone()));
+        vm.load_program(&PROGRAM.to_vec())?;
+        vm.run()?;
+
+        let recording = recorder.recording();
+        assert_eq!(
+            recording.inputs,
+            vec![
+                RecordedIO {
+                    instruction: 0,
+                    input: RecordedInput::KeyDown(false)
+                },
+                RecordedIO {
+                    instruction: 2,
+                    input: RecordedInput::KeyDown(true)
+                },
+                RecordedIO {
+                    instruction: 2,
+                    input: RecordedInput::Char('a')
+                },
+                RecordedIO {
+                    instruction: 5,
+                    input: RecordedInput::Char('b')
+                },
+            ]
+        );
+        assert_eq!(IORecording::parse(&recording.to_string())?, recording);
+
+        let replay = ReplayIOHandle::new(TestIOHandle::new(), recording.clone());
+        let mut vm = VM::new_with_io(replay);
+        vm.load_program(&PROGRAM.to_vec())?;
+        let outcome = vm.run()?;
+        assert_eq!(outcome.chars_read, 2);
+        assert_eq!(vm.into_io_handle().remaining(), 0);
+
+        // A program that reads differently can't be replayed
+        let replay = ReplayIOHandle::new(TestIOHandle::new(), recording);
+        let mut vm = VM::new_with_io(replay);
+        vm.load_program(&vec![0xF020, 0xF025])?;
+        assert!(matches!(vm.run(), Err(LC3Error::ReplayDiverged(_))));
+
+        assert!(matches!(
+            IORecording::parse("1 char 97\nnonsense\n"),
+            Err(LC3Error::BadRecording { line: 2, .. })
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::io_handle::IOHandle;
+use crate::error::{BoxErrors, LC3Error, LC3Result};
+
+// Types out a fixed script as the program's input and keeps everything it
+// writes, for running a program against known input and checking what it
+// printed. Keys are all waiting from the start unless there's a delay, in
+// which case each arrives that long after the one before was read, like a
+// typist. Reading past the end of the script fails with InputUnavailable.
+pub struct ScriptedIOHandle {
+    input: RefCell<VecDeque<char>>,
+    output: RefCell<String>,
+    delay: Duration,
+    // When the next key in the script is typed
+    next_key_at: Cell<Instant>,
+}
+
+impl ScriptedIOHandle {
+    pub fn new(input: &str) -> Self {
+        Self {
+            input: RefCell::new(input.chars().collect()),
+            output: RefCell::new(String::new()),
+            delay: Duration::from_secs(0),
+            next_key_at: Cell::new(Instant::now()),
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> LC3Result<Self> {
+        let input = std::fs::read_to_string(path).map_io_error()?;
+        Ok(Self::new(&input))
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self.next_key_at.set(Instant::now() + delay);
+        self
+    }
+
+    pub fn output(&self) -> String {
+        self.output.borrow().clone()
+    }
+
+    // Keys left over once the run is done usually mean the program stopped
+    // reading earlier than expected
+    pub fn remaining(&self) -> usize {
+        self.input.borrow().len()
+    }
+
+    fn take_key(&self) -> Option<char> {
+        let key = self.input.borrow_mut().pop_front();
+        self.next_key_at.set(Instant::now() + self.delay);
+        key
+    }
+}
+
+impl IOHandle for ScriptedIOHandle {
+    fn getchar(&self) -> LC3Result<char> {
+        if self.input.borrow().is_empty() {
+            return Err(LC3Error::InputUnavailable);
+        }
+        let wait = self.next_key_at.get().saturating_duration_since(Instant::now());
+        thread::sleep(wait);
+        self.take_key().ok_or(LC3Error::InputUnavailable)
+    }
+
+    fn putchar(&self, ch: char) -> LC3Result<()> {
+        self.output.borrow_mut().push(ch);
+        Ok(())
+    }
+
+    fn is_key_down(&self) -> LC3Result<bool> {
+        Ok(!self.input.borrow().is_empty() && Instant::now() >= self.next_key_at.get())
+    }
+
+    fn try_getchar(&self, timeout: Duration) -> LC3Result<Option<char>> {
+        if self.input.borrow().is_empty() {
+            return Ok(None);
+        }
+        let wait = self.next_key_at.get().saturating_duration_since(Instant::now());
+        if wait > timeout {
+            thread::sleep(timeout);
+            return Ok(None);
+        }
+        thread::sleep(wait);
+        Ok(self.take_key())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::vm::VM;
+
+    #[test]
+    fn can_run_programs_against_scripts() -> LC3Result<()> {
+        let program = assemble(
+            r#"
+            .ORIG x3000
+    LOOP    GETC
+            ADD R1, R0, #-10
+            BRz DONE
+            ADD R0, R0, #-16
+            ADD R0, R0, #-16
+            OUT
+            BR LOOP
+    DONE    HALT
+            .END
+        "#,
+        )?;
+
+        let mut vm = VM::new_with_io(ScriptedIOHandle::new("shout\nignored"));
+        vm.load_object(&program.to_object())?;
+        vm.run()?;
+
+        let io_handle = vm.into_io_handle();
+        assert_eq!(io_handle.output(), "SHOUT");
+        assert_eq!(io_handle.remaining(), "ignored".len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_delay_scripted_keys() -> LC3Result<()> {
+        let io_handle = ScriptedIOHandle::new("ab").with_delay(Duration::from_millis(20));
+        assert!(!io_handle.is_key_down()?);
+        assert_eq!(io_handle.try_getchar(Duration::from_secs(0))?, None);
+
+        // getchar waits for the key rather than failing
+        assert_eq!(io_handle.getchar()?, 'a');
+        assert!(!io_handle.is_key_down()?);
+        thread::sleep(Duration::from_millis(20));
+        assert!(io_handle.is_key_down()?);
+        assert_eq!(io_handle.getchar()?, 'b');
+
+        assert!(matches!(
+            io_handle.getchar(),
+            Err(LC3Error::InputUnavailable)
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use std::cell::RefCell;
+use std::io::{self, ErrorKind, Read, Write};
+
+use super::io_handle::IOHandle;
+use crate::error::{BoxErrors, LC3Result};
+
+// Reads the program's input from any byte source and writes its output to
+// any byte sink, so files, pipes, sockets and in-memory buffers can all be
+// the console. Each byte is one character, the same as a key press.
+// Nothing can tell whether more input is on its way without waiting for it,
+// so is_key_down reads ahead a byte and only says no at the end of the
+// input. Asking for a character after the end is an UnexpectedEof error.
+pub struct StreamIOHandle<R: Read, W: Write> {
+    input: RefCell<R>,
+    output: RefCell<W>,
+    // A byte read ahead by is_key_down, but not yet by getchar
+    peeked: RefCell<Option<u8>>,
+}
+
+impl<R: Read, W: Write> StreamIOHandle<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self {
+            input: RefCell::new(input),
+            output: RefCell::new(output),
+            peeked: RefCell::new(None),
+        }
+    }
+
+    // Gives back the input and output, for instance to look at what was
+    // written to a Vec
+    pub fn into_inner(self) -> (R, W) {
+        (self.input.into_inner(), self.output.into_inner())
+    }
+
+    // The next byte, or None at the end of the input
+    fn next_byte(&self) -> io::Result<Option<u8>> {
+        if let Some(byte) = self.peeked.borrow_mut().take() {
+            return Ok(Some(byte));
+        }
+
+        let mut byte = [0u8];
+        loop {
+            match self.input.borrow_mut().read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl<R: Read, W: Write> IOHandle for StreamIOHandle<R, W> {
+    fn getchar(&self) -> LC3Result<char> {
+        match self.next_byte().map_io_error()? {
+            Some(byte) => Ok(char::from(byte)),
+            None => Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "The program read past the end of its input",
+            ))
+            .map_io_error(),
+        }
+    }
+
+    fn putchar(&self, ch: char) -> LC3Result<()> {
+        let mut output = self.output.borrow_mut();
+        write!(output, "{}", ch).map_io_error()?;
+        output.flush().map_io_error()
+    }
+
+    fn is_key_down(&self) -> LC3Result<bool> {
+        let byte = self.next_byte().map_io_error()?;
+        *self.peeked.borrow_mut() = byte;
+        Ok(byte.is_some())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::asm::assemble;
+    use crate::error::LC3Error;
+    use crate::vm::VM;
+
+    #[test]
+    fn can_run_programs_over_streams() -> LC3Result<()> {
+        let program = assemble(
+            r#"
+            .ORIG x3000
+    LOOP    GETC
+            ADD R1, R0, #-10
+            BRz DONE
+            ADD R0, R0, #1
+            OUT
+            BR LOOP
+    DONE    HALT
+            .END
+        "#,
+        )?;
+
+        let io_handle = StreamIOHandle::new(Cursor::new(b"HAL\n".to_vec()), Vec::new());
+        let mut vm = VM::new_with_io(io_handle);
+        vm.load_object(&program.to_object())?;
+        vm.run()?;
+
+        let (_, output) = vm.into_io_handle().into_inner();
+        assert_eq!(String::from_utf8(output).unwrap(), "IBM");
+
+        Ok(())
+    }
+
+    #[test]
+    fn runs_out_of_keys_at_end_of_input() {
+        let io_handle = StreamIOHandle::new(Cursor::new(b"a".to_vec()), io::sink());
+        assert!(io_handle.is_key_down().unwrap());
+        // Checking again doesn't use up the key
+        assert!(io_handle.is_key_down().unwrap());
+        assert_eq!(io_handle.getchar().unwrap(), 'a');
+        assert!(!io_handle.is_key_down().unwrap());
+        assert!(matches!(io_handle.getchar(), Err(LC3Error::IO { .. })));
+    }
+}
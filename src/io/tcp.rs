@@ -0,0 +1,284 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use super::io_handle::IOHandle;
+use super::peekable::PeekableReceiver;
+use crate::error::{BoxErrors, LC3Error, LC3Result};
+
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const ECHO: u8 = 1;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+
+// Bridges the console over a TCP connection, so a program can be used
+// remotely with telnet or netcat. A server can host a VM per connection by
+// accepting each client from the same listener on a thread of its own.
+// The client is asked to send keys as they're typed and leave echoing to
+// the program, as the LC-3 console expects. Telnet commands are dropped from
+// the input and line endings become '\n', so netcat works just as well.
+// Keys are read on a background thread, so polling KBSR never waits on the
+// network. Once the client hangs up and everything it sent has been read,
+// any read of the keyboard, including a check for a key, fails with
+// ConsoleClosed.
+pub struct TcpIOHandle {
+    stream: TcpStream,
+    keys: PeekableReceiver<char>,
+}
+
+impl TcpIOHandle {
+    // Waits for a single client to connect to addr
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> LC3Result<Self> {
+        let listener = TcpListener::bind(addr).map_io_error()?;
+        Self::accept(&listener)
+    }
+
+    // Waits for the next client to connect to listener
+    pub fn accept(listener: &TcpListener) -> LC3Result<Self> {
+        let (stream, _) = listener.accept().map_io_error()?;
+        Self::new(stream)
+    }
+
+    pub fn new(stream: TcpStream) -> LC3Result<Self> {
+        (&stream)
+            .write_all(&[IAC, WILL, ECHO, IAC, WILL, SUPPRESS_GO_AHEAD])
+            .map_io_error()?;
+
+        let (sender, keys) = channel();
+        let reader = stream.try_clone().map_io_error()?;
+        thread::spawn(move || read_keys(reader, sender));
+
+        Ok(Self {
+            stream,
+            keys: PeekableReceiver::new(keys),
+        })
+    }
+}
+
+impl Drop for TcpIOHandle {
+    fn drop(&mut self) {
+        // Also stops the reader, which would otherwise wait on the client
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+impl IOHandle for TcpIOHandle {
+    fn getchar(&self) -> LC3Result<char> {
+        self.keys.recv().map_err(|_| LC3Error::ConsoleClosed)
+    }
+
+    fn putchar(&self, ch: char) -> LC3Result<()> {
+        let mut stream = &self.stream;
+        match ch {
+            '\n' => stream.write_all(b"\r\n"),
+            _ => write!(stream, "{}", ch),
+        }
+        .map_io_error()
+    }
+
+    fn is_key_down(&self) -> LC3Result<bool> {
+        self.keys.has_next().map_err(|_| LC3Error::ConsoleClosed)
+    }
+
+    fn try_getchar(&self, timeout: Duration) -> LC3Result<Option<char>> {
+        match self.keys.recv_timeout(timeout) {
+            Ok(ch) => Ok(Some(ch)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(LC3Error::ConsoleClosed),
+        }
+    }
+}
+
+// Runs until the client hangs up or the handle is dropped, which drops the
+// sender and so tells the handle there's no more input
+fn read_keys(mut stream: TcpStream, sender: Sender<char>) {
+    let mut filter = TelnetFilter::default();
+    let mut buffer = [0u8; 256];
+    loop {
+        let len = match stream.read(&mut buffer) {
+            Ok(0) => return,
+            Ok(len) => len,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        };
+        for &byte in &buffer[..len] {
+            if let Some(ch) = filter.feed(byte) {
+                if sender.send(ch).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FilterState {
+    Data,
+    // After a carriage return, which may be followed by '\n' or '\0'
+    Return,
+    // After IAC, waiting for the command
+    Command,
+    // After a WILL, WONT, DO or DONT, waiting for the option it's about
+    Negotiation,
+    Subnegotiation,
+    SubnegotiationCommand,
+}
+
+// Picks the characters out of what a telnet client sends
+struct TelnetFilter {
+    state: FilterState,
+}
+
+impl Default for TelnetFilter {
+    fn default() -> Self {
+        Self {
+            state: FilterState::Data,
+        }
+    }
+}
+
+impl TelnetFilter {
+    fn feed(&mut self, byte: u8) -> Option<char> {
+        use FilterState::*;
+
+        match (self.state, byte) {
+            (Return, b'\n') | (Return, 0) => {
+                self.state = Data;
+                None
+            }
+            (Return, _) => {
+                self.state = Data;
+                self.feed(byte)
+            }
+            (Data, IAC) => {
+                self.state = Command;
+                None
+            }
+            (Data, b'\r') => {
+                self.state = Return;
+                Some('\n')
+            }
+            (Data, _) => Some(char::from(byte)),
+            // A doubled IAC is the byte itself
+            (Command, IAC) => {
+                self.state = Data;
+                Some(char::from(IAC))
+            }
+            (Command, WILL..=DONT) => {
+                self.state = Negotiation;
+                None
+            }
+            (Command, SB) => {
+                self.state = Subnegotiation;
+                None
+            }
+            (Command, _) | (Negotiation, _) => {
+                self.state = Data;
+                None
+            }
+            (Subnegotiation, IAC) => {
+                self.state = SubnegotiationCommand;
+                None
+            }
+            (Subnegotiation, _) => None,
+            (SubnegotiationCommand, SE) => {
+                self.state = Data;
+                None
+            }
+            (SubnegotiationCommand, _) => {
+                self.state = Subnegotiation;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::vm::VM;
+
+    fn filter(bytes: &[u8]) -> String {
+        let mut filter = TelnetFilter::default();
+        bytes.iter().filter_map(|&byte| filter.feed(byte)).collect()
+    }
+
+    #[test]
+    fn can_filter_telnet_input() {
+        assert_eq!(filter(b"plain"), "plain");
+        assert_eq!(filter(b"a\r\nb\r\0c\rd\ne"), "a\nb\nc\nd\ne");
+        assert_eq!(filter(&[b'x', IAC, WILL, ECHO, b'y']), "xy");
+        assert_eq!(filter(&[IAC, SB, 24, 0, b'v', b't', IAC, SE, b'z']), "z");
+        assert_eq!(filter(&[IAC, IAC]), "\u{ff}");
+    }
+
+    #[test]
+    fn can_run_console_over_tcp() -> LC3Result<()> {
+        let program = assemble(
+            r#"
+            .ORIG x3000
+    LOOP    GETC
+            ADD R1, R0, #-10
+            BRz DONE
+            OUT
+            BR LOOP
+    DONE    AND R0, R0, #0
+            ADD R0, R0, #10
+            OUT
+            HALT
+            .END
+        "#,
+        )?;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"hi\r\n").unwrap();
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        let mut vm = VM::new_with_io(TcpIOHandle::accept(&listener)?);
+        vm.load_object(&program.to_object())?;
+        vm.run()?;
+        // Hangs up, so the client stops waiting for more
+        drop(vm);
+
+        let mut expected = vec![IAC, WILL, ECHO, IAC, WILL, SUPPRESS_GO_AHEAD];
+        expected.extend_from_slice(b"hi\r\n");
+        assert_eq!(client.join().unwrap(), expected);
+
+        Ok(())
+    }
+    #[test]
+    fn cant_read_keys_after_client_hangs_up() -> LC3Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"a").unwrap();
+        });
+
+        let handle = TcpIOHandle::accept(&listener)?;
+        client.join().unwrap();
+        assert_eq!(handle.getchar()?, 'a');
+        // Waits for the reader to see the hang up
+        let timeout = Duration::from_secs(5);
+        assert!(matches!(
+            handle.try_getchar(timeout),
+            Err(LC3Error::ConsoleClosed)
+        ));
+        assert!(matches!(handle.is_key_down(), Err(LC3Error::ConsoleClosed)));
+        assert!(matches!(handle.getchar(), Err(LC3Error::ConsoleClosed)));
+
+        Ok(())
+    }
+}
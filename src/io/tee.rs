@@ -0,0 +1,141 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::io_handle::IOHandle;
+use crate::error::LC3Result;
+
+// Passes everything through to another IO handle, keeping a copy of every
+// character read and written. The copy is shared with transcript(), so it
+// can be looked at while the VM still owns the handle.
+pub struct TeeIOHandle<IOType: IOHandle> {
+    inner: IOType,
+    transcript: Transcript,
+    // Output only goes into the transcript
+    muted: bool,
+}
+
+impl<IOType: IOHandle> TeeIOHandle<IOType> {
+    pub fn new(inner: IOType) -> Self {
+        Self {
+            inner,
+            transcript: Transcript::default(),
+            muted: false,
+        }
+    }
+
+    // Keeps the program's output in the transcript without passing it on,
+    // while keys are still read from the inner handle
+    pub fn muted(mut self) -> Self {
+        self.muted = true;
+        self
+    }
+
+    pub fn transcript(&self) -> Transcript {
+        self.transcript.clone()
+    }
+
+    pub fn into_inner(self) -> IOType {
+        self.inner
+    }
+}
+
+impl<IOType: IOHandle> IOHandle for TeeIOHandle<IOType> {
+    fn getchar(&self) -> LC3Result<char> {
+        let ch = self.inner.getchar()?;
+        self.transcript.input.borrow_mut().push(ch);
+        Ok(ch)
+    }
+
+    fn putchar(&self, ch: char) -> LC3Result<()> {
+        if !self.muted {
+            self.inner.putchar(ch)?;
+        }
+        self.transcript.output.borrow_mut().push(ch);
+        Ok(())
+    }
+
+    fn is_key_down(&self) -> LC3Result<bool> {
+        self.inner.is_key_down()
+    }
+
+    fn try_getchar(&self, timeout: Duration) -> LC3Result<Option<char>> {
+        let key = self.inner.try_getchar(timeout)?;
+        if let Some(ch) = key {
+            self.transcript.input.borrow_mut().push(ch);
+        }
+        Ok(key)
+    }
+
+    fn take_interrupt(&self) -> bool {
+        self.inner.take_interrupt()
+    }
+}
+
+// The characters a TeeIOHandle has seen so far
+#[derive(Clone, Default)]
+pub struct Transcript {
+    input: Rc<RefCell<String>>,
+    output: Rc<RefCell<String>>,
+}
+
+impl Transcript {
+    pub fn input(&self) -> String {
+        self.input.borrow().clone()
+    }
+
+    pub fn output(&self) -> String {
+        self.output.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::io::TestIOHandle;
+    use crate::vm::VM;
+
+    #[test]
+    fn can_capture_input_and_output() -> LC3Result<()> {
+        let program = assemble(
+            r#"
+            .ORIG x3000
+            GETC
+            OUT
+            GETC
+            OUT
+            HALT
+            .END
+        "#,
+        )?;
+
+        let inner = TestIOHandle::new().with_key_presses("ab");
+        let io_handle = TeeIOHandle::new(inner);
+        let transcript = io_handle.transcript();
+
+        let mut vm = VM::new_with_io(io_handle);
+        vm.load_object(&program.to_object())?;
+        vm.run()?;
+
+        assert_eq!(transcript.input(), "ab");
+        assert_eq!(transcript.output(), "ab");
+        // Everything still reached the inner handle
+        let inner = vm.into_io_handle().into_inner();
+        assert_eq!(inner.get_test_outputs(), vec!['a', 'b']);
+
+        // Muted, the output only goes into the transcript
+        let inner = TestIOHandle::new().with_key_presses("cd");
+        let io_handle = TeeIOHandle::new(inner).muted();
+        let transcript = io_handle.transcript();
+        let mut vm = VM::new_with_io(io_handle);
+        vm.load_object(&program.to_object())?;
+        vm.run()?;
+
+        assert_eq!(transcript.output(), "cd");
+        let inner = vm.into_io_handle().into_inner();
+        assert!(inner.get_test_outputs().is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+use std::io::stdout;
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+
+use crossterm::cursor::{Hide, Show};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+
+use crate::error::{BoxErrors, LC3Result};
+
+// How many guards have the terminal in raw mode, and how many of those also
+// have it on the alternate screen. The terminal's only put back once the
+// last of them lets go.
+static RAW_GUARDS: AtomicUsize = AtomicUsize::new(0);
+static SCREEN_GUARDS: AtomicUsize = AtomicUsize::new(0);
+static PANIC_HOOK: Once = Once::new();
+
+// Keeps the terminal in raw mode for as long as it's held, and puts it back
+// to normal with the cursor showing when it's dropped, so an error that
+// skips the cleanup can't leave the shell unusable. A panic puts the
+// terminal back before the message is printed, which also covers panics on
+// threads that don't hold the guard and builds that abort rather than
+// unwind.
+pub(crate) struct TerminalGuard {
+    full_screen: bool,
+    released: bool,
+}
+
+impl TerminalGuard {
+    pub(crate) fn raw_mode() -> LC3Result<Self> {
+        PANIC_HOOK.call_once(install_panic_hook);
+        enable_raw_mode().map_io_error()?;
+        RAW_GUARDS.fetch_add(1, Ordering::SeqCst);
+        Ok(Self {
+            full_screen: false,
+            released: false,
+        })
+    }
+
+    // Raw mode on the alternate screen with the cursor hidden, for taking
+    // over the whole terminal
+    pub(crate) fn full_screen() -> LC3Result<Self> {
+        let mut guard = Self::raw_mode()?;
+        execute!(stdout(), EnterAlternateScreen, Hide).map_io_error()?;
+        SCREEN_GUARDS.fetch_add(1, Ordering::SeqCst);
+        guard.full_screen = true;
+        Ok(guard)
+    }
+
+    // Like dropping the guard, but reports any trouble putting the terminal
+    // back
+    pub(crate) fn restore(mut self) -> LC3Result<()> {
+        self.release()
+    }
+
+    fn release(&mut self) -> LC3Result<()> {
+        if self.released {
+            return Ok(());
+        }
+        self.released = true;
+
+        let mut result = Ok(());
+        if self.full_screen && SCREEN_GUARDS.fetch_sub(1, Ordering::SeqCst) == 1 {
+            result = execute!(stdout(), LeaveAlternateScreen).map_io_error();
+        }
+        if RAW_GUARDS.fetch_sub(1, Ordering::SeqCst) == 1 {
+            result = result
+                .and(execute!(stdout(), Show).map_io_error())
+                .and(disable_raw_mode().map_io_error());
+        }
+        result
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = self.release();
+    }
+}
+
+fn install_panic_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        // The guards are still counted, so they'll put the terminal back
+        // again harmlessly if the panic unwinds through them
+        if SCREEN_GUARDS.load(Ordering::SeqCst) > 0 {
+            let _ = execute!(stdout(), LeaveAlternateScreen);
+        }
+        if RAW_GUARDS.load(Ordering::SeqCst) > 0 {
+            let _ = execute!(stdout(), Show);
+            let _ = disable_raw_mode();
+        }
+        previous(info);
+    }));
+}
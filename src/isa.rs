@@ -0,0 +1,152 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{LC3Error, LC3Result};
+
+// Which instruction set the VM runs and the assembler writes. The LC-3b is
+// the LC-3's byte addressed sibling: memory holds 64K bytes, addresses count
+// bytes, and it has byte loads and stores, XOR and shifts in place of the
+// indirect and PC relative loads and stores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Isa {
+    #[default]
+    Lc3,
+    Lc3b,
+}
+
+impl Isa {
+    // How far apart consecutive words are in the address space
+    pub(crate) fn word_size(self) -> u16 {
+        match self {
+            Self::Lc3 => 1,
+            Self::Lc3b => 2,
+        }
+    }
+
+    pub(crate) fn check_aligned(self, address: u16) -> LC3Result<()> {
+        if !address.is_multiple_of(self.word_size()) {
+            return Err(LC3Error::Unaligned { address });
+        }
+        Ok(())
+    }
+
+    // Words as they're laid out in memory, which for the LC-3b is one every
+    // other address with the odd ones left as zeroes
+    pub(crate) fn layout(self, words: &[u16]) -> Vec<u16> {
+        match self {
+            Self::Lc3 => words.to_vec(),
+            Self::Lc3b => {
+                let mut laid_out = vec![0; (words.len() * 2).saturating_sub(1)];
+                for (index, word) in words.iter().enumerate() {
+                    laid_out[index * 2] = *word;
+                }
+                laid_out
+            }
+        }
+    }
+}
+
+impl fmt::Display for Isa {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Lc3 => write!(f, "lc3"),
+            Self::Lc3b => write!(f, "lc3b"),
+        }
+    }
+}
+
+impl FromStr for Isa {
+    type Err = LC3Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.to_lowercase().replace('-', "").as_str() {
+            "lc3" => Ok(Self::Lc3),
+            "lc3b" => Ok(Self::Lc3b),
+            _ => Err(LC3Error::Other(format!(
+                "Unknown instruction set {}, expected lc3 or lc3b",
+                text
+            ))),
+        }
+    }
+}
+
+// Which reference the VM follows where revisions of the LC-3 disagree, so
+// programs written against one of them behave the same here
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SpecProfile {
+    // The second edition of Patt and Patel's textbook, and the default: LEA
+    // sets the condition codes and TRAP only leaves the return address in R7
+    #[default]
+    Patt2ndEd,
+    // The third edition: LEA leaves the condition codes alone, and TRAP
+    // switches to supervisor mode and pushes the PSR and PC like an
+    // interrupt, for the service routine to return with RTI
+    Patt3rdEd,
+    // The LC3Tools simulator, which follows the third edition in both
+    LC3Tools,
+}
+
+impl SpecProfile {
+    pub(crate) fn lea_sets_flags(self) -> bool {
+        self == Self::Patt2ndEd
+    }
+
+    pub(crate) fn trap_saves_psr(self) -> bool {
+        self != Self::Patt2ndEd
+    }
+}
+
+impl FromStr for SpecProfile {
+    type Err = LC3Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.to_lowercase().as_str() {
+            "patt-2nd-ed" => Ok(Self::Patt2ndEd),
+            "patt-3rd-ed" => Ok(Self::Patt3rdEd),
+            "lc3tools" => Ok(Self::LC3Tools),
+            _ => Err(LC3Error::Other(format!(
+                "Unknown spec profile {}, expected patt-2nd-ed, patt-3rd-ed or lc3tools",
+                text
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Isa, SpecProfile};
+
+    #[test]
+    fn can_parse_instruction_sets() {
+        let test_cases = vec![
+            ("lc3", Isa::Lc3),
+            ("LC-3", Isa::Lc3),
+            ("lc3b", Isa::Lc3b),
+            ("LC-3b", Isa::Lc3b),
+        ];
+        for (text, isa) in test_cases {
+            assert_eq!(text.parse::<Isa>().unwrap(), isa);
+            assert_eq!(isa.to_string().parse::<Isa>().unwrap(), isa);
+        }
+        assert!("lc4".parse::<Isa>().is_err());
+
+        assert_eq!(Isa::Lc3.layout(&[1, 2]), vec![1, 2]);
+        assert_eq!(Isa::Lc3b.layout(&[1, 2]), vec![1, 0, 2]);
+        assert!(Isa::Lc3b.layout(&[]).is_empty());
+        assert!(Isa::Lc3b.check_aligned(0x3001).is_err());
+        assert!(Isa::Lc3.check_aligned(0x3001).is_ok());
+    }
+
+    #[test]
+    fn can_parse_spec_profiles() {
+        let test_cases = vec![
+            ("patt-2nd-ed", SpecProfile::Patt2ndEd),
+            ("patt-3rd-ed", SpecProfile::Patt3rdEd),
+            ("LC3Tools", SpecProfile::LC3Tools),
+        ];
+        for (text, profile) in test_cases {
+            assert_eq!(text.parse::<SpecProfile>().unwrap(), profile);
+        }
+        assert!("patt-4th-ed".parse::<SpecProfile>().is_err());
+    }
+}
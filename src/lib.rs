@@ -1,19 +1,48 @@
+#[cfg(not(target_arch = "wasm32"))]
 extern crate crossterm;
 
+pub mod asm;
+mod bank;
 pub mod cli;
 mod command;
 mod condition_flags;
+pub mod config;
+pub mod coredump;
+pub mod debugger;
+pub mod disasm;
 pub mod error;
+pub mod file_traps;
+pub mod instruction;
+mod interrupt;
 pub mod io;
+mod isa;
+mod json;
+mod memory;
 mod op;
+mod os;
 pub mod plugin;
 mod register;
+mod rng;
+pub mod snapshot;
+mod state;
+mod toml;
+pub mod symbols;
 mod trap;
 #[macro_use]
 mod utils;
 pub mod vm;
 
+pub use bank::BankedMemory;
+pub use condition_flags::ConditionFlags;
+pub use coredump::CoreDump;
 pub use error::{LC3Error, LC3Result};
+pub use instruction::{DecodedInstruction, Effects, Instruction, Operands, Source};
 pub use io::IOHandle;
+pub use isa::{Isa, SpecProfile};
+pub use memory::{MemoryFill, MemoryMap};
+pub use op::Op;
 pub use plugin::{Event, Plugin};
-pub use vm::VM;
+pub use register::Register;
+pub use snapshot::{Snapshot, SnapshotDiff};
+pub use symbols::SymbolTable;
+pub use vm::{RunOutcome, StepOutcome, StopReason, VmState, VM};
@@ -1,15 +1,174 @@
-use lc3rs::cli:: {Options, read_program};
-use lc3rs::error::{BoxErrors, PublicResult};
-use lc3rs::vm::VM;
+use lc3rs::asm::{Assembler, Assembly, Module};
+use lc3rs::cli::{
+    read_program, write_program, AsmOptions, BenchOptions, DebugOptions, DisasmOptions,
+    InspectOptions, LinkOptions, LoadedProgram, Options, ProgramFormat, RunOptions,
+    MODULE_EXTENSION, STDIO_PATH,
+};
+use lc3rs::config::Config;
+use lc3rs::coredump::CoreDump;
+use lc3rs::debugger::{Condition, DapServer, DebugCommand, Debugger, Tui};
+use lc3rs::disasm::{disassemble_program, disassemble_source};
+use lc3rs::error::{LC3Error, LC3Result, PublicResult};
+use lc3rs::io::{
+    IOHandle, NewlineIOHandle, NullIOHandle, RealIOHandle, StreamIOHandle, TcpIOHandle, TeeIOHandle,
+};
+use lc3rs::vm::{StopReason, VM};
 use std::fs::File;
-use lc3rs::plugin::debuglogger::DebugLogger;
+use std::io::{stderr, stdin, stdout, BufWriter, Read, Write};
+use std::path::Path;
+use lc3rs::plugin::builtin::{
+    CoveragePlugin, DebugLogger, ExitCode, HeatmapPlugin, Profiler, StackChecker, TraceFormat,
+    TracePlugin, Watchdog, WatchdogError,
+};
+use lc3rs::plugin::NoPlugins;
+use lc3rs::SymbolTable;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 
-fn main() -> PublicResult<()> {
-    let options = Options::from_args();
-    let program = read_program(&options.path, options.little_endian)?;
+// Exit status for a program stopped by --max-instructions or --timeout
+// rather than halting, the same as the timeout command uses
+const LIMIT_EXIT_STATUS: i32 = 124;
 
-    let mut vm = VM::new();
+// Exit status for a program interrupted with Ctrl+C, the same as a shell
+// gives a process killed by SIGINT
+const INTERRUPTED_EXIT_STATUS: i32 = 130;
+
+// How often watch checks whether the sources have changed
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+fn main() {
+    let result = match Options::from_args() {
+        Options::Run(options) => run(options),
+        Options::Watch(options) => watch(options).map(|_| 0),
+        Options::Bench(options) => bench(options).map(|_| 0),
+        Options::Asm(options) => asm(options).map(|_| 0),
+        Options::Link(options) => link(options).map(|_| 0),
+        Options::Disasm(options) => disasm(options).map(|_| 0),
+        Options::Debug(options) => debug(options).map(|_| 0),
+        Options::Inspect(options) => inspect(options).map(|_| 0),
+        Options::Dap => dap().map(|_| 0),
+    };
+    // Only exit once everything's been dropped, so the terminal is put back
+    match result {
+        Ok(0) => {}
+        Ok(status) => std::process::exit(status),
+        Err(err) if matches!(err.downcast_ref(), Some(LC3Error::Interrupted)) => {
+            std::process::exit(INTERRUPTED_EXIT_STATUS)
+        }
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+// The exit status to finish with
+fn run(mut options: RunOptions) -> PublicResult<i32> {
+    if let Some(config) = Config::find(options.config.as_deref())? {
+        options.apply_config(&config)?;
+    }
+    let from_stdin = options
+        .paths
+        .iter()
+        .filter(|path| *path == STDIO_PATH)
+        .count();
+    if from_stdin > 1 || from_stdin == 1 && options.stdin_file.as_deref() == Some(STDIO_PATH) {
+        return Err("Only one of the programs and their input can come from stdin".into());
+    }
+    let mut programs = options
+        .paths
+        .iter()
+        .map(|path| options.load.read_program(path))
+        .collect::<LC3Result<Vec<_>>>()?;
+    for load_at in &options.at {
+        let index = options
+            .paths
+            .iter()
+            .position(|path| *path == load_at.path)
+            .ok_or_else(|| format!("--at names {}, which isn't being run", load_at.path))?;
+        programs[index].relocate(load_at.origin)?;
+    }
+    check_overlaps(&options.paths, &programs)?;
+
+    if let Some(addr) = &options.listen {
+        eprintln!("Waiting for a connection on {}", addr);
+        let io_handle = TcpIOHandle::listen(addr)?;
+        return run_with_io(options, programs, io_handle);
+    }
+    if let Some(path) = &options.stdin_file {
+        let input: Box<dyn Read> = match path.as_str() {
+            "-" => Box::new(stdin()),
+            _ => Box::new(File::open(path)?),
+        };
+        let io_handle = StreamIOHandle::new(input, stdout());
+        return run_with_io(options, programs, io_handle);
+    }
+    let mut io_handle = RealIOHandle::new();
+    if options.raw_controls {
+        io_handle.set_translate_controls(false);
+    }
+    run_with_io(options, programs, io_handle)
+}
+
+fn run_with_io<IO: IOHandle>(
+    options: RunOptions,
+    programs: Vec<LoadedProgram>,
+    io_handle: IO,
+) -> PublicResult<i32> {
+    let io_handle = NewlineIOHandle::new(io_handle, options.newlines.unwrap_or_default());
+    if options.transcript.is_none() && !options.quiet {
+        return run_vm(options, programs, io_handle);
+    }
+
+    let mut io_handle = TeeIOHandle::new(io_handle);
+    if options.quiet {
+        io_handle = io_handle.muted();
+    }
+    let transcript = io_handle.transcript();
+    let path = options.transcript.clone();
+    let status = run_vm(options, programs, io_handle);
+    // Saved whatever happened, as the output leading up to an error is
+    // usually the interesting part
+    if let Some(path) = path {
+        std::fs::write(path, transcript.output())?;
+    }
+    status
+}
+
+fn run_vm<IO: IOHandle>(
+    options: RunOptions,
+    programs: Vec<LoadedProgram>,
+    io_handle: IO,
+) -> PublicResult<i32> {
+    // Subroutines and traced instructions are named if the assembler left a
+    // symbol table behind, or one was given
+    let symbols = match &options.symbols {
+        Some(path) => Some(SymbolTable::from_file(path)?),
+        None => program_symbols(&options.paths, &programs),
+    };
+
+    let mut vm = VM::new_with_io(io_handle);
+    if let Some(fill) = options.fill {
+        vm.set_memory_fill(fill);
+    }
+    vm.set_spec_profile(options.spec.unwrap_or_default());
+    if let Some(path) = &options.os_image {
+        // --format is for the programs, so the image's format is detected
+        let image = read_program(path, None, options.load.endian())?;
+        vm.load_os_object(&image.to_object())?;
+    } else if options.os {
+        vm.load_os()?;
+    }
+    if let Some(root) = &options.file_root {
+        vm.enable_file_traps(root)?;
+    }
+    if let Some(seed) = options.seed {
+        vm.seed_rng(seed);
+    }
+    vm.set_deterministic_clock(options.deterministic_clock);
+    vm.set_echo(options.echo.unwrap_or_default());
+    vm.set_encoding(options.encoding.unwrap_or_default());
+    vm.set_packed_byte_order(options.packed_byte_order.unwrap_or_default());
 
     if let Some(path) = options.debug_log_path {
         let debug_file = File::create(path)?;
@@ -17,7 +176,468 @@ fn main() -> PublicResult<()> {
         vm.add_plugin(Box::new(logger));
     }
 
-    vm.load_program(&program)?;
+    if let Some(path) = &options.trace {
+        let (sink, format): (Box<dyn Write>, _) = match path {
+            Some(path) if path.ends_with(".jsonl") => (
+                Box::new(BufWriter::new(File::create(path)?)),
+                TraceFormat::JsonLines,
+            ),
+            Some(path) => (
+                Box::new(BufWriter::new(File::create(path)?)),
+                TraceFormat::Text,
+            ),
+            None => (Box::new(BufWriter::new(stderr())), TraceFormat::Text),
+        };
+        let mut tracer = TracePlugin::with_format(sink, format);
+        if let Some(symbols) = &symbols {
+            tracer = tracer.with_symbols(symbols.clone());
+        }
+        vm.add_plugin(Box::new(tracer));
+    }
+
+    let profiler = Profiler::new();
+    if options.profile {
+        vm.add_plugin(Box::new(profiler.clone()));
+    }
+
+    let coverage = CoveragePlugin::new();
+    if options.coverage.is_some() {
+        vm.add_plugin(Box::new(coverage.clone()));
+    }
+
+    // In reverse, so the program starts from the first file's origin
+    for program in programs.iter().rev() {
+        vm.load_object(&program.to_object())?;
+    }
+
+    // Added after loading so the load doesn't count as writes
+    let heatmap = HeatmapPlugin::new();
+    if options.heatmap.is_some() {
+        vm.add_plugin(Box::new(heatmap.clone()));
+    }
+
+    let exit_code = options.exit_code_from.map(ExitCode::new);
+    if let Some(exit_code) = &exit_code {
+        vm.add_plugin(Box::new(exit_code.clone()));
+    }
+
+    let stack_checker = StackChecker::new();
+    if options.check_stack {
+        vm.add_plugin(Box::new(stack_checker.clone()));
+    }
+
+    if let Some(seconds) = options.timeout {
+        // Programs waiting for an interrupt can branch to themselves, so
+        // only the time counts
+        let watchdog = Watchdog::new()
+            .ignore_stuck()
+            .timeout(Duration::from_secs_f64(seconds));
+        vm.add_plugin(Box::new(watchdog));
+    }
+
+    let outcome = match options.max_instructions {
+        Some(max) => vm.run_with_limit(max),
+        None => vm.run(),
+    };
+    // Why the program was stopped, if it didn't halt by itself
+    let stopped = match outcome {
+        Ok(outcome) if outcome.reason == StopReason::FuelExhausted => Some(format!(
+            "Stopped after {} instructions without halting",
+            outcome.instructions
+        )),
+        Ok(_) => None,
+        Err(LC3Error::Plugin { source })
+            if matches!(
+                source.downcast_ref::<WatchdogError>(),
+                Some(WatchdogError::Timeout { .. })
+            ) =>
+        {
+            Some(format!(
+                "Stopped after {}s without halting",
+                options.timeout.unwrap_or_default()
+            ))
+        }
+        Err(err) => {
+            if let Some(path) = &options.core {
+                vm.core_dump(&err, options.core_window).save(path)?;
+                eprintln!("Core dumped to {}", path);
+            }
+            return Err(err.into());
+        }
+    };
+
+    for violation in stack_checker.violations() {
+        eprintln!("{}", violation);
+    }
+
+    if let Some(path) = options.coverage {
+        let coverage = coverage.coverage();
+        if path.ends_with(".json") {
+            coverage.save_json(&path)?;
+        } else {
+            let listings: Vec<String> = programs
+                .iter()
+                .map(|program| coverage.listing(&program.to_object()))
+                .collect();
+            std::fs::write(&path, listings.join("\n"))?;
+        }
+    }
+
+    if let Some(path) = options.heatmap {
+        let heatmap = heatmap.heatmap();
+        if path.ends_with(".json") {
+            heatmap.save_json(&path)?;
+        } else {
+            heatmap.save_csv(&path)?;
+        }
+    }
+
+    if options.profile {
+        eprintln!("\n{}", profiler.profile().report(symbols.as_ref()));
+    }
+
+    match stopped {
+        Some(message) => {
+            eprintln!("\n{}", message);
+            Ok(LIMIT_EXIT_STATUS)
+        }
+        None => Ok(exit_code
+            .and_then(|exit_code| exit_code.status())
+            .map_or(0, i32::from)),
+    }
+}
+
+// The labels from source programs and the .sym files next to the others
+fn program_symbols(paths: &[String], programs: &[LoadedProgram]) -> Option<SymbolTable> {
+    let mut symbols = None;
+    for (path, program) in paths.iter().zip(programs) {
+        let table = match &program.symbols {
+            Some(table) => Ok(table.clone()),
+            None => SymbolTable::from_file(Path::new(path).with_extension("sym")),
+        };
+        if let Ok(table) = table {
+            let symbols = symbols.get_or_insert_with(SymbolTable::new);
+            for (address, label) in table.iter() {
+                symbols.insert(label, address);
+            }
+        }
+    }
+    symbols
+}
+
+// Files are loaded wherever their origin says, so two can easily end up in
+// the same place by mistake
+fn check_overlaps(paths: &[String], programs: &[LoadedProgram]) -> PublicResult<()> {
+    let ranges: Vec<(usize, usize)> = programs
+        .iter()
+        .map(|program| {
+            let start = program.origin as usize;
+            (start, start + program.words.len())
+        })
+        .collect();
+    for (first, (start, end)) in ranges.iter().enumerate() {
+        for (second, (other_start, other_end)) in ranges.iter().enumerate().skip(first + 1) {
+            if start < other_end && other_start < end {
+                return Err(format!(
+                    "{} (x{:04X} to x{:04X}) overlaps {} (x{:04X} to x{:04X})",
+                    paths[first],
+                    start,
+                    end - 1,
+                    paths[second],
+                    other_start,
+                    other_end - 1
+                )
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+
+// Sources are assembled as they're loaded, the same as for run. The files
+// are polled rather than watched through the operating system, which is
+// quick enough for saves from an editor. A program has to halt, or hit
+// --max-instructions or --timeout, before changes are picked up again.
+fn watch(options: RunOptions) -> PublicResult<()> {
+    if options.paths.iter().any(|path| path == STDIO_PATH) {
+        return Err("watch needs source files to watch rather than stdin".into());
+    }
+    let sources = &options.paths;
+
+    let mut last_modified = None;
+    loop {
+        let modified: Vec<_> = sources
+            .iter()
+            .map(|path| {
+                std::fs::metadata(path)
+                    .and_then(|meta| meta.modified())
+                    .ok()
+            })
+            .collect();
+        if last_modified.as_ref() == Some(&modified) {
+            std::thread::sleep(WATCH_INTERVAL);
+            continue;
+        }
+        last_modified = Some(modified);
+
+        eprintln!("--- Running {} ---", sources.join(" "));
+        match run(options.clone()) {
+            // Programs don't always finish their output with a newline
+            Ok(status) => eprintln!(
+                "\n--- Exited with status {}, waiting for changes ---",
+                status
+            ),
+            Err(err) => eprintln!("Error: {}\n--- Waiting for changes ---", err),
+        }
+    }
+}
+
+// Runs on a machine with no plugins, where keys can't be read and output
+// goes nowhere, so what's measured is the interpreter itself
+fn bench(options: BenchOptions) -> PublicResult<()> {
+    if options.runs == 0 {
+        return Err("--runs has to be at least 1".into());
+    }
+    let program = options.load.read_program(&options.path)?;
+
+    let mut fastest: Option<Duration> = None;
+    let mut instructions = 0;
+    for run in 1..=options.runs {
+        let mut vm = VM::new_with_plugins(NullIOHandle::new(), NoPlugins);
+        if options.os {
+            vm.load_os()?;
+        }
+        vm.load_object(&program.to_object())?;
+
+        let start = Instant::now();
+        let outcome = match options.max_instructions {
+            Some(max) => vm.run_with_limit(max)?,
+            None => vm.run()?,
+        };
+        let elapsed = start.elapsed();
+        instructions = outcome.instructions;
+        eprintln!(
+            "Run {}: {} instructions in {:.3}s",
+            run,
+            instructions,
+            elapsed.as_secs_f64()
+        );
+        fastest = Some(fastest.map_or(elapsed, |fastest| fastest.min(elapsed)));
+    }
+
+    let seconds = fastest.expect("There's at least one run").as_secs_f64();
+    println!("instructions  {}", instructions);
+    println!(
+        "time          {:.3}s (fastest of {})",
+        seconds, options.runs
+    );
+    println!(
+        "speed         {:.2} MIPS",
+        instructions as f64 / seconds.max(f64::EPSILON) / 1e6
+    );
+    Ok(())
+}
+
+fn asm(options: AsmOptions) -> PublicResult<()> {
+    let mut assembler = include_paths(&options.include_paths);
+    if options.module {
+        assembler = assembler.module();
+    }
+    let assembly = assemble_file(&options.path, &assembler)?;
+
+    let extension = if options.module {
+        MODULE_EXTENSION
+    } else {
+        "obj"
+    };
+    let path = options.path;
+    let output = options.output.unwrap_or_else(|| {
+        Path::new(&path)
+            .with_extension(extension)
+            .to_string_lossy()
+            .into_owned()
+    });
+    if !options.module {
+        return write_assembly(&output, &assembly);
+    }
+
+    let module = Module::new(path, assembly);
+    if output == STDIO_PATH {
+        println!("{}", module.to_json());
+        return Ok(());
+    }
+    module.save(output)?;
+    Ok(())
+}
+
+fn link(options: LinkOptions) -> PublicResult<()> {
+    let assembler = include_paths(&options.include_paths).module();
+    let modules = options
+        .paths
+        .iter()
+        .map(|path| match ProgramFormat::detect(path) {
+            ProgramFormat::Assembly => {
+                assemble_file(path, &assembler).map(|assembly| Module::new(path, assembly))
+            }
+            _ => Ok(Module::load(path)?),
+        })
+        .collect::<PublicResult<Vec<Module>>>()?;
+    let program = lc3rs::asm::link(&modules)?;
+
+    let first = &options.paths[0];
+    let output = options.output.unwrap_or_else(|| {
+        Path::new(first)
+            .with_extension("obj")
+            .to_string_lossy()
+            .into_owned()
+    });
+    write_assembly(&output, &program)
+}
+
+fn include_paths(paths: &[String]) -> Assembler {
+    paths.iter().fold(Assembler::new(), |assembler, path| {
+        assembler.include_path(path)
+    })
+}
+
+fn assemble_file(path: &str, assembler: &Assembler) -> PublicResult<Assembly> {
+    let source = std::fs::read_to_string(path)?;
+
+    // Everything that's wrong is reported, not just the first thing
+    let (assembly, diagnostics) =
+        assembler.assemble_with_diagnostics(&source, Some(Path::new(path)));
+    for diagnostic in &diagnostics {
+        let rendered = match &diagnostic.file {
+            Some(file) => diagnostic.render(
+                &file.to_string_lossy(),
+                &std::fs::read_to_string(file).unwrap_or_default(),
+            ),
+            None => diagnostic.render(path, &source),
+        };
+        eprintln!("{}\n", rendered);
+    }
+    match assembly {
+        Some(assembly) => Ok(assembly),
+        None => {
+            let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+            let plural = if errors == 1 { "" } else { "s" };
+            Err(format!(
+                "{} failed to assemble with {} error{}",
+                path, errors, plural
+            )
+            .into())
+        }
+    }
+}
+
+// The object file, and the symbol table next to it unless it's going to
+// stdout
+fn write_assembly(output: &str, assembly: &Assembly) -> PublicResult<()> {
+    write_program(output, &assembly.to_object())?;
+    if output == STDIO_PATH {
+        return Ok(());
+    }
+
+    let symbol_path = Path::new(output).with_extension("sym");
+    std::fs::write(symbol_path, assembly.symbols.to_sym_file())?;
+    Ok(())
+}
+
+fn disasm(options: DisasmOptions) -> PublicResult<()> {
+    let program = options.load.read_program(&options.path)?;
+    let lines = if options.source {
+        let symbols = match &options.symbols {
+            Some(path) => Some(SymbolTable::from_file(path)?),
+            None => program_symbols(
+                std::slice::from_ref(&options.path),
+                std::slice::from_ref(&program),
+            ),
+        };
+        disassemble_source(program.origin, &program.words, symbols.as_ref())
+    } else {
+        disassemble_program(program.origin, &program.words)
+    };
+    for line in lines {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn debug(mut options: DebugOptions) -> PublicResult<()> {
+    if let Some(config) = Config::find(options.config.as_deref())? {
+        options.apply_config(&config)?;
+    }
+    let program = options.load.read_program(&options.path)?;
+    let symbols = match &options.symbols {
+        Some(path) => Some(SymbolTable::from_file(path)?),
+        None => program.symbols.clone().or_else(|| {
+            SymbolTable::from_file(Path::new(&options.path).with_extension("sym")).ok()
+        }),
+    };
+    let mut breakpoints = Vec::new();
+    for spec in &options.breakpoints {
+        if let DebugCommand::Break(address, condition) =
+            DebugCommand::parse(&format!("break {}", spec), symbols.as_ref())?
+        {
+            breakpoints.push((address, condition));
+        }
+    }
+
+    if options.tui {
+        let mut tui = Tui::new(&program.to_object(), symbols)?;
+        add_breakpoints(tui.debugger_mut(), breakpoints);
+        tui.run()?;
+        return Ok(());
+    }
+
+    let mut vm = VM::new();
+    vm.load_object(&program.to_object())?;
+
+    let mut debugger = Debugger::new(vm);
+    if let Some(symbols) = symbols {
+        debugger = debugger.with_symbols(symbols);
+    }
+    add_breakpoints(&mut debugger, breakpoints);
+    let stdin = std::io::stdin();
+    debugger.repl(stdin.lock(), std::io::stdout())?;
+    Ok(())
+}
+
+// The dump is loaded into the debugger, so memory and registers can be
+// looked at with the usual commands
+fn inspect(options: InspectOptions) -> PublicResult<()> {
+    let core = CoreDump::load(&options.path)?;
+    let symbols = match &options.symbols {
+        Some(path) => Some(SymbolTable::from_file(path)?),
+        None => None,
+    };
+    println!("{}", core.summary(symbols.as_ref()));
+
+    let mut vm = VM::new();
+    vm.restore(&core.snapshot);
+    let mut debugger = Debugger::new(vm);
+    if let Some(symbols) = symbols {
+        debugger = debugger.with_symbols(symbols);
+    }
+    let stdin = std::io::stdin();
+    debugger.repl(stdin.lock(), std::io::stdout())?;
+    Ok(())
+}
+
+fn add_breakpoints<IO: IOHandle>(
+    debugger: &mut Debugger<IO>,
+    breakpoints: Vec<(u16, Option<Condition>)>,
+) {
+    for (address, condition) in breakpoints {
+        match condition {
+            Some(condition) => debugger.add_conditional_breakpoint(address, condition),
+            None => debugger.add_breakpoint(address),
+        }
+    }
+}
 
-    vm.run().box_error()
+fn dap() -> PublicResult<()> {
+    let stdin = std::io::stdin();
+    DapServer::new(stdin.lock(), std::io::stdout()).run()?;
+    Ok(())
 }
@@ -0,0 +1,308 @@
+use std::cell::RefCell;
+use std::ops::{Deref, Range};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::error::{LC3Error, LC3Result};
+use crate::rng::Rng;
+use crate::vm::{KB_STATUS_POS, MEMORY_SIZE};
+
+// The device registers take up the last 512 words of the address space in
+// the LC-3's own layout, from KBSR at xFE00 to the MCR at xFFFE
+const DEVICES_SIZE: usize = 0x200;
+
+// Words in each page of memory that snapshots share
+pub(crate) const PAGE_SIZE: usize = 1024;
+
+// A copy of one page of memory, shared by every snapshot taken while it
+// stayed the same
+pub(crate) type Page = Arc<[u16]>;
+
+// The machine's memory. It lives on the heap, so the VM is cheap to move,
+// and keeps the pages it last handed out to a snapshot until they're written
+// to, so taking another snapshot only copies the pages that changed.
+pub(crate) struct Memory {
+    words: Box<[u16]>,
+    // The page as it was at the last snapshot, or None if it's been written
+    // since. Snapshots only need to look at memory, hence the RefCell.
+    shared: RefCell<Vec<Option<Page>>>,
+}
+
+impl Memory {
+    pub(crate) fn new() -> Self {
+        Self {
+            words: vec![0; MEMORY_SIZE].into_boxed_slice(),
+            shared: RefCell::new(vec![None; MEMORY_SIZE / PAGE_SIZE]),
+        }
+    }
+
+    pub(crate) fn set(&mut self, address: u16, val: u16) {
+        self.words[address as usize] = val;
+        self.shared.get_mut()[address as usize / PAGE_SIZE] = None;
+    }
+
+    // For changing a run of words at once, all of which count as written
+    pub(crate) fn range_mut(&mut self, range: Range<usize>) -> &mut [u16] {
+        let pages = range.start / PAGE_SIZE..range.end.div_ceil(PAGE_SIZE);
+        for page in &mut self.shared.get_mut()[pages] {
+            *page = None;
+        }
+        &mut self.words[range]
+    }
+
+    // Every page, copying only those written since the last call
+    pub(crate) fn pages(&self) -> Vec<Page> {
+        let mut shared = self.shared.borrow_mut();
+        shared
+            .iter_mut()
+            .zip(self.words.chunks(PAGE_SIZE))
+            .map(|(page, words)| Arc::clone(page.get_or_insert_with(|| Arc::from(words))))
+            .collect()
+    }
+
+    // Put back pages from a snapshot, which can then be shared again
+    pub(crate) fn restore(&mut self, pages: &[Page]) {
+        for ((words, page), shared) in self
+            .words
+            .chunks_mut(PAGE_SIZE)
+            .zip(pages)
+            .zip(self.shared.get_mut().iter_mut())
+        {
+            words.copy_from_slice(page);
+            *shared = Some(Arc::clone(page));
+        }
+    }
+}
+
+impl Deref for Memory {
+    type Target = [u16];
+
+    fn deref(&self) -> &[u16] {
+        &self.words
+    }
+}
+
+// Split words, all of memory, into pages for a snapshot
+pub(crate) fn to_pages(words: &[u16]) -> Vec<Page> {
+    words.chunks(PAGE_SIZE).map(Arc::from).collect()
+}
+
+// Where things are in the address space, for machines with less memory than
+// the LC-3 or with the device registers somewhere else. The default is the
+// LC-3's: all 64K words, with the device registers from xFE00 up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryMap {
+    // Words of memory from x0000. Programs that read or write past the end,
+    // other than to the device registers, fail with an Unmapped error.
+    pub size: usize,
+    // Where the device registers start. They keep their places relative to
+    // each other, so KBSR is here, KBDR two words on and so on up to the
+    // MCR at x1FE on, and hide whatever memory they're over.
+    pub devices: u16,
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        Self {
+            size: MEMORY_SIZE,
+            devices: KB_STATUS_POS,
+        }
+    }
+}
+
+impl MemoryMap {
+    pub(crate) fn check(&self) -> LC3Result<()> {
+        if self.size > MEMORY_SIZE {
+            return Err(LC3Error::BadMemoryMap(format!(
+                "{} words of memory is more than 16 bit addresses can reach",
+                self.size
+            )));
+        }
+        if self.devices as usize + DEVICES_SIZE > MEMORY_SIZE {
+            return Err(LC3Error::BadMemoryMap(format!(
+                "The device registers can't start past x{:04X}, not at x{:04X}",
+                MEMORY_SIZE - DEVICES_SIZE,
+                self.devices
+            )));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_standard(&self) -> bool {
+        self.devices == KB_STATUS_POS
+    }
+
+    // Where a device register is, given where it is in the LC-3's layout
+    pub(crate) fn device(&self, standard: u16) -> u16 {
+        self.devices + (standard - KB_STATUS_POS)
+    }
+
+    // Which device register address is, as its address in the LC-3's
+    // layout, if it's one at all
+    pub(crate) fn standard_device(&self, address: u16) -> Option<u16> {
+        let offset = address.checked_sub(self.devices)?;
+        if (offset as usize) < DEVICES_SIZE {
+            Some(KB_STATUS_POS + offset)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn is_device(&self, address: u16) -> bool {
+        self.standard_device(address).is_some()
+    }
+
+    pub(crate) fn is_mapped(&self, address: u16) -> bool {
+        (address as usize) < self.size || self.is_device(address)
+    }
+
+    // The ordinary memory: everything below size that isn't device
+    // registers
+    pub(crate) fn ram(&self) -> Vec<Range<usize>> {
+        let devices = self.devices as usize..self.devices as usize + DEVICES_SIZE;
+        let below = 0..devices.start.min(self.size);
+        let above = devices.end..self.size.max(devices.end);
+        vec![below, above]
+            .into_iter()
+            .filter(|range| !range.is_empty())
+            .collect()
+    }
+}
+
+// What memory holds before anything's loaded. Anything other than zero makes
+// programs that read memory they never wrote fail loudly rather than work by
+// luck, and the same fill gives the same memory every run.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MemoryFill {
+    #[default]
+    Zero,
+    // Every word the same, such as xDEAD, so stray reads stand out
+    Word(u16),
+    // Random words, the same ones for the same seed
+    Random(u64),
+}
+
+impl MemoryFill {
+    pub(crate) fn fill(&self, memory: &mut [u16]) {
+        match self {
+            Self::Zero => memory.fill(0),
+            Self::Word(word) => memory.fill(*word),
+            Self::Random(seed) => {
+                let mut rng = Rng::new(*seed);
+                for word in memory.iter_mut() {
+                    *word = rng.next_u16();
+                }
+            }
+        }
+    }
+}
+
+// zero, a word in hex such as xDEAD, random, which has seed 0, or
+// random:SEED
+impl FromStr for MemoryFill {
+    type Err = LC3Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            LC3Error::Other(format!(
+                "Unknown memory fill {}, expected zero, a word in hex such as xDEAD, \
+                 random or random:SEED",
+                text
+            ))
+        };
+        match text {
+            "zero" => Ok(Self::Zero),
+            "random" => Ok(Self::Random(0)),
+            _ => {
+                if let Some(seed) = text.strip_prefix("random:") {
+                    return seed.parse().map(Self::Random).map_err(|_| invalid());
+                }
+                let digits = text
+                    .strip_prefix("0x")
+                    .or_else(|| text.strip_prefix('x'))
+                    .or_else(|| text.strip_prefix('X'))
+                    .unwrap_or(text);
+                u16::from_str_radix(digits, 16)
+                    .map(Self::Word)
+                    .map_err(|_| invalid())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MemoryFill, MemoryMap};
+
+    #[test]
+    fn can_fill_memory() {
+        let test_cases = vec![
+            ("zero", MemoryFill::Zero),
+            ("xDEAD", MemoryFill::Word(0xDEAD)),
+            ("0xbeef", MemoryFill::Word(0xBEEF)),
+            ("random", MemoryFill::Random(0)),
+            ("random:42", MemoryFill::Random(42)),
+        ];
+        for (text, fill) in test_cases {
+            assert_eq!(text.parse::<MemoryFill>().unwrap(), fill);
+        }
+        assert!("random:x".parse::<MemoryFill>().is_err());
+        assert!("xDEADBEEF".parse::<MemoryFill>().is_err());
+
+        let mut memory = [1u16; 4];
+        MemoryFill::Word(7).fill(&mut memory);
+        assert_eq!(memory, [7; 4]);
+
+        // The same seed always gives the same words
+        let mut first = [0u16; 64];
+        let mut second = [0u16; 64];
+        MemoryFill::Random(3).fill(&mut first);
+        MemoryFill::Random(3).fill(&mut second);
+        assert_eq!(first, second);
+        assert!(first.iter().any(|word| *word != first[0]));
+        MemoryFill::Random(4).fill(&mut second);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn can_map_memory() {
+        let standard = MemoryMap::default();
+        assert!(standard.check().is_ok());
+        assert!(standard.is_standard());
+        assert_eq!(standard.ram(), vec![0..0xFE00]);
+        assert!(standard.is_mapped(0xFFFF));
+
+        let map = MemoryMap {
+            size: 0x8000,
+            devices: 0x7000,
+        };
+        assert!(map.check().is_ok());
+        assert_eq!(map.ram(), vec![0..0x7000, 0x7200..0x8000]);
+        assert_eq!(map.device(0xFE02), 0x7002);
+        assert_eq!(map.standard_device(0x71FE), Some(0xFFFE));
+        assert_eq!(map.standard_device(0x6FFF), None);
+        assert_eq!(map.standard_device(0x7200), None);
+        assert!(map.is_mapped(0x7FFF));
+        assert!(!map.is_mapped(0x8000));
+
+        // Device registers past the end of memory are still there
+        let map = MemoryMap {
+            size: 0x1000,
+            devices: 0xFE00,
+        };
+        assert_eq!(map.ram(), vec![0..0x1000]);
+        assert!(!map.is_mapped(0x1000));
+        assert!(map.is_mapped(0xFE00));
+
+        let too_big = MemoryMap {
+            size: 0x10001,
+            ..MemoryMap::default()
+        };
+        assert!(too_big.check().is_err());
+        let too_high = MemoryMap {
+            devices: 0xFF00,
+            ..MemoryMap::default()
+        };
+        assert!(too_high.check().is_err());
+    }
+}
@@ -1,14 +1,16 @@
 use super::trap_handler as handle_trap;
 use crate::command::Command;
 use crate::error::{LC3Error, LC3Result};
+use crate::interrupt;
 use crate::io::IOHandle;
+use crate::plugin::PluginSet;
 use crate::register::Register::{RCond, RPC, RR7};
 use crate::trap::TrapCode;
 use crate::utils::sign_extend;
 use crate::vm::VM;
 use crate::wrapping_add;
 
-pub(crate) fn branch<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Result<()> {
+pub(crate) fn branch<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>, command: &Command) -> LC3Result<()> {
     let test_flag = command.bit_slice(4, 6)?;
     let flags = vm.reg_read(RCond)?;
     let will_branch = (flags & test_flag) != 0;
@@ -22,7 +24,7 @@ pub(crate) fn branch<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Res
     Ok(())
 }
 
-pub(crate) fn add<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Result<()> {
+pub(crate) fn add<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>, command: &Command) -> LC3Result<()> {
     let target_reg = command.bit_slice(4, 6)? as u8;
     let left = vm.reg_index_read(command.bit_slice(7, 9)? as u8)?;
     let immediate = command.bit_slice(10, 10)? == 1;
@@ -39,11 +41,12 @@ pub(crate) fn add<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Result
     Ok(())
 }
 
-pub(crate) fn load<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Result<()> {
+pub(crate) fn load<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>, command: &Command) -> LC3Result<()> {
     let target_reg = command.bit_slice(4, 6)? as u8;
     let offset = sign_extend(command.bit_slice(7, 15)?, 9);
     let pc = vm.reg_read(RPC)?;
     let address = wrapping_add!(pc, offset);
+    vm.check_access(address)?;
     let val = vm.mem_read(address)?;
     vm.reg_index_write(target_reg, val)?;
     vm.update_flags(target_reg.into())?;
@@ -51,17 +54,18 @@ pub(crate) fn load<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Resul
     Ok(())
 }
 
-pub(crate) fn store<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Result<()> {
+pub(crate) fn store<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>, command: &Command) -> LC3Result<()> {
     let source = command.bit_slice(4, 6)? as u8;
     let offset = sign_extend(command.bit_slice(7, 15)?, 9);
     let target = wrapping_add!(vm.reg_read(RPC)?, offset);
     let val = vm.reg_index_read(source)?;
+    vm.check_access(target)?;
     vm.mem_write(target, val)?;
 
     Ok(())
 }
 
-pub(crate) fn jump_register<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Result<()> {
+pub(crate) fn jump_register<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>, command: &Command) -> LC3Result<()> {
     // Save program counter
     let pc = vm.reg_read(RPC)?;
     vm.reg_write(RR7, pc)?;
@@ -81,7 +85,7 @@ pub(crate) fn jump_register<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) ->
     Ok(())
 }
 
-pub(crate) fn and<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Result<()> {
+pub(crate) fn and<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>, command: &Command) -> LC3Result<()> {
     let target_reg = command.bit_slice(4, 6)? as u8;
     let left = vm.reg_index_read(command.bit_slice(7, 9)? as u8)?;
     let immediate = command.bit_slice(10, 10)? == 1;
@@ -98,11 +102,12 @@ pub(crate) fn and<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Result
     Ok(())
 }
 
-pub(crate) fn load_register<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Result<()> {
+pub(crate) fn load_register<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>, command: &Command) -> LC3Result<()> {
     let target = command.bit_slice(4, 6)? as u8;
     let base = command.bit_slice(7, 9)? as u8;
     let offset = sign_extend(command.bit_slice(10, 15)?, 6);
     let address = wrapping_add!(vm.reg_index_read(base)?, offset);
+    vm.check_access(address)?;
     let val = vm.mem_read(address)?;
     vm.reg_index_write(target, val)?;
     vm.update_flags(target.into())?;
@@ -110,24 +115,29 @@ pub(crate) fn load_register<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) ->
     Ok(())
 }
 
-pub(crate) fn store_register<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Result<()> {
+pub(crate) fn store_register<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>, command: &Command) -> LC3Result<()> {
     let source = command.bit_slice(4, 6)? as u8;
     let base_register = command.bit_slice(7, 9)? as u8;
     let offset = sign_extend(command.bit_slice(10, 15)?, 6);
     let address = wrapping_add!(vm.reg_index_read(base_register)?, offset);
     let val = vm.reg_index_read(source)?;
+    vm.check_access(address)?;
     vm.mem_write(address, val)?;
 
     Ok(())
 }
 
-pub(crate) fn rti<IO: IOHandle>(_vm: &mut VM<IO>, _command: &Command) -> LC3Result<()> {
-    Err(LC3Error::Internal(
-        "Attempt to execute unimplemented op code".to_string(),
-    ))
+// Pops the PC and PSR pushed when the interrupt or exception was taken,
+// which puts back the privilege, priority and condition codes along with
+// the user stack if that's where it came from
+pub(crate) fn rti<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>, _command: &Command) -> LC3Result<()> {
+    if vm.user_mode {
+        return Err(LC3Error::PrivilegeViolation);
+    }
+    interrupt::exit(vm)
 }
 
-pub(crate) fn not<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Result<()> {
+pub(crate) fn not<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>, command: &Command) -> LC3Result<()> {
     let target = command.bit_slice(4, 6)? as u8;
     let source = command.bit_slice(7, 9)? as u8;
     let negated = !vm.reg_index_read(source)?;
@@ -137,12 +147,15 @@ pub(crate) fn not<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Result
     Ok(())
 }
 
-pub(crate) fn load_indirect<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Result<()> {
+pub(crate) fn load_indirect<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>, command: &Command) -> LC3Result<()> {
     let pc_offset = sign_extend(command.bit_slice(7, 15)?, 9);
     let pc = vm.reg_read(RPC)?;
 
     let target = command.bit_slice(4, 6)? as u8;
-    let final_address = vm.mem_read(wrapping_add!(pc, pc_offset))?;
+    let address = wrapping_add!(pc, pc_offset);
+    vm.check_access(address)?;
+    let final_address = vm.mem_read(address)?;
+    vm.check_access(final_address)?;
     let val = vm.mem_read(final_address)?;
 
     vm.reg_index_write(target, val)?;
@@ -151,19 +164,21 @@ pub(crate) fn load_indirect<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) ->
     Ok(())
 }
 
-pub(crate) fn store_indirect<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Result<()> {
+pub(crate) fn store_indirect<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>, command: &Command) -> LC3Result<()> {
     let source = command.bit_slice(4, 6)? as u8;
     let offset = sign_extend(command.bit_slice(7, 15)?, 9);
     let pc = vm.reg_read(RPC)?;
     let address = wrapping_add!(pc, offset);
+    vm.check_access(address)?;
     let final_address = vm.mem_read(address)?;
     let val = vm.reg_index_read(source)?;
+    vm.check_access(final_address)?;
     vm.mem_write(final_address, val)?;
 
     Ok(())
 }
 
-pub(crate) fn jump<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Result<()> {
+pub(crate) fn jump<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>, command: &Command) -> LC3Result<()> {
     let address_reg = command.bit_slice(7, 9)? as u8;
     let address = vm.reg_index_read(address_reg)?;
     vm.reg_write(RPC, address)?;
@@ -171,24 +186,43 @@ pub(crate) fn jump<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Resul
     Ok(())
 }
 
-pub(crate) fn reserved<IO: IOHandle>(_vm: &mut VM<IO>, _command: &Command) -> LC3Result<()> {
-    Err(LC3Error::Internal(
-        "Attempt to execute unimplemented op code".to_string(),
-    ))
+pub(crate) fn reserved<IO: IOHandle, P: PluginSet<IO>>(_vm: &mut VM<IO, P>, command: &Command) -> LC3Result<()> {
+    Err(LC3Error::IllegalOpcode {
+        word: command.get_bytes(),
+    })
 }
 
-pub(crate) fn load_effective_address<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Result<()> {
+pub(crate) fn load_effective_address<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>, command: &Command) -> LC3Result<()> {
     let target = command.bit_slice(4, 6)? as u8;
     let offset = sign_extend(command.bit_slice(7, 15)?, 9);
     let effective_address = wrapping_add!(vm.reg_read(RPC)?, offset);
     vm.reg_index_write(target, effective_address)?;
-    vm.update_flags(target.into())?;
+    if vm.spec.lea_sets_flags() {
+        vm.update_flags(target.into())?;
+    }
 
     Ok(())
 }
 
-pub(crate) fn trap<IO: IOHandle>(vm: &mut VM<IO>, command: &Command) -> LC3Result<()> {
+pub(crate) fn trap<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>, command: &Command) -> LC3Result<()> {
     let code = command.bit_slice(8, 15)? as u8;
+    if let Some(result) = vm.host_trap(code) {
+        return result;
+    }
+
+    if vm.os_traps {
+        if vm.spec.trap_saves_psr() {
+            return interrupt::enter_trap(vm, code);
+        }
+        // The service routine's address comes from the trap vector table,
+        // and it returns to the address left in R7
+        let pc = vm.reg_read(RPC)?;
+        vm.reg_write(RR7, pc)?;
+        let routine = vm.mem_read(u16::from(code) * vm.isa.word_size())?;
+        vm.reg_write(RPC, routine)?;
+        return Ok(());
+    }
+
     let code = TrapCode::from_int(code);
     match code? {
         TrapCode::GetC => handle_trap::getchar(vm)?,
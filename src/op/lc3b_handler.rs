@@ -0,0 +1,191 @@
+use super::handler;
+use crate::command::Command;
+use crate::error::LC3Result;
+use crate::io::IOHandle;
+use crate::op::Op;
+use crate::plugin::PluginSet;
+use crate::register::Register::{RCond, RPC, RR7};
+use crate::utils::sign_extend;
+use crate::vm::VM;
+use crate::wrapping_add;
+
+// The LC-3b's instructions. Addresses count bytes, so PC relative offsets
+// and word offsets are doubled, and words can only be at even addresses.
+pub(crate) fn execute<IO: IOHandle, P: PluginSet<IO>>(
+    vm: &mut VM<IO, P>,
+    op: Op,
+    command: &Command,
+) -> LC3Result<()> {
+    match op {
+        Op::Br => branch(vm, command),
+        Op::Add => handler::add(vm, command),
+        Op::Ldb => load_byte(vm, command),
+        Op::Stb => store_byte(vm, command),
+        Op::Jsr => jump_register(vm, command),
+        Op::And => handler::and(vm, command),
+        Op::Ldw => load_word(vm, command),
+        Op::Stw => store_word(vm, command),
+        Op::Rti => handler::rti(vm, command),
+        Op::Xor => xor(vm, command),
+        Op::Jmp => handler::jump(vm, command),
+        Op::Shf => shift(vm, command),
+        Op::Lea => load_effective_address(vm, command),
+        Op::Trap => handler::trap(vm, command),
+        _ => handler::reserved(vm, command),
+    }
+}
+
+// PC relative offsets count words
+fn pc_target<IO: IOHandle, P: PluginSet<IO>>(
+    vm: &mut VM<IO, P>,
+    command: &Command,
+    bits: u8,
+) -> LC3Result<u16> {
+    let offset = sign_extend(command.bit_slice(16 - bits, 15)?, bits) << 1;
+    Ok(wrapping_add!(vm.reg_read(RPC)?, offset))
+}
+
+// BaseR plus offset6, which counts bytes or words
+fn base_address<IO: IOHandle, P: PluginSet<IO>>(
+    vm: &mut VM<IO, P>,
+    command: &Command,
+    scale: u16,
+) -> LC3Result<u16> {
+    let base = vm.reg_index_read(command.bit_slice(7, 9)? as u8)?;
+    let offset = sign_extend(command.bit_slice(10, 15)?, 6).wrapping_mul(scale);
+    Ok(wrapping_add!(base, offset))
+}
+
+fn branch<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>, command: &Command) -> LC3Result<()> {
+    let test_flag = command.bit_slice(4, 6)?;
+    let flags = vm.reg_read(RCond)?;
+    if flags & test_flag != 0 {
+        let new_pc = pc_target(vm, command, 9)?;
+        vm.reg_write(RPC, new_pc)?;
+    }
+
+    Ok(())
+}
+
+fn load_byte<IO: IOHandle, P: PluginSet<IO>>(
+    vm: &mut VM<IO, P>,
+    command: &Command,
+) -> LC3Result<()> {
+    let target = command.bit_slice(4, 6)? as u8;
+    let address = base_address(vm, command, 1)?;
+    vm.check_access(address)?;
+    let byte = vm.mem_read_byte(address)?;
+    vm.reg_index_write(target, sign_extend(u16::from(byte), 8))?;
+    vm.update_flags(target.into())?;
+
+    Ok(())
+}
+
+fn store_byte<IO: IOHandle, P: PluginSet<IO>>(
+    vm: &mut VM<IO, P>,
+    command: &Command,
+) -> LC3Result<()> {
+    let source = command.bit_slice(4, 6)? as u8;
+    let address = base_address(vm, command, 1)?;
+    let val = vm.reg_index_read(source)?;
+    vm.check_access(address)?;
+    vm.mem_write_byte(address, val as u8)?;
+
+    Ok(())
+}
+
+// Unlike the LC-3's JSRR, the base register is read before R7 is written,
+// so JSRR R7 goes where R7 pointed
+fn jump_register<IO: IOHandle, P: PluginSet<IO>>(
+    vm: &mut VM<IO, P>,
+    command: &Command,
+) -> LC3Result<()> {
+    let pc = vm.reg_read(RPC)?;
+    let destination = if command.bit_slice(4, 4)? == 1 {
+        pc_target(vm, command, 11)?
+    } else {
+        vm.reg_index_read(command.bit_slice(7, 9)? as u8)?
+    };
+    vm.reg_write(RR7, pc)?;
+    vm.reg_write(RPC, destination)?;
+
+    Ok(())
+}
+
+fn load_word<IO: IOHandle, P: PluginSet<IO>>(
+    vm: &mut VM<IO, P>,
+    command: &Command,
+) -> LC3Result<()> {
+    let target = command.bit_slice(4, 6)? as u8;
+    let address = base_address(vm, command, 2)?;
+    vm.isa.check_aligned(address)?;
+    vm.check_access(address)?;
+    let val = vm.mem_read(address)?;
+    vm.reg_index_write(target, val)?;
+    vm.update_flags(target.into())?;
+
+    Ok(())
+}
+
+fn store_word<IO: IOHandle, P: PluginSet<IO>>(
+    vm: &mut VM<IO, P>,
+    command: &Command,
+) -> LC3Result<()> {
+    let source = command.bit_slice(4, 6)? as u8;
+    let address = base_address(vm, command, 2)?;
+    let val = vm.reg_index_read(source)?;
+    vm.isa.check_aligned(address)?;
+    vm.check_access(address)?;
+    vm.mem_write(address, val)?;
+
+    Ok(())
+}
+
+// NOT is XOR with an immediate of -1
+fn xor<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>, command: &Command) -> LC3Result<()> {
+    let target = command.bit_slice(4, 6)? as u8;
+    let left = vm.reg_index_read(command.bit_slice(7, 9)? as u8)?;
+    let right = if command.bit_slice(10, 10)? == 1 {
+        sign_extend(command.bit_slice(11, 15)?, 5)
+    } else {
+        vm.reg_index_read(command.bit_slice(13, 15)? as u8)?
+    };
+
+    vm.reg_index_write(target, left ^ right)?;
+    vm.update_flags(target.into())?;
+
+    Ok(())
+}
+
+// LSHF, RSHFL and RSHFA, by bits 4 and 5: whether to shift right, and
+// whether a right shift keeps the sign
+fn shift<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>, command: &Command) -> LC3Result<()> {
+    let target = command.bit_slice(4, 6)? as u8;
+    let value = vm.reg_index_read(command.bit_slice(7, 9)? as u8)?;
+    let amount = command.bit_slice(12, 15)?;
+    let shifted = match (
+        command.bit_slice(11, 11)? == 1,
+        command.bit_slice(10, 10)? == 1,
+    ) {
+        (false, _) => value << amount,
+        (true, false) => value >> amount,
+        (true, true) => ((value as i16) >> amount) as u16,
+    };
+
+    vm.reg_index_write(target, shifted)?;
+    vm.update_flags(target.into())?;
+
+    Ok(())
+}
+
+// The LC-3b's LEA leaves the condition codes alone
+fn load_effective_address<IO: IOHandle, P: PluginSet<IO>>(
+    vm: &mut VM<IO, P>,
+    command: &Command,
+) -> LC3Result<()> {
+    let target = command.bit_slice(4, 6)? as u8;
+    let effective_address = pc_target(vm, command, 9)?;
+    vm.reg_index_write(target, effective_address)?;
+
+    Ok(())
+}
@@ -1,8 +1,9 @@
 pub(crate) mod handler;
+pub(crate) mod lc3b_handler;
 mod op;
 pub(crate) mod trap_handler;
 
-pub(crate) use op::Op;
+pub use op::Op;
 
 #[cfg(test)]
 mod test;
@@ -1,4 +1,5 @@
 use crate::error::{LC3Error, LC3Result};
+use crate::isa::Isa;
 
 const OP_CODES: [Op;16] = [
     Op::Br,
@@ -19,8 +20,29 @@ const OP_CODES: [Op;16] = [
     Op::Trap,
 ];
 
-#[derive(Debug, Clone, PartialEq)]
-pub(crate) enum Op {
+// The LC-3b's, which drops the indirect and PC relative loads and stores
+// for byte and word loads and stores, XOR and shifts
+const LC3B_OP_CODES: [Op;16] = [
+    Op::Br,
+    Op::Add,
+    Op::Ldb,
+    Op::Stb,
+    Op::Jsr,
+    Op::And,
+    Op::Ldw,
+    Op::Stw,
+    Op::Rti,
+    Op::Xor,
+    Op::Res,
+    Op::Res,
+    Op::Jmp,
+    Op::Shf,
+    Op::Lea,
+    Op::Trap,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
     Br,   /* branch */
     Add,  /* add  */
     Ld,   /* load */
@@ -37,29 +59,51 @@ pub(crate) enum Op {
     Res,  /* reserved (unused) */
     Lea,  /* load effective address */
     Trap, /* execute trap */
+    Ldb,  /* load byte, LC-3b only */
+    Stb,  /* store byte, LC-3b only */
+    Ldw,  /* load word, LC-3b only */
+    Stw,  /* store word, LC-3b only */
+    Xor,  /* bitwise xor, LC-3b only */
+    Shf,  /* shift, LC-3b only */
 }
 
 impl Op {
     pub(crate) fn from_int(op_code: u8) -> LC3Result<Self> {
         if (op_code as usize) < OP_CODES.len() {
-            return Ok( OP_CODES[op_code as usize].clone());
+            return Ok( OP_CODES[op_code as usize]);
         } else {
             let err = LC3Error::BadOpCode{code: op_code};
             Err(err)
         }
     }
+
+    pub(crate) fn from_int_for(isa: Isa, op_code: u8) -> LC3Result<Self> {
+        match isa {
+            Isa::Lc3 => Self::from_int(op_code),
+            Isa::Lc3b => LC3B_OP_CODES
+                .get(op_code as usize)
+                .copied()
+                .ok_or(LC3Error::BadOpCode { code: op_code }),
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
     use crate::error::LC3Result;
-    use super::{Op, OP_CODES};
+    use super::{Op, LC3B_OP_CODES, OP_CODES};
+    use crate::isa::Isa;
 
     #[test]
     fn can_cast_int_to_instruction() -> LC3Result<()> {
         for (code, op) in OP_CODES.iter().enumerate() {
             assert_eq!(&Op::from_int(code as u8)?, op);
+            assert_eq!(&Op::from_int_for(Isa::Lc3, code as u8)?, op);
+        }
+        for (code, op) in LC3B_OP_CODES.iter().enumerate() {
+            assert_eq!(&Op::from_int_for(Isa::Lc3b, code as u8)?, op);
         }
+        assert!(Op::from_int_for(Isa::Lc3b, 16).is_err());
 
         Ok(())
     }
@@ -1,7 +1,9 @@
 use crate::command::Command;
 use crate::condition_flags::{FL_NEG, FL_POS, FL_ZRO};
 use crate::error::LC3Result;
-use crate::io::TestIOHandle;
+use crate::error::LC3Error;
+use crate::io::{CharEncoding, EchoMode, PackedByteOrder, TestIOHandle};
+use crate::op::trap_handler::IN_PROMPT;
 use crate::register::Register;
 use crate::register::Register::{RCond, RPC};
 use crate::vm::VM;
@@ -415,9 +417,53 @@ fn can_trap_in() -> LC3Result<()> {
     let command = Command::new(0xF023);
     vm.run_command(&command)?;
 
-    let mut outputs = vm.into_io_handle().get_test_outputs();
-    assert!(outputs.len() == 1);
-    assert!(outputs.pop() == Some(test_char));
+    let output = vm.into_io_handle().output();
+    assert_eq!(output, format!("{}{}", IN_PROMPT, test_char));
+
+    Ok(())
+}
+
+#[test]
+fn can_choose_whether_traps_echo() -> LC3Result<()> {
+    let cases = [
+        (EchoMode::Standard, "", "i"),
+        (EchoMode::Always, "g", "i"),
+        (EchoMode::Never, "", ""),
+    ];
+
+    for (mode, getc_echo, in_echo) in cases.iter() {
+        let mut vm = VM::new_with_io(TestIOHandle::new().with_key_presses("gi"));
+        vm.set_echo(*mode);
+        // GETC then IN
+        vm.run_command(&Command::new(0xF020))?;
+        vm.run_command(&Command::new(0xF023))?;
+        let echoed = format!("{}{}{}", getc_echo, IN_PROMPT, in_echo);
+        assert_eq!(vm.into_io_handle().output(), echoed);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn can_choose_how_non_ascii_is_handled() -> LC3Result<()> {
+    // Writes xE9 and reads a key, for each of 'é' and '€'
+    let run = |encoding: CharEncoding, key: char| -> LC3Result<(String, u16)> {
+        let mut vm = VM::new_with_io(TestIOHandle::new().with_key_presses(&key.to_string()));
+        vm.set_encoding(encoding);
+        vm.reg_write(Register::RR0, 0x00E9)?;
+        vm.run_command(&Command::new(0xF021))?;
+        vm.run_command(&Command::new(0xF020))?;
+        let code = vm.reg_read(Register::RR0)?;
+        Ok((vm.into_io_handle().output(), code))
+    };
+
+    assert_eq!(run(CharEncoding::Latin1, 'é')?, ("é".to_string(), 0xE9));
+    assert_eq!(run(CharEncoding::Latin1, '€')?, ("é".to_string(), '?' as u16));
+    assert_eq!(run(CharEncoding::Replace, 'é')?, ("\u{FFFD}".to_string(), '?' as u16));
+    assert!(matches!(
+        run(CharEncoding::Strict, 'a'),
+        Err(LC3Error::NonAscii { code: 0xE9 })
+    ));
 
     Ok(())
 }
@@ -454,6 +500,28 @@ fn can_trap_put_byte_string() -> LC3Result<()> {
     Ok(())
 }
 
+#[test]
+fn can_trap_put_byte_string_in_either_order() -> LC3Result<()> {
+    let cases = [
+        // "abc" with the spec's packing, and anything after the end ignored
+        (PackedByteOrder::LowFirst, [0x6261, 0x0063, 0x0064], "abc"),
+        (PackedByteOrder::LowFirst, [0x6261, 0x0000, 0x0064], "ab"),
+        (PackedByteOrder::HighFirst, [0x6162, 0x6300, 0x0064], "abc"),
+        (PackedByteOrder::HighFirst, [0x6162, 0x6364, 0x0000], "abcd"),
+    ];
+
+    for (order, words, expected) in cases.iter() {
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.set_packed_byte_order(*order);
+        vm.load_program_at(0x4000, words)?;
+        vm.reg_write(Register::RR0, 0x4000)?;
+        vm.run_command(&Command::new(0xF024))?;
+        assert_eq!(vm.into_io_handle().output(), *expected);
+    }
+
+    Ok(())
+}
+
 #[test]
 fn can_trap_halt() -> LC3Result<()> {
     let mut vm = VM::new();
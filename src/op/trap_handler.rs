@@ -1,27 +1,39 @@
 use crate::error::LC3Result;
-use crate::io::{IOHandle};
+use crate::io::{EchoMode, IOHandle};
+use crate::isa::Isa;
+use crate::plugin::PluginSet;
 use crate::register::Register::{RR0};
-use crate::vm::VM;
+use crate::vm::{CLOCK_ENABLE, MACHINE_CONTROL_POS, VM};
 use crate::wrapping_add;
 
-pub(crate) fn getchar<IO: IOHandle>(vm: &mut VM<IO>) -> LC3Result<()> {
-    let ch = vm.getchar()? as u16;
-    vm.reg_write(RR0, ch)?;
+// Written by IN before it waits for the key
+pub(crate) const IN_PROMPT: &str = "Enter a character: \n";
+
+pub(crate) fn getchar<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>) -> LC3Result<()> {
+    let ch = vm.getchar()?;
+    let code = vm.char_code(ch)?;
+    vm.reg_write(RR0, code)?;
+    if vm.echo == EchoMode::Always {
+        vm.put_byte(code)?;
+    }
     Ok(())
 }
 
-pub(crate) fn trap_out<IO: IOHandle>(vm: &mut VM<IO>) -> LC3Result<()> {
-    let ch = vm.reg_read(RR0)? as u8 as char;
-    vm.putchar(ch)?;
+pub(crate) fn trap_out<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>) -> LC3Result<()> {
+    let ch = vm.reg_read(RR0)?;
+    vm.put_byte(ch)?;
     Ok(())
 }
 
-pub(crate) fn put_string<IO: IOHandle>(vm: &mut VM<IO>) -> LC3Result<()> {
+pub(crate) fn put_string<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>) -> LC3Result<()> {
+    if vm.isa == Isa::Lc3b {
+        return put_bytes(vm);
+    }
     let mut next_address = vm.reg_read(RR0)?;
     loop {
         let value = vm.mem_read(next_address)?;
         if value != 0 {
-            vm.putchar(value as u8 as char)?;
+            vm.put_byte(value)?;
         } else {
             break;
         }
@@ -30,39 +42,65 @@ pub(crate) fn put_string<IO: IOHandle>(vm: &mut VM<IO>) -> LC3Result<()> {
     Ok(())
 }
 
-pub(crate) fn trap_in<IO: IOHandle>(vm: &mut VM<IO>) -> LC3Result<()> {
-    // TODO: Swap println out for something using the io handle, otherwise
-    // this first part isn't testable.
-    println!("Enter a character: ");
+pub(crate) fn trap_in<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>) -> LC3Result<()> {
+    for ch in IN_PROMPT.chars() {
+        vm.putchar(ch)?;
+    }
     let ch = vm.getchar()?;
-    vm.reg_write(RR0, ch as u16)?;
-    vm.putchar(ch)?;
+    let code = vm.char_code(ch)?;
+    vm.reg_write(RR0, code)?;
+    if vm.echo != EchoMode::Never {
+        vm.put_byte(code)?;
+    }
     Ok(())
 }
 
-pub(crate) fn put_byte_string<IO: IOHandle>(vm: &mut VM<IO>) -> LC3Result<()> {
+// Writes the string R0 points to, packed two characters to a word. It ends
+// at a word of x0000, or after the first character of a word whose second
+// is zero, as an odd length string does.
+pub(crate) fn put_byte_string<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>) -> LC3Result<()> {
+    if vm.isa == Isa::Lc3b {
+        return put_bytes(vm);
+    }
     let mut next_address = vm.reg_read(RR0)?;
-    // TODO: Might be a nicer way to express this loop. Feels a bit close
-    // to "while true"
-    'outer: loop {
-        let raw_value = vm.mem_read(next_address)?;
-        let left = raw_value as u8;
-        let right = (raw_value >> 8) as u8;
+    loop {
+        let word = vm.mem_read(next_address)?;
+        let (first, second) = vm.packed_byte_order.split(word);
+        if first == 0 {
+            break;
+        }
+        vm.put_byte(first)?;
+        if second == 0 {
+            break;
+        }
+        vm.put_byte(second)?;
+        next_address = wrapping_add!(next_address, 1);
+    }
+
+    Ok(())
+}
 
-        for value in &[left, right] {
-            if *value != 0 {
-                vm.putchar(*value as char)?;
-            } else {
-                break 'outer;
-            }
+// On the LC-3b, where memory is bytes, strings are a byte to a character
+// whichever trap writes them
+fn put_bytes<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>) -> LC3Result<()> {
+    let mut next_address = vm.reg_read(RR0)?;
+    loop {
+        let byte = vm.mem_read_byte(next_address)?;
+        if byte == 0 {
+            break;
         }
+        vm.put_byte(u16::from(byte))?;
         next_address = wrapping_add!(next_address, 1);
     }
 
     Ok(())
 }
 
-pub(crate) fn trap_halt<IO: IOHandle>(vm: &mut VM<IO>) -> LC3Result<()> {
-    vm.set_running(false)?;
+// Stops the clock by clearing the MCR's clock enable bit, the same way the
+// LC-3 OS does it
+pub(crate) fn trap_halt<IO: IOHandle, P: PluginSet<IO>>(vm: &mut VM<IO, P>) -> LC3Result<()> {
+    let mcr_address = vm.device(MACHINE_CONTROL_POS);
+    let mcr = vm.mem_read(mcr_address)?;
+    vm.mem_write(mcr_address, mcr & !CLOCK_ENABLE)?;
     Ok(())
 }
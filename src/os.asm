@@ -0,0 +1,177 @@
+; The operating system loaded by VM::load_os: trap service routines and
+; exception handlers, talking to the devices through their memory mapped
+; registers. The trap and interrupt vector tables pointing at these are
+; filled in by os.rs. Like the textbook's OS, the routines return with RET
+; and save the registers they use.
+            .ORIG x0200
+
+; Device registers and constants, kept where every routine can reach them
+NEWLINE     .FILL x000A
+KBSR        .FILL xFE00
+KBDR        .FILL xFE02
+DSR         .FILL xFE04
+DDR         .FILL xFE06
+MCR         .FILL xFFFE
+
+; Write the character in R0 to the display once it's ready
+WRITE_CHAR  ST R1, WC_R1
+WC_WAIT     LDI R1, DSR
+            BRzp WC_WAIT
+            STI R0, DDR
+            LD R1, WC_R1
+            RET
+WC_R1       .BLKW 1
+
+; Write the string R0 points to, one character per word
+WRITE_STRING ST R0, WS_R0
+            ST R1, WS_R1
+            ST R7, WS_R7
+            ADD R1, R0, #0
+WS_NEXT     LDR R0, R1, #0
+            BRz WS_DONE
+            JSR WRITE_CHAR
+            ADD R1, R1, #1
+            BR WS_NEXT
+WS_DONE     LD R0, WS_R0
+            LD R1, WS_R1
+            LD R7, WS_R7
+            RET
+WS_R0       .BLKW 1
+WS_R1       .BLKW 1
+WS_R7       .BLKW 1
+
+; TRAP x20: read a character from the keyboard into R0 without echoing it
+TRAP_GETC   LDI R0, KBSR
+            BRzp TRAP_GETC
+            LDI R0, KBDR
+            RET
+
+; TRAP x21: write the character in R0 to the display
+TRAP_OUT    ST R7, OUT_R7
+            JSR WRITE_CHAR
+            LD R7, OUT_R7
+            RET
+OUT_R7      .BLKW 1
+
+; TRAP x22: write the string R0 points to, one character per word
+TRAP_PUTS   ST R7, PUTS_R7
+            JSR WRITE_STRING
+            LD R7, PUTS_R7
+            RET
+PUTS_R7     .BLKW 1
+
+; TRAP x23: prompt for a character, echo it and leave it in R0
+TRAP_IN     ST R1, IN_R1
+            ST R7, IN_R7
+            LEA R0, IN_PROMPT
+            JSR WRITE_STRING
+IN_WAIT     LDI R0, KBSR
+            BRzp IN_WAIT
+            LDI R0, KBDR
+            JSR WRITE_CHAR
+            ADD R1, R0, #0
+            LD R0, NEWLINE
+            JSR WRITE_CHAR
+            ADD R0, R1, #0
+            LD R1, IN_R1
+            LD R7, IN_R7
+            RET
+IN_R1       .BLKW 1
+IN_R7       .BLKW 1
+IN_PROMPT   .STRINGZ "\nInput a character> "
+
+; TRAP x24: write the string R0 points to, two characters per word with
+; the first in the low byte
+TRAP_PUTSP  ST R0, SP_R0
+            ST R1, SP_R1
+            ST R2, SP_R2
+            ST R3, SP_R3
+            ST R4, SP_R4
+            ST R5, SP_R5
+            ST R7, SP_R7
+            ADD R1, R0, #0
+SP_NEXT     LDR R2, R1, #0
+            LD R3, LOW_BYTE
+            AND R0, R2, R3
+            BRz SP_DONE
+            JSR WRITE_CHAR
+            ; There's no right shift, so copy the high byte down a bit at a
+            ; time. R3 walks the bits of the high byte, R4 the low one.
+            AND R0, R0, #0
+            LD R3, HIGH_BIT
+            AND R4, R4, #0
+            ADD R4, R4, #1
+SP_SHIFT    AND R5, R2, R3
+            BRz SP_CLEAR
+            ADD R0, R0, R4
+SP_CLEAR    ADD R4, R4, R4
+            ADD R3, R3, R3
+            BRnp SP_SHIFT
+            ADD R0, R0, #0
+            BRz SP_DONE
+            JSR WRITE_CHAR
+            ADD R1, R1, #1
+            BR SP_NEXT
+SP_DONE     LD R0, SP_R0
+            LD R1, SP_R1
+            LD R2, SP_R2
+            LD R3, SP_R3
+            LD R4, SP_R4
+            LD R5, SP_R5
+            LD R7, SP_R7
+            RET
+SP_R0       .BLKW 1
+SP_R1       .BLKW 1
+SP_R2       .BLKW 1
+SP_R3       .BLKW 1
+SP_R4       .BLKW 1
+SP_R5       .BLKW 1
+SP_R7       .BLKW 1
+LOW_BYTE    .FILL x00FF
+HIGH_BIT    .FILL x0100
+
+; TRAP x25: print a message and stop the clock. If something starts it
+; again, the program carries on after the HALT.
+TRAP_HALT   ST R0, HALT_R0
+            ST R1, HALT_R1
+            ST R7, HALT_R7
+            LEA R0, HALT_MESSAGE
+            JSR WRITE_STRING
+            LDI R0, MCR
+            LD R1, CLOCK_OFF
+            AND R0, R0, R1
+            STI R0, MCR
+            LD R0, HALT_R0
+            LD R1, HALT_R1
+            LD R7, HALT_R7
+            RET
+HALT_R0     .BLKW 1
+HALT_R1     .BLKW 1
+HALT_R7     .BLKW 1
+CLOCK_OFF   .FILL x7FFF
+HALT_MESSAGE .STRINGZ "\n--- Halting the LC-3 ---\n"
+
+; Any trap vector without a service routine
+BAD_TRAP    LEA R0, BAD_TRAP_MESSAGE
+            BR HALT_WITH
+BAD_TRAP_MESSAGE .STRINGZ "\n--- Undefined trap executed ---\n"
+
+; Exception handlers. There's nothing to go back to, so they report what
+; happened and halt.
+PRIV_VIOLATION LEA R0, PRIV_MESSAGE
+            BR HALT_WITH
+PRIV_MESSAGE .STRINGZ "\n--- Privilege mode violation ---\n"
+ILLEGAL_OPCODE LEA R0, ILLEGAL_MESSAGE
+            BR HALT_WITH
+ILLEGAL_MESSAGE .STRINGZ "\n--- Illegal opcode ---\n"
+ACCESS_VIOLATION LEA R0, ACV_MESSAGE
+            BR HALT_WITH
+ACV_MESSAGE .STRINGZ "\n--- Access control violation ---\n"
+HALT_WITH   JSR WRITE_STRING
+            HALT
+            BR HALT_WITH
+
+; Any interrupt without a handler is ignored
+BAD_INTERRUPT RTI
+
+            .END
@@ -0,0 +1,175 @@
+use crate::asm::{assemble, Assembly};
+use crate::error::{LC3Error, LC3Result};
+use crate::interrupt::{
+    ACCESS_VIOLATION_VECTOR, ILLEGAL_OPCODE_VECTOR, PRIVILEGE_VECTOR, VECTOR_TABLE,
+};
+
+const SOURCE: &str = include_str!("os.asm");
+
+const TRAP_TABLE: u16 = 0x0000;
+const TABLE_SIZE: usize = 0x100;
+
+const TRAP_ROUTINES: [(u8, &str); 6] = [
+    (0x20, "TRAP_GETC"),
+    (0x21, "TRAP_OUT"),
+    (0x22, "TRAP_PUTS"),
+    (0x23, "TRAP_IN"),
+    (0x24, "TRAP_PUTSP"),
+    (0x25, "TRAP_HALT"),
+];
+
+const EXCEPTION_HANDLERS: [(u8, &str); 3] = [
+    (PRIVILEGE_VECTOR, "PRIV_VIOLATION"),
+    (ILLEGAL_OPCODE_VECTOR, "ILLEGAL_OPCODE"),
+    (ACCESS_VIOLATION_VECTOR, "ACCESS_VIOLATION"),
+];
+
+// The blocks making up the bundled operating system, as (origin, words):
+// the trap vector table, the interrupt vector table and the code they
+// point into. Vectors with nothing behind them lead to a routine that
+// reports the bad trap, or for interrupts, just returns.
+pub(crate) fn image() -> LC3Result<Vec<(u16, Vec<u16>)>> {
+    let assembly = assemble(SOURCE)?;
+    let trap_table = vector_table(&assembly, "BAD_TRAP", &TRAP_ROUTINES)?;
+    let interrupt_table = vector_table(&assembly, "BAD_INTERRUPT", &EXCEPTION_HANDLERS)?;
+
+    Ok(vec![
+        (TRAP_TABLE, trap_table),
+        (VECTOR_TABLE, interrupt_table),
+        (assembly.origin, assembly.words),
+    ])
+}
+
+fn vector_table(
+    assembly: &Assembly,
+    default: &str,
+    entries: &[(u8, &str)],
+) -> LC3Result<Vec<u16>> {
+    let mut table = vec![address_of(assembly, default)?; TABLE_SIZE];
+    for (vector, label) in entries {
+        table[*vector as usize] = address_of(assembly, label)?;
+    }
+    Ok(table)
+}
+
+fn address_of(assembly: &Assembly, label: &str) -> LC3Result<u16> {
+    assembly
+        .symbols
+        .address_of(label)
+        .ok_or_else(|| LC3Error::Internal(format!("The OS image has no label {}", label)))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::asm::assemble;
+    use crate::error::LC3Result;
+    use crate::io::TestIOHandle;
+    use crate::register::Register::RR2;
+    use crate::vm::{StopReason, VM};
+
+    fn run_with_os(source: &str, io_handle: TestIOHandle) -> LC3Result<VM<TestIOHandle>> {
+        let program = assemble(source)?;
+        let mut vm = VM::new_with_io(io_handle);
+        vm.load_os()?;
+        vm.load_object(&program.to_object())?;
+        let outcome = vm.run()?;
+        assert_eq!(outcome.reason, StopReason::HaltTrap);
+        Ok(vm)
+    }
+
+    fn output(vm: VM<TestIOHandle>) -> String {
+        vm.into_io_handle().get_test_outputs().into_iter().collect()
+    }
+
+    #[test]
+    fn can_write_through_os_traps() -> LC3Result<()> {
+        let vm = run_with_os(
+            r#"
+            .ORIG x3000
+            LEA R0, HELLO
+            PUTS
+            LD R0, BANG
+            OUT
+            LEA R0, PACKED
+            PUTSP
+            HALT
+HELLO       .STRINGZ "Hi"
+BANG        .FILL x0021
+PACKED      .FILL x6261
+            .FILL x0063
+            .FILL x0000
+            .END
+        "#,
+            TestIOHandle::new(),
+        )?;
+
+        assert_eq!(output(vm), "Hi!abc\n--- Halting the LC-3 ---\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_read_through_os_traps() -> LC3Result<()> {
+        let mut io_handle = TestIOHandle::new();
+        io_handle.add_key_press('q');
+        io_handle.add_keydown_response(true);
+
+        let vm = run_with_os(
+            r#"
+            .ORIG x3000
+            GETC
+            ADD R2, R0, #0
+            HALT
+            .END
+        "#,
+            io_handle,
+        )?;
+
+        // HALT is still using R0 when the clock stops
+        assert_eq!(vm.register(RR2), 'q' as u16);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_handle_exceptions_in_os() -> LC3Result<()> {
+        let vm = run_with_os(
+            r#"
+            .ORIG x3000
+            .FILL xD000
+            .END
+        "#,
+            TestIOHandle::new(),
+        )?;
+
+        assert_eq!(
+            output(vm),
+            "\n--- Illegal opcode ---\n\n--- Halting the LC-3 ---\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_run_again_after_reset() -> LC3Result<()> {
+        let mut vm = run_with_os(
+            r#"
+            .ORIG x3000
+            .FILL xD000
+            .END
+        "#,
+            TestIOHandle::new(),
+        )?;
+
+        // The exception needs the supervisor stack back where loading the
+        // OS put it
+        vm.reset(true);
+        assert_eq!(vm.run()?.reason, StopReason::HaltTrap);
+        assert_eq!(
+            output(vm),
+            "\n--- Illegal opcode ---\n\n--- Halting the LC-3 ---\n".repeat(2)
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use thiserror::Error;
+
+use crate::debugger::Condition;
+use crate::error::{LC3Error, LC3Result};
+use crate::io::IOHandle;
+use crate::plugin::{Event, Plugin};
+use crate::symbols::SymbolTable;
+use crate::vm::VmState;
+use crate::wrapping_add;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Checkpoint {
+    // Just before the instruction at this address runs
+    Address(u16),
+    Halt,
+}
+
+impl fmt::Display for Checkpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Checkpoint::Address(address) => write!(f, "x{:04X}", address),
+            Checkpoint::Halt => write!(f, "halt"),
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("Assertion '{condition}' failed at {location}")]
+pub struct AssertionFailure {
+    // The condition as written
+    pub condition: String,
+    // The address of the instruction about to run, or "halt"
+    pub location: String,
+}
+
+// Checks conditions on the machine state as the program runs, written the
+// same way as the debugger's conditional breakpoints. Failures are
+// collected for reading afterwards, or with strict set the first one stops
+// the VM with an error.
+#[derive(Clone, Default)]
+pub struct AssertionPlugin {
+    assertions: Vec<(Checkpoint, Condition)>,
+    symbols: Option<SymbolTable>,
+    strict: bool,
+    failures: Rc<RefCell<Vec<AssertionFailure>>>,
+}
+
+impl AssertionPlugin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Lets conditions added afterwards refer to labels
+    pub fn with_symbols(mut self, symbols: SymbolTable) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    // Check condition each time execution reaches address, before the
+    // instruction there runs
+    pub fn assert_at(self, address: u16, condition: &str) -> LC3Result<Self> {
+        self.add(Checkpoint::Address(address), condition)
+    }
+
+    // Check condition when the program halts
+    pub fn assert_on_halt(self, condition: &str) -> LC3Result<Self> {
+        self.add(Checkpoint::Halt, condition)
+    }
+
+    pub fn failures(&self) -> Vec<AssertionFailure> {
+        self.failures.borrow().clone()
+    }
+
+    fn add(mut self, checkpoint: Checkpoint, condition: &str) -> LC3Result<Self> {
+        let condition = Condition::parse(condition, self.symbols.as_ref()).map_err(|err| {
+            LC3Error::Other(format!("Invalid assertion '{}': {}", condition, err))
+        })?;
+        self.assertions.push((checkpoint, condition));
+        Ok(self)
+    }
+
+    fn check<IOType: IOHandle>(
+        &self,
+        vm: &VmState<IOType>,
+        checkpoint: Checkpoint,
+        pc: u16,
+    ) -> LC3Result<()> {
+        let failed = self
+            .assertions
+            .iter()
+            .filter(|(at, condition)| *at == checkpoint && !condition.evaluate_at(vm, pc));
+        for (_, condition) in failed {
+            let failure = AssertionFailure {
+                condition: condition.source().to_string(),
+                location: checkpoint.to_string(),
+            };
+            if self.strict {
+                return Err(LC3Error::Plugin {
+                    source: Box::new(failure),
+                });
+            }
+            self.failures.borrow_mut().push(failure);
+        }
+        Ok(())
+    }
+}
+
+impl<IOType: IOHandle> Plugin<IOType> for AssertionPlugin {
+    fn handle_event(&mut self, vm: &mut VmState<IOType>, event: &Event) -> LC3Result<()> {
+        match event {
+            Event::Command { .. } => {
+                // The PC has already moved past the instruction
+                let address = wrapping_add!(vm.pc(), 0xFFFF);
+                self.check(vm, Checkpoint::Address(address), address)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn on_halt(&mut self, vm: &mut VmState<IOType>) -> LC3Result<()> {
+        self.check(vm, Checkpoint::Halt, vm.pc())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AssertionFailure, AssertionPlugin};
+    use crate::asm::assemble;
+    use crate::error::{LC3Error, LC3Result};
+    use crate::io::TestIOHandle;
+    use crate::vm::VM;
+
+    const SUM: &str = r#"
+            .ORIG x3000
+            AND R0, R0, #0
+            ADD R1, R0, #4
+LOOP        ADD R0, R0, R1
+            ADD R1, R1, #-1
+            BRp LOOP
+            ST R0, RESULT
+            HALT
+RESULT      .BLKW 1
+            .END
+    "#;
+
+    #[test]
+    fn can_check_assertions() -> LC3Result<()> {
+        let assembly = assemble(SUM)?;
+        let assertions = AssertionPlugin::new()
+            .with_symbols(assembly.symbols.clone())
+            .assert_at(0x3002, "R1 > 0 && PC == LOOP")?
+            .assert_at(0x3005, "R0 == 11")?
+            .assert_on_halt("mem[RESULT] == 10")?;
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.add_plugin(Box::new(assertions.clone()));
+        vm.load_object(&assembly.to_object())?;
+        vm.run()?;
+
+        assert_eq!(
+            assertions.failures(),
+            vec![AssertionFailure {
+                condition: "R0 == 11".to_string(),
+                location: "x3005".to_string()
+            }]
+        );
+
+        let assertions = AssertionPlugin::new().assert_on_halt("R0 == 0")?.strict();
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.add_plugin(Box::new(assertions));
+        vm.load_object(&assembly.to_object())?;
+        let err = vm.run().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Plugin encountered an error: Assertion 'R0 == 0' failed at halt"
+        );
+
+        assert!(matches!(
+            AssertionPlugin::new().assert_on_halt("R0 =="),
+            Err(LC3Error::Other(_))
+        ));
+
+        Ok(())
+    }
+}
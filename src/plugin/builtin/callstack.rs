@@ -0,0 +1,213 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::error::LC3Result;
+use crate::io::IOHandle;
+use crate::plugin::{Event, Plugin};
+use crate::register::Register;
+use crate::symbols::SymbolTable;
+use crate::vm::VmState;
+use crate::wrapping_add;
+
+// JMP R7
+const RET: u16 = 0xC1C0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CallFrame {
+    // First instruction of the subroutine
+    pub entry: u16,
+    // Address of the JSR or JSRR that called it
+    pub call_site: u16,
+}
+
+pub(crate) enum CallChange {
+    Enter,
+    Return(CallFrame),
+}
+
+// A shadow of the program's call stack, built by watching for JSR, JSRR and
+// RET. Subroutines that return some other way, or that are left with a
+// plain jump, will throw it off.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CallStack {
+    frames: Vec<CallFrame>,
+    max_depth: usize,
+    // The JSR or RET currently executing. Where it goes is only known once
+    // it writes the PC, as for a JSRR or RET that comes from a register.
+    pending_call: Option<u16>,
+    pending_return: bool,
+}
+
+impl CallStack {
+    // Outermost call first
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    // The deepest the stack has been since the plugin was added
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    // Innermost first, starting from pc, e.g.
+    //
+    // #0  x3011  in INC
+    // #1  x3007  in DOUBLE
+    // #2  x3002
+    pub fn backtrace(&self, pc: u16, symbols: Option<&SymbolTable>) -> String {
+        let describe = |address: u16| match symbols {
+            Some(symbols) => symbols.describe(address),
+            None => format!("x{:04X}", address),
+        };
+
+        let mut lines = Vec::new();
+        let mut location = pc;
+        for (depth, frame) in self.frames.iter().rev().enumerate() {
+            lines.push(format!(
+                "#{}  x{:04X}  in {}",
+                depth,
+                location,
+                describe(frame.entry)
+            ));
+            location = frame.call_site;
+        }
+        lines.push(format!("#{}  x{:04X}", self.frames.len(), location));
+        lines.join("\n")
+    }
+
+    // Called as each instruction starts, with its address
+    pub(crate) fn instruction_started(&mut self, address: u16, word: u16) {
+        // JSR and JSRR
+        self.pending_call = if word >> 12 == 0b0100 {
+            Some(address)
+        } else {
+            None
+        };
+        self.pending_return = word == RET;
+    }
+
+    // Called when an instruction writes the PC
+    pub(crate) fn pc_written(&mut self, pc: u16) -> Option<CallChange> {
+        if let Some(call_site) = self.pending_call.take() {
+            let frame = CallFrame {
+                entry: pc,
+                call_site,
+            };
+            self.frames.push(frame);
+            self.max_depth = self.max_depth.max(self.frames.len());
+            Some(CallChange::Enter)
+        } else if self.pending_return {
+            self.pending_return = false;
+            // A RET with nothing to return from, e.g. from the program's
+            // entry point, isn't a subroutine we know about
+            self.frames.pop().map(CallChange::Return)
+        } else {
+            None
+        }
+    }
+}
+
+// Keeps a CallStack up to date. Clones share the stack.
+#[derive(Clone, Default)]
+pub struct CallStackPlugin {
+    stack: Rc<RefCell<CallStack>>,
+}
+
+impl CallStackPlugin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn call_stack(&self) -> CallStack {
+        self.stack.borrow().clone()
+    }
+
+    pub(crate) fn restore(&self, stack: CallStack) {
+        *self.stack.borrow_mut() = stack;
+    }
+}
+
+impl<IOType: IOHandle> Plugin<IOType> for CallStackPlugin {
+    fn handle_event(&mut self, vm: &mut VmState<IOType>, event: &Event) -> LC3Result<()> {
+        let mut stack = self.stack.borrow_mut();
+        match event {
+            Event::Command { bytes } => {
+                // The PC has already moved past the instruction
+                stack.instruction_started(wrapping_add!(vm.pc(), 0xFFFF), *bytes);
+            }
+            Event::RegSet { index, value } if *index == Register::RPC.to_u8() => {
+                stack.pc_written(*value);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CallFrame, CallStackPlugin};
+    use crate::asm::assemble;
+    use crate::error::LC3Result;
+    use crate::io::TestIOHandle;
+    use crate::vm::VM;
+
+    #[test]
+    fn can_track_calls() -> LC3Result<()> {
+        let source = r#"
+            .ORIG x3000
+            JSR OUTER
+            HALT
+OUTER       ST R7, SAVE
+            LEA R1, INNER
+            JSRR R1
+            LD R7, SAVE
+            RET
+INNER       ADD R0, R0, #1
+            RET
+SAVE        .BLKW 1
+            .END
+        "#;
+        let assembly = assemble(source)?;
+        let outer = assembly.symbols.address_of("OUTER").unwrap();
+        let inner = assembly.symbols.address_of("INNER").unwrap();
+
+        let calls = CallStackPlugin::new();
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.add_plugin(Box::new(calls.clone()));
+        vm.load_object(&assembly.to_object())?;
+
+        while vm.pc() != inner {
+            vm.step()?;
+        }
+        let stack = calls.call_stack();
+        assert_eq!(
+            stack.frames(),
+            &[
+                CallFrame {
+                    entry: outer,
+                    call_site: 0x3000
+                },
+                CallFrame {
+                    entry: inner,
+                    call_site: 0x3004
+                },
+            ]
+        );
+        assert_eq!(
+            stack.backtrace(vm.pc(), Some(&assembly.symbols)),
+            "#0  x3007  in INNER\n#1  x3004  in OUTER\n#2  x3000"
+        );
+
+        while !vm.step()?.halted {}
+        let stack = calls.call_stack();
+        assert_eq!(stack.depth(), 0);
+        assert_eq!(stack.max_depth(), 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::disasm::disassemble;
+use crate::error::{BoxErrors, LC3Result};
+use crate::io::IOHandle;
+use crate::json::Json;
+use crate::plugin::{Event, Plugin};
+use crate::vm::VmState;
+use crate::wrapping_add;
+
+const REPORT_VERSION: u64 = 1;
+
+// Records which addresses were executed and which way each branch went.
+// Clones share results, so keep one to read them.
+#[derive(Clone, Default)]
+pub struct CoveragePlugin {
+    state: Rc<RefCell<CoverageState>>,
+}
+
+#[derive(Default)]
+struct CoverageState {
+    coverage: Coverage,
+    // Address of the BR that's executing. Whether it was taken is only
+    // known once the next instruction starts.
+    pending_branch: Option<u16>,
+}
+
+impl CoveragePlugin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn coverage(&self) -> Coverage {
+        self.state.borrow().coverage.clone()
+    }
+}
+
+impl<IOType: IOHandle> Plugin<IOType> for CoveragePlugin {
+    fn handle_event(&mut self, vm: &mut VmState<IOType>, event: &Event) -> LC3Result<()> {
+        if let Event::Command { bytes } = event {
+            let mut state = self.state.borrow_mut();
+            // The PC has already moved past the instruction
+            let address = wrapping_add!(vm.pc(), 0xFFFF);
+            if let Some(branch) = state.pending_branch.take() {
+                let taken = address != wrapping_add!(branch, 1);
+                let counts = state.coverage.branches.entry(branch).or_default();
+                if taken {
+                    counts.taken += 1;
+                } else {
+                    counts.not_taken += 1;
+                }
+            }
+
+            *state.coverage.executed.entry(address).or_insert(0) += 1;
+            if bytes >> 12 == 0 {
+                state.pending_branch = Some(address);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BranchCoverage {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl BranchCoverage {
+    pub fn is_covered(&self) -> bool {
+        self.taken > 0 && self.not_taken > 0
+    }
+}
+
+// Results gathered by a CoveragePlugin
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    // Times each executed address ran
+    pub executed: BTreeMap<u16, u64>,
+    // Outcomes of each executed BR. If a BR was the last instruction to
+    // run, that execution can't be counted either way.
+    pub branches: BTreeMap<u16, BranchCoverage>,
+}
+
+impl Coverage {
+    pub fn is_executed(&self, address: u16) -> bool {
+        self.executed.contains_key(&address)
+    }
+
+    // Branches that only ever went one way
+    pub fn partial_branches(&self) -> Vec<u16> {
+        self.branches
+            .iter()
+            .filter(|(_, branch)| !branch.is_covered())
+            .map(|(address, _)| *address)
+            .collect()
+    }
+
+    // A disassembly of an object (origin word first, as for VM::load_object)
+    // with the execution count down the side. Words that never ran are
+    // marked with a -, which for data is expected.
+    //
+    //        3  x3004  x03FD  BRp x3002                 taken 2, not taken 1
+    //        -  x3006  x0000  .FILL x0000
+    pub fn listing(&self, object: &[u16]) -> String {
+        let (origin, words) = match object.split_first() {
+            Some((origin, words)) => (*origin, words),
+            None => return String::new(),
+        };
+
+        let mut lines: Vec<String> = words
+            .iter()
+            .enumerate()
+            .map(|(index, word)| {
+                let address = wrapping_add!(origin, index as u16);
+                let count = match self.executed.get(&address) {
+                    Some(count) => count.to_string(),
+                    None => "-".to_string(),
+                };
+                let line = format!(
+                    "{:>8}  x{:04X}  x{:04X}  {}",
+                    count,
+                    address,
+                    word,
+                    disassemble(address, *word)
+                );
+                match self.branches.get(&address) {
+                    Some(branch) => format!(
+                        "{:<50}taken {}, not taken {}",
+                        line, branch.taken, branch.not_taken
+                    ),
+                    None => line,
+                }
+            })
+            .collect();
+
+        lines.push(String::new());
+        lines.push(format!(
+            "Executed {} addresses, {} of {} branches went both ways",
+            self.executed.len(),
+            self.branches.len() - self.partial_branches().len(),
+            self.branches.len()
+        ));
+        lines.join("\n")
+    }
+
+    // {"version":1,"executed":[{"address":12288,"count":1}],
+    //  "branches":[{"address":12292,"taken":2,"not_taken":1}]}
+    pub fn to_json(&self) -> String {
+        let executed: Vec<Json> = self
+            .executed
+            .iter()
+            .map(|(address, count)| {
+                Json::object(vec![
+                    ("address", (*address).into()),
+                    ("count", (*count).into()),
+                ])
+            })
+            .collect();
+        let branches: Vec<Json> = self
+            .branches
+            .iter()
+            .map(|(address, branch)| {
+                Json::object(vec![
+                    ("address", (*address).into()),
+                    ("taken", branch.taken.into()),
+                    ("not_taken", branch.not_taken.into()),
+                ])
+            })
+            .collect();
+
+        Json::object(vec![
+            ("version", REPORT_VERSION.into()),
+            ("executed", executed.into()),
+            ("branches", branches.into()),
+        ])
+        .to_string()
+    }
+
+    pub fn save_json<P: AsRef<Path>>(&self, path: P) -> LC3Result<()> {
+        std::fs::write(path, self.to_json()).map_io_error()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BranchCoverage, CoveragePlugin};
+    use crate::asm::assemble;
+    use crate::error::LC3Result;
+    use crate::io::TestIOHandle;
+    use crate::vm::VM;
+
+    #[test]
+    fn can_record_coverage() -> LC3Result<()> {
+        let source = r#"
+            .ORIG x3000
+            AND R1, R1, #0
+            ADD R1, R1, #2
+LOOP        ADD R1, R1, #-1
+            BRp LOOP
+            BRn NEGATIVE
+            HALT
+NEGATIVE    NOT R1, R1
+            HALT
+            .END
+        "#;
+        let assembly = assemble(source)?;
+        let object = assembly.to_object();
+
+        let coverage = CoveragePlugin::new();
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.add_plugin(Box::new(coverage.clone()));
+        vm.load_object(&object)?;
+        vm.run()?;
+
+        let coverage = coverage.coverage();
+        assert_eq!(coverage.executed[&0x3002], 2);
+        assert!(!coverage.is_executed(0x3006));
+        assert_eq!(
+            coverage.branches[&0x3003],
+            BranchCoverage {
+                taken: 1,
+                not_taken: 1
+            }
+        );
+        assert_eq!(coverage.partial_branches(), vec![0x3004]);
+
+        let listing = coverage.listing(&object);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(
+            lines[3],
+            "       2  x3003  x03FE  BRp x3002                 taken 1, not taken 1"
+        );
+        assert_eq!(lines[6], "       -  x3006  x927F  NOT R1, R1");
+        assert_eq!(
+            lines.last(),
+            Some(&"Executed 6 addresses, 1 of 2 branches went both ways")
+        );
+
+        assert_eq!(
+            coverage.to_json(),
+            r#"{"version":1,"executed":[{"address":12288,"count":1},{"address":12289,"count":1},{"address":12290,"count":2},{"address":12291,"count":2},{"address":12292,"count":1},{"address":12293,"count":1}],"branches":[{"address":12291,"taken":1,"not_taken":1},{"address":12292,"taken":0,"not_taken":1}]}"#
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use crate::error::{LC3Error, LC3Result};
+use crate::io::IOHandle;
+use crate::plugin::{Event, Plugin};
+use crate::register::Register;
+use crate::vm::VmState;
+
+const HALT: u16 = 0xF025;
+
+// Where the program leaves its exit status
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExitCodeSource {
+    Register(Register),
+    Memory(u16),
+}
+
+// A register from R0 to R7, or an address in hex such as xFE20
+impl FromStr for ExitCodeSource {
+    type Err = LC3Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let register = text
+            .strip_prefix('R')
+            .or_else(|| text.strip_prefix('r'))
+            .and_then(|index| index.parse::<u8>().ok())
+            .filter(|index| *index < 8)
+            .and_then(Register::from_index);
+        if let Some(register) = register {
+            return Ok(Self::Register(register));
+        }
+
+        let digits = text
+            .strip_prefix("0x")
+            .or_else(|| text.strip_prefix('x'))
+            .or_else(|| text.strip_prefix('X'))
+            .unwrap_or(text);
+        u16::from_str_radix(digits, 16)
+            .map(Self::Memory)
+            .map_err(|_| {
+                LC3Error::Other(format!(
+                    "Expected a register from R0 to R7 or an address such as xFE20, not {}",
+                    text
+                ))
+            })
+    }
+}
+
+// Gives a program a way to report whether it passed, by reading a register
+// or word of memory as the program executes HALT. That's before the
+// operating system's HALT routine gets a chance to overwrite it.
+#[derive(Clone)]
+pub struct ExitCode {
+    source: ExitCodeSource,
+    value: Rc<Cell<Option<u16>>>,
+}
+
+impl ExitCode {
+    pub fn new(source: ExitCodeSource) -> Self {
+        Self {
+            source,
+            value: Rc::new(Cell::new(None)),
+        }
+    }
+
+    // The word the program left behind, or None if it hasn't halted
+    pub fn value(&self) -> Option<u16> {
+        self.value.get()
+    }
+
+    // The value as a process exit status, which is only a byte. Anything
+    // above 255 becomes 255 rather than its low byte, so a failure like x0100
+    // can't turn into success.
+    pub fn status(&self) -> Option<u8> {
+        self.value().map(|value| value.min(255) as u8)
+    }
+}
+
+impl<IOType: IOHandle> Plugin<IOType> for ExitCode {
+    fn on_run_start(&mut self, _vm: &mut VmState<IOType>) -> LC3Result<()> {
+        self.value.set(None);
+        Ok(())
+    }
+
+    fn handle_event(&mut self, vm: &mut VmState<IOType>, event: &Event) -> LC3Result<()> {
+        if let Event::PreInstruction { instruction } = event {
+            if instruction.word == HALT {
+                let value = match self.source {
+                    ExitCodeSource::Register(register) => vm.register(register),
+                    ExitCodeSource::Memory(address) => vm.memory_at(address),
+                };
+                self.value.set(Some(value));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ExitCode, ExitCodeSource};
+    use crate::asm::assemble;
+    use crate::error::LC3Result;
+    use crate::io::TestIOHandle;
+    use crate::register::Register;
+    use crate::vm::VM;
+
+    #[test]
+    fn can_read_exit_code_at_halt() -> LC3Result<()> {
+        let source = r#"
+            .ORIG x3000
+            AND R0, R0, #0
+            ADD R0, R0, #3
+            ST R0, CODE
+            HALT
+CODE        .FILL #0
+            .END
+        "#;
+        let object = assemble(source)?.to_object();
+
+        for text in ["R0", "x3004"] {
+            let exit_code = ExitCode::new(text.parse()?);
+            let mut vm = VM::new_with_io(TestIOHandle::new());
+            vm.add_plugin(Box::new(exit_code.clone()));
+            vm.load_object(&object)?;
+            assert_eq!(exit_code.status(), None);
+            vm.run()?;
+            assert_eq!(exit_code.status(), Some(3));
+        }
+
+        // The OS's HALT routine uses R0 to print its message, but the value
+        // from before it ran is kept
+        let exit_code = ExitCode::new(ExitCodeSource::Register(Register::RR0));
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_os()?;
+        vm.add_plugin(Box::new(exit_code.clone()));
+        vm.load_object(&object)?;
+        vm.run()?;
+        assert_eq!(exit_code.status(), Some(3));
+
+        assert!("R8".parse::<ExitCodeSource>().is_err());
+        assert!("xyz".parse::<ExitCodeSource>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn caps_large_exit_codes() -> LC3Result<()> {
+        let object = assemble(
+            r#"
+            .ORIG x3000
+            LD R0, CODE
+            HALT
+CODE        .FILL x0100
+            .END
+        "#,
+        )?
+        .to_object();
+
+        let exit_code = ExitCode::new(ExitCodeSource::Register(Register::RR0));
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.add_plugin(Box::new(exit_code.clone()));
+        vm.load_object(&object)?;
+        vm.run()?;
+        assert_eq!(exit_code.value(), Some(0x0100));
+        assert_eq!(exit_code.status(), Some(255));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::error::{BoxErrors, LC3Result};
+use crate::io::IOHandle;
+use crate::json::Json;
+use crate::plugin::{Event, Plugin};
+use crate::vm::VmState;
+use crate::wrapping_add;
+
+const REPORT_VERSION: u64 = 1;
+
+// Tallies reads and writes of every memory address. Add it after loading
+// the program, or the load counts as writes too.
+#[derive(Clone, Default)]
+pub struct HeatmapPlugin {
+    heatmap: Rc<RefCell<Heatmap>>,
+}
+
+impl HeatmapPlugin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn heatmap(&self) -> Heatmap {
+        self.heatmap.borrow().clone()
+    }
+}
+
+impl<IOType: IOHandle> Plugin<IOType> for HeatmapPlugin {
+    fn handle_event(&mut self, vm: &mut VmState<IOType>, event: &Event) -> LC3Result<()> {
+        let mut heatmap = self.heatmap.borrow_mut();
+        match event {
+            Event::MemGet { location, .. } => heatmap.entry(*location).reads += 1,
+            Event::MemSet { location, .. } => heatmap.entry(*location).writes += 1,
+            Event::Command { .. } => {
+                // The read just before an instruction starts is its fetch,
+                // and the PC has already moved past it
+                let counts = heatmap.entry(wrapping_add!(vm.pc(), 0xFFFF));
+                counts.reads = counts.reads.saturating_sub(1);
+                counts.fetches += 1;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AccessCounts {
+    // Reads by instructions, not counting fetches
+    pub reads: u64,
+    pub writes: u64,
+    // Times the address was fetched as an instruction
+    pub fetches: u64,
+}
+
+// Results gathered by a HeatmapPlugin, for addresses accessed at least once
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Heatmap {
+    pub addresses: BTreeMap<u16, AccessCounts>,
+}
+
+impl Heatmap {
+    fn entry(&mut self, address: u16) -> &mut AccessCounts {
+        self.addresses.entry(address).or_default()
+    }
+
+    pub fn counts(&self, address: u16) -> AccessCounts {
+        self.addresses.get(&address).copied().unwrap_or_default()
+    }
+
+    // Addresses that were both executed and written to. Unless a program
+    // modifies itself on purpose, these are usually stray stores.
+    pub fn code_writes(&self) -> Vec<u16> {
+        self.addresses
+            .iter()
+            .filter(|(_, counts)| counts.fetches > 0 && counts.writes > 0)
+            .map(|(address, _)| *address)
+            .collect()
+    }
+
+    // address,reads,writes,fetches with the address in decimal, one row per
+    // address accessed
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("address,reads,writes,fetches\n");
+        for (address, counts) in &self.addresses {
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                address, counts.reads, counts.writes, counts.fetches
+            ));
+        }
+        csv
+    }
+
+    // {"version":1,"addresses":[{"address":12288,"reads":0,"writes":0,"fetches":1}]}
+    pub fn to_json(&self) -> String {
+        let addresses: Vec<Json> = self
+            .addresses
+            .iter()
+            .map(|(address, counts)| {
+                Json::object(vec![
+                    ("address", (*address).into()),
+                    ("reads", counts.reads.into()),
+                    ("writes", counts.writes.into()),
+                    ("fetches", counts.fetches.into()),
+                ])
+            })
+            .collect();
+
+        Json::object(vec![
+            ("version", REPORT_VERSION.into()),
+            ("addresses", addresses.into()),
+        ])
+        .to_string()
+    }
+
+    pub fn save_csv<P: AsRef<Path>>(&self, path: P) -> LC3Result<()> {
+        std::fs::write(path, self.to_csv()).map_io_error()
+    }
+
+    pub fn save_json<P: AsRef<Path>>(&self, path: P) -> LC3Result<()> {
+        std::fs::write(path, self.to_json()).map_io_error()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AccessCounts, HeatmapPlugin};
+    use crate::asm::assemble;
+    use crate::error::LC3Result;
+    use crate::io::TestIOHandle;
+    use crate::vm::VM;
+
+    #[test]
+    fn can_tally_memory_accesses() -> LC3Result<()> {
+        let source = r#"
+            .ORIG x3000
+            LD R0, VALUE
+            ADD R0, R0, R0
+            ST R0, VALUE
+            ST R0, TARGET
+TARGET      HALT
+VALUE       .FILL #3
+            .END
+        "#;
+        let assembly = assemble(source)?;
+
+        let heatmap = HeatmapPlugin::new();
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_object(&assembly.to_object())?;
+        vm.add_plugin(Box::new(heatmap.clone()));
+        // Storing over the HALT means the program never stops
+        vm.run_with_limit(5)?;
+
+        let heatmap = heatmap.heatmap();
+        assert_eq!(
+            heatmap.counts(0x3005),
+            AccessCounts {
+                reads: 1,
+                writes: 1,
+                fetches: 0
+            }
+        );
+        assert_eq!(
+            heatmap.counts(0x3000),
+            AccessCounts {
+                reads: 0,
+                writes: 0,
+                fetches: 1
+            }
+        );
+        assert_eq!(heatmap.code_writes(), vec![0x3004]);
+
+        let csv = heatmap.to_csv();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows[0], "address,reads,writes,fetches");
+        assert_eq!(rows[5], "12292,0,1,1");
+        assert_eq!(rows[6], "12293,1,1,0");
+        assert!(heatmap
+            .to_json()
+            .ends_with(r#"{"address":12293,"reads":1,"writes":1,"fetches":0}]}"#));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,22 @@
+// Ready made plugins. Most of them are moved into the VM as a clone, so the
+// copy kept by the caller can read results once the program has run.
+mod assertion;
+mod callstack;
+mod coverage;
+mod exitcode;
+mod heatmap;
+mod profiler;
+mod stackcheck;
+mod trace;
+mod watchdog;
+
+pub use super::debuglogger::DebugLogger;
+pub use assertion::{AssertionFailure, AssertionPlugin};
+pub use callstack::{CallFrame, CallStack, CallStackPlugin};
+pub use coverage::{BranchCoverage, Coverage, CoveragePlugin};
+pub use exitcode::{ExitCode, ExitCodeSource};
+pub use heatmap::{AccessCounts, Heatmap, HeatmapPlugin};
+pub use profiler::{Profile, Profiler, SubroutineProfile};
+pub use stackcheck::{StackChecker, StackViolation};
+pub use trace::{TraceFormat, TracePlugin};
+pub use watchdog::{Watchdog, WatchdogError};
@@ -0,0 +1,306 @@
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use super::callstack::{CallChange, CallStack};
+use crate::disasm::disassemble;
+use crate::error::LC3Result;
+use crate::io::IOHandle;
+use crate::op::Op;
+use crate::plugin::{Event, Plugin};
+use crate::register::Register;
+use crate::symbols::SymbolTable;
+use crate::vm::VmState;
+use crate::wrapping_add;
+
+// Counts how often each instruction runs and how long each subroutine takes,
+// in instructions executed. Clones share results, so keep one to read them.
+#[derive(Clone, Default)]
+pub struct Profiler {
+    state: Rc<RefCell<ProfilerState>>,
+}
+
+#[derive(Default)]
+struct ProfilerState {
+    profile: Profile,
+    calls: CallStack,
+    // Timing for each frame in calls. Frames cut short by a halt are
+    // finished here but left in calls.
+    frames: Vec<Frame>,
+}
+
+struct Frame {
+    started: u64,
+    // Instructions spent in subroutines called from this one
+    in_callees: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn profile(&self) -> Profile {
+        self.state.borrow().profile.clone()
+    }
+}
+
+impl<IOType: IOHandle> Plugin<IOType> for Profiler {
+    fn handle_event(&mut self, vm: &mut VmState<IOType>, event: &Event) -> LC3Result<()> {
+        let mut state = self.state.borrow_mut();
+        match event {
+            Event::Command { bytes } => {
+                // The PC has already moved past the instruction
+                let address = wrapping_add!(vm.pc(), 0xFFFF);
+                state.calls.instruction_started(address, *bytes);
+                state.profile.record(address, *bytes);
+            }
+            Event::RegSet { index, value } if *index == Register::RPC.to_u8() => {
+                match state.calls.pc_written(*value) {
+                    Some(CallChange::Enter) => state.enter(),
+                    Some(CallChange::Return(frame)) => state.leave(frame.entry),
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn on_halt(&mut self, _vm: &mut VmState<IOType>) -> LC3Result<()> {
+        // Anything still running when the program stops is cut short
+        let mut state = self.state.borrow_mut();
+        let open: Vec<u16> = state
+            .calls
+            .frames()
+            .iter()
+            .map(|frame| frame.entry)
+            .collect();
+        for entry in open.into_iter().rev() {
+            state.leave(entry);
+        }
+        Ok(())
+    }
+}
+
+impl ProfilerState {
+    fn enter(&mut self) {
+        self.frames.push(Frame {
+            started: self.profile.instructions,
+            in_callees: 0,
+        });
+    }
+
+    fn leave(&mut self, entry: u16) {
+        let frame = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+
+        let total = self.profile.instructions - frame.started;
+        let subroutine = self.profile.subroutines.entry(entry).or_default();
+        subroutine.calls += 1;
+        // Recursive calls are already counted by the outer call, which is
+        // still on the stack
+        if !self.calls.frames()[..self.frames.len()]
+            .iter()
+            .any(|outer| outer.entry == entry)
+        {
+            subroutine.instructions += total;
+        }
+        subroutine.self_instructions += total - frame.in_callees;
+
+        if let Some(caller) = self.frames.last_mut() {
+            caller.in_callees += total;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SubroutineProfile {
+    pub calls: u64,
+    // Everything executed from the first instruction of the subroutine up
+    // to and including its RET, across all calls
+    pub instructions: u64,
+    // As above, but leaving out time spent in subroutines it called
+    pub self_instructions: u64,
+}
+
+// Results gathered by a Profiler
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    pub instructions: u64,
+    // Executions and the instruction word for each address that ran
+    pub addresses: BTreeMap<u16, (u64, u16)>,
+    // Subroutines by entry address
+    pub subroutines: BTreeMap<u16, SubroutineProfile>,
+    op_counts: [u64; 16],
+}
+
+impl Profile {
+    fn record(&mut self, address: u16, word: u16) {
+        self.instructions += 1;
+        let entry = self.addresses.entry(address).or_insert((0, word));
+        entry.0 += 1;
+        entry.1 = word;
+        self.op_counts[(word >> 12) as usize] += 1;
+    }
+
+    pub fn executions_at(&self, address: u16) -> u64 {
+        self.addresses
+            .get(&address)
+            .map(|(count, _)| *count)
+            .unwrap_or(0)
+    }
+
+    // Opcodes that ran, most frequent first
+    pub fn op_counts(&self) -> Vec<(Op, u64)> {
+        let mut counts: Vec<(Op, u64)> = self
+            .op_counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .filter_map(|(code, count)| Op::from_int(code as u8).ok().map(|op| (op, *count)))
+            .collect();
+        counts.sort_by_key(|(_, count)| Reverse(*count));
+        counts
+    }
+
+    // Addresses that ran, most frequent first, ties in address order
+    pub fn hot_spots(&self) -> Vec<(u16, u64)> {
+        let mut spots: Vec<(u16, u64)> = self
+            .addresses
+            .iter()
+            .map(|(address, (count, _))| (*address, *count))
+            .collect();
+        spots.sort_by_key(|(_, count)| Reverse(*count));
+        spots
+    }
+
+    // A plain text summary for printing after a run. Labels from symbols
+    // are used for subroutine names where available.
+    pub fn report(&self, symbols: Option<&SymbolTable>) -> String {
+        const HOT_SPOTS: usize = 20;
+        let describe = |address: u16| match symbols {
+            Some(symbols) => symbols.describe(address),
+            None => format!("x{:04X}", address),
+        };
+
+        let mut lines = vec![format!("Instructions executed: {}", self.instructions)];
+
+        lines.push(String::new());
+        lines.push("Hot spots:".to_string());
+        lines.push(format!("{:>10}  {:<8}  instruction", "count", "address"));
+        for (address, count) in self.hot_spots().into_iter().take(HOT_SPOTS) {
+            let word = self.addresses[&address].1;
+            lines.push(format!(
+                "{:>10}  x{:04X}     {}",
+                count,
+                address,
+                disassemble(address, word)
+            ));
+        }
+
+        lines.push(String::new());
+        lines.push("Opcodes:".to_string());
+        for (op, count) in self.op_counts() {
+            lines.push(format!("{:>10}  {:?}", count, op));
+        }
+
+        if !self.subroutines.is_empty() {
+            let mut subroutines: Vec<(&u16, &SubroutineProfile)> =
+                self.subroutines.iter().collect();
+            subroutines.sort_by_key(|(_, subroutine)| Reverse(subroutine.instructions));
+
+            lines.push(String::new());
+            lines.push("Subroutines:".to_string());
+            lines.push(format!(
+                "{:>10}  {:>10}  {:>10}  subroutine",
+                "calls", "total", "self"
+            ));
+            for (entry, subroutine) in subroutines {
+                lines.push(format!(
+                    "{:>10}  {:>10}  {:>10}  {}",
+                    subroutine.calls,
+                    subroutine.instructions,
+                    subroutine.self_instructions,
+                    describe(*entry)
+                ));
+            }
+        }
+
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Profiler, SubroutineProfile};
+    use crate::asm::assemble;
+    use crate::error::LC3Result;
+    use crate::io::TestIOHandle;
+    use crate::op::Op;
+    use crate::vm::VM;
+
+    #[test]
+    fn can_profile_program() -> LC3Result<()> {
+        let source = r#"
+            .ORIG x3000
+            AND R1, R1, #0
+            ADD R1, R1, #3
+LOOP        JSR DOUBLE
+            ADD R1, R1, #-1
+            BRp LOOP
+            HALT
+DOUBLE      ST R7, SAVE
+            JSR INC
+            ADD R0, R0, R0
+            LD R7, SAVE
+            RET
+INC         ADD R0, R0, #1
+            RET
+SAVE        .BLKW 1
+            .END
+        "#;
+        let assembly = assemble(source)?;
+
+        let profiler = Profiler::new();
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.add_plugin(Box::new(profiler.clone()));
+        vm.load_object(&assembly.to_object())?;
+        vm.run()?;
+
+        let profile = profiler.profile();
+        assert_eq!(profile.instructions, 3 + 3 * 3 + 3 * 5 + 3 * 2);
+        assert_eq!(profile.executions_at(0x3002), 3);
+        assert_eq!(profile.executions_at(0x3000), 1);
+        assert_eq!(profile.op_counts()[0], (Op::Add, 10));
+
+        let double = assembly.symbols.address_of("DOUBLE").unwrap();
+        let inc = assembly.symbols.address_of("INC").unwrap();
+        assert_eq!(
+            profile.subroutines[&double],
+            SubroutineProfile {
+                calls: 3,
+                instructions: 21,
+                self_instructions: 15
+            }
+        );
+        assert_eq!(
+            profile.subroutines[&inc],
+            SubroutineProfile {
+                calls: 3,
+                instructions: 6,
+                self_instructions: 6
+            }
+        );
+
+        let report = profile.report(Some(&assembly.symbols));
+        assert!(report.starts_with("Instructions executed: 33\n"));
+        assert!(report.contains("         3          21          15  DOUBLE"));
+
+        Ok(())
+    }
+}
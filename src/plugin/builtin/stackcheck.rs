@@ -0,0 +1,247 @@
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+use thiserror::Error;
+
+use super::callstack::{CallChange, CallStack};
+use crate::error::{LC3Error, LC3Result};
+use crate::io::IOHandle;
+use crate::plugin::{Event, Plugin};
+use crate::register::Register;
+use crate::vm::VmState;
+use crate::wrapping_add;
+
+// A mistake in how the program used R6. pc is the address of the
+// instruction that made it.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum StackViolation {
+    #[error("Stack overflow at x{pc:04X}: R6 moved to x{sp:04X}, {reason}")]
+    Overflow { pc: u16, sp: u16, reason: String },
+    #[error(
+        "Stack underflow at x{pc:04X}: R6 moved to x{sp:04X}, above the stack base x{base:04X}"
+    )]
+    Underflow { pc: u16, sp: u16, base: u16 },
+    #[error(
+        "Unbalanced stack in subroutine x{entry:04X}: R6 was x{before:04X} when it was called \
+         but x{after:04X} when it returned at x{pc:04X}"
+    )]
+    Unbalanced {
+        pc: u16,
+        entry: u16,
+        before: u16,
+        after: u16,
+    },
+}
+
+// Treats R6 as a stack pointer growing downwards and watches for the usual
+// ways of getting it wrong:
+//
+// - overflow, when R6 moves onto an instruction that has already been
+//   executed, or below a limit set with with_limit
+// - underflow, when R6 moves above the stack base. That's the first value
+//   the program puts in R6 unless set with with_base.
+// - unbalanced subroutines, where R6 isn't the same at RET as it was at
+//   the JSR or JSRR that called it
+//
+// Violations are collected for reading afterwards, or with strict set the
+// first one stops the VM with an error.
+#[derive(Clone, Default)]
+pub struct StackChecker {
+    state: Rc<RefCell<CheckerState>>,
+    limit: Option<u16>,
+    base: Option<u16>,
+    strict: bool,
+}
+
+#[derive(Default)]
+struct CheckerState {
+    violations: Vec<StackViolation>,
+    // Address of the instruction currently executing
+    pc: u16,
+    executed: BTreeSet<u16>,
+    base: Option<u16>,
+    calls: CallStack,
+    // R6 when each frame in calls was entered
+    entry_sps: Vec<u16>,
+}
+
+impl StackChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The lowest address the stack may use
+    pub fn with_limit(mut self, limit: u16) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    // The highest value R6 may hold, usually the empty stack's R6
+    pub fn with_base(mut self, base: u16) -> Self {
+        self.base = Some(base);
+        self
+    }
+
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    pub fn violations(&self) -> Vec<StackViolation> {
+        self.state.borrow().violations.clone()
+    }
+
+    fn stack_pointer_set(&self, state: &mut CheckerState, sp: u16) -> Option<StackViolation> {
+        let pc = state.pc;
+        let base = *state.base.get_or_insert(self.base.unwrap_or(sp));
+        if sp > base {
+            return Some(StackViolation::Underflow { pc, sp, base });
+        }
+
+        let reason = match self.limit {
+            Some(limit) if sp < limit => format!("below the stack limit x{:04X}", limit),
+            _ if state.executed.contains(&sp) => "onto code that has already run".to_string(),
+            _ => return None,
+        };
+        Some(StackViolation::Overflow { pc, sp, reason })
+    }
+}
+
+impl<IOType: IOHandle> Plugin<IOType> for StackChecker {
+    fn handle_event(&mut self, vm: &mut VmState<IOType>, event: &Event) -> LC3Result<()> {
+        let mut state = self.state.borrow_mut();
+        let violation = match event {
+            Event::Command { bytes } => {
+                // The PC has already moved past the instruction
+                let address = wrapping_add!(vm.pc(), 0xFFFF);
+                state.pc = address;
+                state.executed.insert(address);
+                state.calls.instruction_started(address, *bytes);
+                None
+            }
+            Event::RegSet { index, value } if *index == Register::RR6.to_u8() => {
+                self.stack_pointer_set(&mut state, *value)
+            }
+            Event::RegSet { index, value } if *index == Register::RPC.to_u8() => {
+                let sp = vm.register(Register::RR6);
+                match state.calls.pc_written(*value) {
+                    Some(CallChange::Enter) => {
+                        state.entry_sps.push(sp);
+                        None
+                    }
+                    Some(CallChange::Return(frame)) => match state.entry_sps.pop() {
+                        Some(before) if before != sp => Some(StackViolation::Unbalanced {
+                            pc: state.pc,
+                            entry: frame.entry,
+                            before,
+                            after: sp,
+                        }),
+                        _ => None,
+                    },
+                    None => None,
+                }
+            }
+            _ => None,
+        };
+
+        if let Some(violation) = violation {
+            if self.strict {
+                return Err(LC3Error::Plugin {
+                    source: Box::new(violation),
+                });
+            }
+            state.violations.push(violation);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{StackChecker, StackViolation};
+    use crate::asm::assemble;
+    use crate::error::{LC3Error, LC3Result};
+    use crate::io::TestIOHandle;
+    use crate::vm::VM;
+
+    fn check(source: &str, checker: &StackChecker) -> LC3Result<()> {
+        let assembly = assemble(source)?;
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_object(&assembly.to_object())?;
+        vm.add_plugin(Box::new(checker.clone()));
+        vm.run_with_limit(1000)?;
+        Ok(())
+    }
+
+    #[test]
+    fn can_check_balanced_stack() -> LC3Result<()> {
+        let source = r#"
+            .ORIG x3000
+            LD R6, STACK
+            JSR PUSH_POP
+            HALT
+PUSH_POP    ADD R6, R6, #-1
+            STR R7, R6, #0
+            LDR R7, R6, #0
+            ADD R6, R6, #1
+            RET
+STACK       .FILL x4000
+            .END
+        "#;
+        let checker = StackChecker::new();
+        check(source, &checker)?;
+        assert_eq!(checker.violations(), vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn can_find_stack_violations() -> LC3Result<()> {
+        let source = r#"
+            .ORIG x3000
+            LD R6, STACK
+            JSR LEAKY
+            ADD R6, R6, #2
+            LEA R6, LEAKY
+            HALT
+LEAKY       ADD R6, R6, #-1
+            RET
+STACK       .FILL x4000
+            .END
+        "#;
+        let checker = StackChecker::new();
+        check(source, &checker)?;
+        assert_eq!(
+            checker.violations(),
+            vec![
+                StackViolation::Unbalanced {
+                    pc: 0x3006,
+                    entry: 0x3005,
+                    before: 0x4000,
+                    after: 0x3FFF
+                },
+                StackViolation::Underflow {
+                    pc: 0x3002,
+                    sp: 0x4001,
+                    base: 0x4000
+                },
+                StackViolation::Overflow {
+                    pc: 0x3003,
+                    sp: 0x3005,
+                    reason: "onto code that has already run".to_string()
+                },
+            ]
+        );
+
+        let checker = StackChecker::new().with_limit(0x3FFF).strict();
+        let err = check(source, &checker).unwrap_err();
+        assert!(matches!(err, LC3Error::Plugin { .. }));
+        assert_eq!(
+            err.to_string(),
+            "Plugin encountered an error: Unbalanced stack in subroutine x3005: R6 was x4000 \
+             when it was called but x3FFF when it returned at x3006"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::error::{BoxErrors, LC3Result};
+use crate::instruction::{DecodedInstruction, Effects};
+use crate::io::IOHandle;
+use crate::json::Json;
+use crate::plugin::{Event, Plugin};
+use crate::register::Register;
+use crate::symbols::SymbolTable;
+use crate::vm::VmState;
+
+const TRACED_REGISTERS: [Register; 8] = [
+    Register::RR0,
+    Register::RR1,
+    Register::RR2,
+    Register::RR3,
+    Register::RR4,
+    Register::RR5,
+    Register::RR6,
+    Register::RR7,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    // Aligned columns for reading:
+    //
+    // x3000  x1025  ADD R0, R0, #5      R0=x0005 R1=x0000 ... R7=x0000 PC=x3001 CC=P
+    Text,
+    // One JSON object per line for other tools. Fields always appear in this
+    // order, and new ones will only ever be added at the end:
+    //
+    // {"step":0,"address":12288,"word":4133,"disassembly":"ADD R0, R0, #5",
+    //  "registers":[5,0,0,0,0,0,0,0],"pc":12289,"cc":"P",
+    //  "writes":[{"address":12294,"value":5}]}
+    //
+    // step counts from 0, registers holds R0 to R7 and writes lists every
+    // memory write the instruction made, in order. With symbols, label
+    // follows with the instruction's label, or null if it doesn't have one.
+    JsonLines,
+}
+
+// Writes a line per executed instruction with the registers it left behind
+pub struct TracePlugin<Sink: Write> {
+    sink: Sink,
+    format: TraceFormat,
+    symbols: Option<SymbolTable>,
+    steps: u64,
+}
+
+impl<Sink: Write> TracePlugin<Sink> {
+    pub fn new(sink: Sink) -> Self {
+        Self::with_format(sink, TraceFormat::Text)
+    }
+
+    pub fn with_format(sink: Sink, format: TraceFormat) -> Self {
+        Self {
+            sink,
+            format,
+            symbols: None,
+            steps: 0,
+        }
+    }
+
+    // Labels each instruction that has one, in a column before the address
+    // for text traces
+    pub fn with_symbols(mut self, symbols: SymbolTable) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    fn text_line<IOType: IOHandle>(
+        &self,
+        vm: &VmState<IOType>,
+        instruction: &DecodedInstruction,
+        effects: &Effects,
+    ) -> String {
+        let registers: Vec<String> = TRACED_REGISTERS
+            .iter()
+            .map(|register| format!("{}=x{:04X}", register, vm.register(*register)))
+            .collect();
+        let line = format!(
+            "x{:04X}  x{:04X}  {:<18}  {} PC=x{:04X} CC={}",
+            instruction.address,
+            instruction.word,
+            instruction.to_string(),
+            registers.join(" "),
+            effects.pc,
+            effects.flags
+        );
+        match &self.symbols {
+            Some(symbols) => {
+                let label = symbols.label_at(instruction.address).unwrap_or("");
+                format!("{:<12}  {}", label, line)
+            }
+            None => line,
+        }
+    }
+
+    fn json_line<IOType: IOHandle>(
+        &self,
+        vm: &VmState<IOType>,
+        instruction: &DecodedInstruction,
+        effects: &Effects,
+    ) -> String {
+        let registers: Vec<Json> = TRACED_REGISTERS
+            .iter()
+            .map(|register| vm.register(*register).into())
+            .collect();
+        let writes: Vec<Json> = effects
+            .memory_written
+            .iter()
+            .map(|(address, value)| {
+                Json::object(vec![
+                    ("address", (*address).into()),
+                    ("value", (*value).into()),
+                ])
+            })
+            .collect();
+
+        let mut fields = vec![
+            ("step", self.steps.into()),
+            ("address", instruction.address.into()),
+            ("word", instruction.word.into()),
+            ("disassembly", instruction.to_string().into()),
+            ("registers", registers.into()),
+            ("pc", effects.pc.into()),
+            ("cc", effects.flags.to_string().into()),
+            ("writes", writes.into()),
+        ];
+        if let Some(symbols) = &self.symbols {
+            let label = symbols.label_at(instruction.address);
+            fields.push(("label", label.map_or(Json::Null, Json::from)));
+        }
+        Json::object(fields).to_string()
+    }
+}
+
+impl TracePlugin<BufWriter<File>> {
+    // Creates or truncates the file at path. The output is buffered and
+    // flushed when the plugin is dropped along with the VM.
+    pub fn to_file<P: AsRef<Path>>(path: P) -> LC3Result<Self> {
+        let file = File::create(path).map_plugin_error()?;
+        Ok(Self::new(BufWriter::new(file)))
+    }
+}
+
+impl<Sink: Write + 'static, IOType: IOHandle> Plugin<IOType> for TracePlugin<Sink> {
+    // Ahead of anything that might change registers or memory
+    fn priority(&self) -> i32 {
+        100
+    }
+
+    fn handle_event(&mut self, vm: &mut VmState<IOType>, event: &Event) -> LC3Result<()> {
+        if let Event::PostInstruction {
+            instruction,
+            effects,
+        } = event
+        {
+            let line = match self.format {
+                TraceFormat::Text => self.text_line(vm, instruction, effects),
+                TraceFormat::JsonLines => self.json_line(vm, instruction, effects),
+            };
+            self.steps += 1;
+            writeln!(self.sink, "{}", line).map_plugin_error()?;
+        }
+
+        Ok(())
+    }
+
+    fn on_halt(&mut self, _vm: &mut VmState<IOType>) -> LC3Result<()> {
+        self.sink.flush().map_plugin_error()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::io::Write;
+    use std::rc::Rc;
+
+    use super::{TraceFormat, TracePlugin};
+    use crate::asm::assemble;
+    use crate::error::LC3Result;
+    use crate::io::TestIOHandle;
+    use crate::json::Json;
+    use crate::vm::VM;
+
+    #[derive(Clone, Default)]
+    struct SharedSink(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn can_trace_instructions() -> LC3Result<()> {
+        let program = vec![
+            // Add 5 to RR0 and put the result in RR0
+            0b0001_0000_0010_0101,
+            // Add -6 to RR0
+            0b0001_0000_0011_1010,
+            // Halt
+            0xF025,
+        ];
+
+        let sink = SharedSink::default();
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.add_plugin(Box::new(TracePlugin::new(sink.clone())));
+        vm.load_program(&program)?;
+        vm.run()?;
+
+        let output = String::from_utf8(sink.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines[..2],
+            [
+                "x3000  x1025  ADD R0, R0, #5      R0=x0005 R1=x0000 R2=x0000 R3=x0000 \
+                 R4=x0000 R5=x0000 R6=x0000 R7=x0000 PC=x3001 CC=P",
+                "x3001  x103A  ADD R0, R0, #-6     R0=xFFFF R1=x0000 R2=x0000 R3=x0000 \
+                 R4=x0000 R5=x0000 R6=x0000 R7=x0000 PC=x3002 CC=N",
+            ]
+        );
+        assert_eq!(lines.len(), 3);
+        assert!(lines[2].starts_with("x3002  xF025  HALT"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_trace_as_json_lines() -> LC3Result<()> {
+        let source = r#"
+            .ORIG x3000
+            AND R0, R0, #0
+            ADD R0, R0, #7
+            ST R0, RESULT
+            HALT
+RESULT      .BLKW 1
+            .END
+        "#;
+        let assembly = assemble(source)?;
+
+        let sink = SharedSink::default();
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.add_plugin(Box::new(TracePlugin::with_format(
+            sink.clone(),
+            TraceFormat::JsonLines,
+        )));
+        vm.load_object(&assembly.to_object())?;
+        vm.run()?;
+
+        let output = String::from_utf8(sink.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[2],
+            r#"{"step":2,"address":12290,"word":12289,"disassembly":"ST R0, x3004","registers":[7,0,0,0,0,0,0,0],"pc":12291,"cc":"P","writes":[{"address":12292,"value":7}]}"#
+        );
+
+        for (step, line) in lines.iter().enumerate() {
+            let json = Json::parse(line).unwrap();
+            assert_eq!(json.get("step").and_then(Json::as_u64), Some(step as u64));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_label_traces() -> LC3Result<()> {
+        let source = r#"
+            .ORIG x3000
+START       AND R0, R0, #0
+            ADD R0, R0, #1
+DONE        HALT
+            .END
+        "#;
+        let assembly = assemble(source)?;
+        let object = assembly.to_object();
+
+        let sink = SharedSink::default();
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        let plugin = TracePlugin::new(sink.clone()).with_symbols(assembly.symbols.clone());
+        vm.add_plugin(Box::new(plugin));
+        vm.load_object(&object)?;
+        vm.run()?;
+
+        let output = String::from_utf8(sink.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].starts_with("START         x3000  x5020  AND R0, R0, #0"));
+        assert!(lines[1].starts_with("              x3001  x1021  ADD R0, R0, #1"));
+        assert!(lines[2].starts_with("DONE          x3002  xF025  HALT"));
+
+        let sink = SharedSink::default();
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        let plugin = TracePlugin::with_format(sink.clone(), TraceFormat::JsonLines)
+            .with_symbols(assembly.symbols);
+        vm.add_plugin(Box::new(plugin));
+        vm.load_object(&object)?;
+        vm.run()?;
+
+        let output = String::from_utf8(sink.0.borrow().clone()).unwrap();
+        let labels: Vec<Json> = output
+            .lines()
+            .map(|line| Json::parse(line).unwrap().get("label").unwrap().clone())
+            .collect();
+        assert_eq!(
+            labels,
+            vec![Json::from("START"), Json::Null, Json::from("DONE")]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use crate::error::{LC3Error, LC3Result};
+use crate::io::IOHandle;
+use crate::plugin::{Event, Plugin};
+use crate::vm::VmState;
+use crate::wrapping_add;
+
+// Reading the clock for every instruction is noticeably slow
+const CLOCK_CHECK_INTERVAL: u64 = 1024;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum WatchdogError {
+    #[error("Program executed more than {limit} instructions")]
+    InstructionLimit { limit: u64 },
+    #[error("Program ran for longer than {limit:?}")]
+    Timeout { limit: Duration },
+    #[error("Program is stuck in an infinite loop at x{pc:04X}")]
+    Stuck { pc: u16 },
+}
+
+// Stops programs that are never going to halt by failing the run with a
+// WatchdogError. An instruction that branches or jumps to itself can only
+// get out again through an interrupt, so that's caught unless ignore_stuck
+// is set. Limits on the number of instructions and on running time can be
+// added, and apply to each run.
+#[derive(Debug, Clone, Default)]
+pub struct Watchdog {
+    max_instructions: Option<u64>,
+    timeout: Option<Duration>,
+    ignore_stuck: bool,
+    instructions: u64,
+    started: Option<Instant>,
+    last_address: Option<u16>,
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_instructions(mut self, limit: u64) -> Self {
+        self.max_instructions = Some(limit);
+        self
+    }
+
+    pub fn timeout(mut self, limit: Duration) -> Self {
+        self.timeout = Some(limit);
+        self
+    }
+
+    // Programs waiting for an interrupt often branch to themselves on
+    // purpose, so for those only the limits apply
+    pub fn ignore_stuck(mut self) -> Self {
+        self.ignore_stuck = true;
+        self
+    }
+
+    fn check(&mut self, address: u16) -> Result<(), WatchdogError> {
+        if !self.ignore_stuck && self.last_address == Some(address) {
+            return Err(WatchdogError::Stuck { pc: address });
+        }
+        self.last_address = Some(address);
+
+        self.instructions += 1;
+        if let Some(limit) = self.max_instructions {
+            if self.instructions > limit {
+                return Err(WatchdogError::InstructionLimit { limit });
+            }
+        }
+
+        if let Some(limit) = self.timeout {
+            let started = *self.started.get_or_insert_with(Instant::now);
+            if self.instructions.is_multiple_of(CLOCK_CHECK_INTERVAL) && started.elapsed() > limit {
+                return Err(WatchdogError::Timeout { limit });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<IOType: IOHandle> Plugin<IOType> for Watchdog {
+    fn handle_event(&mut self, vm: &mut VmState<IOType>, event: &Event) -> LC3Result<()> {
+        match event {
+            Event::Command { .. } => {
+                // The PC has already moved past the instruction
+                let address = wrapping_add!(vm.pc(), 0xFFFF);
+                self.check(address).map_err(|err| LC3Error::Plugin {
+                    source: Box::new(err),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn on_run_start(&mut self, _vm: &mut VmState<IOType>) -> LC3Result<()> {
+        self.instructions = 0;
+        self.started = None;
+        self.last_address = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Watchdog;
+    use crate::error::LC3Result;
+    use crate::io::TestIOHandle;
+    use crate::vm::VM;
+
+    #[test]
+    fn can_stop_runaway_programs() -> LC3Result<()> {
+        let test_cases = vec![
+            // AND R0, R0, #0 then BRnzp to itself
+            (
+                vec![0x5020, 0x0FFF],
+                Watchdog::new(),
+                "Program is stuck in an infinite loop at x3001",
+            ),
+            // ADD R0, R0, #1 then BRnzp back to it
+            (
+                vec![0x1021, 0x0FFE],
+                Watchdog::new().max_instructions(100),
+                "Program executed more than 100 instructions",
+            ),
+        ];
+
+        for (program, watchdog, expected) in test_cases {
+            let mut vm = VM::new_with_io(TestIOHandle::new());
+            vm.add_plugin(Box::new(watchdog));
+            vm.load_program(&program)?;
+            let err = vm.run().unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("Plugin encountered an error: {}", expected)
+            );
+        }
+
+        // Branching to itself only counts against the limit when allowed
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        let watchdog = Watchdog::new().ignore_stuck().max_instructions(100);
+        vm.add_plugin(Box::new(watchdog));
+        vm.load_program(&vec![0x5020, 0x0FFF])?;
+        let err = vm.run().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Plugin encountered an error: Program executed more than 100 instructions"
+        );
+
+        // Programs that halt in time are left alone
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.add_plugin(Box::new(Watchdog::new().max_instructions(2)));
+        vm.load_program(&vec![0x1021, 0xF025])?;
+        vm.run()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+use super::PluginId;
+
+// A message is whatever the publisher sent. Subscribers downcast it to the
+// type they expect for the topic, and ignore it if it's something else.
+pub type Message = Rc<dyn Any>;
+
+// Topic based publish/subscribe between plugins, run by the VM. Messages
+// published while the plugins are busy handling an event are queued and
+// delivered as soon as they're free again.
+#[derive(Default)]
+pub(crate) struct MessageBus {
+    subscriptions: HashMap<String, Vec<PluginId>>,
+    queue: VecDeque<(String, Message)>,
+}
+
+impl MessageBus {
+    pub(crate) fn subscribe(&mut self, id: PluginId, topic: &str) {
+        let subscribers = self.subscriptions.entry(topic.to_string()).or_default();
+        if !subscribers.contains(&id) {
+            subscribers.push(id);
+        }
+    }
+
+    pub(crate) fn unsubscribe(&mut self, id: PluginId, topic: &str) {
+        if let Some(subscribers) = self.subscriptions.get_mut(topic) {
+            subscribers.retain(|subscriber| *subscriber != id);
+        }
+    }
+
+    // Drop every subscription the plugin has, for when it's removed
+    pub(crate) fn forget(&mut self, id: PluginId) {
+        for subscribers in self.subscriptions.values_mut() {
+            subscribers.retain(|subscriber| *subscriber != id);
+        }
+    }
+
+    pub(crate) fn publish(&mut self, topic: &str, message: Message) {
+        self.queue.push_back((topic.to_string(), message));
+    }
+
+    // The next queued message along with who should get it
+    pub(crate) fn next(&mut self) -> Option<(String, Message, Vec<PluginId>)> {
+        let (topic, message) = self.queue.pop_front()?;
+        let subscribers = self.subscriptions.get(&topic).cloned().unwrap_or_default();
+        Some((topic, message, subscribers))
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.queue.clear();
+    }
+}
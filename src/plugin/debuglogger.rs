@@ -5,7 +5,7 @@ use crate::command::Command;
 use crate::error::{BoxErrors, LC3Result};
 use crate::io::IOHandle;
 use crate::op::Op;
-use crate::vm::VM;
+use crate::vm::VmState;
 
 pub struct DebugLogger<Sink: Write> {
     sink: Sink,
@@ -22,8 +22,8 @@ impl<Sink: Write> DebugLogger<Sink> {
     }
 }
 
-impl<Sink: Write, IOType: IOHandle> Plugin<IOType> for DebugLogger<Sink> {
-    fn handle_event(&mut self, _: &mut VM<IOType>, event: &Event) -> LC3Result<()> {
+impl<Sink: Write + 'static, IOType: IOHandle> Plugin<IOType> for DebugLogger<Sink> {
+    fn handle_event(&mut self, _: &mut VmState<IOType>, event: &Event) -> LC3Result<()> {
         self.sink
             .write(debug_format(event)?.as_bytes())
             .map_plugin_error()?;
@@ -0,0 +1,220 @@
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+
+use super::set::discard_pending;
+use super::{Event, EventDecision, Plugin, PluginId, PluginSet};
+use crate::error::{LC3Error, LC3Result};
+use crate::io::IOHandle;
+use crate::state::VmState;
+
+// How many levels of events raised by plugins handling other events get
+// delivered
+const DEFAULT_MAX_EVENT_DEPTH: usize = 4;
+
+// Stops plugins that keep answering each other's messages from hanging the VM
+const MAX_MESSAGES_IN_A_ROW: usize = 1024;
+
+struct PluginEntry<IOType: IOHandle> {
+    id: PluginId,
+    priority: i32,
+    plugin: Box<dyn Plugin<IOType>>,
+}
+
+// Owns the plugins attached to a VM and delivers events, lifecycle calls and
+// messages to them. Keeping the plugins apart from the VmState they're
+// handed means the plugins can be iterated over while each one changes the
+// machine, with no need to take them out of the VM first.
+//
+// Plugins can't reach the manager, so events they cause by changing the
+// machine can't start another round of notifications while one is in
+// progress. Instead they're collected in the VmState and delivered once
+// every plugin has seen the current event, and the events those cause are
+// delivered in turn, down to max_event_depth levels. Past that they're
+// dropped, which stops two plugins fighting over a register from looping
+// forever.
+//
+// This is the VM's default PluginSet.
+pub struct PluginManager<IOType: IOHandle> {
+    plugins: Vec<PluginEntry<IOType>>,
+    next_id: u64,
+    max_event_depth: usize,
+}
+
+impl<IOType: IOHandle> PluginManager<IOType> {
+    pub(crate) fn new() -> Self {
+        Self {
+            plugins: Vec::new(),
+            next_id: 0,
+            max_event_depth: DEFAULT_MAX_EVENT_DEPTH,
+        }
+    }
+
+    pub(crate) fn add(
+        &mut self,
+        state: &mut VmState<IOType>,
+        mut plugin: Box<dyn Plugin<IOType>>,
+    ) -> PluginId {
+        let id = PluginId(self.next_id);
+        self.next_id += 1;
+        plugin.on_attach(state, id);
+        let priority = plugin.priority();
+        self.plugins.push(PluginEntry {
+            id,
+            priority,
+            plugin,
+        });
+        // The sort is stable, so insertion order breaks ties
+        self.plugins.sort_by_key(|entry| Reverse(entry.priority));
+        id
+    }
+
+    pub(crate) fn remove(&mut self, id: PluginId) -> LC3Result<Box<dyn Plugin<IOType>>> {
+        let position = self
+            .plugins
+            .iter()
+            .position(|entry| entry.id == id)
+            .ok_or(LC3Error::UnknownPlugin(id))?;
+        Ok(self.plugins.remove(position).plugin)
+    }
+
+    pub(crate) fn get(&self, id: PluginId) -> LC3Result<&dyn Plugin<IOType>> {
+        self.plugins
+            .iter()
+            .find(|entry| entry.id == id)
+            .map(|entry| entry.plugin.as_ref())
+            .ok_or(LC3Error::UnknownPlugin(id))
+    }
+
+    pub(crate) fn get_mut(&mut self, id: PluginId) -> LC3Result<&mut Box<dyn Plugin<IOType>>> {
+        Ok(&mut self.entry(id)?.plugin)
+    }
+
+    pub(crate) fn set_priority(&mut self, id: PluginId, priority: i32) -> LC3Result<()> {
+        self.entry(id)?.priority = priority;
+        self.plugins.sort_by_key(|entry| Reverse(entry.priority));
+        Ok(())
+    }
+
+    pub(crate) fn set_max_event_depth(&mut self, depth: usize) {
+        self.max_event_depth = depth;
+    }
+
+    pub(crate) fn ids(&self) -> Vec<PluginId> {
+        self.plugins.iter().map(|entry| entry.id).collect()
+    }
+
+    fn intercept_and_handle(
+        &mut self,
+        state: &mut VmState<IOType>,
+        event: &mut Event,
+    ) -> LC3Result<bool> {
+        for entry in self.plugins.iter_mut() {
+            if entry.plugin.intercept_event(state, event)? == EventDecision::Cancel {
+                return Ok(false);
+            }
+        }
+        for entry in self.plugins.iter_mut() {
+            entry.plugin.handle_event(state, event)?;
+        }
+        Ok(true)
+    }
+
+    // Calls f on every plugin in notification order, then delivers anything
+    // they raised or published along the way
+    fn for_each<F>(&mut self, state: &mut VmState<IOType>, mut f: F) -> LC3Result<()>
+    where
+        F: FnMut(&mut dyn Plugin<IOType>, &mut VmState<IOType>) -> LC3Result<()>,
+    {
+        for entry in self.plugins.iter_mut() {
+            if let Err(err) = f(entry.plugin.as_mut(), state) {
+                discard_pending(state);
+                return Err(err);
+            }
+        }
+        self.deliver_pending(state)
+    }
+
+    // Raised events and published messages, until there are none left
+    pub(crate) fn deliver_pending(&mut self, state: &mut VmState<IOType>) -> LC3Result<()> {
+        let result = self
+            .deliver_raised(state)
+            .and_then(|_| self.deliver_messages(state));
+        if result.is_err() {
+            discard_pending(state);
+        }
+        result
+    }
+
+    fn deliver_raised(&mut self, state: &mut VmState<IOType>) -> LC3Result<()> {
+        let mut queue: VecDeque<(Event, usize)> =
+            state.raised.drain(..).map(|event| (event, 1)).collect();
+        while let Some((event, depth)) = queue.pop_front() {
+            if depth > self.max_event_depth {
+                continue;
+            }
+            for entry in self.plugins.iter_mut() {
+                entry.plugin.handle_event(state, &event)?;
+            }
+            queue.extend(state.raised.drain(..).map(|event| (event, depth + 1)));
+        }
+        Ok(())
+    }
+
+    fn deliver_messages(&mut self, state: &mut VmState<IOType>) -> LC3Result<()> {
+        let mut delivered = 0;
+        while let Some((topic, message, subscribers)) = state.bus.next() {
+            delivered += 1;
+            if delivered > MAX_MESSAGES_IN_A_ROW {
+                return Err(LC3Error::MessageLoop(MAX_MESSAGES_IN_A_ROW));
+            }
+
+            for entry in self.plugins.iter_mut() {
+                if subscribers.contains(&entry.id) {
+                    entry.plugin.on_message(state, &topic, &message)?;
+                }
+            }
+            self.deliver_raised(state)?;
+        }
+        Ok(())
+    }
+
+    fn entry(&mut self, id: PluginId) -> LC3Result<&mut PluginEntry<IOType>> {
+        self.plugins
+            .iter_mut()
+            .find(|entry| entry.id == id)
+            .ok_or(LC3Error::UnknownPlugin(id))
+    }
+}
+
+impl<IOType: IOHandle> PluginSet<IOType> for PluginManager<IOType> {
+    // Interceptors get the first look at the event and can rewrite or
+    // cancel it, then every plugin is told about whatever is left
+    fn notify(
+        &mut self,
+        state: &mut VmState<IOType>,
+        mut event: Event,
+    ) -> LC3Result<Option<Event>> {
+        let proceed = match self.intercept_and_handle(state, &mut event) {
+            Ok(proceed) => proceed,
+            Err(err) => {
+                discard_pending(state);
+                return Err(err);
+            }
+        };
+
+        self.deliver_pending(state)?;
+        Ok(if proceed { Some(event) } else { None })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    fn on_run_start(&mut self, state: &mut VmState<IOType>) -> LC3Result<()> {
+        self.for_each(state, |plugin, state| plugin.on_run_start(state))
+    }
+
+    fn on_halt(&mut self, state: &mut VmState<IOType>) -> LC3Result<()> {
+        self.for_each(state, |plugin, state| plugin.on_halt(state))
+    }
+}
@@ -1,6 +1,14 @@
+mod bus;
+mod manager;
 mod plugin;
+mod set;
+pub mod builtin;
 pub mod debuglogger;
 #[cfg(test)]
 mod test;
 
-pub use plugin::{Plugin, Event};
+pub use bus::Message;
+pub(crate) use bus::MessageBus;
+pub use manager::PluginManager;
+pub use plugin::{AsAny, Event, EventDecision, Plugin, PluginId};
+pub use set::{NoPlugins, PluginSet, StaticPlugin};
@@ -1,6 +1,11 @@
+use std::any::Any;
+use std::fmt;
+
+use super::Message;
 use crate::error::LC3Result;
+use crate::instruction::{DecodedInstruction, Effects};
 use crate::io::IOHandle;
-use crate::vm::VM;
+use crate::vm::VmState;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Event {
@@ -14,8 +19,102 @@ pub enum Event {
     RegSet { index: u8, value: u16 },
     RunningGet { value: bool },
     RunningSet { value: bool },
+    // Sent once the instruction has been fetched and any interceptors have
+    // had their say on the Command, just before it executes
+    PreInstruction { instruction: DecodedInstruction },
+    // Sent once the instruction has finished executing
+    PostInstruction { instruction: DecodedInstruction, effects: Effects },
+}
+
+// Handed out by VM::add_plugin so the plugin can be removed or swapped out
+// later, e.g. when a debugger detaches a tool part way through a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PluginId(pub(crate) u64);
+
+impl fmt::Display for PluginId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// What an interceptor wants done with an event
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventDecision {
+    Proceed,
+    // Stop the event taking effect: a skipped instruction, a write that
+    // never lands, a character that isn't printed. Cancelling a read just
+    // hides it from the other plugins.
+    Cancel,
+}
+
+// Lets a boxed plugin be turned back into its concrete type. Every plugin
+// gets this for free, there's no need to implement it.
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
 }
 
-pub trait Plugin<IOType: IOHandle> {
-    fn handle_event(&mut self, vm: &mut VM<IOType>, event: &Event) -> LC3Result<()>;
+pub trait Plugin<IOType: IOHandle>: AsAny {
+    fn handle_event(&mut self, vm: &mut VmState<IOType>, event: &Event) -> LC3Result<()>;
+
+    // Called before the event takes effect and before any plugin's
+    // handle_event. Changing the value the event carries changes what the
+    // VM does, e.g. rewriting the value of a MemSet changes what gets
+    // written, or the bytes of a Command changes the instruction that runs.
+    // Locations and register indexes can't be redirected.
+    fn intercept_event(
+        &mut self,
+        _vm: &mut VmState<IOType>,
+        _event: &mut Event,
+    ) -> LC3Result<EventDecision> {
+        Ok(EventDecision::Proceed)
+    }
+
+    // Plugins with a higher priority hear about each event first. Anything
+    // that only watches the VM should go above plugins that change it, so
+    // it sees the state the event came from.
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    // Called when the plugin is added to a VM, before it hears about any
+    // events
+    fn on_attach(&mut self, _vm: &mut VmState<IOType>, _id: PluginId) {}
+
+    // Called each time run starts the program over from the beginning
+    fn on_run_start(&mut self, _vm: &mut VmState<IOType>) -> LC3Result<()> {
+        Ok(())
+    }
+
+    // Called once the instruction that halted the machine has finished,
+    // which is a good time to write out any reports
+    fn on_halt(&mut self, _vm: &mut VmState<IOType>) -> LC3Result<()> {
+        Ok(())
+    }
+
+    // Called with each message published on a topic the plugin has
+    // subscribed to with VM::subscribe
+    fn on_message(
+        &mut self,
+        _vm: &mut VmState<IOType>,
+        _topic: &str,
+        _message: &Message,
+    ) -> LC3Result<()> {
+        Ok(())
+    }
 }
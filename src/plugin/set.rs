@@ -0,0 +1,127 @@
+use super::{Event, EventDecision, Plugin, PluginId};
+use crate::error::LC3Result;
+use crate::io::IOHandle;
+use crate::state::VmState;
+
+// Whatever the VM hands its events to. The VM is generic over this so that
+// the cost of instrumentation is only paid for what's attached: with
+// NoPlugins every notification compiles away to nothing, and StaticPlugin
+// calls a single known plugin directly rather than through a Box. The
+// default, PluginManager, is the one plugins can be added to and removed
+// from at runtime.
+pub trait PluginSet<IOType: IOHandle> {
+    // Returns the event as it should take effect, or None if it was
+    // cancelled
+    fn notify(&mut self, state: &mut VmState<IOType>, event: Event) -> LC3Result<Option<Event>>;
+
+    // Whether anything is listening. When nothing is, the VM skips work
+    // that's only done for the plugins' benefit, like decoding instructions.
+    fn is_empty(&self) -> bool;
+
+    fn on_run_start(&mut self, state: &mut VmState<IOType>) -> LC3Result<()>;
+
+    fn on_halt(&mut self, state: &mut VmState<IOType>) -> LC3Result<()>;
+
+    // Called once when the VM is built around the set
+    fn attach(&mut self, _state: &mut VmState<IOType>) {}
+}
+
+// For running programs as fast as possible with no instrumentation at all
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoPlugins;
+
+impl<IOType: IOHandle> PluginSet<IOType> for NoPlugins {
+    #[inline(always)]
+    fn notify(&mut self, _state: &mut VmState<IOType>, event: Event) -> LC3Result<Option<Event>> {
+        Ok(Some(event))
+    }
+
+    #[inline(always)]
+    fn is_empty(&self) -> bool {
+        true
+    }
+
+    #[inline(always)]
+    fn on_run_start(&mut self, _state: &mut VmState<IOType>) -> LC3Result<()> {
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn on_halt(&mut self, _state: &mut VmState<IOType>) -> LC3Result<()> {
+        Ok(())
+    }
+}
+
+// Exactly one plugin, fixed when the VM is built, so every call to it can
+// be dispatched statically and inlined. There's nobody else to hear about
+// the events it raises or the messages it publishes, so those are dropped.
+// It's given PluginId 0 in on_attach.
+#[derive(Debug, Clone, Default)]
+pub struct StaticPlugin<P> {
+    plugin: P,
+}
+
+impl<P> StaticPlugin<P> {
+    pub fn new(plugin: P) -> Self {
+        Self { plugin }
+    }
+
+    pub fn get(&self) -> &P {
+        &self.plugin
+    }
+
+    pub fn get_mut(&mut self) -> &mut P {
+        &mut self.plugin
+    }
+
+    pub fn into_inner(self) -> P {
+        self.plugin
+    }
+}
+
+impl<IOType: IOHandle, P: Plugin<IOType>> PluginSet<IOType> for StaticPlugin<P> {
+    #[inline]
+    fn notify(
+        &mut self,
+        state: &mut VmState<IOType>,
+        mut event: Event,
+    ) -> LC3Result<Option<Event>> {
+        let result = match self.plugin.intercept_event(state, &mut event) {
+            Ok(EventDecision::Proceed) => {
+                self.plugin.handle_event(state, &event).map(|_| Some(event))
+            }
+            Ok(EventDecision::Cancel) => Ok(None),
+            Err(err) => Err(err),
+        };
+        discard_pending(state);
+        result
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        false
+    }
+
+    fn on_run_start(&mut self, state: &mut VmState<IOType>) -> LC3Result<()> {
+        let result = self.plugin.on_run_start(state);
+        discard_pending(state);
+        result
+    }
+
+    fn on_halt(&mut self, state: &mut VmState<IOType>) -> LC3Result<()> {
+        let result = self.plugin.on_halt(state);
+        discard_pending(state);
+        result
+    }
+
+    fn attach(&mut self, state: &mut VmState<IOType>) {
+        self.plugin.on_attach(state, PluginId(0));
+        discard_pending(state);
+    }
+}
+
+// Whatever was raised or published but won't be delivered
+pub(crate) fn discard_pending<IOType: IOHandle>(state: &mut VmState<IOType>) {
+    state.raised.clear();
+    state.bus.clear();
+}
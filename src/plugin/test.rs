@@ -1,11 +1,14 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use crate::error::LC3Result;
-use crate::io::IOHandle;
-use crate::vm::VM;
+use crate::condition_flags::ConditionFlags;
+use crate::error::{LC3Error, LC3Result};
+use crate::instruction::{DecodedInstruction, Effects};
+use crate::io::{IOHandle, TestIOHandle};
+use crate::register::Register::RR0;
+use crate::vm::{VmState, VM};
 use super::Event::*;
-use super::{Event, Plugin};
+use super::{Event, EventDecision, Message, NoPlugins, Plugin, PluginId, StaticPlugin};
 
 type EventsReference = Rc<RefCell<Vec<Event>>>;
 
@@ -26,7 +29,7 @@ impl TestPlugin {
 }
 
 impl<IOType: IOHandle> Plugin<IOType> for TestPlugin {
-    fn handle_event(&mut self, _vm: &mut VM<IOType>, event: &Event) -> LC3Result<()> {
+    fn handle_event(&mut self, _vm: &mut VmState<IOType>, event: &Event) -> LC3Result<()> {
         self.events.borrow_mut().push(event.clone());
         Ok(())
     }
@@ -66,3 +69,548 @@ fn can_push_events_to_plugin() -> LC3Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn can_remove_and_replace_plugins() -> LC3Result<()> {
+    let first = TestPlugin::new();
+    let second = TestPlugin::new();
+    let third = TestPlugin::new();
+    let (first_events, second_events, third_events) = (
+        first.get_events_ref(),
+        second.get_events_ref(),
+        third.get_events_ref(),
+    );
+
+    let mut vm = VM::new_with_io(TestIOHandle::new());
+    let first_id = vm.add_plugin(Box::new(first));
+    let second_id = vm.add_plugin(Box::new(second));
+    assert_ne!(first_id, second_id);
+
+    vm.remove_plugin(first_id)?;
+    vm.notify_plugins(CharPut { ch: 'a' })?;
+    vm.replace_plugin(second_id, Box::new(third))?;
+    vm.notify_plugins(CharPut { ch: 'b' })?;
+
+    assert!(first_events.borrow().is_empty());
+    assert_eq!(*second_events.borrow(), vec![CharPut { ch: 'a' }]);
+    assert_eq!(*third_events.borrow(), vec![CharPut { ch: 'b' }]);
+
+    assert!(matches!(
+        vm.remove_plugin(first_id),
+        Err(LC3Error::UnknownPlugin(id)) if id == first_id
+    ));
+
+    Ok(())
+}
+
+struct OrderPlugin {
+    name: &'static str,
+    priority: i32,
+    order: Rc<RefCell<Vec<&'static str>>>,
+}
+
+impl<IOType: IOHandle> Plugin<IOType> for OrderPlugin {
+    fn handle_event(&mut self, _vm: &mut VmState<IOType>, _event: &Event) -> LC3Result<()> {
+        self.order.borrow_mut().push(self.name);
+        Ok(())
+    }
+
+    fn priority(&self) -> i32 {
+        self.priority
+    }
+}
+
+#[test]
+fn notifies_plugins_in_priority_order() -> LC3Result<()> {
+    let order = Rc::new(RefCell::new(Vec::new()));
+    let plugin = |name, priority| {
+        Box::new(OrderPlugin {
+            name,
+            priority,
+            order: order.clone(),
+        })
+    };
+
+    let mut vm = VM::new_with_io(TestIOHandle::new());
+    let mutator = vm.add_plugin(plugin("mutator", 0));
+    let tracer = vm.add_plugin(plugin("tracer", 100));
+    let logger = vm.add_plugin(plugin("logger", 0));
+    assert_eq!(vm.plugin_ids(), vec![tracer, mutator, logger]);
+
+    vm.notify_plugins(CharPut { ch: 'a' })?;
+    assert_eq!(*order.borrow(), vec!["tracer", "mutator", "logger"]);
+
+    order.borrow_mut().clear();
+    vm.set_plugin_priority(logger, 200)?;
+    vm.notify_plugins(CharPut { ch: 'b' })?;
+    assert_eq!(*order.borrow(), vec!["logger", "tracer", "mutator"]);
+
+    Ok(())
+}
+
+struct Interceptor<F: FnMut(&mut Event) -> EventDecision>(F);
+
+impl<F, IOType> Plugin<IOType> for Interceptor<F>
+where
+    F: FnMut(&mut Event) -> EventDecision + 'static,
+    IOType: IOHandle,
+{
+    fn handle_event(&mut self, _vm: &mut VmState<IOType>, _event: &Event) -> LC3Result<()> {
+        Ok(())
+    }
+
+    fn intercept_event(
+        &mut self,
+        _vm: &mut VmState<IOType>,
+        event: &mut Event,
+    ) -> LC3Result<EventDecision> {
+        Ok((self.0)(event))
+    }
+}
+
+type BoxedDecider = Box<dyn FnMut(&mut Event) -> EventDecision>;
+
+#[test]
+fn cant_lose_keys_to_intercepted_key_checks() -> LC3Result<()> {
+    // POLL: LDI R0, KBSR; BRzp POLL; LDI R0, KBDR; HALT; KBSR; KBDR
+    let program = vec![0xA003, 0x07FE, 0xA002, 0xF025, 0xFE00, 0xFE02];
+    let io_handle = TestIOHandle::new()
+        .with_key_presses("k")
+        .with_keydown_responses(&[true]);
+    let mut vm = VM::new_with_io(io_handle);
+    // Says the first key isn't there yet
+    let mut hidden = false;
+    vm.add_plugin(Box::new(Interceptor(move |event: &mut Event| {
+        if let KeyDownGet { value } = event {
+            if *value && !hidden {
+                *value = false;
+                hidden = true;
+            }
+        }
+        EventDecision::Proceed
+    })));
+    vm.load_program(&program)?;
+    vm.run_with_limit(20)?;
+    assert_eq!(vm.register(RR0), 'k' as u16);
+
+    Ok(())
+}
+
+#[test]
+fn can_intercept_events() -> LC3Result<()> {
+    // ADD R0, R0, #5; ST R0, #1; HALT; .FILL #0
+    let program = vec![0x1025, 0x3001, 0xF025, 0x0000];
+    let run = |interceptor: Interceptor<BoxedDecider>| {
+        let observer = TestPlugin::new();
+        let events = observer.get_events_ref();
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.add_plugin(Box::new(interceptor));
+        vm.add_plugin(Box::new(observer));
+        vm.load_program(&program)?;
+        vm.run()?;
+        let events = events.borrow().clone();
+        Ok::<_, LC3Error>((vm.register(RR0), vm.memory_at(0x3003), events))
+    };
+
+    // Rewriting the value written to R0
+    let (r0, stored, events) = run(Interceptor(Box::new(|event| {
+        if let RegSet { index: 0, value } = event {
+            *value += 1;
+        }
+        EventDecision::Proceed
+    })))?;
+    assert_eq!((r0, stored), (6, 6));
+    assert!(events.contains(&RegSet { index: 0, value: 6 }));
+
+    // Protecting memory from writes
+    let (r0, stored, events) = run(Interceptor(Box::new(|event| match event {
+        MemSet {
+            location: 0x3003, ..
+        } => EventDecision::Cancel,
+        _ => EventDecision::Proceed,
+    })))?;
+    assert_eq!((r0, stored), (5, 0));
+    assert!(!events.contains(&MemSet {
+        location: 0x3003,
+        value: 5
+    }));
+
+    // Skipping an instruction
+    let (r0, stored, _) = run(Interceptor(Box::new(|event| match event {
+        Command { bytes: 0x1025 } => EventDecision::Cancel,
+        _ => EventDecision::Proceed,
+    })))?;
+    assert_eq!((r0, stored), (0, 0));
+
+    // Turning one kind of event into another isn't allowed
+    let err = run(Interceptor(Box::new(|event| {
+        if let RunningSet { value: false } = event {
+            *event = RunningGet { value: false };
+        }
+        EventDecision::Proceed
+    })))
+    .unwrap_err();
+    assert!(matches!(err, LC3Error::Plugin { .. }));
+
+    Ok(())
+}
+
+#[test]
+fn can_send_decoded_instructions() -> LC3Result<()> {
+    // ADD R0, R0, #5; ST R0, #1; HALT; .FILL #0
+    let program = vec![0x1025, 0x3001, 0xF025, 0x0000];
+    let plugin = TestPlugin::new();
+    let events = plugin.get_events_ref();
+    let mut vm = VM::new_with_io(TestIOHandle::new());
+    vm.load_program(&program)?;
+    vm.add_plugin(Box::new(plugin));
+    vm.step()?;
+    vm.step()?;
+
+    let instructions: Vec<Event> = events
+        .borrow()
+        .iter()
+        .filter(|event| matches!(event, PreInstruction { .. } | PostInstruction { .. }))
+        .cloned()
+        .collect();
+    let add = DecodedInstruction::decode(0x3000, 0x1025)?;
+    let store = DecodedInstruction::decode(0x3001, 0x3001)?;
+    let flags = ConditionFlags {
+        negative: false,
+        zero: false,
+        positive: true,
+    };
+    assert_eq!(
+        instructions,
+        vec![
+            PreInstruction {
+                instruction: add.clone()
+            },
+            PostInstruction {
+                instruction: add,
+                effects: Effects {
+                    registers_written: vec![(0, 5), (9, 1)],
+                    memory_written: vec![],
+                    pc: 0x3001,
+                    flags,
+                    halted: false,
+                },
+            },
+            PreInstruction {
+                instruction: store.clone()
+            },
+            PostInstruction {
+                instruction: store,
+                effects: Effects {
+                    registers_written: vec![],
+                    memory_written: vec![(0x3003, 5)],
+                    pc: 0x3002,
+                    flags,
+                    halted: false,
+                },
+            },
+        ]
+    );
+
+    Ok(())
+}
+
+struct LifecyclePlugin {
+    calls: Rc<RefCell<Vec<String>>>,
+}
+
+impl<IOType: IOHandle> Plugin<IOType> for LifecyclePlugin {
+    fn handle_event(&mut self, _vm: &mut VmState<IOType>, event: &Event) -> LC3Result<()> {
+        if let PostInstruction { instruction, .. } = event {
+            self.calls.borrow_mut().push(instruction.to_string());
+        }
+        Ok(())
+    }
+
+    fn on_attach(&mut self, _vm: &mut VmState<IOType>, id: PluginId) {
+        self.calls.borrow_mut().push(format!("attach {}", id));
+    }
+
+    fn on_run_start(&mut self, vm: &mut VmState<IOType>) -> LC3Result<()> {
+        self.calls
+            .borrow_mut()
+            .push(format!("run start x{:04X}", vm.pc()));
+        Ok(())
+    }
+
+    fn on_halt(&mut self, vm: &mut VmState<IOType>) -> LC3Result<()> {
+        self.calls.borrow_mut().push(format!("halt x{:04X}", vm.pc()));
+        Ok(())
+    }
+}
+
+#[test]
+fn can_call_lifecycle_hooks() -> LC3Result<()> {
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let mut vm = VM::new_with_io(TestIOHandle::new());
+    // ADD R0, R0, #5; HALT
+    vm.load_program(&vec![0x1025, 0xF025])?;
+    vm.add_plugin(Box::new(LifecyclePlugin {
+        calls: calls.clone(),
+    }));
+    vm.run()?;
+    vm.run()?;
+
+    assert_eq!(
+        *calls.borrow(),
+        vec![
+            "attach 0",
+            "run start x3000",
+            "ADD R0, R0, #5",
+            "HALT",
+            "halt x3002",
+            "run start x3000",
+            "ADD R0, R0, #5",
+            "HALT",
+            "halt x3002",
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn can_take_plugins_back() -> LC3Result<()> {
+    let mut vm = VM::new_with_io(TestIOHandle::new());
+    let id = vm.add_plugin(Box::new(TestPlugin::new()));
+    vm.notify_plugins(CharPut { ch: 'a' })?;
+
+    assert!(vm.plugin::<TestPlugin>(id)?.is_some());
+    assert!(vm.plugin::<OrderPlugin>(id)?.is_none());
+    vm.plugin_mut::<TestPlugin>(id)?
+        .unwrap()
+        .events
+        .borrow_mut()
+        .push(CharPut { ch: 'b' });
+
+    // Asking for the wrong type leaves the plugin attached
+    assert!(matches!(
+        vm.take_plugin::<OrderPlugin>(id),
+        Err(LC3Error::WrongPluginType(_))
+    ));
+    assert_eq!(vm.plugin_ids(), vec![id]);
+
+    let plugin: TestPlugin = vm.take_plugin(id)?;
+    assert_eq!(
+        *plugin.events.borrow(),
+        vec![CharPut { ch: 'a' }, CharPut { ch: 'b' }]
+    );
+    assert!(vm.plugin_ids().is_empty());
+
+    Ok(())
+}
+
+// Names the address of each instruction as it runs, for anyone listening
+struct Labeller;
+
+impl<IOType: IOHandle> Plugin<IOType> for Labeller {
+    fn handle_event(&mut self, vm: &mut VmState<IOType>, event: &Event) -> LC3Result<()> {
+        if let PreInstruction { instruction } = event {
+            vm.publish("label", format!("L{:04X}", instruction.address));
+        }
+        Ok(())
+    }
+}
+
+struct LabelReader {
+    labels: Rc<RefCell<Vec<String>>>,
+}
+
+impl<IOType: IOHandle> Plugin<IOType> for LabelReader {
+    fn handle_event(&mut self, _vm: &mut VmState<IOType>, _event: &Event) -> LC3Result<()> {
+        Ok(())
+    }
+
+    fn on_attach(&mut self, vm: &mut VmState<IOType>, id: PluginId) {
+        vm.subscribe(id, "label");
+    }
+
+    fn on_message(
+        &mut self,
+        vm: &mut VmState<IOType>,
+        topic: &str,
+        message: &Message,
+    ) -> LC3Result<()> {
+        if let Some(label) = message.downcast_ref::<String>() {
+            self.labels.borrow_mut().push(format!("{} {}", topic, label));
+            // Answers go round forever if anyone's listening for them
+            vm.publish("reply", ());
+        }
+        Ok(())
+    }
+}
+
+struct Replier;
+
+impl<IOType: IOHandle> Plugin<IOType> for Replier {
+    fn handle_event(&mut self, _vm: &mut VmState<IOType>, _event: &Event) -> LC3Result<()> {
+        Ok(())
+    }
+
+    fn on_message(
+        &mut self,
+        vm: &mut VmState<IOType>,
+        _topic: &str,
+        _message: &Message,
+    ) -> LC3Result<()> {
+        vm.publish("label", "again".to_string());
+        Ok(())
+    }
+}
+
+#[test]
+fn can_pass_messages_between_plugins() -> LC3Result<()> {
+    let labels = Rc::new(RefCell::new(Vec::new()));
+    let mut vm = VM::new_with_io(TestIOHandle::new());
+    // ADD R0, R0, #5; HALT
+    vm.load_program(&vec![0x1025, 0xF025])?;
+    vm.add_plugin(Box::new(Labeller));
+    let reader = vm.add_plugin(Box::new(LabelReader {
+        labels: labels.clone(),
+    }));
+    vm.run()?;
+    vm.publish("label", "from the host".to_string())?;
+    vm.publish("other", "ignored".to_string())?;
+    assert_eq!(
+        *labels.borrow(),
+        vec!["label L3000", "label L3001", "label from the host"]
+    );
+
+    // Once unsubscribed nothing more arrives
+    vm.unsubscribe(reader, "label");
+    vm.run()?;
+    assert_eq!(labels.borrow().len(), 3);
+
+    // Plugins that keep replying to each other are stopped
+    vm.subscribe(reader, "label");
+    let replier = vm.add_plugin(Box::new(Replier));
+    vm.subscribe(replier, "reply");
+    assert!(matches!(vm.run(), Err(LC3Error::MessageLoop(_))));
+
+    Ok(())
+}
+
+// Keeps any message it's sent, without subscribing to anything itself
+struct Eavesdropper {
+    messages: Rc<RefCell<Vec<String>>>,
+}
+
+impl<IOType: IOHandle> Plugin<IOType> for Eavesdropper {
+    fn handle_event(&mut self, _vm: &mut VmState<IOType>, _event: &Event) -> LC3Result<()> {
+        Ok(())
+    }
+
+    fn on_message(
+        &mut self,
+        _vm: &mut VmState<IOType>,
+        topic: &str,
+        _message: &Message,
+    ) -> LC3Result<()> {
+        self.messages.borrow_mut().push(topic.to_string());
+        Ok(())
+    }
+}
+
+#[test]
+fn can_attach_replacement_plugins() -> LC3Result<()> {
+    let messages = Rc::new(RefCell::new(Vec::new()));
+    let labels = Rc::new(RefCell::new(Vec::new()));
+    let mut vm = VM::new_with_io(TestIOHandle::new());
+    let id = vm.add_plugin(Box::new(LabelReader {
+        labels: Rc::new(RefCell::new(Vec::new())),
+    }));
+
+    // The old plugin's subscriptions go with it
+    vm.replace_plugin(
+        id,
+        Box::new(Eavesdropper {
+            messages: messages.clone(),
+        }),
+    )?;
+    vm.publish("label", "dropped".to_string())?;
+    assert!(messages.borrow().is_empty());
+
+    // The new one subscribes in on_attach
+    vm.replace_plugin(
+        id,
+        Box::new(LabelReader {
+            labels: labels.clone(),
+        }),
+    )?;
+    vm.publish("label", "kept".to_string())?;
+    assert_eq!(*labels.borrow(), vec!["label kept"]);
+
+    Ok(())
+}
+
+// Copies every write on to the next address along
+struct Chain;
+
+impl<IOType: IOHandle> Plugin<IOType> for Chain {
+    fn handle_event(&mut self, vm: &mut VmState<IOType>, event: &Event) -> LC3Result<()> {
+        if let MemSet { location, value } = event {
+            vm.write_memory(location + 1, *value);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn can_deliver_nested_events() -> LC3Result<()> {
+    for (depth, expected_writes) in [(0, 1), (4, 5)] {
+        let observer = TestPlugin::new();
+        let events = observer.get_events_ref();
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.set_max_event_depth(depth);
+        vm.add_plugin(Box::new(Chain));
+        vm.add_plugin(Box::new(observer));
+
+        vm.write_memory(0x4000, 7)?;
+
+        let seen: Vec<Event> = (0..expected_writes)
+            .map(|offset| MemSet {
+                location: 0x4000 + offset,
+                value: 7,
+            })
+            .collect();
+        assert_eq!(*events.borrow(), seen);
+        // The write that would have gone one level deeper still happens,
+        // but nobody hears about it so the chain stops there
+        assert_eq!(vm.memory_at(0x4000 + expected_writes), 7);
+        assert_eq!(vm.memory_at(0x4001 + expected_writes), 0);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn can_use_static_plugin_sets() -> LC3Result<()> {
+    // ADD R0, R0, #5 then HALT
+    let program = vec![0x1025, 0xF025];
+
+    let dynamic = TestPlugin::new();
+    let dynamic_events = dynamic.get_events_ref();
+    let mut vm = VM::new_with_io(TestIOHandle::new());
+    vm.add_plugin(Box::new(dynamic));
+    vm.load_program(&program)?;
+    vm.run()?;
+
+    let mut vm = VM::new_with_plugins(TestIOHandle::new(), StaticPlugin::new(TestPlugin::new()));
+    vm.load_program(&program)?;
+    vm.run()?;
+    assert_eq!(vm.register(RR0), 5);
+    let static_events = vm.into_plugins().into_inner().get_events_ref();
+    assert_eq!(*static_events.borrow(), *dynamic_events.borrow());
+
+    let mut vm = VM::new_with_plugins(TestIOHandle::new(), NoPlugins);
+    vm.load_program(&program)?;
+    vm.run()?;
+    assert_eq!(vm.register(RR0), 5);
+
+    Ok(())
+}
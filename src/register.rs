@@ -1,3 +1,5 @@
+use std::fmt;
+
 const REGISTERS: [Register;11] = [
     Register::RR0,
     Register::RR1,
@@ -15,8 +17,8 @@ const REGISTERS: [Register;11] = [
 
 pub(crate) const NUM_REGISTERS: usize = REGISTERS.len();
 
-#[derive(Copy, Clone)]
-pub(crate) enum Register {
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Register {
     RR0 = 0,
     RR1 = 1,
     RR2 = 2,
@@ -35,8 +37,23 @@ impl Register {
         *self as u8
     }
 
+    pub(crate) fn from_index(index: u8) -> Option<Register> {
+        REGISTERS.get(index as usize).copied()
+    }
+
     #[cfg(test)]
     pub(crate) fn index(&self) -> usize {
         self.to_u8() as usize
     }
 }
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::RPC => write!(f, "PC"),
+            Register::RCond => write!(f, "CC"),
+            Register::RCount => write!(f, "COUNT"),
+            general => write!(f, "R{}", general.to_u8()),
+        }
+    }
+}
@@ -0,0 +1,61 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The generator behind the RNG device register. It's xorshift64*, which is
+// plenty for games and simulations and means there's no need for a crate.
+// The same seed always gives the same sequence, so tests can rely on it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        // xorshift gets stuck at zero, so the seed is scrambled with a
+        // splitmix64 step first. That also spreads out small seeds like 1
+        // and 2 that would otherwise start off looking alike.
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Self {
+            state: if z == 0 { 1 } else { z },
+        }
+    }
+
+    // Seeded from the clock, for when nobody asked for a particular sequence
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or(0);
+        Self::new(nanos)
+    }
+
+    // SystemTime::now panics on wasm, so every VM there starts with the same
+    // sequence unless it's seeded
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) fn from_time() -> Self {
+        Self::new(0)
+    }
+
+    // Where the sequence has got to, for snapshots to pick it up from again
+    pub(crate) fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub(crate) fn from_state(state: u64) -> Self {
+        Self {
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+
+    pub(crate) fn next_u16(&mut self) -> u16 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        // The high bits are the best mixed
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 48) as u16
+    }
+}
@@ -0,0 +1,579 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::error::{BoxErrors, LC3Error, LC3Result};
+use crate::interrupt::{PSR_USER, SUPERVISOR_STACK_START};
+use crate::json::Json;
+use crate::memory::{to_pages, Page, PAGE_SIZE};
+use crate::register::{Register, NUM_REGISTERS};
+use crate::rng::Rng;
+use crate::vm::MEMORY_SIZE;
+
+// Version 1 snapshots only had the registers, running flag, start PC and
+// memory, and are still read
+const FORMAT_VERSION: u64 = 2;
+
+// A copy of the complete machine state, taken with VM::snapshot and put
+// back with VM::restore. Plugins, the IO handle and the record of loaded
+// programs aren't part of the snapshot, so restoring one doesn't change
+// what VM::reset(true) resets memory to.
+#[derive(Clone, PartialEq)]
+pub struct Snapshot {
+    // All of memory, in pages shared with the other snapshots taken while
+    // they didn't change
+    pub(crate) memory: Vec<Page>,
+    pub(crate) registers: Box<[u16]>,
+    pub(crate) running: bool,
+    pub(crate) start_pc: u16,
+    // The rest of the PSR, the condition codes being in registers, and
+    // whichever stack pointer isn't in R6
+    pub(crate) user_mode: bool,
+    pub(crate) priority: u8,
+    pub(crate) saved_ssp: u16,
+    pub(crate) saved_usp: u16,
+    // Kept so the RNG, timer and clock registers read the same again after
+    // a restore
+    pub(crate) rng: Rng,
+    pub(crate) timer_count: u16,
+    pub(crate) instructions_executed: u64,
+}
+
+impl Snapshot {
+    pub fn register(&self, reg: Register) -> u16 {
+        self.registers[reg.to_u8() as usize]
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.register(Register::RPC)
+    }
+
+    pub fn memory_at(&self, address: u16) -> u16 {
+        let address = address as usize;
+        self.memory[address / PAGE_SIZE][address % PAGE_SIZE]
+    }
+
+    // Every word of memory, from address x0000 up
+    pub fn memory(&self) -> impl Iterator<Item = u16> + '_ {
+        self.memory.iter().flat_map(|page| page.iter().copied())
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn psr(&self) -> u16 {
+        let privilege = if self.user_mode { PSR_USER } else { 0 };
+        privilege | (u16::from(self.priority) << 8) | self.register(Register::RCond)
+    }
+
+    // Everything that differs between this snapshot and a later one
+    pub fn diff(&self, after: &Snapshot) -> SnapshotDiff {
+        let registers = self
+            .registers
+            .iter()
+            .zip(after.registers.iter())
+            .enumerate()
+            .filter(|(_, (before, after))| before != after)
+            .filter_map(|(index, (before, after))| {
+                Register::from_index(index as u8).map(|register| RegisterChange {
+                    register,
+                    before: *before,
+                    after: *after,
+                })
+            })
+            .collect();
+
+        // Pages that are shared can't have changed
+        let memory = self
+            .memory
+            .iter()
+            .zip(after.memory.iter())
+            .enumerate()
+            .filter(|(_, (before, after))| !Arc::ptr_eq(before, after))
+            .flat_map(|(page, (before, after))| {
+                let start = page * PAGE_SIZE;
+                before
+                    .iter()
+                    .zip(after.iter())
+                    .enumerate()
+                    .filter(|(_, (before, after))| before != after)
+                    .map(move |(offset, (before, after))| MemoryChange {
+                        address: (start + offset) as u16,
+                        before: *before,
+                        after: *after,
+                    })
+            })
+            .collect();
+
+        SnapshotDiff {
+            registers,
+            memory,
+            running: if self.running != after.running {
+                Some((self.running, after.running))
+            } else {
+                None
+            },
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> LC3Result<()> {
+        std::fs::write(path, self.to_json()).map_io_error()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> LC3Result<Self> {
+        let text = std::fs::read_to_string(path).map_io_error()?;
+        Self::from_json(&text)
+    }
+
+    // Memory is mostly zeroes, so only the runs of non-zero words are
+    // written out, each as its start address and the words themselves:
+    //
+    // {"version":2,"registers":[...],"running":false,"start_pc":12288,
+    //  "user_mode":false,"priority":0,"saved_ssp":12288,"saved_usp":0,
+    //  "rng":"9e3779b97f4a7c15","timer_count":0,"instructions_executed":0,
+    //  "memory":[{"address":12288,"words":[57346,61474]}]}
+    //
+    // The RNG's state is a hex string, as JSON numbers can't hold all 64
+    // bits. Snapshots without the fields after start_pc, such as those from
+    // version 1, are read as a machine that's just been reset, with the RNG
+    // seeded with 0 so that they always restore the same way.
+    pub fn to_json(&self) -> String {
+        self.to_json_value().to_string()
+    }
+
+    pub fn from_json(text: &str) -> LC3Result<Self> {
+        let json = Json::parse(text).map_err(LC3Error::BadSnapshot)?;
+        Self::from_json_value(&json)
+    }
+
+    // As a value, so core dumps can include a snapshot in their own JSON
+    pub(crate) fn to_json_value(&self) -> Json {
+        let memory: Vec<u16> = self.memory().collect();
+        let mut segments = Vec::new();
+        let mut address = 0;
+        while address < memory.len() {
+            if memory[address] == 0 {
+                address += 1;
+                continue;
+            }
+            let start = address;
+            while address < memory.len() && memory[address] != 0 {
+                address += 1;
+            }
+            let words: Vec<Json> = memory[start..address]
+                .iter()
+                .map(|word| (*word).into())
+                .collect();
+            segments.push(Json::object(vec![
+                ("address", start.into()),
+                ("words", words.into()),
+            ]));
+        }
+
+        let registers: Vec<Json> = self.registers.iter().map(|reg| (*reg).into()).collect();
+        Json::object(vec![
+            ("version", FORMAT_VERSION.into()),
+            ("registers", registers.into()),
+            ("running", self.running.into()),
+            ("start_pc", self.start_pc.into()),
+            ("user_mode", self.user_mode.into()),
+            ("priority", u16::from(self.priority).into()),
+            ("saved_ssp", self.saved_ssp.into()),
+            ("saved_usp", self.saved_usp.into()),
+            ("rng", format!("{:016x}", self.rng.state()).into()),
+            ("timer_count", self.timer_count.into()),
+            ("instructions_executed", self.instructions_executed.into()),
+            ("memory", segments.into()),
+        ])
+    }
+
+    pub(crate) fn from_json_value(json: &Json) -> LC3Result<Self> {
+        let field = |name: &str| {
+            json.get(name)
+                .ok_or_else(|| LC3Error::BadSnapshot(format!("Missing field '{}'", name)))
+        };
+
+        let optional_word = |name: &str, default: u16| match json.get(name) {
+            None => Ok(default),
+            Some(value) => value
+                .as_u64()
+                .filter(|word| *word <= u16::MAX as u64)
+                .map(|word| word as u16)
+                .ok_or_else(|| LC3Error::BadSnapshot(format!("Invalid {}", name))),
+        };
+
+        if !matches!(field("version")?.as_u64(), Some(1..=FORMAT_VERSION)) {
+            return Err(LC3Error::BadSnapshot(
+                "Unsupported snapshot version".to_string(),
+            ));
+        }
+
+        let registers = words(field("registers")?)?;
+        if registers.len() != NUM_REGISTERS {
+            return Err(LC3Error::BadSnapshot(format!(
+                "Expected {} registers, found {}",
+                NUM_REGISTERS,
+                registers.len()
+            )));
+        }
+
+        let mut memory = vec![0u16; MEMORY_SIZE];
+        let segments = field("memory")?
+            .as_array()
+            .ok_or_else(|| LC3Error::BadSnapshot("memory should be an array".to_string()))?;
+        for segment in segments {
+            let start = segment
+                .get("address")
+                .and_then(Json::as_u64)
+                .map(|address| address as usize)
+                .ok_or_else(|| LC3Error::BadSnapshot("Segment without an address".to_string()))?;
+            let segment_words = words(segment.get("words").unwrap_or(&Json::Null))?;
+            let end = start + segment_words.len();
+            if end > memory.len() {
+                return Err(LC3Error::BadSnapshot(format!(
+                    "Segment at {:#06x} runs past the end of memory",
+                    start
+                )));
+            }
+            memory[start..end].copy_from_slice(&segment_words);
+        }
+
+        Ok(Self {
+            memory: to_pages(&memory),
+            registers: registers.into_boxed_slice(),
+            running: field("running")?.as_bool().unwrap_or(false),
+            start_pc: field("start_pc")?
+                .as_u64()
+                .filter(|pc| *pc <= u16::MAX as u64)
+                .ok_or_else(|| LC3Error::BadSnapshot("Invalid start_pc".to_string()))?
+                as u16,
+            user_mode: json
+                .get("user_mode")
+                .and_then(Json::as_bool)
+                .unwrap_or(false),
+            priority: match optional_word("priority", 0)? {
+                priority if priority <= 7 => priority as u8,
+                _ => return Err(LC3Error::BadSnapshot("Invalid priority".to_string())),
+            },
+            saved_ssp: optional_word("saved_ssp", SUPERVISOR_STACK_START)?,
+            saved_usp: optional_word("saved_usp", 0)?,
+            rng: match json.get("rng") {
+                None => Rng::new(0),
+                Some(state) => state
+                    .as_str()
+                    .and_then(|state| u64::from_str_radix(state, 16).ok())
+                    .map(Rng::from_state)
+                    .ok_or_else(|| LC3Error::BadSnapshot("Invalid rng".to_string()))?,
+            },
+            timer_count: optional_word("timer_count", 0)?,
+            instructions_executed: match json.get("instructions_executed") {
+                None => 0,
+                Some(count) => count.as_u64().ok_or_else(|| {
+                    LC3Error::BadSnapshot("Invalid instructions_executed".to_string())
+                })?,
+            },
+        })
+    }
+}
+
+// The same fields as the JSON, so a snapshot can go in any format serde
+// supports and still be checked the same way when it's read back
+#[cfg(feature = "serde")]
+impl serde::Serialize for Snapshot {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_json_value().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Snapshot {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let json = Json::deserialize(deserializer)?;
+        Self::from_json_value(&json).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegisterChange {
+    pub register: Register,
+    pub before: u16,
+    pub after: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryChange {
+    pub address: u16,
+    pub before: u16,
+    pub after: u16,
+}
+
+// The result of Snapshot::diff. Changes are ordered by register index and
+// address.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SnapshotDiff {
+    pub registers: Vec<RegisterChange>,
+    pub memory: Vec<MemoryChange>,
+    // (before, after) if the running flag changed
+    pub running: Option<(bool, bool)>,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.registers.is_empty() && self.memory.is_empty() && self.running.is_none()
+    }
+
+    pub fn changed_registers(&self) -> Vec<Register> {
+        self.registers
+            .iter()
+            .map(|change| change.register)
+            .collect()
+    }
+
+    pub fn changed_addresses(&self) -> Vec<u16> {
+        self.memory.iter().map(|change| change.address).collect()
+    }
+}
+
+// One change per line, e.g. "R0: x0000 -> x0005" or "x4000: x0000 -> x0048"
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut lines = Vec::new();
+        for change in &self.registers {
+            lines.push(format!(
+                "{}: x{:04X} -> x{:04X}",
+                change.register, change.before, change.after
+            ));
+        }
+        for change in &self.memory {
+            lines.push(format!(
+                "x{:04X}: x{:04X} -> x{:04X}",
+                change.address, change.before, change.after
+            ));
+        }
+        if let Some((before, after)) = self.running {
+            lines.push(format!("running: {} -> {}", before, after));
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+// Full memory dumps make for unreadable assertion failures, so only the
+// interesting parts are shown
+impl fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("registers", &self.registers)
+            .field("running", &self.running)
+            .field("start_pc", &self.start_pc)
+            .field("psr", &self.psr())
+            .field("saved_ssp", &self.saved_ssp)
+            .field("saved_usp", &self.saved_usp)
+            .field(
+                "nonzero_words",
+                &self.memory().filter(|word| *word != 0).count(),
+            )
+            .finish()
+    }
+}
+
+fn words(json: &Json) -> LC3Result<Vec<u16>> {
+    json.as_array()
+        .ok_or_else(|| LC3Error::BadSnapshot("Expected an array of words".to_string()))?
+        .iter()
+        .map(|word| {
+            word.as_u64()
+                .filter(|word| *word <= u16::MAX as u64)
+                .map(|word| word as u16)
+                .ok_or_else(|| LC3Error::BadSnapshot(format!("Invalid word {}", word)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::{MemoryChange, Snapshot};
+    use crate::asm::assemble;
+    use crate::error::{LC3Error, LC3Result};
+    use crate::io::TestIOHandle;
+    use crate::register::Register::{RCond, RPC, RR0, RR1};
+    use crate::rng::Rng;
+    use crate::vm::VM;
+
+    #[test]
+    fn can_snapshot_and_restore() -> LC3Result<()> {
+        let program = vec![
+            // Add 5 to RR0 and put the result in RR0
+            0b0001_0000_0010_0101,
+            // Add 5 to RR0 again
+            0b0001_0000_0010_0101,
+            // Halt
+            0xF025,
+        ];
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_program(&program)?;
+        vm.step()?;
+        let snapshot = vm.snapshot();
+        assert_eq!(snapshot.register(RR0), 5);
+        assert_eq!(snapshot.pc(), 0x3001);
+        assert!(snapshot.is_running());
+
+        vm.step()?;
+        assert_eq!(vm.register(RR0), 10);
+        vm.restore(&snapshot);
+        assert_eq!(vm.register(RR0), 5);
+        assert_eq!(vm.snapshot(), snapshot);
+
+        let reloaded = Snapshot::from_json(&snapshot.to_json())?;
+        assert_eq!(reloaded, snapshot);
+        assert_eq!(reloaded.memory_at(0x3002), 0xF025);
+
+        let mut restored = VM::new_with_io(TestIOHandle::new());
+        restored.restore(&reloaded);
+        restored.step()?;
+        assert_eq!(restored.register(RR0), 10);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_share_unchanged_pages() -> LC3Result<()> {
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_program(&vec![0xF025])?;
+        let first = vm.snapshot();
+        vm.set_memory_at(0x4000, 1);
+        let second = vm.snapshot();
+
+        // Only the page that was written to is copied
+        let shared = |a: &Snapshot, b: &Snapshot| {
+            a.memory
+                .iter()
+                .zip(&b.memory)
+                .filter(|(a, b)| Arc::ptr_eq(a, b))
+                .count()
+        };
+        assert_eq!(shared(&first, &second), first.memory.len() - 1);
+        assert_eq!(first.memory_at(0x4000), 0);
+        assert_eq!(second.memory_at(0x4000), 1);
+        assert_eq!(first.diff(&second).changed_addresses(), vec![0x4000]);
+
+        // Restoring shares the snapshot's pages again
+        vm.memory_mut().fill(2);
+        vm.restore(&first);
+        assert_eq!(vm.memory_at(0x4000), 0);
+        assert_eq!(shared(&first, &vm.snapshot()), first.memory.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_diff_snapshots() -> LC3Result<()> {
+        let source = r#"
+            .ORIG x3000
+            LEA R1, BUFFER
+            AND R0, R0, #0
+            ADD R0, R0, #7
+            STR R0, R1, #0
+            STR R0, R1, #2
+            HALT
+BUFFER      .BLKW 3
+            .END
+        "#;
+        let assembly = assemble(source)?;
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_object(&assembly.to_object())?;
+
+        let before = vm.snapshot();
+        assert!(before.diff(&before).is_empty());
+        for _ in 0..5 {
+            vm.step()?;
+        }
+        let diff = before.diff(&vm.snapshot());
+
+        assert_eq!(diff.changed_registers(), vec![RR0, RR1, RPC, RCond]);
+        // The MCR's clock enable bit is set once the machine starts
+        assert_eq!(diff.changed_addresses(), vec![0x3006, 0x3008, 0xFFFE]);
+        assert_eq!(
+            diff.memory[0],
+            MemoryChange {
+                address: 0x3006,
+                before: 0,
+                after: 7
+            }
+        );
+        assert_eq!(diff.running, Some((false, true)));
+        assert_eq!(
+            diff.to_string(),
+            "R0: x0000 -> x0007\n\
+             R1: x0000 -> x3006\n\
+             PC: x3000 -> x3005\n\
+             CC: x0000 -> x0001\n\
+             x3006: x0000 -> x0007\n\
+             x3008: x0000 -> x0007\n\
+             xFFFE: x0000 -> x8000\n\
+             running: false -> true"
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn can_serialize_snapshots_with_serde() -> LC3Result<()> {
+        let program = assemble(
+            r#"
+            .ORIG x3000
+            AND R0, R0, #0
+            ADD R0, R0, #-3
+            HALT
+            .END
+        "#,
+        )?;
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_object(&program.to_object())?;
+        vm.step()?;
+        vm.step()?;
+        let snapshot = vm.snapshot();
+
+        let text = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(text, snapshot.to_json());
+        let restored: Snapshot = serde_json::from_str(&text).unwrap();
+        assert!(restored == snapshot);
+        assert!(serde_json::from_str::<Snapshot>(r#"{"version":3}"#).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_read_version_1_snapshots() -> LC3Result<()> {
+        let text = r#"{"version":1,"registers":[0,0,0,0,0,0,0,0,12288,0,0],"running":false,"start_pc":12288,"memory":[{"address":12288,"words":[61477]}]}"#;
+        let first = Snapshot::from_json(text)?;
+        let second = Snapshot::from_json(text)?;
+        assert_eq!(first.start_pc, 0x3000);
+        assert_eq!(first.memory_at(0x3000), 0xF025);
+        assert_eq!(first.rng.state(), Rng::new(0).state());
+        assert_eq!(first.rng.state(), second.rng.state());
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_malformed_snapshots() {
+        let test_cases = vec![
+            "not json",
+            r#"{"version":3}"#,
+            r#"{"version":1,"registers":[1,2],"running":false,"start_pc":0,"memory":[]}"#,
+            r#"{"version":1,"registers":[0,0,0,0,0,0,0,0,0,0,0],"running":false,"start_pc":0,"memory":[{"address":65535,"words":[1,2]}]}"#,
+        ];
+
+        for text in test_cases {
+            assert!(matches!(
+                Snapshot::from_json(text),
+                Err(LC3Error::BadSnapshot(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,507 @@
+use std::any::Any;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::rc::Rc;
+use std::time::Instant;
+
+use crate::bank::Banks;
+use crate::condition_flags::ConditionFlags;
+use crate::coredump::CoreDump;
+use crate::error::{LC3Error, LC3Result};
+use crate::interrupt::{is_privileged, PSR_USER, SUPERVISOR_STACK_START};
+use crate::io::{
+    CharEncoding, EchoMode, IOHandle, InjectedInput, PackedByteOrder, RecordedInput,
+};
+use crate::isa::{Isa, SpecProfile};
+use crate::memory::{to_pages, Memory, MemoryFill, MemoryMap};
+use crate::plugin::{Event, MessageBus, PluginId};
+use crate::register::Register::{RCond, RPC, RR6};
+use crate::register::{Register, NUM_REGISTERS};
+use crate::rng::Rng;
+use crate::snapshot::Snapshot;
+use crate::vm::{BANK_SELECT_POS, MEMORY_SIZE, PC_START};
+
+// Register (index, value) and memory (address, value) writes
+type Writes = (Vec<(u8, u16)>, Vec<(u16, u16)>);
+
+// Everything about the machine apart from its plugins: memory, registers and
+// IO. This is what plugins get handed along with each event, so they can
+// look at and change the machine while the VM keeps hold of the plugins.
+pub struct VmState<IOType: IOHandle> {
+    pub(crate) memory: Memory,
+    pub(crate) registers: [u16; NUM_REGISTERS],
+    pub(crate) running: bool,
+    // Where run starts executing from
+    pub(crate) start_pc: u16,
+    // The privilege and priority fields of the PSR. The condition codes
+    // that make up the rest of it live in the condition register.
+    pub(crate) user_mode: bool,
+    pub(crate) priority: u8,
+    // Whichever of the supervisor and user stack pointers isn't in R6 at
+    // the moment
+    pub(crate) saved_ssp: u16,
+    pub(crate) saved_usp: u16,
+    // Whether the reserved op code raises the illegal op code exception, or
+    // fails the run with an IllegalOpcode error
+    pub(crate) illegal_opcode_exceptions: bool,
+    // Whether TRAP jumps to the service routines in memory, or is handled
+    // natively
+    pub(crate) os_traps: bool,
+    // Which revision of the spec LEA and TRAP follow
+    pub(crate) spec: SpecProfile,
+    pub(crate) chars_read: u64,
+    pub(crate) chars_written: u64,
+    pub(crate) echo: EchoMode,
+    pub(crate) encoding: CharEncoding,
+    pub(crate) packed_byte_order: PackedByteOrder,
+    // Behind the RNG device register
+    pub(crate) rng: Rng,
+    // Instructions executed since the timer last ticked
+    pub(crate) timer_count: u16,
+    // What the clock registers measure from. With the deterministic clock
+    // they count instructions executed since the VM was created instead, as
+    // they always do on wasm, where there's no clock to read and this is None.
+    pub(crate) created: Option<Instant>,
+    pub(crate) instructions_executed: u64,
+    // Where the instruction being executed, or the last one to finish, was
+    // fetched from, so a failed run can say which instruction failed
+    pub(crate) last_fetch: u16,
+    pub(crate) deterministic_clock: bool,
+    // Everything written by load_program_at as (origin, words) pairs, kept
+    // so reset can put memory back into its post-load state.
+    pub(crate) loaded_segments: Vec<(u16, Vec<u16>)>,
+    // What memory below the device registers is set to before anything's
+    // loaded into it
+    pub(crate) memory_fill: MemoryFill,
+    pub(crate) memory_map: MemoryMap,
+    // Fixed when the VM is made, as memory is laid out differently for each
+    pub(crate) isa: Isa,
+    // The banks switched into memory by the bank select register, if it's
+    // been turned on with set_banked_memory
+    pub(crate) banks: Option<Banks>,
+    // While set, input comes from here instead of the IO handle and output
+    // is dropped, since it was already written the first time round
+    pub(crate) replay: Option<VecDeque<RecordedInput>>,
+    pub(crate) io_handle: IOType,
+    // Keys from other threads, once anyone's asked for an InputSender
+    pub(crate) injected: Option<InjectedInput>,
+    // A key taken while plugins said the keyboard wasn't ready, kept for
+    // the next read rather than dropped
+    pub(crate) held_key: Option<char>,
+    // Register and memory writes made by the instruction being executed,
+    // collected for the PostInstruction event
+    pub(crate) writes: Option<Writes>,
+    pub(crate) bus: MessageBus,
+    // Events caused by plugins through the methods below, waiting for the
+    // plugin manager to deliver them
+    pub(crate) raised: VecDeque<Event>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn start_time() -> Option<Instant> {
+    Some(Instant::now())
+}
+
+// Instant::now panics on wasm
+#[cfg(target_arch = "wasm32")]
+fn start_time() -> Option<Instant> {
+    None
+}
+
+impl<IOType: IOHandle> VmState<IOType> {
+    pub(crate) fn new(io_handle: IOType) -> Self {
+        let mut registers = [0u16; NUM_REGISTERS];
+        // Start at the default program location so the VM can be stepped
+        // straight after loading a program, without going through run().
+        registers[RPC.to_u8() as usize] = PC_START;
+        Self {
+            memory: Memory::new(),
+            registers,
+            running: false,
+            start_pc: PC_START,
+            user_mode: false,
+            priority: 0,
+            saved_ssp: SUPERVISOR_STACK_START,
+            saved_usp: 0,
+            illegal_opcode_exceptions: false,
+            os_traps: false,
+            spec: SpecProfile::Patt2ndEd,
+            chars_read: 0,
+            chars_written: 0,
+            echo: EchoMode::Standard,
+            encoding: CharEncoding::Latin1,
+            packed_byte_order: PackedByteOrder::LowFirst,
+            rng: Rng::from_time(),
+            timer_count: 0,
+            created: start_time(),
+            instructions_executed: 0,
+            last_fetch: PC_START,
+            deterministic_clock: false,
+            loaded_segments: Vec::new(),
+            memory_fill: MemoryFill::Zero,
+            memory_map: MemoryMap::default(),
+            isa: Isa::Lc3,
+            banks: None,
+            replay: None,
+            io_handle,
+            injected: None,
+            held_key: None,
+            writes: None,
+            bus: MessageBus::default(),
+            raised: VecDeque::new(),
+        }
+    }
+
+    // The inspection methods below give direct access to the machine state
+    // for debuggers and other embedders. They bypass the plugins and the
+    // memory mapped devices, so reading KBSR here won't consume a key press.
+    pub fn register(&self, reg: Register) -> u16 {
+        self.registers[reg.to_u8() as usize]
+    }
+
+    pub fn set_register(&mut self, reg: Register, val: u16) {
+        self.registers[reg.to_u8() as usize] = val;
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.register(RPC)
+    }
+
+    pub fn set_pc(&mut self, val: u16) {
+        self.set_register(RPC, val);
+    }
+
+    // The address of the instruction being executed, or the last one
+    // executed, which is the one that failed after a run ends in an error
+    pub fn last_fetch(&self) -> u16 {
+        self.last_fetch
+    }
+
+    pub fn condition_flags(&self) -> ConditionFlags {
+        ConditionFlags::from_bits(self.register(RCond))
+    }
+
+    pub fn memory_at(&self, address: u16) -> u16 {
+        self.memory[address as usize]
+    }
+
+    pub fn set_memory_at(&mut self, address: u16, val: u16) {
+        self.memory.set(address, val);
+    }
+
+    // All of memory, indexed by address, for looking at or changing large
+    // parts of it at once, such as saving it or drawing it
+    pub fn memory(&self) -> &[u16] {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u16] {
+        self.memory.range_mut(0..MEMORY_SIZE)
+    }
+
+    // Overwrite memory with words from address on, for depositing code,
+    // injecting faults or fixing up a loaded program between runs. Patches
+    // to a loaded program are kept when reset reloads it.
+    pub fn patch(&mut self, address: u16, words: &[u16]) -> LC3Result<()> {
+        self.isa.check_aligned(address)?;
+        let words = &self.isa.layout(words);
+        let start = address as usize;
+        let end = range_end(address, words.len())?;
+        self.memory.range_mut(start..end).copy_from_slice(words);
+        for (origin, segment) in &mut self.loaded_segments {
+            let origin = *origin as usize;
+            let (from, to) = (start.max(origin), end.min(origin + segment.len()));
+            if from < to {
+                segment[from - origin..to - origin]
+                    .copy_from_slice(&words[from - start..to - start]);
+            }
+        }
+        Ok(())
+    }
+
+    // The len words from address on
+    pub fn read_range(&self, address: u16, len: usize) -> LC3Result<Vec<u16>> {
+        self.isa.check_aligned(address)?;
+        let step = self.isa.word_size() as usize;
+        let end = range_end(address, (len * step).saturating_sub(step - 1))?;
+        Ok(self.memory[address as usize..end]
+            .iter()
+            .step_by(step)
+            .copied()
+            .collect())
+    }
+
+    pub fn isa(&self) -> Isa {
+        self.isa
+    }
+
+    pub fn spec_profile(&self) -> SpecProfile {
+        self.spec
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    // The processor status register: privilege in bit 15, priority in bits
+    // 10 to 8 and the condition codes in bits 2 to 0. Programs start out in
+    // supervisor mode at priority 0, so they can reach the device registers.
+    pub fn psr(&self) -> u16 {
+        let privilege = if self.user_mode { PSR_USER } else { 0 };
+        privilege | (u16::from(self.priority) << 8) | self.register(RCond)
+    }
+
+    pub fn set_psr(&mut self, psr: u16) {
+        self.user_mode = psr & PSR_USER != 0;
+        self.priority = ((psr >> 8) & 0x7) as u8;
+        self.set_register(RCond, psr & 0x7);
+    }
+
+    // User mode programs can't touch the operating system's memory or the
+    // device registers. Errors with AccessViolation, which the VM turns into
+    // an access control violation exception.
+    pub(crate) fn check_access(&self, address: u16) -> LC3Result<()> {
+        if self.user_mode && is_privileged(&self.memory_map, address) {
+            return Err(LC3Error::AccessViolation { address });
+        }
+        Ok(())
+    }
+
+    // Where the supervisor stack starts the next time an interrupt switches
+    // to it from user mode
+    pub fn set_supervisor_stack(&mut self, address: u16) {
+        self.saved_ssp = address;
+    }
+
+    // Unlike the inspection methods above, these raise events, so the other
+    // plugins hear about a plugin's reads and writes once they've all
+    // finished with the current event. They can't be intercepted, since
+    // they've already happened by then.
+    pub fn read_memory(&mut self, address: u16) -> u16 {
+        let value = self.memory_at(address);
+        self.raised.push_back(Event::MemGet {
+            location: address,
+            value,
+        });
+        value
+    }
+
+    pub fn write_memory(&mut self, address: u16, val: u16) {
+        self.store_memory(address, val);
+        self.raised.push_back(Event::MemSet {
+            location: address,
+            value: val,
+        });
+    }
+
+    pub fn read_register(&mut self, reg: Register) -> u16 {
+        let value = self.register(reg);
+        self.raised.push_back(Event::RegGet {
+            index: reg.to_u8(),
+            value,
+        });
+        value
+    }
+
+    pub fn write_register(&mut self, reg: Register, val: u16) {
+        self.store_register(reg.to_u8(), val);
+        self.raised.push_back(Event::RegSet {
+            index: reg.to_u8(),
+            value: val,
+        });
+    }
+
+    // Have messages published on topic passed to the plugin's on_message.
+    // Plugins usually do this for themselves in on_attach.
+    pub fn subscribe(&mut self, id: PluginId, topic: &str) {
+        self.bus.subscribe(id, topic);
+    }
+
+    pub fn unsubscribe(&mut self, id: PluginId, topic: &str) {
+        self.bus.unsubscribe(id, topic);
+    }
+
+    // Send message to every plugin subscribed to topic. Messages published
+    // by a plugin are delivered once all the plugins have heard about the
+    // current event.
+    pub fn publish<T: Any>(&mut self, topic: &str, message: T) {
+        self.bus.publish(topic, Rc::new(message));
+    }
+
+    // Copy out the memory, registers and the rest of the machine's state so
+    // they can be put back later with restore, possibly into a different VM
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory: self.memory.pages(),
+            registers: self.registers.to_vec().into_boxed_slice(),
+            running: self.running,
+            start_pc: self.start_pc,
+            user_mode: self.user_mode,
+            priority: self.priority,
+            saved_ssp: self.saved_ssp,
+            saved_usp: self.saved_usp,
+            rng: self.rng.clone(),
+            timer_count: self.timer_count,
+            instructions_executed: self.instructions_executed,
+        }
+    }
+
+    // The machine after a run failed with error, keeping window words of
+    // memory either side of the failed instruction, or all of it for None
+    pub fn core_dump(&self, error: &LC3Error, window: Option<u16>) -> CoreDump {
+        let address = self.last_fetch;
+        let window =
+            window.map(|words| (address.saturating_sub(words), address.saturating_add(words)));
+        let mut snapshot = self.snapshot();
+        if let Some((start, end)) = window {
+            let mut memory = vec![0; MEMORY_SIZE];
+            let kept = start as usize..=end as usize;
+            memory[kept.clone()].copy_from_slice(&self.memory[kept]);
+            snapshot.memory = to_pages(&memory);
+        }
+        CoreDump {
+            error: error.to_string(),
+            address,
+            instruction: self.memory_at(address),
+            window,
+            snapshot,
+        }
+    }
+
+    // Snapshots only hold the bank that's in the window. The others are left
+    // as they are.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        if let Some(banks) = self.banks.as_mut() {
+            banks.save(&self.memory);
+        }
+        self.memory.restore(&snapshot.memory);
+        let bank_select = self.device(BANK_SELECT_POS);
+        if let Some(banks) = self.banks.as_mut() {
+            banks.selected = self.memory[bank_select as usize] as usize % banks.config.banks;
+        }
+        self.registers.copy_from_slice(&snapshot.registers);
+        self.running = snapshot.running;
+        self.start_pc = snapshot.start_pc;
+        self.user_mode = snapshot.user_mode;
+        self.priority = snapshot.priority;
+        self.saved_ssp = snapshot.saved_ssp;
+        self.saved_usp = snapshot.saved_usp;
+        self.rng = snapshot.rng.clone();
+        self.timer_count = snapshot.timer_count;
+        self.instructions_executed = snapshot.instructions_executed;
+    }
+
+    // Put the machine back into the state it was in straight after
+    // load_program, so the same program can be run again. Registers and
+    // condition codes are always cleared, apart from R6 pointing at the
+    // supervisor stack again if an OS is loaded. Memory is only wiped and
+    // reloaded if restore_memory is set, otherwise anything the program
+    // wrote is left in place. Plugins aren't notified, since this happens
+    // outside of program execution.
+    pub fn reset(&mut self, restore_memory: bool) {
+        self.registers = [0u16; NUM_REGISTERS];
+        self.registers[RPC.to_u8() as usize] = self.start_pc;
+        if self.os_traps {
+            self.registers[RR6.to_u8() as usize] = SUPERVISOR_STACK_START;
+        }
+        self.running = false;
+        self.user_mode = false;
+        self.priority = 0;
+        self.saved_ssp = SUPERVISOR_STACK_START;
+        self.saved_usp = 0;
+        self.timer_count = 0;
+
+        if restore_memory {
+            self.memory.range_mut(0..MEMORY_SIZE).fill(0);
+            self.apply_memory_fill();
+            for (origin, words) in &self.loaded_segments {
+                let start = *origin as usize;
+                self.memory
+                    .range_mut(start..start + words.len())
+                    .copy_from_slice(words);
+            }
+            if let Some(banks) = self.banks.as_mut() {
+                banks.reset(self.memory_fill, &mut self.memory);
+            }
+        }
+    }
+
+    // Set the ordinary memory as memory_fill says, leaving the device
+    // registers alone
+    pub(crate) fn apply_memory_fill(&mut self) {
+        let mut words = vec![0; self.memory_map.size];
+        self.memory_fill.fill(&mut words);
+        for range in self.memory_map.ram() {
+            self.memory
+                .range_mut(range.clone())
+                .copy_from_slice(&words[range]);
+        }
+    }
+
+    // Put range back to what memory_fill left there, as if nothing had
+    // been loaded over it
+    pub(crate) fn clear_memory(&mut self, range: Range<usize>) {
+        let mut words = vec![0; self.memory_map.size];
+        self.memory_fill.fill(&mut words);
+        for ram in self.memory_map.ram() {
+            let cleared = ram.start.max(range.start)..ram.end.min(range.end);
+            if !cleared.is_empty() {
+                self.memory
+                    .range_mut(cleared.clone())
+                    .copy_from_slice(&words[cleared]);
+            }
+        }
+    }
+
+    // Which bank is in the window, if memory is banked
+    pub fn selected_bank(&self) -> Option<usize> {
+        self.banks.as_ref().map(|banks| banks.selected)
+    }
+
+    // Everything in bank, whether or not it's in the window
+    pub fn read_bank(&self, bank: usize) -> LC3Result<Vec<u16>> {
+        match &self.banks {
+            Some(banks) => banks.read(bank, &self.memory),
+            None => Err(LC3Error::NoSuchBank { bank, banks: 0 }),
+        }
+    }
+
+    // Swap bank into the window, as writing it to the bank select register
+    // does
+    pub(crate) fn select_bank(&mut self, bank: u16) {
+        let bank_select = self.device(BANK_SELECT_POS);
+        if let Some(banks) = self.banks.as_mut() {
+            let bank = banks.select(bank as usize, &mut self.memory);
+            self.memory.set(bank_select, bank as u16);
+        }
+    }
+
+    // Where a device register is, given where the LC-3 has it
+    pub(crate) fn device(&self, standard: u16) -> u16 {
+        self.memory_map.device(standard)
+    }
+
+    // Writes that count towards the current instruction's effects
+    pub(crate) fn store_memory(&mut self, address: u16, val: u16) {
+        self.memory.set(address, val);
+        if let Some((_, memory)) = self.writes.as_mut() {
+            memory.push((address, val));
+        }
+    }
+
+    pub(crate) fn store_register(&mut self, index: u8, val: u16) {
+        self.registers[index as usize] = val;
+        if let Some((registers, _)) = self.writes.as_mut() {
+            registers.push((index, val));
+        }
+    }
+}
+
+// Where len words from address end, unless that's past the end of memory
+fn range_end(address: u16, len: usize) -> LC3Result<usize> {
+    let end = address as usize + len;
+    if end > MEMORY_SIZE {
+        return Err(LC3Error::MemoryRange { address, len });
+    }
+    Ok(end)
+}
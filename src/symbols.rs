@@ -0,0 +1,194 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::error::{BoxErrors, LC3Error, LC3Result};
+
+// Two-way mapping between labels and addresses, usually loaded from the
+// .sym file lc3as writes next to the object file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SymbolTable {
+    addresses: HashMap<String, u16>,
+    labels: BTreeMap<u16, String>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> LC3Result<Self> {
+        let text = std::fs::read_to_string(path).map_io_error()?;
+        Self::parse(&text)
+    }
+
+    // lc3as symbol files look like this:
+    //
+    // // Symbol table
+    // // Scope level 0:
+    // //	Symbol Name       Page Address
+    // //	----------------  ------------
+    // //	LOOP              3004
+    //
+    // Every line is commented out so the file can be included in assembly
+    // source, which means we can't tell entries from headers by the comment
+    // marker. Instead any line that looks like "<label> <hex address>" is
+    // treated as an entry and the rest are skipped.
+    pub fn parse(text: &str) -> LC3Result<Self> {
+        let mut table = Self::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let commented = line.trim_start().starts_with("//");
+            let content = line.trim_start().trim_start_matches("//").trim();
+            let fields: Vec<&str> = content.split_whitespace().collect();
+
+            let entry = match fields.as_slice() {
+                [label, address] => parse_address(address).map(|address| (*label, address)),
+                _ => None,
+            };
+
+            match entry {
+                Some((label, address)) => table.insert(label, address),
+                // Uncommented lines should only ever hold entries
+                None if !commented && !content.is_empty() => {
+                    return Err(LC3Error::BadSymbolTable {
+                        line: index + 1,
+                        text: line.to_string(),
+                    })
+                }
+                None => {}
+            }
+        }
+
+        Ok(table)
+    }
+
+    // Redefining a label moves it, so its old address loses the label
+    // unless another label has taken that address since
+    pub fn insert(&mut self, label: &str, address: u16) {
+        if let Some(old) = self.addresses.insert(label.to_string(), address) {
+            if old != address && self.label_at(old) == Some(label) {
+                self.labels.remove(&old);
+            }
+        }
+        self.labels.insert(address, label.to_string());
+    }
+
+    pub fn address_of(&self, label: &str) -> Option<u16> {
+        self.addresses.get(label).copied()
+    }
+
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(|label| label.as_str())
+    }
+
+    // The label for an address if there is one, otherwise the address in
+    // the usual LC-3 hex notation.
+    pub fn describe(&self, address: u16) -> String {
+        match self.label_at(address) {
+            Some(label) => label.to_string(),
+            None => format!("x{:04X}", address),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    // Render the table in the same format lc3as uses, so it can be read
+    // back with parse
+    pub fn to_sym_file(&self) -> String {
+        let mut text = String::from(
+            "// Symbol table\n\
+             // Scope level 0:\n\
+             //\tSymbol Name       Page Address\n\
+             //\t----------------  ------------\n",
+        );
+        for (address, label) in self.iter() {
+            text.push_str(&format!("//\t{:<16}  {:04X}\n", label, address));
+        }
+        text
+    }
+
+    // Entries ordered by address
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.labels
+            .iter()
+            .map(|(address, label)| (*address, label.as_str()))
+    }
+}
+
+// Addresses are written as bare hex by lc3as, but accept the x prefix
+// used elsewhere in LC-3 tooling too.
+fn parse_address(text: &str) -> Option<u16> {
+    let digits = text
+        .strip_prefix('x')
+        .or_else(|| text.strip_prefix('X'))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::SymbolTable;
+    use crate::error::{LC3Error, LC3Result};
+
+    #[test]
+    fn can_parse_lc3as_symbols() -> LC3Result<()> {
+        let text = "// Symbol table\n\
+                    // Scope level 0:\n\
+                    //\tSymbol Name       Page Address\n\
+                    //\t----------------  ------------\n\
+                    //\tLOOP              3004\n\
+                    //\tMSG               300A\n\
+                    \n";
+
+        let table = SymbolTable::parse(text)?;
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.address_of("LOOP"), Some(0x3004));
+        assert_eq!(table.label_at(0x300A), Some("MSG"));
+        assert_eq!(table.describe(0x3004), "LOOP");
+        assert_eq!(table.describe(0x3005), "x3005");
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_round_trip_symbol_file() -> LC3Result<()> {
+        let mut table = SymbolTable::new();
+        table.insert("START", 0x3000);
+        table.insert("DATA", 0x3010);
+
+        assert_eq!(SymbolTable::parse(&table.to_sym_file())?, table);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_redefine_labels() {
+        let mut table = SymbolTable::new();
+        table.insert("LOOP", 0x3000);
+        table.insert("LOOP", 0x3004);
+        assert_eq!(table.address_of("LOOP"), Some(0x3004));
+        assert_eq!(table.label_at(0x3004), Some("LOOP"));
+        assert_eq!(table.label_at(0x3000), None);
+
+        // A label that's taken the old address keeps it
+        table.insert("DONE", 0x3004);
+        table.insert("LOOP", 0x3008);
+        assert_eq!(table.label_at(0x3004), Some("DONE"));
+        assert_eq!(table.label_at(0x3008), Some("LOOP"));
+    }
+
+    #[test]
+    fn rejects_malformed_entries() {
+        let result = SymbolTable::parse("LOOP x3004\nnot a symbol\n");
+        assert!(matches!(
+            result,
+            Err(LC3Error::BadSymbolTable { line: 2, .. })
+        ));
+    }
+}
@@ -0,0 +1,319 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+// Just enough TOML for configuration files, in the same spirit as json.rs:
+// [tables] of key = value pairs, where values are strings, integers, floats,
+// booleans or arrays of them. Dotted keys, inline tables, dates and multi-line
+// strings aren't supported.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Toml {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    Array(Vec<Toml>),
+}
+
+// The keys of a table, in the order they appear
+pub(crate) type TomlTable = Vec<(String, Toml)>;
+
+impl Toml {
+    // Tables in the order they appear. Keys before the first [table] header
+    // go in a table with an empty name.
+    pub(crate) fn parse(text: &str) -> Result<Vec<(String, TomlTable)>, String> {
+        let mut parser = Parser {
+            chars: text.chars().peekable(),
+            line: 1,
+        };
+        parser
+            .document()
+            .map_err(|message| format!("line {}: {}", parser.line, message))
+    }
+
+    // The kind of value, for error messages
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Toml::String(_) => "a string",
+            Toml::Integer(_) => "an integer",
+            Toml::Float(_) => "a number",
+            Toml::Bool(_) => "a boolean",
+            Toml::Array(_) => "an array",
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn document(&mut self) -> Result<Vec<(String, TomlTable)>, String> {
+        let mut tables: Vec<(String, TomlTable)> = vec![(String::new(), Vec::new())];
+        loop {
+            self.skip_blank_lines();
+            match self.chars.peek() {
+                None => return Ok(tables),
+                Some('[') => {
+                    self.chars.next();
+                    self.skip_spaces();
+                    let name = self.key()?;
+                    self.skip_spaces();
+                    self.expect(']')?;
+                    if tables.iter().any(|(existing, _)| *existing == name) {
+                        return Err(format!("Table [{}] appears twice", name));
+                    }
+                    tables.push((name, Vec::new()));
+                }
+                Some(_) => {
+                    let key = self.key()?;
+                    self.skip_spaces();
+                    self.expect('=')?;
+                    self.skip_spaces();
+                    let value = self.value()?;
+                    let (_, table) = tables.last_mut().expect("There's always a table");
+                    if table.iter().any(|(existing, _)| *existing == key) {
+                        return Err(format!("Key {} appears twice", key));
+                    }
+                    table.push((key, value));
+                }
+            }
+            self.end_of_line()?;
+        }
+    }
+
+    fn key(&mut self) -> Result<String, String> {
+        if self.chars.peek() == Some(&'"') {
+            self.chars.next();
+            return self.basic_string();
+        }
+        let mut key = String::new();
+        while let Some(&ch) = self.chars.peek() {
+            if !(ch.is_ascii_alphanumeric() || ch == '_' || ch == '-') {
+                break;
+            }
+            key.push(ch);
+            self.chars.next();
+        }
+        if key.is_empty() {
+            return Err("Expected a key".to_string());
+        }
+        Ok(key)
+    }
+
+    fn value(&mut self) -> Result<Toml, String> {
+        match self.chars.peek() {
+            Some('"') => {
+                self.chars.next();
+                self.basic_string().map(Toml::String)
+            }
+            Some('\'') => {
+                self.chars.next();
+                self.literal_string().map(Toml::String)
+            }
+            Some('[') => {
+                self.chars.next();
+                self.array()
+            }
+            Some(_) => self.bare_value(),
+            None => Err("Expected a value".to_string()),
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, String> {
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(text),
+                Some('\\') => match self.chars.next() {
+                    Some('"') => text.push('"'),
+                    Some('\\') => text.push('\\'),
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some('r') => text.push('\r'),
+                    other => return Err(format!("Unknown escape \\{}", other.unwrap_or(' '))),
+                },
+                Some('\n') | None => return Err("Unterminated string".to_string()),
+                Some(ch) => text.push(ch),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, String> {
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                Some('\'') => return Ok(text),
+                Some('\n') | None => return Err("Unterminated string".to_string()),
+                Some(ch) => text.push(ch),
+            }
+        }
+    }
+
+    // Arrays can be split over several lines
+    fn array(&mut self) -> Result<Toml, String> {
+        let mut values = Vec::new();
+        loop {
+            self.skip_blank_lines();
+            if self.chars.peek() == Some(&']') {
+                self.chars.next();
+                return Ok(Toml::Array(values));
+            }
+            values.push(self.value()?);
+            self.skip_blank_lines();
+            match self.chars.next() {
+                Some(',') => {}
+                Some(']') => return Ok(Toml::Array(values)),
+                _ => return Err("Expected , or ] in array".to_string()),
+            }
+        }
+    }
+
+    // Booleans and numbers
+    fn bare_value(&mut self) -> Result<Toml, String> {
+        let mut text = String::new();
+        while let Some(&ch) = self.chars.peek() {
+            if !(ch.is_ascii_alphanumeric() || "_+-.".contains(ch)) {
+                break;
+            }
+            text.push(ch);
+            self.chars.next();
+        }
+
+        let invalid = || format!("Invalid value {}", text);
+        let digits = text.replace('_', "");
+        let (negative, unsigned) = match digits.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, digits.strip_prefix('+').unwrap_or(&digits)),
+        };
+        let radix = match unsigned.get(..2) {
+            Some("0x") => 16,
+            Some("0o") => 8,
+            Some("0b") => 2,
+            _ => 10,
+        };
+        match text.as_str() {
+            "true" => Ok(Toml::Bool(true)),
+            "false" => Ok(Toml::Bool(false)),
+            _ if radix != 10 => i64::from_str_radix(&unsigned[2..], radix)
+                .map(|value| Toml::Integer(if negative { -value } else { value }))
+                .map_err(|_| invalid()),
+            _ if unsigned.contains(['.', 'e', 'E']) => {
+                digits.parse().map(Toml::Float).map_err(|_| invalid())
+            }
+            _ => digits.parse().map(Toml::Integer).map_err(|_| invalid()),
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.chars.next() {
+            Some(ch) if ch == expected => Ok(()),
+            _ => Err(format!("Expected {}", expected)),
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.chars.peek(), Some(' ') | Some('\t')) {
+            self.chars.next();
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.chars.peek() == Some(&'#') {
+            while !matches!(self.chars.peek(), Some('\n') | None) {
+                self.chars.next();
+            }
+        }
+    }
+
+    fn skip_blank_lines(&mut self) {
+        loop {
+            self.skip_spaces();
+            self.skip_comment();
+            match self.chars.peek() {
+                Some('\n') => self.line += 1,
+                Some('\r') => {}
+                _ => return,
+            }
+            self.chars.next();
+        }
+    }
+
+    // Only a comment can follow a key and value or a table header
+    fn end_of_line(&mut self) -> Result<(), String> {
+        self.skip_spaces();
+        self.skip_comment();
+        if self.chars.peek() == Some(&'\r') {
+            self.chars.next();
+        }
+        match self.chars.peek() {
+            Some('\n') | None => Ok(()),
+            Some(ch) => Err(format!("Unexpected {} at the end of the line", ch)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Toml;
+
+    #[test]
+    fn can_parse_toml() {
+        let text = r#"
+# Settings for everyone
+top = "level"
+
+[run]
+os = true
+seed = 0x2A
+timeout = 1.5
+echo = 'always' # trailing comment
+"quoted key" = "a\"b\\c\n"
+break = [
+    "LOOP",
+    "DONE if R0 == 0",  # comments in arrays too
+]
+max-instructions = 1_000_000
+"#;
+        let tables = Toml::parse(text).unwrap();
+        assert_eq!(tables.len(), 2);
+        assert_eq!(tables[0].0, "");
+        assert_eq!(
+            tables[0].1,
+            vec![("top".to_string(), Toml::String("level".to_string()))]
+        );
+
+        let (name, run) = &tables[1];
+        assert_eq!(name, "run");
+        let values: Vec<&Toml> = run.iter().map(|(_, value)| value).collect();
+        assert_eq!(
+            values,
+            vec![
+                &Toml::Bool(true),
+                &Toml::Integer(42),
+                &Toml::Float(1.5),
+                &Toml::String("always".to_string()),
+                &Toml::String("a\"b\\c\n".to_string()),
+                &Toml::Array(vec![
+                    Toml::String("LOOP".to_string()),
+                    Toml::String("DONE if R0 == 0".to_string()),
+                ]),
+                &Toml::Integer(1_000_000),
+            ]
+        );
+        assert_eq!(run[4].0, "quoted key");
+    }
+
+    #[test]
+    fn can_report_bad_toml() {
+        assert_eq!(
+            Toml::parse("a = 1\nb = \"open\n"),
+            Err("line 2: Unterminated string".to_string())
+        );
+        assert!(Toml::parse("a = 1\na = 2").is_err());
+        assert!(Toml::parse("[run]\n[run]").is_err());
+        assert!(Toml::parse("a = 1 2").is_err());
+        assert!(Toml::parse("a = [1, 2").is_err());
+        assert!(Toml::parse("a = nope").is_err());
+    }
+}
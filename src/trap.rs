@@ -1,4 +1,13 @@
 use crate::error::{LC3Error, LC3Result};
+use crate::state::VmState;
+
+// The vectors below this are the standard service routines, or reserved
+pub(crate) const FIRST_HOST_TRAP: u8 = 0x26;
+
+// A trap serviced by the host rather than a routine in LC-3 memory. Like a
+// service routine, it takes its arguments from the registers and memory and
+// leaves its results there.
+pub(crate) type TrapHandler<IOType> = Box<dyn FnMut(&mut VmState<IOType>) -> LC3Result<()>>;
 
 pub(crate) enum TrapCode {
     GetC = 0x20,  /* get character from keyboard, not echoed onto the terminal */
@@ -1,37 +1,135 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::bank::{BankedMemory, Banks};
 use crate::command::Command;
 use crate::condition_flags::{FL_NEG, FL_POS, FL_ZRO};
 use crate::error::{BoxErrors, LC3Error, LC3Result};
-use crate::io::{IOHandle, RealIOHandle};
-use crate::op::{handler, Op};
-use crate::plugin::{Event, Plugin};
-use crate::register::Register::{RCond, RPC};
-use crate::register::{Register, NUM_REGISTERS};
+use crate::file_traps;
+use crate::instruction::{DecodedInstruction, Effects};
+use crate::interrupt::{
+    self, ACCESS_VIOLATION_VECTOR, ILLEGAL_OPCODE_VECTOR, PRIVILEGE_VECTOR, SUPERVISOR_STACK_START,
+};
+use crate::io::{
+    CharEncoding, EchoMode, IOHandle, InjectedInput, InputSender, PackedByteOrder, RecordedInput,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::io::RealIOHandle;
+use crate::isa::{Isa, SpecProfile};
+use crate::memory::{MemoryFill, MemoryMap};
+use crate::op::{handler, lc3b_handler, Op};
+use crate::os;
+use crate::plugin::{Event, Plugin, PluginId, PluginManager, PluginSet};
+use crate::register::Register;
+use crate::register::Register::{RCond, RPC, RR6};
+use crate::rng::Rng;
+use crate::trap::{TrapHandler, FIRST_HOST_TRAP};
+
+pub use crate::state::VmState;
 
-const MEMORY_SIZE: usize = (u16::MAX as usize) + 1;
+pub(crate) const MEMORY_SIZE: usize = (u16::MAX as usize) + 1;
 
-const PC_START: u16 = 0x3000; // Initial program counter
+pub(crate) const PC_START: u16 = 0x3000; // Initial program counter
 
 // Mem Mapped Register Locations
-// There are 3 registers listed in the spec
+// These are the registers listed in the spec
 // (https://courses.engr.illinois.edu/ece411/fa2019/mp/LC3b_ISA.pdf
-// or https://justinmeiners.github.io/lc3-vm/supplies/lc3-isa.pdf) we don't
-// implement here yet, the display status register, display data register and
-// the machine control register.
-const KB_STATUS_POS: u16 = 0xFE00; // Keyboard Status Register
-const KB_DATA_POS: u16 = 0xFE02; // Keyboard Data Register
-
-pub struct VM<IOType: IOHandle> {
-    // TODO: Splitting the state between a VM state component and
-    // a  plugin manager component would make it easier for the compiler to
-    // reason about mutability during plugin notifications and push some of
-    // the fiddly plugin management logic into a class where it's more relevant.
-    memory: [u16; MEMORY_SIZE],
-    registers: [u16; NUM_REGISTERS],
-    running: bool,
-    io_handle: IOType,
-    plugins: Option<Vec<Box<dyn Plugin<IOType>>>>,
+// or https://justinmeiners.github.io/lc3-vm/supplies/lc3-isa.pdf).
+pub(crate) const KB_STATUS_POS: u16 = 0xFE00; // Keyboard Status Register
+pub(crate) const KB_DATA_POS: u16 = 0xFE02; // Keyboard Data Register
+const DISPLAY_STATUS_POS: u16 = 0xFE04; // Display Status Register
+const DISPLAY_DATA_POS: u16 = 0xFE06; // Display Data Register
+pub(crate) const MACHINE_CONTROL_POS: u16 = 0xFFFE; // Machine Control Register
+
+// Not part of the spec. Reads give a new pseudo random number every time.
+pub(crate) const RNG_POS: u16 = 0xFE08;
+
+// Also not part of the spec, see interrupt::tick. The interval is how many
+// instructions go by between ticks.
+pub(crate) const TIMER_CONTROL_POS: u16 = 0xFE0A;
+pub(crate) const TIMER_INTERVAL_POS: u16 = 0xFE0C;
+
+// Nor are these. Milliseconds since the VM was created, split into low and
+// high words. Reading the low word latches the high one, so a program that
+// reads them in that order gets a consistent pair.
+pub(crate) const CLOCK_LOW_POS: u16 = 0xFE0E;
+pub(crate) const CLOCK_HIGH_POS: u16 = 0xFE10;
+
+// The number of instructions executed since the VM was created, read the
+// same way as the clock
+pub(crate) const INSTRUCTIONS_LOW_POS: u16 = 0xFE12;
+pub(crate) const INSTRUCTIONS_HIGH_POS: u16 = 0xFE14;
+
+// Nor is this. Writing a bank number swaps that bank into the window when
+// memory is banked, see VM::set_banked_memory, and reading it gives the bank
+// that's there.
+pub(crate) const BANK_SELECT_POS: u16 = 0xFE16;
+
+// Bit 15 of the MCR, which stops the machine when cleared
+pub(crate) const CLOCK_ENABLE: u16 = 1 << 15;
+
+// Bit 15 of KBSR, set when there's a character waiting in KBDR
+pub(crate) const KB_READY: u16 = 1 << 15;
+
+const HALT: u16 = 0xF025;
+
+// How long getchar waits on the IO handle before checking for keys from an
+// InputSender again
+const INJECTED_INPUT_POLL: Duration = Duration::from_millis(10);
+
+// Summary of a single instruction executed by VM::step
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepOutcome {
+    // Address the instruction was fetched from
+    pub address: u16,
+    pub instruction: u16,
+    pub op: Op,
+    // Program counter after the instruction has executed
+    pub pc: u16,
+    pub halted: bool,
+}
+
+// Why a run came to an end. Errors are still reported through the
+// LC3Result returned by run, so they don't get a variant here.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    // The program executed the HALT trap
+    HaltTrap,
+    // The machine stopped some other way, e.g. the program cleared the
+    // clock enable bit in the MCR itself
+    ClockStopped,
+    // The instruction limit passed to run_with_limit was reached
+    FuelExhausted,
+}
+
+// Summary of a complete run, returned by run and run_with_limit
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunOutcome {
+    pub reason: StopReason,
+    pub instructions: u64,
+    pub traps: u64,
+    pub chars_read: u64,
+    pub chars_written: u64,
 }
 
+// The machine state plus the plugins attached to it. VM derefs to VmState,
+// so everything for inspecting the machine is available straight from the
+// VM as well as to plugins.
+//
+// By default plugins can be added and removed at runtime. Programs that
+// only ever want one plugin, or none, can pick StaticPlugin or NoPlugins
+// instead and skip the dynamic dispatch on every memory and register access.
+pub struct VM<IOType: IOHandle, Plugins: PluginSet<IOType> = PluginManager<IOType>> {
+    state: VmState<IOType>,
+    plugins: Plugins,
+    traps: HashMap<u8, TrapHandler<IOType>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 impl VM<RealIOHandle> {
     // Want the default constructor to use a standard IO Handle, hence
     // the specific treatment.
@@ -40,42 +138,346 @@ impl VM<RealIOHandle> {
     }
 }
 
+impl<IOType: IOHandle, Plugins: PluginSet<IOType>> Deref for VM<IOType, Plugins> {
+    type Target = VmState<IOType>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.state
+    }
+}
+
+impl<IOType: IOHandle, Plugins: PluginSet<IOType>> DerefMut for VM<IOType, Plugins> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.state
+    }
+}
+
 impl<IOType: IOHandle> VM<IOType> {
-    pub fn add_plugin(&mut self, plugin: Box<dyn Plugin<IOType>>) {
-        self.plugins.as_mut().map(|s| s.push(plugin));
+    // Plugins are notified in order of priority, highest first, with ties
+    // going in the order they were added. The priority starts out as
+    // whatever the plugin asks for through Plugin::priority.
+    pub fn add_plugin(&mut self, plugin: Box<dyn Plugin<IOType>>) -> PluginId {
+        self.plugins.add(&mut self.state, plugin)
+    }
+
+    // Detaches the plugin, handing it back so any state it holds can still
+    // be read.
+    pub fn remove_plugin(&mut self, id: PluginId) -> LC3Result<Box<dyn Plugin<IOType>>> {
+        let plugin = self.plugins.remove(id)?;
+        self.state.bus.forget(id);
+        Ok(plugin)
+    }
+
+    // Send message to every plugin subscribed to topic straight away
+    pub fn publish<T: Any>(&mut self, topic: &str, message: T) -> LC3Result<()> {
+        self.state.publish(topic, message);
+        self.plugins.deliver_pending(&mut self.state)
+    }
+
+    // Detaches the plugin and hands it back as its concrete type, e.g. to
+    // read the results a profiler built up over a run. Nothing is removed
+    // if the plugin isn't a P.
+    pub fn take_plugin<P: Plugin<IOType> + 'static>(&mut self, id: PluginId) -> LC3Result<P> {
+        if self.plugin::<P>(id)?.is_none() {
+            return Err(LC3Error::WrongPluginType(id));
+        }
+        self.remove_plugin(id)?
+            .into_any()
+            .downcast::<P>()
+            .map(|plugin| *plugin)
+            .map_err(|_| LC3Error::WrongPluginType(id))
+    }
+
+    // Look at an attached plugin as its concrete type, or None if it isn't
+    // a P
+    pub fn plugin<P: Plugin<IOType> + 'static>(&self, id: PluginId) -> LC3Result<Option<&P>> {
+        Ok(self.plugins.get(id)?.as_any().downcast_ref::<P>())
+    }
+
+    pub fn plugin_mut<P: Plugin<IOType> + 'static>(
+        &mut self,
+        id: PluginId,
+    ) -> LC3Result<Option<&mut P>> {
+        Ok(self.plugins.get_mut(id)?.as_any_mut().downcast_mut::<P>())
+    }
+
+    // Swaps in a new plugin at the same point in the notification order,
+    // returning the old one. The id and priority stay the same, but the old
+    // plugin's subscriptions go with it, and the new one is attached afresh.
+    pub fn replace_plugin(
+        &mut self,
+        id: PluginId,
+        mut plugin: Box<dyn Plugin<IOType>>,
+    ) -> LC3Result<Box<dyn Plugin<IOType>>> {
+        let slot = self.plugins.get_mut(id)?;
+        self.state.bus.forget(id);
+        plugin.on_attach(&mut self.state, id);
+        Ok(std::mem::replace(slot, plugin))
+    }
+
+    // Overrides the priority the plugin asked for, moving it to its new
+    // place in the notification order.
+    pub fn set_plugin_priority(&mut self, id: PluginId, priority: i32) -> LC3Result<()> {
+        self.plugins.set_priority(id, priority)
+    }
+
+    // Events that plugins cause while handling another event, e.g. by writing
+    // memory with VmState::write_memory, are delivered once every plugin has
+    // seen the first one. Those can cause more events in turn, and depth
+    // limits how far that goes before further events are dropped. Zero means
+    // plugins never hear about each other's changes.
+    pub fn set_max_event_depth(&mut self, depth: usize) {
+        self.plugins.set_max_event_depth(depth);
+    }
+
+    // The ids of the attached plugins, in the order they're notified
+    pub fn plugin_ids(&self) -> Vec<PluginId> {
+        self.plugins.ids()
     }
 
     // If there end up being more options to tweak might want to break out
     // a builder for this one, but right now this is fine.
     pub fn new_with_io(io_handle: IOType) -> Self {
-        let memory = [0u16; MEMORY_SIZE];
-        let registers = [0u16; NUM_REGISTERS];
+        Self::new_with_plugins(io_handle, PluginManager::new())
+    }
+
+    // A VM for the LC-3b, or the LC-3 as new_with_io gives. It can't be
+    // changed afterwards, since programs and memory are laid out
+    // differently for each.
+    pub fn new_with_isa(io_handle: IOType, isa: Isa) -> Self {
+        let mut vm = Self::new_with_io(io_handle);
+        vm.state.isa = isa;
+        vm
+    }
+}
+
+impl<IOType: IOHandle, Plugins: PluginSet<IOType>> VM<IOType, Plugins> {
+    pub fn new_with_plugins(io_handle: IOType, mut plugins: Plugins) -> Self {
+        let mut state = VmState::new(io_handle);
+        plugins.attach(&mut state);
         VM {
-            memory,
-            registers,
-            running: false,
-            io_handle,
-            plugins: Some(Vec::new()),
+            state,
+            plugins,
+            traps: HashMap::new(),
         }
     }
 
-    pub fn run(&mut self) -> LC3Result<()> {
+    // Whether the reserved op code raises the illegal op code exception
+    // through vector x01, like the real LC-3, rather than failing the run.
+    // Loading an operating system turns it on, as there's nothing to handle
+    // the exception without one.
+    pub fn set_illegal_opcode_exceptions(&mut self, enabled: bool) {
+        self.illegal_opcode_exceptions = enabled;
+    }
+
+    // By default TRAP is handled natively for speed, and the service
+    // routines don't need to be in memory. With OS traps turned on it works
+    // like the real machine instead: R7 is set to the return address and
+    // execution carries on from the routine listed at the vector's entry
+    // in the trap vector table, x0000 to x00FF. TRAP doesn't change the
+    // privilege mode, so the program needs to run in supervisor mode to
+    // reach the routines, unless the spec profile says it saves the PSR.
+    pub fn set_os_traps(&mut self, enabled: bool) {
+        self.os_traps = enabled;
+    }
+
+    // Follow a different revision of the spec where they disagree. The
+    // default, SpecProfile::Patt2ndEd, has LEA set the condition codes and
+    // TRAP leave the return address in R7. The third edition and LC3Tools
+    // leave the condition codes alone, and with OS traps turned on, TRAP
+    // pushes the PSR and PC onto the supervisor stack and switches to
+    // supervisor mode for the routine to return with RTI. The bundled OS's
+    // routines return with RET, so it only loads with the default.
+    pub fn set_spec_profile(&mut self, profile: SpecProfile) {
+        self.spec = profile;
+    }
+
+    // Have TRAP with the given vector call handler, so the host can offer
+    // services of its own to programs. x20 to x25 are the standard routines
+    // and the ones below are reserved, leaving x26 to xFF. Host traps are
+    // handled natively even with OS traps turned on. Registering a vector
+    // again replaces its handler.
+    pub fn register_trap<F>(&mut self, vector: u8, handler: F) -> LC3Result<()>
+    where
+        F: FnMut(&mut VmState<IOType>) -> LC3Result<()> + 'static,
+    {
+        if vector < FIRST_HOST_TRAP {
+            return Err(LC3Error::ReservedTrap { code: vector });
+        }
+        self.traps.insert(vector, Box::new(handler));
+        Ok(())
+    }
+
+    // Lets programs open, read, write and close files on the host through
+    // the traps in file_traps, x26 to x29. File names are taken relative to
+    // root and can't reach outside it. This isn't part of the LC-3, so it's
+    // off unless asked for.
+    pub fn enable_file_traps<P: AsRef<Path>>(&mut self, root: P) -> LC3Result<()> {
+        file_traps::register(self, root.as_ref().to_path_buf())
+    }
+
+    // Returns None if the vector has no host trap registered
+    pub(crate) fn host_trap(&mut self, vector: u8) -> Option<LC3Result<()>> {
+        let handler = self.traps.get_mut(&vector)?;
+        Some(handler(&mut self.state))
+    }
+
+    // Restart the RNG device register's sequence from seed. Without a seed
+    // it's seeded from the clock, so every run gets different numbers.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    // Have the clock registers count one millisecond per instruction
+    // executed instead of following real time, so programs that measure
+    // time behave the same on every run. On wasm they always do.
+    pub fn set_deterministic_clock(&mut self, enabled: bool) {
+        self.deterministic_clock = enabled;
+    }
+
+    // Whether the GETC and IN traps echo what they read. They follow the
+    // spec by default, where only IN does. This only affects the traps
+    // handled natively, not the OS's routines.
+    pub fn set_echo(&mut self, mode: EchoMode) {
+        self.echo = mode;
+    }
+
+    // How characters outside ASCII are written and read. By default bytes
+    // from x80 up are Latin-1.
+    pub fn set_encoding(&mut self, encoding: CharEncoding) {
+        self.encoding = encoding;
+    }
+
+    // Which byte of each word the PUTSP trap writes first. The spec says
+    // the low byte, which is the default. Like set_echo, this only affects
+    // the trap when it's handled natively.
+    pub fn set_packed_byte_order(&mut self, order: PackedByteOrder) {
+        self.packed_byte_order = order;
+    }
+
+    // For other threads to type into the program while it runs. Once one
+    // has been handed out, getchar waits on the senders as well as the IO
+    // handle, checking the handle with try_getchar.
+    pub fn input_sender(&mut self) -> InputSender {
+        self.injected.get_or_insert_with(InjectedInput::new).sender()
+    }
+
+    pub fn plugins(&self) -> &Plugins {
+        &self.plugins
+    }
+
+    pub fn plugins_mut(&mut self) -> &mut Plugins {
+        &mut self.plugins
+    }
+
+    pub fn into_plugins(self) -> Plugins {
+        self.plugins
+    }
+
+    pub fn run(&mut self) -> LC3Result<RunOutcome> {
+        self.execute(None)
+    }
+
+    // Like run, but gives up after max_instructions have been executed so
+    // programs that never halt can't hang the caller.
+    pub fn run_with_limit(&mut self, max_instructions: u64) -> LC3Result<RunOutcome> {
+        self.execute(Some(max_instructions))
+    }
+
+    fn execute(&mut self, limit: Option<u64>) -> LC3Result<RunOutcome> {
         self.set_running(true)?;
-        self.reg_write(RPC, PC_START)?;
+        self.reg_write(RPC, self.start_pc)?;
+        self.chars_read = 0;
+        self.chars_written = 0;
+        self.plugins.on_run_start(&mut self.state)?;
 
+        let mut instructions = 0u64;
+        let mut traps = 0u64;
+        let mut reason = StopReason::HaltTrap;
+        // An OS's HALT routine stops the clock itself some instructions
+        // after the TRAP, so what counts is whether one ran at all
+        let mut halt_trap = false;
         while self.get_running()? {
-            let program_count = self.reg_read(RPC)?;
-            self.reg_write(RPC, program_count + 1)?;
+            if matches!(limit, Some(max) if instructions >= max) {
+                reason = StopReason::FuelExhausted;
+                break;
+            }
 
-            let command = Command::new(self.mem_read(program_count)?);
-            self.run_command(&command)?;
+            let outcome = self.step()?;
+            instructions += 1;
+            if outcome.op == Op::Trap {
+                traps += 1;
+            }
+            if outcome.instruction == HALT {
+                halt_trap = true;
+            }
+            if outcome.halted && !halt_trap {
+                reason = StopReason::ClockStopped;
+            }
         }
 
-        Ok(())
+        Ok(RunOutcome {
+            reason,
+            instructions,
+            traps,
+            chars_read: self.chars_read,
+            chars_written: self.chars_written,
+        })
+    }
+
+    // Fetch, decode and execute exactly one instruction at the current
+    // program counter. Stepping a halted machine starts it up again from
+    // wherever the program counter is pointing.
+    pub fn step(&mut self) -> LC3Result<StepOutcome> {
+        if !self.running {
+            self.set_running(true)?;
+        }
+
+        if self.io_handle.take_interrupt() {
+            return Err(LC3Error::Interrupted);
+        }
+        interrupt::poll(self)?;
+        self.last_fetch = self.pc();
+        interrupt::check_fetch(self)?;
+
+        let address = self.reg_read(RPC)?;
+        self.isa.check_aligned(address)?;
+        self.reg_write(RPC, wrapping_add!(address, self.isa.word_size()))?;
+
+        let instruction = self.mem_read(address)?;
+        let command = Command::new(instruction);
+        let op = self.run_command(&command)?;
+        self.instructions_executed += 1;
+        if self.running {
+            interrupt::tick(self)?;
+        } else {
+            self.plugins.on_halt(&mut self.state)?;
+        }
+
+        Ok(StepOutcome {
+            address,
+            instruction,
+            op,
+            pc: self.pc(),
+            halted: !self.running,
+        })
     }
 
     pub fn load_program(&mut self, program: &Vec<u16>) -> LC3Result<()> {
-        let max_len = MEMORY_SIZE - PC_START as usize;
+        self.load_program_at(PC_START, program)
+    }
+
+    // Load a program or block of data starting at origin. Loading over the
+    // top of something previously loaded at the same origin replaces it, but
+    // any other overlap with an earlier load is an error.
+    pub fn load_program_at(&mut self, origin: u16, program: &[u16]) -> LC3Result<()> {
+        self.isa.check_aligned(origin)?;
+        let step = self.isa.word_size();
+        let max_len = self
+            .memory_map
+            .size
+            .saturating_sub(origin as usize)
+            .div_ceil(step as usize);
         if program.len() > max_len {
             let err = LC3Error::ProgramSize {
                 len: program.len(),
@@ -83,49 +485,266 @@ impl<IOType: IOHandle> VM<IOType> {
             };
             return Err(err);
         }
+        let image = self.isa.layout(program);
+
+        let start = origin as usize;
+        let end = start + image.len();
+        for (existing, words) in &self.loaded_segments {
+            if *existing == origin {
+                continue;
+            }
+            let existing_start = *existing as usize;
+            let existing_end = existing_start + words.len();
+            if start < existing_end && existing_start < end {
+                return Err(LC3Error::ProgramOverlap {
+                    origin,
+                    existing: *existing,
+                });
+            }
+        }
 
         for (index, instruction) in program.iter().enumerate() {
-            self.mem_write(PC_START + index as u16, *instruction)?;
+            self.mem_write(origin + index as u16 * step, *instruction)?;
+        }
+        let replaced = self
+            .loaded_segments
+            .iter()
+            .position(|(existing, _)| *existing == origin);
+        if let Some(index) = replaced {
+            let (_, words) = self.loaded_segments.remove(index);
+            self.clear_memory(end..start + words.len());
+        }
+        self.loaded_segments.push((origin, image));
+
+        Ok(())
+    }
+
+    // Load the bundled operating system: the trap vector table at x0000,
+    // the interrupt vector table at x0100 and the trap service routines and
+    // exception handlers from x0200. Traps and exceptions then run through
+    // it like on the real machine. R6 is pointed at the supervisor stack, as
+    // the program starts out in supervisor mode.
+    pub fn load_os(&mut self) -> LC3Result<()> {
+        if !self.memory_map.is_standard() {
+            return Err(LC3Error::BadMemoryMap(
+                "The bundled OS needs the device registers at xFE00".to_string(),
+            ));
+        }
+        if self.isa != Isa::Lc3 {
+            return Err(LC3Error::Other("The bundled OS is only for the LC-3".to_string()));
+        }
+        if self.spec.trap_saves_psr() {
+            return Err(LC3Error::Other(
+                "The bundled OS's routines return with RET, so it needs the patt-2nd-ed spec \
+                 profile"
+                    .to_string(),
+            ));
+        }
+        for (origin, words) in os::image()? {
+            self.load_program_at(origin, &words)?;
+        }
+        self.enable_os();
+
+        Ok(())
+    }
+
+    // Load an operating system of your own in place of the bundled one, as
+    // an object file. It has to bring its own trap vector table at x0000
+    // and interrupt vector table at x0100, since traps and exceptions run
+    // through it the same way.
+    pub fn load_os_object(&mut self, object: &[u16]) -> LC3Result<()> {
+        let (origin, words) = object.split_first().ok_or(LC3Error::EmptyObject)?;
+        self.load_program_at(*origin, words)?;
+        self.enable_os();
+
+        Ok(())
+    }
+
+    fn enable_os(&mut self) {
+        self.os_traps = true;
+        self.illegal_opcode_exceptions = true;
+        self.registers[RR6.to_u8() as usize] = SUPERVISOR_STACK_START;
+    }
+
+    // Set every word below the device registers to value, so programs that
+    // read memory they never wrote get something recognisable rather than
+    // zero. Anything loaded before is overwritten.
+    pub fn fill_memory(&mut self, value: u16) {
+        self.set_memory_fill(MemoryFill::Word(value));
+    }
+
+    // Fill memory, apart from the device registers, as fill says, which
+    // reset(true) then does again before reloading the programs. Anything
+    // loaded before is overwritten.
+    pub fn set_memory_fill(&mut self, fill: MemoryFill) {
+        self.memory_fill = fill;
+        self.apply_memory_fill();
+    }
+
+    // Change how much memory there is and where the device registers are.
+    // Best done before loading anything, as the bundled OS needs the device
+    // registers where the LC-3 has them and programs need to fit.
+    pub fn set_memory_map(&mut self, map: MemoryMap) -> LC3Result<()> {
+        map.check()?;
+        self.memory_map = map;
+        Ok(())
+    }
+
+    pub fn memory_map(&self) -> MemoryMap {
+        self.memory_map
+    }
+
+    // Turn on the bank select register, with whatever's in the window
+    // becoming bank 0 and the other banks filled as memory is. Turning it on
+    // again starts the banks over.
+    pub fn set_banked_memory(&mut self, banked: BankedMemory) -> LC3Result<()> {
+        banked.check(&self.memory_map)?;
+        self.state.banks = Some(Banks::new(banked, self.memory_fill, &self.memory));
+        let bank_select = self.device(BANK_SELECT_POS);
+        self.state.memory.set(bank_select, 0);
+        Ok(())
+    }
+
+    // Load words into the start of a bank, which reset(true) puts back like
+    // programs loaded with load_program_at
+    pub fn load_bank(&mut self, bank: usize, words: &[u16]) -> LC3Result<()> {
+        let state = &mut self.state;
+        match state.banks.as_mut() {
+            Some(banks) => banks.load(bank, words, &mut state.memory),
+            None => Err(LC3Error::NoSuchBank { bank, banks: 0 }),
         }
+    }
+
+    // Load a program in the standard LC-3 object format, where the first
+    // word is the origin the rest of the program should be loaded at.
+    // Execution will start from that origin.
+    pub fn load_object(&mut self, object: &[u16]) -> LC3Result<()> {
+        let (origin, program) = object.split_first().ok_or(LC3Error::EmptyObject)?;
+        self.load_program_at(*origin, program)?;
+        self.start_pc = *origin;
+        self.registers[RPC.to_u8() as usize] = *origin;
 
         Ok(())
     }
 
+    // Unlike the inspection methods VmState provides, these go through the
+    // plugins just like the program's own reads and writes, so plugins can
+    // intercept them. Reading KBSR here polls the keyboard.
+    pub fn read_memory(&mut self, address: u16) -> LC3Result<u16> {
+        self.mem_read(address)
+    }
+
+    pub fn write_memory(&mut self, address: u16, val: u16) -> LC3Result<()> {
+        self.mem_write(address, val)
+    }
+
+    pub fn read_register(&mut self, reg: Register) -> LC3Result<u16> {
+        self.reg_read(reg)
+    }
+
+    pub fn write_register(&mut self, reg: Register, val: u16) -> LC3Result<()> {
+        self.reg_write(reg, val)
+    }
+
     pub(crate) fn mem_read(&mut self, pos: u16) -> LC3Result<u16> {
-        // Deal with the mem-mapped device registers
-        if pos == KB_STATUS_POS {
-            if self.is_key_down()? {
-                // TODO: Right now, I think there's a bug here. If the key
-                // being pressed is not a key handled by getchar()
-                // then the vm will fill the status register and pause
-                // waiting for the user to press one of those keys before
-                // actually doing anything. Not a show stopper, but one to
-                // watch.
-                self.mem_write(KB_STATUS_POS, 1 << 15)?;
-                let ch = self.getchar()?;
-                self.mem_write(KB_DATA_POS, ch as u16)?;
-            } else {
-                self.mem_write(KB_STATUS_POS, 0)?;
+        if !self.memory_map.is_mapped(pos) {
+            return Err(LC3Error::Unmapped { address: pos });
+        }
+
+        // Deal with the mem-mapped device registers, which are matched by
+        // where the LC-3 has them whatever the memory map says
+        match self.memory_map.standard_device(pos) {
+            Some(KB_STATUS_POS) => {
+                self.poll_keyboard()?;
             }
-        };
+            Some(KB_DATA_POS) => {
+                // Taking the key lets the next one in
+                let kbsr = self.memory_at(self.device(KB_STATUS_POS));
+                self.mem_write(self.device(KB_STATUS_POS), kbsr & !KB_READY)?;
+            }
+            Some(DISPLAY_STATUS_POS) => {
+                // Output goes straight to the IO handle, so the display is
+                // always ready for the next character
+                self.mem_write(pos, 1 << 15)?;
+            }
+            Some(RNG_POS) => {
+                let value = self.rng.next_u16();
+                self.mem_write(pos, value)?;
+            }
+            Some(CLOCK_LOW_POS) => {
+                let millis = self.elapsed_millis();
+                self.latch_pair(CLOCK_LOW_POS, CLOCK_HIGH_POS, millis)?;
+            }
+            Some(INSTRUCTIONS_LOW_POS) => {
+                let count = self.instructions_executed;
+                self.latch_pair(INSTRUCTIONS_LOW_POS, INSTRUCTIONS_HIGH_POS, count)?;
+            }
+            _ => {}
+        }
 
         let val = self.memory[pos as usize];
-        self.notify_plugins(&Event::MemGet {
+        match self.notify_plugins(Event::MemGet {
             location: pos,
             value: val,
-        })?;
-        Ok(val)
+        })? {
+            Some(Event::MemGet { value, .. }) => Ok(value),
+            Some(other) => Err(changed_kind(other)),
+            None => Ok(val),
+        }
     }
 
     pub(crate) fn mem_write(&mut self, pos: u16, val: u16) -> LC3Result<()> {
-        self.notify_plugins(&Event::MemSet {
+        if !self.memory_map.is_mapped(pos) {
+            return Err(LC3Error::Unmapped { address: pos });
+        }
+
+        match self.notify_plugins(Event::MemSet {
             location: pos,
             value: val,
-        })?;
-        self.memory[pos as usize] = val;
+        })? {
+            Some(Event::MemSet { value, .. }) => {
+                self.state.store_memory(pos, value);
+                match self.memory_map.standard_device(pos) {
+                    Some(DISPLAY_DATA_POS) => self.put_byte(value)?,
+                    Some(MACHINE_CONTROL_POS) if value & CLOCK_ENABLE == 0 => {
+                        self.set_running(false)?
+                    }
+                    // A new interval starts from scratch
+                    Some(TIMER_INTERVAL_POS) => self.timer_count = 0,
+                    Some(BANK_SELECT_POS) => self.select_bank(value),
+                    _ => {}
+                }
+            }
+            Some(other) => return Err(changed_kind(other)),
+            None => {}
+        }
         Ok(())
     }
 
+    // The LC-3b's byte loads and stores, which go through the word the byte
+    // is in. Bytes are little endian, with the low byte of each word at its
+    // even address.
+    pub(crate) fn mem_read_byte(&mut self, address: u16) -> LC3Result<u8> {
+        let word = self.mem_read(address & !1)?;
+        Ok(if address & 1 == 0 {
+            word as u8
+        } else {
+            (word >> 8) as u8
+        })
+    }
+
+    pub(crate) fn mem_write_byte(&mut self, address: u16, byte: u8) -> LC3Result<()> {
+        // Read straight from memory, so the other byte of a device register
+        // doesn't set off a read
+        let word = self.memory_at(address & !1);
+        let word = if address & 1 == 0 {
+            (word & 0xFF00) | u16::from(byte)
+        } else {
+            (word & 0x00FF) | (u16::from(byte) << 8)
+        };
+        self.mem_write(address & !1, word)
+    }
+
     pub(crate) fn reg_read(&mut self, reg: Register) -> LC3Result<u16> {
         self.reg_index_read(reg.to_u8())
     }
@@ -136,45 +755,218 @@ impl<IOType: IOHandle> VM<IOType> {
     }
 
     pub(crate) fn reg_index_read(&mut self, index: u8) -> LC3Result<u16> {
-        let value = self.registers[index as usize];
-        self.notify_plugins(&Event::RegGet { index, value })?;
-        Ok(value)
+        let val = self.registers[index as usize];
+        match self.notify_plugins(Event::RegGet { index, value: val })? {
+            Some(Event::RegGet { value, .. }) => Ok(value),
+            Some(other) => Err(changed_kind(other)),
+            None => Ok(val),
+        }
     }
 
     pub(crate) fn reg_index_write(&mut self, index: u8, val: u16) -> LC3Result<()> {
-        self.notify_plugins(&Event::RegSet { index, value: val })?;
-        self.registers[index as usize] = val;
+        match self.notify_plugins(Event::RegSet { index, value: val })? {
+            Some(Event::RegSet { value, .. }) => self.state.store_register(index, value),
+            Some(other) => return Err(changed_kind(other)),
+            None => {}
+        }
 
         Ok(())
     }
 
+    // The low 32 bits of value, split across a pair of device registers
+    fn latch_pair(&mut self, low_pos: u16, high_pos: u16, value: u64) -> LC3Result<()> {
+        self.mem_write(self.device(high_pos), (value >> 16) as u16)?;
+        self.mem_write(self.device(low_pos), value as u16)
+    }
+
+    fn elapsed_millis(&self) -> u64 {
+        match self.created {
+            Some(created) if !self.deterministic_clock => created.elapsed().as_millis() as u64,
+            _ => self.instructions_executed,
+        }
+    }
+
     pub(crate) fn putchar(&mut self, ch: char) -> LC3Result<()> {
-        self.notify_plugins(&Event::CharPut { ch })?;
-        self.io_handle.putchar(ch)?;
+        let ch = match self.notify_plugins(Event::CharPut { ch })? {
+            Some(Event::CharPut { ch }) => ch,
+            Some(other) => return Err(changed_kind(other)),
+            None => return Ok(()),
+        };
+        if self.replay.is_none() {
+            self.io_handle.putchar(ch)?;
+        }
+        self.chars_written += 1;
         Ok(())
     }
 
+    // Writes the character the low byte of word stands for
+    pub(crate) fn put_byte(&mut self, word: u16) -> LC3Result<()> {
+        let ch = self.encoding.decode(word)?;
+        self.putchar(ch)
+    }
+
+    // The word ch is read into the machine as
+    pub(crate) fn char_code(&self, ch: char) -> LC3Result<u16> {
+        self.encoding.encode(ch)
+    }
+
     pub(crate) fn getchar(&mut self) -> LC3Result<char> {
-        let ch = self.io_handle.getchar()?;
-        self.notify_plugins(&Event::CharGet { ch })?;
-        Ok(ch)
+        let ch = match self.next_replay_input()? {
+            Some(RecordedInput::Char(ch)) => ch,
+            Some(other) => return Err(replay_mismatch(other)),
+            None => self.wait_for_key()?,
+        };
+        self.chars_read += 1;
+        match self.notify_plugins(Event::CharGet { ch })? {
+            Some(Event::CharGet { ch }) => Ok(ch),
+            Some(other) => Err(changed_kind(other)),
+            None => Ok(ch),
+        }
+    }
+
+    // Latch the next key into KBDR and set KBSR's ready bit, if there's a
+    // key waiting and the last one has been taken. Returns whether KBSR is
+    // ready. The interrupt enable bit is the program's to set, so it
+    // survives the status being updated.
+    pub(crate) fn poll_keyboard(&mut self) -> LC3Result<bool> {
+        let kbsr = self.memory_at(self.device(KB_STATUS_POS));
+        if kbsr & KB_READY != 0 {
+            return Ok(true);
+        }
+        match self.try_getchar()? {
+            Some(ch) => {
+                let code = self.char_code(ch)?;
+                self.mem_write(self.device(KB_DATA_POS), code)?;
+                self.mem_write(self.device(KB_STATUS_POS), kbsr | KB_READY)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    // Takes a key if one's waiting, but never waits for one, so reading the
+    // device registers can't hang the VM. Plugins and replays see it as a
+    // check for a key followed by reading it.
+    pub(crate) fn try_getchar(&mut self) -> LC3Result<Option<char>> {
+        if self.replay.is_some() {
+            return if self.is_key_down()? {
+                self.getchar().map(Some)
+            } else {
+                Ok(None)
+            };
+        }
+
+        let mut key = self.state.held_key.take();
+        if key.is_none() {
+            key = self.state.injected.as_mut().and_then(InjectedInput::try_getchar);
+        }
+        if key.is_none() {
+            key = self.io_handle.try_getchar(Duration::from_millis(0))?;
+        }
+        let key_down = match self.notify_plugins(Event::KeyDownGet {
+            value: key.is_some(),
+        })? {
+            Some(Event::KeyDownGet { value }) => value,
+            Some(other) => return Err(changed_kind(other)),
+            None => key.is_some(),
+        };
+
+        let ch = match key {
+            Some(ch) if key_down => ch,
+            Some(ch) => {
+                self.state.held_key = Some(ch);
+                return Ok(None);
+            }
+            None => return Ok(None),
+        };
+        self.chars_read += 1;
+        match self.notify_plugins(Event::CharGet { ch })? {
+            Some(Event::CharGet { ch }) => Ok(Some(ch)),
+            Some(other) => Err(changed_kind(other)),
+            None => Ok(Some(ch)),
+        }
+    }
+
+    fn wait_for_key(&mut self) -> LC3Result<char> {
+        if let Some(ch) = self.state.held_key.take() {
+            return Ok(ch);
+        }
+        let injected = match &mut self.state.injected {
+            Some(injected) => injected,
+            None => return self.state.io_handle.getchar(),
+        };
+        loop {
+            if let Some(ch) = injected.try_getchar() {
+                return Ok(ch);
+            }
+            if let Some(ch) = self.state.io_handle.try_getchar(INJECTED_INPUT_POLL)? {
+                return Ok(ch);
+            }
+        }
     }
 
     pub(crate) fn is_key_down(&mut self) -> LC3Result<bool> {
-        let key_down = self.io_handle.is_key_down().map_io_error()?;
-        self.notify_plugins(&Event::KeyDownGet { value: key_down })?;
-        Ok(key_down)
+        let key_down = match self.next_replay_input()? {
+            Some(RecordedInput::KeyDown(key_down)) => key_down,
+            Some(other) => return Err(replay_mismatch(other)),
+            None => {
+                let injected = self.state.injected.as_mut().map(InjectedInput::is_key_down);
+                self.state.held_key.is_some()
+                    || injected == Some(true)
+                    || self.io_handle.is_key_down().map_io_error()?
+            }
+        };
+        match self.notify_plugins(Event::KeyDownGet { value: key_down })? {
+            Some(Event::KeyDownGet { value }) => Ok(value),
+            Some(other) => Err(changed_kind(other)),
+            None => Ok(key_down),
+        }
+    }
+
+    pub(crate) fn start_replay(&mut self, inputs: VecDeque<RecordedInput>) {
+        self.replay = Some(inputs);
+    }
+
+    pub(crate) fn stop_replay(&mut self) {
+        self.replay = None;
+    }
+
+    fn next_replay_input(&mut self) -> LC3Result<Option<RecordedInput>> {
+        match &mut self.replay {
+            Some(inputs) => inputs.pop_front().map(Some).ok_or_else(|| {
+                LC3Error::ReplayDiverged("Replay asked for more input than was recorded".to_string())
+            }),
+            None => Ok(None),
+        }
     }
 
     pub(crate) fn get_running(&mut self) -> LC3Result<bool> {
-        let value = self.running;
-        self.notify_plugins(&Event::RunningGet { value })?;
-        Ok(value)
+        let val = self.running;
+        match self.notify_plugins(Event::RunningGet { value: val })? {
+            Some(Event::RunningGet { value }) => Ok(value),
+            Some(other) => Err(changed_kind(other)),
+            None => Ok(val),
+        }
     }
 
     pub(crate) fn set_running(&mut self, val: bool) -> LC3Result<()> {
-        self.notify_plugins(&Event::RunningSet { value: val })?;
-        self.running = val;
+        match self.notify_plugins(Event::RunningSet { value: val })? {
+            Some(Event::RunningSet { value }) => {
+                self.running = value;
+                // The clock enable bit always matches whether the machine
+                // is running, the same as the hardware register would
+                let address = self.device(MACHINE_CONTROL_POS);
+                let mcr = self.memory_at(address);
+                let mcr = if value {
+                    mcr | CLOCK_ENABLE
+                } else {
+                    mcr & !CLOCK_ENABLE
+                };
+                self.state.memory.set(address, mcr);
+            }
+            Some(other) => return Err(changed_kind(other)),
+            None => {}
+        }
 
         Ok(())
     }
@@ -192,91 +984,134 @@ impl<IOType: IOHandle> VM<IOType> {
         Ok(())
     }
 
-    pub(crate) fn notify_plugins(&mut self, event: &Event) -> LC3Result<()> {
-        // This memory swapping dance prevents a safety issue.
-        // Basically, if we were iterating over the plugins vector contained
-        // in the VM while also allowing the plugins to mutate the VM while
-        // they were handling the event, then the plugins could theoretically
-        // mutate their own vector while it is being iterated over, which is
-        // obviously bad for business.
-        //
-        // The other issue here is loops. Imagine you have two
-        // plugins, one has the job of always setting register 0 to 1 (plugin 1)
-        // and the other has the job of setting it to 2 (plugin 2). These
-        // plugins are set up so whenever they receive a reg_write event to
-        // register 0, they overwrite it with their value. So if these
-        // events can be generated in the middle of the notifications
-        // loop plugin 1 setting the value will trigger another iteration
-        // of the loop. Even if plugin 1 somehow didn't cause a loop by putting
-        // reg_read/ reg_write notifications out there, the interaction
-        // of plugin 1 and plugin 2 fighting over the value will. If you
-        // prevent new events being generated while the notification loop is
-        // running, it prevents the issue, at the cost of not being able to
-        // get notifications on what the other plugins are doing.
+    pub(crate) fn notify_plugins(&mut self, event: Event) -> LC3Result<Option<Event>> {
+        self.plugins.notify(&mut self.state, event)
+    }
 
-        if self.plugins.is_none() {
-            // We're in the notifications loop, don't push the event
-            return Ok(());
-        }
+    pub(crate) fn run_command(&mut self, command: &Command) -> LC3Result<Op> {
+        let event = Event::Command {
+            bytes: command.get_bytes(),
+        };
+        let command = match self.notify_plugins(event)? {
+            Some(Event::Command { bytes }) => Command::new(bytes),
+            Some(other) => return Err(changed_kind(other)),
+            // Skipped by an interceptor
+            None => return Op::from_int_for(self.isa, command.op_code()?),
+        };
+        let command = &command;
 
-        let mut plugins_option = None;
-        std::mem::swap(&mut plugins_option, &mut self.plugins);
+        // Decoding is only worth doing if someone's listening
+        let instruction = if !self.plugins.is_empty() {
+            let address = self.pc().wrapping_sub(self.isa.word_size());
+            let instruction =
+                DecodedInstruction::decode_for(self.isa, address, command.get_bytes())?;
+            self.notify_plugins(Event::PreInstruction {
+                instruction: instruction.clone(),
+            })?;
+            self.writes = Some((Vec::new(), Vec::new()));
+            Some(instruction)
+        } else {
+            None
+        };
 
-        // The option should never be None by here, but this ok_or call
-        // handles that just in case.
-        let mut plugins = plugins_option.ok_or(LC3Error::Internal(
-            "None was returned for plugins after None check".to_string(),
-        ))?;
+        let op = Op::from_int_for(self.isa, command.op_code()?)?;
+        let result = match self.isa {
+            Isa::Lc3 => match op {
+                Op::Br => handler::branch(self, command),
+                Op::Add => handler::add(self, command),
+                Op::Ld => handler::load(self, command),
+                Op::St => handler::store(self, command),
+                Op::Jsr => handler::jump_register(self, command),
+                Op::And => handler::and(self, command),
+                Op::Ldr => handler::load_register(self, command),
+                Op::Str => handler::store_register(self, command),
+                Op::Rti => handler::rti(self, command),
+                Op::Not => handler::not(self, command),
+                Op::Ldi => handler::load_indirect(self, command),
+                Op::Sti => handler::store_indirect(self, command),
+                Op::Jmp => handler::jump(self, command),
+                Op::Res => handler::reserved(self, command),
+                Op::Lea => handler::load_effective_address(self, command),
+                Op::Trap => handler::trap(self, command),
+                // Only the LC-3b has these
+                Op::Ldb | Op::Stb | Op::Ldw | Op::Stw | Op::Xor | Op::Shf => {
+                    handler::reserved(self, command)
+                }
+            },
+            Isa::Lc3b => lc3b_handler::execute(self, op, command),
+        };
+        // The program broke the rules, which the operating system gets to
+        // deal with rather than the host
+        let result = match result {
+            Err(LC3Error::AccessViolation { .. }) => {
+                interrupt::exception(self, ACCESS_VIOLATION_VECTOR)
+            }
+            Err(LC3Error::PrivilegeViolation) => interrupt::exception(self, PRIVILEGE_VECTOR),
+            Err(LC3Error::IllegalOpcode { .. }) if self.illegal_opcode_exceptions => {
+                interrupt::exception(self, ILLEGAL_OPCODE_VECTOR)
+            }
+            other => other,
+        };
+        let writes = self.writes.take();
+        result?;
 
-        for plugin in &mut plugins {
-            plugin.handle_event(self, event)?
+        if let (Some(instruction), Some((registers_written, memory_written))) = (instruction, writes)
+        {
+            let effects = Effects {
+                registers_written,
+                memory_written,
+                pc: self.pc(),
+                flags: self.condition_flags(),
+                halted: !self.running,
+            };
+            self.notify_plugins(Event::PostInstruction {
+                instruction,
+                effects,
+            })?;
         }
 
-        self.plugins = Some(plugins);
+        Ok(op)
+    }
 
-        Ok(())
+    // Gives back the IO handle, for instance to look at what a
+    // StreamIOHandle wrote
+    pub fn into_io_handle(self) -> IOType {
+        self.state.io_handle
     }
+}
 
-    pub(crate) fn run_command(&mut self, command: &Command) -> LC3Result<()> {
-        let event = Event::Command {
-            bytes: command.get_bytes(),
-        };
-        self.notify_plugins(&event)?;
-
-        let op = Op::from_int(command.op_code()?)?;
-        match op {
-            Op::Br => handler::branch(self, command),
-            Op::Add => handler::add(self, command),
-            Op::Ld => handler::load(self, command),
-            Op::St => handler::store(self, command),
-            Op::Jsr => handler::jump_register(self, command),
-            Op::And => handler::and(self, command),
-            Op::Ldr => handler::load_register(self, command),
-            Op::Str => handler::store_register(self, command),
-            Op::Rti => handler::rti(self, command),
-            Op::Not => handler::not(self, command),
-            Op::Ldi => handler::load_indirect(self, command),
-            Op::Sti => handler::store_indirect(self, command),
-            Op::Jmp => handler::jump(self, command),
-            Op::Res => handler::reserved(self, command),
-            Op::Lea => handler::load_effective_address(self, command),
-            Op::Trap => handler::trap(self, command),
-        }
-    }
-
-    #[cfg(test)]
-    pub(crate) fn into_io_handle(self) -> IOType {
-        self.io_handle
+// Interceptors may change what an event carries but not what kind of event
+// it is
+fn changed_kind(event: Event) -> LC3Error {
+    LC3Error::Plugin {
+        source: format!("An interceptor changed an event into {:?}", event).into(),
     }
 }
 
+fn replay_mismatch(input: RecordedInput) -> LC3Error {
+    LC3Error::ReplayDiverged(format!(
+        "Replay diverged from the recording, next recorded input was {:?}",
+        input
+    ))
+}
+
 #[cfg(test)]
 mod test {
-    use super::VM;
-    use crate::condition_flags::{FL_NEG, FL_POS, FL_ZRO};
-    use crate::error::LC3Result;
-    use crate::io::TestIOHandle;
-    use crate::register::Register::RCond;
+    use std::cell::{Cell, RefCell};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{RunOutcome, StepOutcome, StopReason, KB_STATUS_POS, VM};
+    use crate::bank::BankedMemory;
+    use crate::asm::{assemble, Assembler};
+    use crate::condition_flags::{ConditionFlags, FL_NEG, FL_POS, FL_ZRO};
+    use crate::error::{LC3Error, LC3Result};
+    use crate::io::{IOHandle, TestIOHandle};
+    use crate::isa::{Isa, SpecProfile};
+    use crate::memory::{MemoryFill, MemoryMap};
+    use crate::op::Op;
+    use crate::interrupt::{PSR_USER, SUPERVISOR_STACK_START};
+    use crate::register::Register::{RCond, RR0, RR1, RR2, RR3, RR4, RR5, RR6, RR7};
 
     #[test]
     fn can_update_flags() -> LC3Result<()> {
@@ -318,6 +1153,38 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn can_write_to_display_registers() -> LC3Result<()> {
+        let program: Vec<u16> = vec![
+            // LD R0, CHAR
+            0x2004,
+            // POLL: LDI R1, DSR
+            0xA204,
+            // BRzp POLL
+            0x07FE,
+            // STI R0, DDR
+            0xB003,
+            // Halt
+            0xF025,
+            // CHAR
+            'A' as u16,
+            // DSR
+            super::DISPLAY_STATUS_POS,
+            // DDR
+            super::DISPLAY_DATA_POS,
+        ];
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_program(&program)?;
+        let outcome = vm.run()?;
+        assert_eq!(outcome.chars_written, 1);
+
+        let io_handle = vm.into_io_handle();
+        assert_eq!(io_handle.get_test_outputs(), vec!['A']);
+
+        Ok(())
+    }
+
     #[test]
     fn can_run_program() -> LC3Result<()> {
         let mut program: Vec<u16> = vec![
@@ -336,7 +1203,17 @@ mod test {
         let io_handle = TestIOHandle::new();
         let mut vm = VM::new_with_io(io_handle);
         vm.load_program(&program)?;
-        vm.run()?;
+        let outcome = vm.run()?;
+        assert_eq!(
+            outcome,
+            RunOutcome {
+                reason: StopReason::HaltTrap,
+                instructions: 3,
+                traps: 2,
+                chars_read: 0,
+                chars_written: test_string.len() as u64,
+            }
+        );
 
         let io_handle = vm.into_io_handle();
         let outputs: String = io_handle.get_test_outputs().iter().collect();
@@ -344,4 +1221,810 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn can_step_program() -> LC3Result<()> {
+        let program: Vec<u16> = vec![
+            // Add 5 to RR0 and put the result in RR0
+            0b0001_0000_0010_0101,
+            // Halt
+            0xF025,
+        ];
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_program(&program)?;
+
+        let outcome = vm.step()?;
+        assert_eq!(
+            outcome,
+            StepOutcome {
+                address: 0x3000,
+                instruction: program[0],
+                op: Op::Add,
+                pc: 0x3001,
+                halted: false,
+            }
+        );
+        assert_eq!(vm.reg_index_read(0)?, 5);
+
+        let outcome = vm.step()?;
+        assert_eq!(outcome.op, Op::Trap);
+        assert_eq!(outcome.pc, 0x3002);
+        assert!(outcome.halted);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_run_with_limit() -> LC3Result<()> {
+        // Branch back to the same instruction forever
+        let looping: Vec<u16> = vec![0b0000_1111_1111_1111];
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_program(&looping)?;
+        assert_eq!(vm.run_with_limit(100)?.reason, StopReason::FuelExhausted);
+
+        let halting: Vec<u16> = vec![0xF025];
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_program(&halting)?;
+        assert_eq!(vm.run_with_limit(100)?.reason, StopReason::HaltTrap);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_dispatch_os_traps() -> LC3Result<()> {
+        let program: Vec<u16> = vec![
+            // OUT
+            0xF021,
+        ];
+        let routine: Vec<u16> = vec![
+            // ADD R1, R1, #1
+            0x1261,
+            // RET
+            0xC1C0,
+        ];
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.set_os_traps(true);
+        vm.load_program(&program)?;
+        vm.load_program_at(0x0400, &routine)?;
+        vm.load_program_at(0x0021, &[0x0400])?;
+
+        assert_eq!(vm.step()?.pc, 0x0400);
+        assert_eq!(vm.register(RR7), 0x3001);
+        vm.step()?;
+        assert_eq!(vm.step()?.pc, 0x3001);
+        assert_eq!(vm.register(RR1), 1);
+        // Nothing was printed natively
+        assert!(vm.into_io_handle().get_test_outputs().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_follow_spec_profiles() -> LC3Result<()> {
+        let program: Vec<u16> = vec![
+            // AND R1, R1, #0
+            0x5260,
+            // LEA R0, #0
+            0xE000,
+            // OUT
+            0xF021,
+        ];
+        let routine: Vec<u16> = vec![
+            // ADD R1, R1, #1
+            0x1261,
+            // RTI
+            0x8000,
+        ];
+
+        // LEA sets the condition codes by default
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        assert_eq!(vm.spec_profile(), SpecProfile::Patt2ndEd);
+        vm.load_program(&program)?;
+        vm.step()?;
+        vm.step()?;
+        assert_eq!(vm.register(RCond), FL_POS);
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.set_spec_profile(SpecProfile::Patt3rdEd);
+        assert!(vm.load_os().is_err());
+        vm.set_os_traps(true);
+        vm.load_program(&program)?;
+        vm.load_program_at(0x0400, &routine)?;
+        vm.load_program_at(0x0021, &[0x0400])?;
+        vm.set_psr(PSR_USER);
+        vm.set_register(RR6, 0x4000);
+        vm.set_register(RR7, 0x1234);
+        vm.step()?;
+        vm.step()?;
+        assert_eq!(vm.register(RR0), 0x3002);
+        assert_eq!(vm.register(RCond), FL_ZRO);
+
+        // TRAP saves the PSR and PC on the supervisor stack, leaving R7
+        // alone, and RTI goes back to user mode
+        assert_eq!(vm.step()?.pc, 0x0400);
+        assert_eq!(vm.psr() & PSR_USER, 0);
+        assert_eq!(vm.register(RR6), SUPERVISOR_STACK_START - 2);
+        assert_eq!(vm.memory_at(SUPERVISOR_STACK_START - 1), PSR_USER | FL_ZRO);
+        assert_eq!(vm.memory_at(SUPERVISOR_STACK_START - 2), 0x3003);
+        assert_eq!(vm.register(RR7), 0x1234);
+        vm.step()?;
+        assert_eq!(vm.step()?.pc, 0x3003);
+        assert_eq!(vm.psr() & PSR_USER, PSR_USER);
+        assert_eq!(vm.register(RR6), 0x4000);
+        assert_eq!(vm.register(RR1), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_load_os_object() -> LC3Result<()> {
+        // The vector for OUT at x0021, pointing just past itself at a
+        // routine that counts in R1
+        let os: Vec<u16> = vec![0x0021, 0x0022, 0x1261, 0xC1C0];
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_os_object(&os)?;
+        vm.load_object(&[0x3000, 0xF021, 0xF025])?;
+
+        assert_eq!(vm.step()?.pc, 0x0022);
+        vm.step()?;
+        assert_eq!(vm.step()?.pc, 0x3001);
+        assert_eq!(vm.register(RR1), 1);
+        assert_eq!(vm.register(RR6), SUPERVISOR_STACK_START);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_fill_memory() -> LC3Result<()> {
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.fill_memory(0xDEAD);
+        vm.load_program(&vec![0xF025])?;
+
+        assert_eq!(vm.memory_at(0x0000), 0xDEAD);
+        assert_eq!(vm.memory_at(0x3000), 0xF025);
+        assert_eq!(vm.memory_at(0xFDFF), 0xDEAD);
+        // The device registers are left alone
+        assert_eq!(vm.memory_at(KB_STATUS_POS), 0);
+
+        // Resetting memory fills it the same way again
+        vm.set_memory_fill(MemoryFill::Random(7));
+        vm.load_program(&vec![0xF025])?;
+        let filled = vm.memory()[..0x3000].to_vec();
+        assert!(filled.iter().any(|word| *word != filled[0]));
+        vm.set_memory_at(0x0000, !filled[0]);
+        vm.reset(true);
+        assert_eq!(vm.memory()[..0x3000], filled[..]);
+        assert_eq!(vm.memory_at(0x3000), 0xF025);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_register_host_traps() -> LC3Result<()> {
+        let program: Vec<u16> = vec![
+            // ADD R0, R0, #5
+            0x1025,
+            // TRAP x30
+            0xF030,
+            // HALT
+            0xF025,
+        ];
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.register_trap(0x30, |state| {
+            let value = state.read_register(RR0);
+            state.write_register(RR0, value * 2);
+            Ok(())
+        })?;
+        assert!(matches!(
+            vm.register_trap(0x25, |_| Ok(())),
+            Err(LC3Error::ReservedTrap { code: 0x25 })
+        ));
+
+        vm.load_program(&program)?;
+        vm.run()?;
+        assert_eq!(vm.register(RR0), 10);
+
+        // Still handled natively with the OS running the other traps
+        vm.set_os_traps(true);
+        vm.set_pc(0x3001);
+        assert_eq!(vm.step()?.pc, 0x3002);
+        assert_eq!(vm.register(RR0), 20);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_read_seeded_random_numbers() -> LC3Result<()> {
+        let program: Vec<u16> = vec![
+            // LDI R0, RNG
+            0xA002,
+            // LDI R1, RNG
+            0xA201,
+            // HALT
+            0xF025,
+            // RNG
+            super::RNG_POS,
+        ];
+
+        let mut first = VM::new_with_io(TestIOHandle::new());
+        first.seed_rng(1234);
+        first.load_program(&program)?;
+        first.run()?;
+        assert_ne!(first.register(RR0), first.register(RR1));
+
+        // The same seed gives the same numbers
+        let mut second = VM::new_with_io(TestIOHandle::new());
+        second.seed_rng(1234);
+        second.load_program(&program)?;
+        second.run()?;
+        assert_eq!(second.register(RR0), first.register(RR0));
+        assert_eq!(second.register(RR1), first.register(RR1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_read_deterministic_clock() -> LC3Result<()> {
+        let program: Vec<u16> = vec![
+            // LDI R0, CLOCK_LOW
+            0xA003,
+            // LDI R1, CLOCK_HIGH
+            0xA203,
+            // LDI R0, CLOCK_LOW
+            0xA001,
+            // HALT
+            0xF025,
+            // CLOCK_LOW
+            super::CLOCK_LOW_POS,
+            // CLOCK_HIGH
+            super::CLOCK_HIGH_POS,
+        ];
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.set_deterministic_clock(true);
+        vm.load_program(&program)?;
+        vm.run()?;
+        // Two instructions had finished by the second read
+        assert_eq!(vm.register(RR0), 2);
+        assert_eq!(vm.register(RR1), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_count_instructions_executed() -> LC3Result<()> {
+        let program: Vec<u16> = vec![
+            // ADD R2, R2, #1
+            0x14A1,
+            // ADD R2, R2, #1
+            0x14A1,
+            // ADD R2, R2, #1
+            0x14A1,
+            // LDI R0, INSTRUCTIONS_LOW
+            0xA002,
+            // LDI R1, INSTRUCTIONS_HIGH
+            0xA202,
+            // HALT
+            0xF025,
+            // INSTRUCTIONS_LOW
+            super::INSTRUCTIONS_LOW_POS,
+            // INSTRUCTIONS_HIGH
+            super::INSTRUCTIONS_HIGH_POS,
+        ];
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_program(&program)?;
+        vm.run()?;
+        assert_eq!(vm.register(RR0), 3);
+        assert_eq!(vm.register(RR1), 0);
+
+        Ok(())
+    }
+
+    // All the input has to come from another thread, since nobody ever
+    // types anything
+    #[derive(Default)]
+    struct NoKeyboard(RefCell<String>);
+
+    impl IOHandle for NoKeyboard {
+        fn getchar(&self) -> LC3Result<char> {
+            Err(LC3Error::Other("There's no keyboard".to_string()))
+        }
+
+        fn putchar(&self, ch: char) -> LC3Result<()> {
+            self.0.borrow_mut().push(ch);
+            Ok(())
+        }
+
+        fn is_key_down(&self) -> LC3Result<bool> {
+            Ok(false)
+        }
+    }
+
+    #[test]
+    fn can_inject_input_from_other_threads() -> LC3Result<()> {
+        let program = assemble(
+            r#"
+            .ORIG x3000
+POLL        LDI R0, KBSR
+            BRzp POLL
+            LDI R0, KBDR
+            OUT
+            GETC
+            OUT
+            HALT
+KBSR        .FILL xFE00
+KBDR        .FILL xFE02
+            .END
+        "#,
+        )?;
+
+        let mut vm = VM::new_with_io(NoKeyboard::default());
+        vm.load_object(&program.to_object())?;
+        let sender = vm.input_sender();
+        let typist = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(5));
+            sender.send_str("hi").is_ok()
+        });
+        vm.run()?;
+        assert!(typist.join().unwrap());
+
+        assert_eq!(vm.into_io_handle().0.into_inner(), "hi");
+
+        Ok(())
+    }
+
+    // Presses Ctrl+C once the VM has asked often enough
+    struct ImpatientUser(Cell<u32>);
+
+    impl IOHandle for ImpatientUser {
+        fn getchar(&self) -> LC3Result<char> {
+            Err(LC3Error::Other("There's no keyboard".to_string()))
+        }
+
+        fn putchar(&self, _ch: char) -> LC3Result<()> {
+            Ok(())
+        }
+
+        fn is_key_down(&self) -> LC3Result<bool> {
+            Ok(false)
+        }
+
+        fn take_interrupt(&self) -> bool {
+            let patience = self.0.get().saturating_sub(1);
+            self.0.set(patience);
+            patience == 0
+        }
+    }
+
+    #[test]
+    fn can_interrupt_programs_that_dont_read() -> LC3Result<()> {
+        let program: Vec<u16> = vec![
+            // ADD R1, R1, #1
+            0x1261,
+            // BRnzp #-2
+            0x0FFE,
+        ];
+
+        let mut vm = VM::new_with_io(ImpatientUser(Cell::new(100)));
+        vm.load_program(&program)?;
+        match vm.run() {
+            Err(LC3Error::Interrupted) => {}
+            result => panic!("Expected an interrupted run, got {:?}", result),
+        }
+        assert_eq!(vm.register(RR1), 50);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_stop_clock_with_mcr() -> LC3Result<()> {
+        let program: Vec<u16> = vec![
+            // AND R0, R0, #0
+            0x5020,
+            // STI R0, MCR
+            0xB001,
+            // ADD R1, R1, #1, never reached
+            0x1261,
+            // MCR
+            super::MACHINE_CONTROL_POS,
+        ];
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_program(&program)?;
+        let outcome = vm.run()?;
+        assert_eq!(outcome.reason, StopReason::ClockStopped);
+        assert_eq!(outcome.instructions, 2);
+        assert_eq!(vm.register(RR1), 0);
+        assert!(!vm.is_running());
+
+        // HALT goes through the MCR too
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_program(&vec![0xF025])?;
+        assert_eq!(vm.run()?.reason, StopReason::HaltTrap);
+        assert_eq!(vm.memory_at(super::MACHINE_CONTROL_POS), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_reset() -> LC3Result<()> {
+        let program: Vec<u16> = vec![
+            // Add 5 to RR0 and put the result in RR0
+            0b0001_0000_0010_0101,
+            // Store RR0 into the word after the halt
+            0b0011_0000_0000_0001,
+            // Halt
+            0xF025,
+            0x0000,
+        ];
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_program(&program)?;
+        vm.run()?;
+        assert_eq!(vm.reg_index_read(0)?, 5);
+        assert_eq!(vm.mem_read(0x3003)?, 5);
+
+        vm.reset(false);
+        assert_eq!(vm.reg_index_read(0)?, 0);
+        assert_eq!(vm.reg_read(RCond)?, 0);
+        assert_eq!(vm.mem_read(0x3003)?, 5);
+
+        vm.reset(true);
+        assert_eq!(vm.mem_read(0x3003)?, 0);
+        vm.run()?;
+        assert_eq!(vm.reg_index_read(0)?, 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_inspect_state() -> LC3Result<()> {
+        let program: Vec<u16> = vec![
+            // Add -1 to RR0 and put the result in RR1
+            0b0001_0010_0011_1111,
+        ];
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_program(&program)?;
+        assert_eq!(vm.pc(), 0x3000);
+        assert_eq!(vm.memory_at(0x3000), program[0]);
+
+        vm.set_register(RR0, 3);
+        vm.step()?;
+        assert_eq!(vm.register(RR1), 2);
+        assert_eq!(vm.pc(), 0x3001);
+        assert_eq!(
+            vm.condition_flags(),
+            ConditionFlags {
+                negative: false,
+                zero: false,
+                positive: true,
+            }
+        );
+
+        vm.set_pc(0x4000);
+        vm.set_memory_at(0x4000, 0xBEEF);
+        assert_eq!(vm.reg_read(super::RPC)?, 0x4000);
+        assert_eq!(vm.mem_read(0x4000)?, 0xBEEF);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_load_program_at() -> LC3Result<()> {
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_program_at(0x4000, &[1, 2, 3])?;
+        vm.load_program_at(0x4003, &[4])?;
+        assert_eq!(vm.memory_at(0x4000), 1);
+        assert_eq!(vm.memory_at(0x4003), 4);
+
+        let overlap = vm.load_program_at(0x3FFF, &[5, 6]);
+        assert!(matches!(
+            overlap,
+            Err(LC3Error::ProgramOverlap {
+                origin: 0x3FFF,
+                existing: 0x4000
+            })
+        ));
+
+        let too_long = vm.load_program_at(0xFFFF, &[7, 8]);
+        assert!(matches!(
+            too_long,
+            Err(LC3Error::ProgramSize { len: 2, max_len: 1 })
+        ));
+
+        // A reload that overlaps something else leaves the earlier program
+        // in place
+        assert!(vm.load_program_at(0x4000, &[1, 2, 3, 4, 5]).is_err());
+        vm.reset(true);
+        assert_eq!(vm.memory_at(0x4002), 3);
+
+        // Reloading at the same origin replaces the earlier program, and
+        // clears what's left of it
+        vm.load_program_at(0x4000, &[9])?;
+        assert_eq!(vm.memory_at(0x4001), 0);
+        vm.set_memory_at(0x4000, 0);
+        vm.reset(true);
+        assert_eq!(vm.memory_at(0x4000), 9);
+        assert_eq!(vm.memory_at(0x4001), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_patch_memory() -> LC3Result<()> {
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_program_at(0x4000, &[1, 2, 3])?;
+
+        // Straddling the end of the program, which only keeps its part
+        vm.patch(0x4002, &[7, 8])?;
+        assert_eq!(vm.read_range(0x4000, 4)?, vec![1, 2, 7, 8]);
+        vm.reset(true);
+        assert_eq!(vm.read_range(0x4000, 4)?, vec![1, 2, 7, 0]);
+
+        assert_eq!(vm.read_range(0xFFFF, 1)?, vec![0]);
+        assert!(vm.read_range(0x4000, 0)?.is_empty());
+        assert!(matches!(
+            vm.patch(0xFFFF, &[1, 2]),
+            Err(LC3Error::MemoryRange {
+                address: 0xFFFF,
+                len: 2
+            })
+        ));
+        assert!(vm.read_range(0xFFFE, 3).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_access_memory_slices() -> LC3Result<()> {
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_program_at(0x4000, &[1, 2, 3])?;
+        assert_eq!(vm.memory().len(), super::MEMORY_SIZE);
+        assert_eq!(&vm.memory()[0x4000..0x4003], [1, 2, 3]);
+
+        vm.memory_mut()[0x5000..0x5002].copy_from_slice(&[4, 5]);
+        assert_eq!(vm.memory_at(0x5001), 5);
+        assert_eq!(vm.snapshot().memory().nth(0x5000), Some(4));
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_use_smaller_memory_maps() -> LC3Result<()> {
+        let program: Vec<u16> = vec![
+            // LD R0, CHAR
+            0x2003,
+            // STI R0, DDR
+            0xB003,
+            // LDI R1, FAR
+            0xA203,
+            // Halt
+            0xF025,
+            // CHAR
+            'A' as u16,
+            // DDR, where the memory map below puts it
+            0x3E06,
+            // FAR, past the end of memory
+            0x5000,
+        ];
+        let halting: Vec<u16> = vec![
+            // STI R0, DDR
+            0xB001,
+            // Halt
+            0xF025,
+            // DDR
+            0x3E06,
+        ];
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        let map = MemoryMap {
+            size: 0x4000,
+            devices: 0x3E00,
+        };
+        vm.set_memory_map(map)?;
+        assert_eq!(vm.memory_map(), map);
+        vm.set_memory_fill(MemoryFill::Word(0xDEAD));
+        assert_eq!(vm.memory_at(0x3DFF), 0xDEAD);
+        assert_eq!(vm.memory_at(0x3E00), 0);
+        assert_eq!(vm.memory_at(0x4000), 0);
+
+        vm.load_program(&program)?;
+        assert!(matches!(
+            vm.run(),
+            Err(LC3Error::Unmapped { address: 0x5000 })
+        ));
+
+        // HALT stops the clock through the MCR wherever it is
+        vm.load_program(&halting)?;
+        vm.set_register(RR0, 'B' as u16);
+        let outcome = vm.run()?;
+        assert_eq!(outcome.reason, StopReason::HaltTrap);
+        assert_eq!(vm.memory_at(0x3FFE), 0);
+
+        assert!(matches!(
+            vm.load_program_at(0x3F00, &[0; 0x200]),
+            Err(LC3Error::ProgramSize { .. })
+        ));
+        assert!(matches!(vm.load_os(), Err(LC3Error::BadMemoryMap(_))));
+        assert!(vm
+            .set_memory_map(MemoryMap {
+                size: 0x4000,
+                devices: 0xFF00,
+            })
+            .is_err());
+
+        let io_handle = vm.into_io_handle();
+        assert_eq!(io_handle.get_test_outputs(), vec!['A', 'B']);
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_load_object() -> LC3Result<()> {
+        let object: Vec<u16> = vec![
+            // Origin
+            0x4000,
+            // Add 5 to RR0 and put the result in RR0
+            0b0001_0000_0010_0101,
+            // Halt
+            0xF025,
+        ];
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        vm.load_object(&object)?;
+        assert_eq!(vm.pc(), 0x4000);
+        assert_eq!(vm.memory_at(0x4001), 0xF025);
+
+        let outcome = vm.run()?;
+        assert_eq!(outcome.instructions, 2);
+        assert_eq!(vm.register(RR0), 5);
+
+        assert!(matches!(vm.load_object(&[]), Err(LC3Error::EmptyObject)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_switch_banks() -> LC3Result<()> {
+        let program = assemble(
+            r#"
+            .ORIG x3000
+            LD R0, ONE
+            STI R0, BSR
+            LDI R1, WINDOW
+            LD R0, TWO
+            STI R0, BSR
+            LDI R2, WINDOW
+            STI R1, WINDOW
+            LDI R3, BSR
+            HALT
+ONE         .FILL 1
+TWO         .FILL 6
+BSR         .FILL xFE16
+WINDOW      .FILL x4000
+            .END
+        "#,
+        )?;
+
+        let mut vm = VM::new_with_io(TestIOHandle::new());
+        assert!(vm.load_bank(1, &[0x1111]).is_err());
+        vm.load_program_at(0x4000, &[0x0BAD])?;
+        vm.set_banked_memory(BankedMemory {
+            window: 0x4000,
+            banks: 4,
+        })?;
+        vm.load_bank(1, &[0x1111])?;
+        vm.load_bank(2, &[0x2222])?;
+        vm.load_object(&program.to_object())?;
+        vm.run()?;
+
+        // Bank 6 wraps round to bank 2
+        assert_eq!(vm.register(RR1), 0x1111);
+        assert_eq!(vm.register(RR2), 0x2222);
+        assert_eq!(vm.register(RR3), 2);
+        assert_eq!(vm.selected_bank(), Some(2));
+        assert_eq!(vm.memory_at(0x4000), 0x1111);
+        assert_eq!(vm.read_bank(0)?[0], 0x0BAD);
+        assert_eq!(vm.read_bank(2)?[0], 0x1111);
+        assert!(matches!(
+            vm.read_bank(4),
+            Err(LC3Error::NoSuchBank { bank: 4, banks: 4 })
+        ));
+
+        // Restoring a snapshot brings back the bank that was in the window
+        let snapshot = vm.snapshot();
+        // and leaves the other banks alone
+        vm.select_bank(1);
+        vm.set_memory_at(0x4001, 0x5555);
+        vm.restore(&snapshot);
+        assert_eq!(vm.selected_bank(), Some(2));
+        assert_eq!(vm.memory_at(0x4001), 0);
+        assert_eq!(vm.read_bank(1)?[..2], [0x1111, 0x5555]);
+
+        vm.reset(true);
+        assert_eq!(vm.selected_bank(), Some(0));
+        assert_eq!(vm.memory_at(0x4000), 0x0BAD);
+        assert_eq!(vm.read_bank(2)?[0], 0x2222);
+
+        assert!(vm
+            .set_banked_memory(BankedMemory {
+                window: 0xC000,
+                banks: 2,
+            })
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn can_run_lc3b_programs() -> LC3Result<()> {
+        let program = Assembler::new().isa(Isa::Lc3b).assemble(
+            r#"
+            .ORIG x3000
+            LEA R1, DATA
+            LDW R2, R1, #0
+            LDB R3, R1, #0
+            LDB R4, R1, #1
+            XOR R5, R2, #-1
+            LSHF R6, R4, #4
+            RSHFA R5, R5, #4
+            STB R3, R1, #3
+            STW R5, R1, #2
+            JSR SUB
+            LEA R0, MSG
+            PUTS
+            HALT
+SUB         ADD R2, R2, #1
+            RET
+DATA        .FILL x1234
+            .FILL 0
+            .FILL 0
+MSG         .STRINGZ "Hi!"
+            .END
+        "#,
+            None,
+        )?;
+
+        let mut vm = VM::new_with_isa(TestIOHandle::new(), Isa::Lc3b);
+        assert_eq!(vm.isa(), Isa::Lc3b);
+        vm.load_object(&program.to_object())?;
+        // Words are at even addresses
+        assert_eq!(vm.memory_at(0x3002), 0x6440);
+        assert_eq!(vm.memory_at(0x3001), 0);
+        let outcome = vm.run()?;
+        assert_eq!(outcome.reason, StopReason::HaltTrap);
+
+        assert_eq!(vm.register(RR1), 0x301E);
+        assert_eq!(vm.register(RR2), 0x1235);
+        assert_eq!(vm.register(RR3), 0x34);
+        assert_eq!(vm.register(RR4), 0x12);
+        assert_eq!(vm.register(RR5), 0xFEDC);
+        assert_eq!(vm.register(RR6), 0x120);
+        assert_eq!(vm.memory_at(0x3020), 0x3400);
+        assert_eq!(vm.memory_at(0x3022), 0xFEDC);
+
+        // Words can't be loaded from odd addresses, or programs put at them
+        vm.load_program(&vec![0x6040])?;
+        vm.set_register(RR1, 0x4001);
+        assert!(matches!(
+            vm.run(),
+            Err(LC3Error::Unaligned { address: 0x4001 })
+        ));
+        assert!(matches!(
+            vm.load_program_at(0x3001, &[0]),
+            Err(LC3Error::Unaligned { address: 0x3001 })
+        ));
+        assert!(vm.load_os().is_err());
+
+        let io_handle = vm.into_io_handle();
+        assert_eq!(io_handle.get_test_outputs(), vec!['H', 'i', '!']);
+
+        Ok(())
+    }
 }